@@ -0,0 +1,33 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* The MBR starts the bootloader from UICR.NRFFW[0]; the MBR parameter and the bootloader settings pages
+     at 0xFE000 - 0x100000 stay where the Adafruit bootloader had them */
+  FLASH                             : ORIGIN = 0x000F4000, LENGTH = 40K
+  /* Keep in sync with src/device/config.rs and the application memory.x, the application build checks them */
+  BOOTLOADER_STATE                  : ORIGIN = 0x000C2000, LENGTH = 4K
+  ACTIVE                            : ORIGIN = 0x00027000, LENGTH = 308K
+  DFU                               : ORIGIN = 0x00074000, LENGTH = 312K
+  /* The softdevice is not enabled yet, only the MBR owns the first bytes */
+  RAM                         (rwx) : ORIGIN = 0x20000008, LENGTH = 255K
+  uicr_bootloader_start_address (r) : ORIGIN = 0x10001014, LENGTH = 0x4
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE);
+
+__bootloader_active_start = ORIGIN(ACTIVE);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE);
+
+__bootloader_dfu_start = ORIGIN(DFU);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU);
+
+__bootloader_start = ORIGIN(FLASH);
+
+SECTIONS
+{
+  .uicr_bootloader_start_address :
+  {
+    LONG(__bootloader_start)
+  } > uicr_bootloader_start_address
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+use embassy_boot_nrf::*;
+use embassy_nrf::nvmc::Nvmc;
+use embassy_nrf::wdt;
+use embassy_sync::blocking_mutex::Mutex;
+
+/// 10 s, `WATCHDOG_TIMEOUT` of the application. A running watchdog can't be reconfigured: it keeps this
+/// configuration across soft resets and the application adopts it, see `start_watchdog`.
+const WATCHDOG_TIMEOUT_TICKS: u32 = 10 * 32768;
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_nrf::init(Default::default());
+
+    let mut wdt_config = wdt::Config::default();
+    wdt_config.timeout_ticks = WATCHDOG_TIMEOUT_TICKS;
+    wdt_config.run_during_sleep = true;
+    wdt_config.run_during_debug_halt = false;
+
+    // a swap of the whole partition takes longer than the timeout, the flash driver feeds the watchdog
+    let flash = WatchdogFlash::start(Nvmc::new(p.NVMC), p.WDT, wdt_config);
+    let flash = Mutex::new(RefCell::new(flash));
+
+    // swaps in a new image marked updated by the application, or reverts one that has not marked itself booted
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash);
+    let active_offset = config.active.offset();
+    let bl: BootLoader = BootLoader::prepare(config);
+
+    // forwards the interrupts to the softdevice, which starts the application at `ACTIVE`
+    unsafe { bl.load(active_offset) }
+}
+
+#[no_mangle]
+#[cfg_attr(target_os = "none", link_section = ".HardFault.user")]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    const SCB_ICSR: *const u32 = 0xE000_ED04 as *const u32;
+    let irqn = core::ptr::read_volatile(SCB_ICSR) as u8 as i16 - 16;
+
+    panic!("DefaultHandler #{:?}", irqn);
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::asm::udf();
+}
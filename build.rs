@@ -8,10 +8,21 @@
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
 
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
+use std::process::Command;
+
+#[allow(dead_code)]
+#[path = "src/ble/encoding.rs"]
+mod encoding;
+#[allow(dead_code)]
+#[path = "src/ble/gatt_table.rs"]
+mod gatt_table;
+#[path = "build/gatt_schema.rs"]
+mod gatt_schema;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -26,7 +37,114 @@ fn main() {
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
 
-    println!("cargo:rustc-link-arg-bins=--nmagic");
-    println!("cargo:rustc-link-arg-bins=-Tlink.x");
-    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    // Firmware revision reported by the Device Information Service
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=SHBLE_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    // Ed25519 public key for firmware images received over BLE, 64 hex characters; the host simulator and the tests
+    // sign their images with keys of their own
+    let is_device = env::var("TARGET").map_or(false, |target| target.starts_with("thumb"));
+    let dfu_public_key = match env::var("SHBLE_DFU_PUBLIC_KEY") {
+        Ok(key) => parse_hex_key(key.trim()).expect("SHBLE_DFU_PUBLIC_KEY has to be 64 hex characters, an Ed25519 key"),
+        Err(_) if is_device => panic!("SHBLE_DFU_PUBLIC_KEY is not set, see the firmware update section of the README"),
+        Err(_) => [0u8; 32],
+    };
+    File::create(out.join("dfu_public_key.bin")).unwrap().write_all(&dfu_public_key).unwrap();
+    println!("cargo:rerun-if-env-changed=SHBLE_DFU_PUBLIC_KEY");
+
+    // GATT schema for clients, `SHBLE_GATT_SCHEMA=<path>` copies it out of the build directory
+    let [services_rs, _, _, config_rs] = gatt_schema::SOURCES.map(|source| {
+        println!("cargo:rerun-if-changed={}", source);
+        std::fs::read_to_string(source).unwrap()
+    });
+    let schema = gatt_schema::generate(&services_rs, &config_rs)
+        .unwrap_or_else(|err| panic!("Invalid GATT table: {}", err));
+    File::create(out.join("gatt_schema.json")).unwrap().write_all(schema.as_bytes()).unwrap();
+    if let Ok(path) = env::var("SHBLE_GATT_SCHEMA") {
+        if let Err(err) = std::fs::write(&path, &schema) {
+            println!("cargo:warning=Failed to write the GATT schema to {}: {}", path, err);
+        }
+    }
+    println!("cargo:rerun-if-env-changed=SHBLE_GATT_SCHEMA");
+
+    // the simulator runs the event handling of the services without the softdevice macros
+    let service_events =
+        gatt_schema::service_events(&services_rs).unwrap_or_else(|err| panic!("Invalid GATT table: {}", err));
+    File::create(out.join("service_events.rs")).unwrap().write_all(service_events.as_bytes()).unwrap();
+
+    // the bootloader partitions, the settings pages and the history region follow the application
+    println!("cargo:rerun-if-changed=bootloader/memory.x");
+    let constants = gatt_schema::parse_constants(&config_rs);
+    check_flash_layout(include_str!("memory.x"), include_str!("bootloader/memory.x"), &constants);
+
+    // the host simulator links with the default linker scripts
+    if is_device {
+        println!("cargo:rustc-link-arg-bins=--nmagic");
+        println!("cargo:rustc-link-arg-bins=-Tlink.x");
+        println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    }
+}
+
+/// `FLASH : ORIGIN = 0x00027000, LENGTH = 308K`, None if the region is missing or unparsable
+fn memory_region(memory_x: &str, name: &str) -> Option<(u64, u64)> {
+    let line = memory_x.lines().map(str::trim).find(|line| line.split([' ', ':']).next() == Some(name))?;
+    let field = |field: &str| {
+        line.split(',')
+            .find_map(|part| part.split_once(field))
+            .map(|(_, value)| value.trim_start_matches([' ', '=']).trim())
+    };
+    let origin = field("ORIGIN").and_then(|value| u64::from_str_radix(value.trim_start_matches("0x"), 16).ok())?;
+    let length = field("LENGTH").and_then(|value| value.strip_suffix('K')?.parse::<u64>().ok())? * 1024;
+    Some((origin, length))
+}
+
+/// The application is the bootloader's ACTIVE partition, the DFU and the state partitions are where config.rs
+/// expects them, and the history region ends before the bootloader
+fn check_flash_layout(memory_x: &str, bootloader_memory_x: &str, constants: &BTreeMap<String, u64>) {
+    let constant = |name: &str| constants.get(name).copied().unwrap_or_else(|| panic!("No {} in config.rs", name));
+    let partition = |offset: &str, size: &str| Some((constant(offset), constant(size)));
+
+    let bootloader = |name: &str| memory_region(bootloader_memory_x, name);
+    let checks = [
+        ("memory.x FLASH", memory_region(memory_x, "FLASH"), partition("ACTIVE_OFFSET", "ACTIVE_SIZE")),
+        ("bootloader/memory.x ACTIVE", bootloader("ACTIVE"), partition("ACTIVE_OFFSET", "ACTIVE_SIZE")),
+        ("bootloader/memory.x DFU", bootloader("DFU"), partition("DFU_OFFSET", "DFU_SIZE")),
+        (
+            "bootloader/memory.x BOOTLOADER_STATE",
+            bootloader("BOOTLOADER_STATE"),
+            partition("BOOTLOADER_STATE_OFFSET", "BOOTLOADER_STATE_SIZE"),
+        ),
+    ];
+    for (region_name, region, expected) in checks {
+        if region != expected {
+            let (offset, size) = expected.unwrap_or_default();
+            panic!("{}: expected {:#x} of {}K as in config.rs, found {:x?}", region_name, offset, size / 1024, region);
+        }
+    }
+
+    let history_end = constant("HISTORY_FLASH_OFFSET") + constant("HISTORY_FLASH_PAGES") * constant("FLASH_PAGE_SIZE");
+    match bootloader("FLASH") {
+        Some((origin, _)) if origin == constant("BOOTLOADER_OFFSET") && history_end <= origin => {}
+        region => panic!("bootloader/memory.x FLASH has to be at BOOTLOADER_OFFSET after the history: {:x?}", region),
+    }
+}
+
+fn parse_hex_key(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    for (index, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    Some(key)
 }
@@ -1,6 +1,22 @@
 #!/usr/bin/env bash
+# ./build.sh             - serial DFU through the stock Adafruit bootloader
+# ./build.sh bootloader  - the in-tree bootloader and the application over a debug probe, enables BLE updates
 set -e
 
+if [ -z "$SHBLE_DFU_PUBLIC_KEY" ]; then
+  echo "SHBLE_DFU_PUBLIC_KEY is not set, see the firmware update section of the README" >&2
+  exit 1
+fi
+
+if [ "$1" = "bootloader" ]; then
+  (cd bootloader && cargo build --release)
+  probe-rs download --chip nRF52840_xxAA bootloader/target/thumbv7em-none-eabihf/release/shble-bootloader
+  cargo build --release --bin main --features dfu-bootloader
+  probe-rs download --chip nRF52840_xxAA target/thumbv7em-none-eabihf/release/main
+  probe-rs reset --chip nRF52840_xxAA
+  exit 0
+fi
+
 #cargo clean
 cargo objcopy --release --bin main -- -O ihex ./target/blink.hex
 
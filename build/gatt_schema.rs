@@ -0,0 +1,569 @@
+//! Exports the GATT table as JSON for clients such as the collector.
+//!
+//! The services built by the `ServiceBuilder` (the ESS and the ADC service) come from the tables in
+//! `src/ble/gatt_table.rs`, which the firmware registers them from. The `#[nrf_softdevice::gatt_service]` structs
+//! in `src/ble/services.rs` are declared by their attributes, so those are read from the source; their value
+//! encodings are the `src/ble/encoding.rs` constants named by the Presentation Format descriptors.
+//!
+//! The same structs give the host simulator the event enums the `gatt_service` macro generates on the device.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::encoding::{Encoding, Format, ENCODINGS};
+use crate::gatt_table::{
+    CharacteristicSpec, GattUuid, Properties, ServiceSpec, WriteSecurity, ADC_SERVICE, ESS_SERVICE,
+    PRESENTATION_FORMAT_UUID, USER_DESCRIPTION_UUID,
+};
+
+pub(crate) const SOURCES: [&str; 4] =
+    ["src/ble/services.rs", "src/ble/encoding.rs", "src/ble/gatt_table.rs", "src/device/config.rs"];
+
+struct Characteristic {
+    name: String,
+    uuid: String,
+    properties: Vec<String>,
+    security: Option<String>,
+    rust_type: Option<String>,
+    descriptors: Vec<String>,
+    /// User Description (0x2901) text
+    description: Option<String>,
+    description_writable: bool,
+    doc: String,
+    encoding: Option<Encoding>,
+}
+
+struct Service {
+    name: String,
+    uuid: String,
+    doc: String,
+    characteristics: Vec<Characteristic>,
+}
+
+/// Value length: known in bytes, or the expression as written if it could not be evaluated
+enum Length {
+    Fixed(u64),
+    Max(u64),
+    Unresolved(String),
+}
+
+/// Fails if the GATT table can't be registered by the softdevice
+pub(crate) fn generate(services_rs: &str, config_rs: &str) -> Result<String, String> {
+    let constants = parse_constants(config_rs);
+    let mut services = parse_gatt_services(services_rs)?;
+    services.extend([&ESS_SERVICE, &ADC_SERVICE].map(service_from_spec));
+    check_vendor_uuid_bases(&services, &constants)?;
+    check_cccd_count(&services, &constants)?;
+    check_encodings(&services, &constants)?;
+    Ok(to_json(&services, &constants))
+}
+
+/// `<Service>Event` enums as `#[nrf_softdevice::gatt_service]` declares them: a `<Field>Write` with the value
+/// for every writable characteristic and a `<Field>CccdWrite` for every one with a CCCD
+pub(crate) fn service_events(services_rs: &str) -> Result<String, String> {
+    let mut events = String::new();
+    for service in parse_gatt_services(services_rs)? {
+        writeln!(events, "pub(crate) enum {}Event {{", service.name).unwrap();
+        for characteristic in &service.characteristics {
+            let case = upper_camel_case(&characteristic.name);
+            let has = |name: &str| characteristic.properties.iter().any(|property| property == name);
+            if has("write") || has("write_without_response") {
+                let rust_type = characteristic
+                    .rust_type
+                    .as_deref()
+                    .ok_or_else(|| format!("{}.{} has no type", service.name, characteristic.name))?;
+                writeln!(events, "    {}Write({}),", case, rust_type).unwrap();
+            }
+            let cccd = match (has("indicate"), has("notify")) {
+                (true, true) => "indications: bool, notifications: bool",
+                (true, false) => "indications: bool",
+                (false, true) => "notifications: bool",
+                (false, false) => continue,
+            };
+            writeln!(events, "    {}CccdWrite {{ {} }},", case, cccd).unwrap();
+        }
+        writeln!(events, "}}").unwrap();
+    }
+    Ok(events)
+}
+
+fn upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_ascii_uppercase()).into_iter().chain(chars)
+        })
+        .collect()
+}
+
+/// `ConvExt` and the descriptors encode by the encoding the Presentation Format names, the softdevice by the
+/// field type: a scalar field has to be of the encoded format, a byte array as long as the encoded value
+fn check_encodings(services: &[Service], constants: &BTreeMap<String, u64>) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for service in services {
+        for characteristic in &service.characteristics {
+            let Some(rust_type) = characteristic.rust_type.as_deref() else {
+                continue;
+            };
+            let Some(encoding) = characteristic.encoding else {
+                errors.push(format!("{}.{} has no Presentation Format", service.name, characteristic.name));
+                continue;
+            };
+            let compatible = match (rust_type_format(rust_type, constants), encoding.format.len()) {
+                ((Format::Bytes, Some(Length::Fixed(length))), Some(encoded)) => length == encoded as u64,
+                ((Format::Bytes, _), encoded) => encoded.is_none(),
+                ((format, _), _) => format == encoding.format,
+            };
+            if !compatible {
+                errors.push(format!(
+                    "{}.{} is {}, encoded as {}",
+                    service.name,
+                    characteristic.name,
+                    rust_type,
+                    encoding.format.name()
+                ));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Encodings do not match the characteristics: {}", errors.join(", ")))
+    }
+}
+
+/// The CCCD values of a bonded peer are stored in `BLE_SYS_ATTRS_LEN` bytes sized by `BLE_CCCD_COUNT`
+fn check_cccd_count(services: &[Service], constants: &BTreeMap<String, u64>) -> Result<(), String> {
+    let limit = *constants.get("BLE_CCCD_COUNT").ok_or("BLE_CCCD_COUNT is not defined")?;
+    let count = services
+        .iter()
+        .flat_map(|service| service.characteristics.iter())
+        .filter(|characteristic| {
+            characteristic.properties.iter().any(|property| property == "notify" || property == "indicate")
+        })
+        .count();
+
+    if count as u64 > limit {
+        return Err(format!("{} characteristics with a CCCD, BLE_CCCD_COUNT is {}", count, limit));
+    }
+    Ok(())
+}
+
+/// Every 128-bit UUID is registered as a vendor base with bytes 12-13 masked out, the softdevice
+/// only has room for `BLE_VS_UUID_COUNT` of them and `BleServer::new` fails with NoMem otherwise
+fn check_vendor_uuid_bases(services: &[Service], constants: &BTreeMap<String, u64>) -> Result<(), String> {
+    let limit = *constants.get("BLE_VS_UUID_COUNT").ok_or("BLE_VS_UUID_COUNT is not defined")?;
+    let mut bases = BTreeMap::new();
+    let uuids = services.iter().flat_map(|service| {
+        core::iter::once((&service.name, &service.uuid))
+            .chain(service.characteristics.iter().map(|characteristic| (&characteristic.name, &characteristic.uuid)))
+    });
+    for (name, uuid) in uuids.filter(|(_, uuid)| uuid.len() == 36) {
+        let base = format!("{}xxxx{}", &uuid[..4], &uuid[8..]).to_lowercase();
+        bases.entry(base).or_insert(name);
+    }
+
+    if bases.len() as u64 > limit {
+        let list: Vec<String> = bases.iter().map(|(base, name)| format!("{} ({})", base, name)).collect();
+        return Err(format!(
+            "{} vendor UUID bases, the softdevice is configured for {}; vary only bytes 12-13 \
+             (the 2nd 4 hex digits) of an existing base: {}",
+            bases.len(),
+            limit,
+            list.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// `pub(crate) const NAME: usize = <expr>;`, in the order they are defined
+pub(crate) fn parse_constants(source: &str) -> BTreeMap<String, u64> {
+    let mut constants = BTreeMap::new();
+    for line in source.lines() {
+        let Some(rest) = line.trim().strip_prefix("pub(crate) const ") else {
+            continue;
+        };
+        let Some((name, rest)) = rest.split_once(':') else {
+            continue;
+        };
+        let Some((_, expression)) = rest.split_once('=') else {
+            continue;
+        };
+        let expression = expression.trim().trim_end_matches(';');
+        if let Some(value) = evaluate(expression, &constants) {
+            constants.insert(name.trim().to_string(), value);
+        }
+    }
+    constants
+}
+
+/// Sums and products of integer literals and known constants, enough for buffer lengths and flash offsets
+fn evaluate(expression: &str, constants: &BTreeMap<String, u64>) -> Option<u64> {
+    let mut total: i64 = 0;
+    let mut sign = 1;
+    for term in expression.split_inclusive(['+', '-']) {
+        let (term, next_sign) = match term.chars().last() {
+            Some('+') => (&term[..term.len() - 1], 1),
+            Some('-') => (&term[..term.len() - 1], -1),
+            _ => (term, 1),
+        };
+        let mut product: i64 = 1;
+        for factor in term.split('*') {
+            let factor = factor.split(" as ").next().unwrap_or_default().trim();
+            let literal = match factor.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => factor.parse::<u64>(),
+            };
+            let value = match literal {
+                Ok(value) => value,
+                Err(_) => *constants.get(factor)?,
+            };
+            product = product.checked_mul(value as i64)?;
+        }
+        total += sign * product;
+        sign = next_sign;
+    }
+    u64::try_from(total).ok()
+}
+
+fn take_doc(doc: &mut Vec<String>) -> String {
+    let text = doc.join("\n");
+    doc.clear();
+    text
+}
+
+/// `uuid = "180A", read, notify, security = "LescMitm"`
+fn parse_attribute_arguments(arguments: &str) -> (Option<String>, Vec<String>, Option<String>) {
+    let (mut uuid, mut properties, mut security) = (None, Vec::new(), None);
+    for argument in arguments.split(',').map(str::trim).filter(|argument| !argument.is_empty()) {
+        match argument.split_once('=') {
+            Some((key, value)) => {
+                let value = value.trim().trim_matches('"').to_string();
+                match key.trim() {
+                    "uuid" => uuid = Some(value),
+                    "security" => security = Some(value),
+                    _ => {}
+                }
+            }
+            None => properties.push(argument.to_string()),
+        }
+    }
+    (uuid, properties, security)
+}
+
+/// `name = "value"` out of an attribute, for values that may contain commas
+fn quoted_argument<'a>(arguments: &'a str, name: &str) -> Option<&'a str> {
+    let start = arguments.find(&format!("{} = \"", name))? + name.len() + 4;
+    let len = arguments[start..].find('"')?;
+    Some(&arguments[start..start + len])
+}
+
+/// `value = TEXT.presentation_format(..)` of a 0x2904 descriptor
+fn presentation_format_encoding(arguments: &str) -> Result<Encoding, String> {
+    let name = arguments
+        .split_once("value =")
+        .and_then(|(_, value)| value.trim().split_once(".presentation_format("))
+        .map(|(name, _)| name.trim())
+        .ok_or_else(|| format!("The Presentation Format is not built from an encoding: {}", arguments))?;
+    ENCODINGS
+        .iter()
+        .find(|(candidate, _)| *candidate == name)
+        .map(|(_, encoding)| *encoding)
+        .ok_or_else(|| format!("{} is not one of the encodings", name))
+}
+
+fn attribute_arguments<'a>(statement: &'a str, prefix: &str) -> Option<&'a str> {
+    statement.strip_prefix(prefix)?.strip_suffix(")]")
+}
+
+/// Trimmed source lines, an attribute spanning several lines is joined into one
+fn statements(source: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut attribute = String::new();
+    let mut depth = 0;
+    for line in source.lines().map(str::trim) {
+        if attribute.is_empty() && !line.starts_with("#[") {
+            statements.push(line.to_string());
+            continue;
+        }
+        if !attribute.is_empty() {
+            attribute.push(' ');
+        }
+        attribute.push_str(line);
+        depth += bracket_balance(line);
+        if depth <= 0 {
+            statements.push(std::mem::take(&mut attribute));
+            depth = 0;
+        }
+    }
+    statements.extend((!attribute.is_empty()).then_some(attribute));
+    statements
+}
+
+/// Opened minus closed brackets and parentheses outside of string literals
+fn bracket_balance(line: &str) -> i32 {
+    let mut balance = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in line.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' | '(' if !in_string => balance += 1,
+            ']' | ')' if !in_string => balance -= 1,
+            _ => {}
+        }
+    }
+    balance
+}
+
+fn parse_gatt_services(source: &str) -> Result<Vec<Service>, String> {
+    let mut services = Vec::new();
+    let mut doc = Vec::new();
+    let mut service: Option<Service> = None;
+    let mut characteristic: Option<Characteristic> = None;
+
+    for statement in statements(source) {
+        let statement = statement.as_str();
+        if let Some(text) = statement.strip_prefix("///") {
+            doc.push(text.trim().to_string());
+        } else if let Some(arguments) = attribute_arguments(statement, "#[nrf_softdevice::gatt_service(") {
+            let (uuid, _, _) = parse_attribute_arguments(arguments);
+            service = Some(Service {
+                name: String::new(),
+                uuid: uuid.unwrap_or_default(),
+                doc: take_doc(&mut doc),
+                characteristics: Vec::new(),
+            });
+        } else if let Some(arguments) = attribute_arguments(statement, "#[characteristic(") {
+            let (uuid, properties, security) = parse_attribute_arguments(arguments);
+            characteristic = Some(Characteristic {
+                name: String::new(),
+                uuid: uuid.unwrap_or_default(),
+                properties,
+                security,
+                rust_type: None,
+                descriptors: Vec::new(),
+                description: None,
+                description_writable: false,
+                doc: take_doc(&mut doc),
+                encoding: None,
+            });
+        } else if let Some(arguments) = attribute_arguments(statement, "#[descriptor(") {
+            if let (Some(current), Some(uuid)) = (characteristic.as_mut(), quoted_argument(arguments, "uuid")) {
+                match u16::from_str_radix(uuid, 16) {
+                    Ok(USER_DESCRIPTION_UUID) => {
+                        current.description = quoted_argument(arguments, "value").map(str::to_string)
+                    }
+                    Ok(PRESENTATION_FORMAT_UUID) => current.encoding = Some(presentation_format_encoding(arguments)?),
+                    _ => {}
+                }
+                current.descriptors.push(uuid.to_string());
+            }
+        } else if let Some(name) = statement.strip_prefix("pub(crate) struct ").and_then(|rest| rest.strip_suffix(" {"))
+        {
+            if let Some(service) = service.as_mut() {
+                service.name = name.to_string();
+            }
+            doc.clear();
+        } else if statement == "}" {
+            services.extend(service.take());
+            doc.clear();
+        } else if let Some(field) = statement.strip_prefix("pub(crate) ") {
+            if let (Some(service), Some(mut current), Some((name, rust_type))) =
+                (service.as_mut(), characteristic.take(), field.split_once(':'))
+            {
+                current.name = name.trim().to_string();
+                current.rust_type = Some(rust_type.trim().trim_end_matches(',').to_string());
+                service.characteristics.push(current);
+            }
+            doc.clear();
+        } else if !statement.is_empty() && !statement.starts_with("//") {
+            doc.clear();
+        }
+    }
+
+    Ok(services)
+}
+
+fn uuid_string(uuid: GattUuid) -> String {
+    match uuid {
+        GattUuid::Sig(uuid) => format!("{:04X}", uuid),
+        GattUuid::Vendor(uuid) => uuid.to_string(),
+    }
+}
+
+fn characteristic_from_spec(service: &ServiceSpec, spec: &CharacteristicSpec) -> Characteristic {
+    let Properties { read, write, notify } = spec.properties;
+    let properties = [("read", read), ("write", write), ("notify", notify)];
+    let descriptors = service
+        .descriptors
+        .iter()
+        .copied()
+        .chain(spec.presentation_format.map(|_| PRESENTATION_FORMAT_UUID))
+        .chain(spec.user_description.map(|_| USER_DESCRIPTION_UUID));
+    Characteristic {
+        name: spec.name.to_string(),
+        uuid: uuid_string(spec.uuid),
+        properties: properties.iter().filter(|(_, set)| *set).map(|(name, _)| name.to_string()).collect(),
+        security: match spec.write_security {
+            WriteSecurity::NoAccess => None,
+            WriteSecurity::LescMitm => Some("LescMitm".to_string()),
+        },
+        rust_type: None,
+        descriptors: descriptors.map(|uuid| format!("{:04X}", uuid)).collect(),
+        description: spec.user_description.map(str::to_string),
+        description_writable: spec.user_description_writable,
+        doc: String::new(),
+        encoding: Some(spec.encoding),
+    }
+}
+
+fn service_from_spec(spec: &ServiceSpec) -> Service {
+    Service {
+        name: spec.name.to_string(),
+        uuid: uuid_string(spec.uuid),
+        doc: spec.doc.to_string(),
+        characteristics: spec
+            .characteristics
+            .iter()
+            .map(|characteristic| characteristic_from_spec(spec, characteristic))
+            .collect(),
+    }
+}
+
+fn rust_type_format(rust_type: &str, constants: &BTreeMap<String, u64>) -> (Format, Option<Length>) {
+    let scalar = |format, len| (format, Some(Length::Fixed(len)));
+    match rust_type {
+        "u8" => scalar(Format::Uint8, 1),
+        "i8" => scalar(Format::Sint8, 1),
+        "u16" => scalar(Format::Uint16, 2),
+        "i16" => scalar(Format::Sint16, 2),
+        "u32" => scalar(Format::Uint32, 4),
+        "i32" => scalar(Format::Sint32, 4),
+        "u64" => scalar(Format::Uint64, 8),
+        "f32" => scalar(Format::Float32, 4),
+        _ => {
+            let length = if let Some(expression) = rust_type.strip_prefix("[u8;").and_then(|rest| rest.strip_suffix(']')) {
+                evaluate(expression.trim(), constants)
+                    .map(Length::Fixed)
+                    .unwrap_or_else(|| Length::Unresolved(expression.trim().to_string()))
+            } else if let Some(expression) = rust_type.strip_prefix("Vec<u8,").and_then(|rest| rest.strip_suffix('>')) {
+                evaluate(expression.trim(), constants)
+                    .map(Length::Max)
+                    .unwrap_or_else(|| Length::Unresolved(expression.trim().to_string()))
+            } else {
+                Length::Unresolved(rust_type.to_string())
+            };
+            (Format::Bytes, Some(length))
+        }
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_list(values: &[String]) -> String {
+    format!("[{}]", values.iter().map(|value| json_string(value)).collect::<Vec<_>>().join(", "))
+}
+
+fn to_json(services: &[Service], constants: &BTreeMap<String, u64>) -> String {
+    let mut json = String::new();
+    let _ = writeln!(json, "{{");
+    let _ = writeln!(json, "  \"version\": {},", json_string(&std::env::var("CARGO_PKG_VERSION").unwrap_or_default()));
+    let _ = writeln!(json, "  \"value_formula\": \"R = C * M * 10^d * 2^b\",");
+    let _ = writeln!(json, "  \"services\": [");
+
+    for (service_index, service) in services.iter().enumerate() {
+        let _ = writeln!(json, "    {{");
+        let _ = writeln!(json, "      \"name\": {},", json_string(&service.name));
+        let _ = writeln!(json, "      \"uuid\": {},", json_string(&service.uuid));
+        let _ = writeln!(json, "      \"doc\": {},", json_string(&service.doc));
+        let _ = writeln!(json, "      \"characteristics\": [");
+
+        for (index, characteristic) in service.characteristics.iter().enumerate() {
+            let encoding = characteristic.encoding;
+            let (rust_format, rust_length) = match characteristic.rust_type.as_deref() {
+                Some(rust_type) => rust_type_format(rust_type, constants),
+                None => (Format::Bytes, None),
+            };
+            let format = encoding.map_or(rust_format, |encoding| encoding.format);
+            let length = match (format.len(), rust_length) {
+                (Some(length), _) => Some(Length::Fixed(length as u64)),
+                (None, Some(Length::Fixed(length))) if format == Format::Utf8 => Some(Length::Max(length)),
+                (None, length) => length,
+            };
+
+            let _ = writeln!(json, "        {{");
+            let _ = writeln!(json, "          \"name\": {},", json_string(&characteristic.name));
+            let _ = writeln!(json, "          \"uuid\": {},", json_string(&characteristic.uuid));
+            let _ = writeln!(json, "          \"properties\": {},", json_list(&characteristic.properties));
+            match &characteristic.security {
+                Some(security) => {
+                    let _ = writeln!(json, "          \"security\": {},", json_string(security));
+                }
+                None => {
+                    let _ = writeln!(json, "          \"security\": null,");
+                }
+            }
+            let _ = writeln!(json, "          \"format\": {},", json_string(format.name()));
+            match length {
+                Some(Length::Fixed(length)) => {
+                    let _ = writeln!(json, "          \"length\": {},", length);
+                }
+                Some(Length::Max(length)) => {
+                    let _ = writeln!(json, "          \"max_length\": {},", length);
+                }
+                Some(Length::Unresolved(expression)) => {
+                    let _ = writeln!(json, "          \"length\": {},", json_string(&expression));
+                }
+                None => {}
+            }
+            if let Some(encoding) = encoding.filter(|encoding| encoding.format.len().is_some()) {
+                let _ = writeln!(
+                    json,
+                    "          \"multiplier\": {}, \"decimal_exponent\": {}, \"binary_exponent\": {}, \"unit\": {},",
+                    encoding.multiplier,
+                    encoding.decimal_exponent,
+                    encoding.binary_exponent,
+                    json_string(encoding.unit),
+                );
+            }
+            if !characteristic.descriptors.is_empty() {
+                let _ = writeln!(json, "          \"descriptors\": {},", json_list(&characteristic.descriptors));
+            }
+            if let Some(description) = &characteristic.description {
+                let _ = writeln!(json, "          \"description\": {},", json_string(description));
+                let _ = writeln!(json, "          \"description_writable\": {},", characteristic.description_writable);
+            }
+            let _ = writeln!(json, "          \"doc\": {}", json_string(&characteristic.doc));
+            let separator = if index + 1 < service.characteristics.len() { "," } else { "" };
+            let _ = writeln!(json, "        }}{}", separator);
+        }
+
+        let _ = writeln!(json, "      ]");
+        let separator = if service_index + 1 < services.len() { "," } else { "" };
+        let _ = writeln!(json, "    }}{}", separator);
+    }
+
+    let _ = writeln!(json, "  ]");
+    let _ = writeln!(json, "}}");
+    json
+}
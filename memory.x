@@ -2,6 +2,12 @@ MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
   /* These values correspond to the NRF52840 with Softdevices S140 7.0.1 */
-  FLASH : ORIGIN = 0x00027000, LENGTH = 868K
+  /* The application is the ACTIVE partition of the bootloader, followed by the DFU partition, the bootloader state,
+     the settings pages and the offline history region, see config.rs and bootloader/memory.x */
+  FLASH : ORIGIN = 0x00027000, LENGTH = 308K
   RAM : ORIGIN = 0x20020000, LENGTH = 128K
 }
+
+/* The region overflow error alone does not say what is in the way; build.rs checks that FLASH is the ACTIVE partition */
+ASSERT(__sidata + (__edata - __sdata) <= ORIGIN(FLASH) + LENGTH(FLASH),
+       "The firmware does not fit below the DFU partition at 0x74000, see memory.x and config.rs")
@@ -13,40 +13,75 @@ use defmt_rtt as _;
 use embassy_executor::Spawner;
 #[allow(unused)]
 use embassy_nrf as _;
+use embassy_time::{Instant, Timer};
 use embedded_alloc::Heap;
+use futures::{FutureExt, select_biased};
 use nrf_softdevice::ble::{Connection, gatt_server, peripheral, TxPower};
 use nrf_softdevice::Flash;
 use nrf_softdevice::Softdevice;
-use nrf_softdevice_s140::ble_gap_conn_params_t;
 #[allow(unused)]
 use panic_probe as _;
 use rclite::Arc;
 
 use common::util::ble_debugger::ble_debug_notify_task;
+use common::util::history_log::restore_history_from_flash;
+use common::util::log_buffer::{LOG, persist_log_task, restore_log_from_flash};
 
 use crate::common::ble::{
     ACCELEROMETER_EVENT_PROCESSOR,
     ACCELEROMETER_SERVICE_EVENTS,
     ADC_EVENT_PROCESSOR,
     ADC_SERVICE_EVENTS,
+    BATTERY_EVENT_PROCESSOR,
+    BATTERY_SERVICE_EVENTS,
     BME_EVENT_PROCESSOR,
+    BONDER,
     BME_SERVICE_EVENTS,
+    BROADCAST_SERVICE_EVENTS,
     COLOR_EVENT_PROCESSOR,
     COLOR_SERVICE_EVENTS,
     DEVICE_EVENT_PROCESSOR,
-    DI_SERVICE_EVENTS,
+    DFU_SERVICE_EVENTS,
+    DIAGNOSTICS_SERVICE_EVENTS,
+    ESS_EVENT_PROCESSOR,
+    ESS_SERVICE_EVENTS,
     FLASH_MANAGER,
+    HISTORY_SERVICE_EVENTS,
     SERVER,
-    SPI_EXPANDER_EVENTS
+    SHELL_EVENTS,
+    SNAPSHOT_EVENT_PROCESSOR,
+    SNAPSHOT_SERVICE_EVENTS,
+    SPI_EXPANDER_EVENTS,
+    update_external_sensor_demand,
 };
+use crate::common::ble::adc::restore_adc_labels;
+use crate::common::ble::boot_health::{BOOT_CONNECTED, check_boot_health, confirm_boot_task, take_reset_reason};
+use crate::common::ble::bthome::{prepare_broadcast_adv_data, read_broadcast_events_channel, restore_broadcast_settings};
+use crate::common::ble::advertising::{
+    ADVERTISING_RESTART, advertising_phys, extended_adv_data, is_accept_list_only, is_coded_phy,
+    prepare_filter_policy, request_connection_phy,
+};
+use crate::common::ble::current_time::{current_time_task, handle_current_time_write};
+#[cfg(feature = "ble-gatt-client")]
+use crate::common::ble::current_time::sync_time_from_central;
+use crate::common::ble::conn_params::{CONN_PARAMS, manage_connection_params_task};
+use crate::common::ble::bme_config::restore_bme_config;
+use crate::common::ble::device_info::populate_device_information;
+use crate::common::ble::dfu::dfu_task;
+use crate::common::ble::history::{history_task, history_transfer_task, restore_history_settings};
+use crate::common::ble::identity::{restore_device_identity, sync_gap_device_name};
+use crate::common::ble::weather::{pressure_trend_task, restore_reference_altitude};
 use crate::common::ble::event_processor::{
-    read_accelerometer_notification_settings_channel, read_adc_notification_settings_channel,
-    read_bme_notification_settings_channel, read_color_notification_settings_channel,
-    read_di_notification_settings_channel,
+    copy_calibration_data_from_flash, read_accelerometer_notification_settings_channel, read_adc_notification_settings_channel,
+    read_battery_notification_settings_channel, read_bme_notification_settings_channel, read_color_notification_settings_channel,
+    read_diagnostics_notification_settings_channel, read_ess_notification_settings_channel,
 };
-use crate::common::ble::services::{BleServer, BleServerEvent};
-use crate::common::ble::softdevice::{prepare_adv_scan_data, prepare_softdevice_config};
-use crate::common::device::persistence::flash_manager::{copy_calibration_data_from_flash, FlashManager};
+use crate::common::ble::security::{persist_bonds_task, restore_bonds_from_flash};
+use crate::common::ble::services::{BleServer, BleServerEvent, CurrentTimeServiceEvent, DiagnosticsServiceEvent};
+use crate::common::ble::shell::shell_task;
+use crate::common::ble::snapshot::{notify_snapshot_task, read_snapshot_notification_settings_channel};
+use crate::common::ble::softdevice::{prepare_adv_scan_data, prepare_broadcast_scan_data, prepare_softdevice_config};
+use crate::common::device::persistence::flash_manager::FlashManager;
 use crate::common::device::expander::{handle_expander_disconnect, TIMEOUT_TRACKER};
 use crate::common::device::peripherals_manager::PeripheralsManager;
 use crate::common::device::task::adc::{read_saadc_battery_voltage_task, read_saadc_task};
@@ -55,6 +90,7 @@ use crate::common::device::task::expander::{expander_mutex_timeout_task, expande
 use crate::common::device::task::i2c::read_i2c0_task;
 use crate::common::device::task::nrf_temp::notify_nrf_temp;
 use crate::common::device::task::spi::epd_task;
+use crate::common::device::task::watchdog::{feed_watchdog_task, start_watchdog};
 use crate::common::device::ui::UI_STORE;
 
 #[path = "../common.rs"]
@@ -78,11 +114,19 @@ async fn main(spawner: Spawner) {
     }
 
     let peripherals_manager = PeripheralsManager::new().await.unwrap();
+    // the in-tree bootloader has started the watchdog already and feeds it during a swap, the stock one would not
+    if cfg!(feature = "dfu-bootloader") {
+        unwrap!(spawner.spawn(feed_watchdog_task(start_watchdog(peripherals_manager.wdt))));
+    }
     let sd_config = prepare_softdevice_config();
     let sd = Softdevice::enable(&sd_config);
+    BONDER.generate_lesc_key_pair(sd).await;
     let server = unwrap!(BleServer::new(sd));
 
     SERVER.init_ro(server);
+    if let Err(err) = populate_device_information(SERVER.get()) {
+        info!("Failed to populate device information {:?}", err);
+    }
     unwrap!(spawner.spawn(softdevice_task(sd)));
     FLASH_MANAGER.init_ro(FlashManager::new(Flash::take(sd)));
     if let Err(err) = FLASH_MANAGER.get().init().await {
@@ -91,8 +135,62 @@ async fn main(spawner: Spawner) {
         info!("Failed to copy calibration data from flash");
     }
 
+    if let Err(err) = restore_bonds_from_flash().await {
+        info!("Failed to restore bonds {:?}", err);
+    }
+    if let Err(err) = SERVER.get().diagnostics.accept_list_only_set(&(is_accept_list_only() as u8)) {
+        info!("Failed to set accept list only flag {:?}", err);
+    }
+    if let Err(err) = SERVER.get().diagnostics.coded_phy_set(&(is_coded_phy() as u8)) {
+        info!("Failed to set coded phy flag {:?}", err);
+    }
+    unwrap!(spawner.spawn(persist_bonds_task()));
+
+    let log_index = match restore_log_from_flash().await {
+        Ok(index) => index,
+        Err(err) => {
+            info!("Failed to restore log {:?}", err);
+            0
+        }
+    };
+    unwrap!(spawner.spawn(persist_log_task(log_index)));
+
+    let reset_reason = take_reset_reason();
+    match check_boot_health(reset_reason).await {
+        Ok(Some(record)) => unwrap!(spawner.spawn(confirm_boot_task(record, reset_reason))),
+        Ok(None) => {}
+        Err(err) => info!("Failed to check boot health {:?}", err),
+    }
+
+    if let Err(err) = restore_device_identity().await {
+        info!("Failed to restore device identity {:?}", err);
+    }
+
+    if let Err(err) = restore_adc_labels().await {
+        info!("Failed to restore ADC channel labels {:?}", err);
+    }
+
+    if let Err(err) = restore_history_from_flash().await {
+        info!("Failed to restore history {:?}", err);
+    }
+    if let Err(err) = restore_history_settings().await {
+        info!("Failed to restore history settings {:?}", err);
+    }
+    if let Err(err) = restore_bme_config().await {
+        info!("Failed to restore BME settings {:?}", err);
+    }
+    if let Err(err) = restore_reference_altitude().await {
+        info!("Failed to restore the reference altitude {:?}", err);
+    }
+
+    if let Err(err) = restore_broadcast_settings().await {
+        info!("Failed to restore broadcast settings {:?}", err);
+    }
+    update_external_sensor_demand().await;
+
     unwrap!(spawner.spawn(expander_task(Arc::clone(&peripherals_manager.expander_pins))));
     unwrap!(spawner.spawn(expander_mutex_timeout_task(Arc::clone(&peripherals_manager.expander_pins))));
+    unwrap!(spawner.spawn(shell_task(Arc::clone(&peripherals_manager.expander_pins))));
 
     unwrap!(spawner.spawn(epd_task(
         Arc::clone(&peripherals_manager.spi2_pins),
@@ -111,38 +209,53 @@ async fn main(spawner: Spawner) {
 
     unwrap!(spawner.spawn(read_adc_notification_settings_channel()));
     unwrap!(spawner.spawn(read_bme_notification_settings_channel()));
-    unwrap!(spawner.spawn(read_di_notification_settings_channel()));
+    unwrap!(spawner.spawn(read_diagnostics_notification_settings_channel()));
     unwrap!(spawner.spawn(read_accelerometer_notification_settings_channel()));
     unwrap!(spawner.spawn(read_color_notification_settings_channel()));
-
-    let (adv_data, scan_data) = prepare_adv_scan_data();
+    unwrap!(spawner.spawn(read_ess_notification_settings_channel()));
+    unwrap!(spawner.spawn(read_battery_notification_settings_channel()));
+    unwrap!(spawner.spawn(read_snapshot_notification_settings_channel()));
+    unwrap!(spawner.spawn(notify_snapshot_task()));
+    unwrap!(spawner.spawn(read_broadcast_events_channel()));
+    unwrap!(spawner.spawn(manage_connection_params_task()));
+    unwrap!(spawner.spawn(dfu_task()));
+    unwrap!(spawner.spawn(current_time_task()));
+    unwrap!(spawner.spawn(history_task()));
+    unwrap!(spawner.spawn(history_transfer_task()));
+    unwrap!(spawner.spawn(pressure_trend_task()));
 
     info!("Init has finished successfully");
 
     loop {
+        ADVERTISING_RESTART.reset();
+        let (filter_policy, policy_deadline) = prepare_filter_policy(sd);
+        let (primary_phy, secondary_phy) = advertising_phys();
         let config = peripheral::Config {
-            // primary_phy: Phy::M2,
-            // secondary_phy: Phy::M2,
+            primary_phy,
+            secondary_phy,
             tx_power: TxPower::Plus8dBm,
+            filter_policy,
             ..Default::default()
         };
-        let adv = peripheral::ConnectableAdvertisement::ScannableUndirected { adv_data, scan_data };
+        // rebuilt on every restart, so it always carries the latest readings and the current name
+        let (adv_data, scan_data) = match prepare_broadcast_adv_data(sd) {
+            Some(broadcast_data) => (broadcast_data, prepare_broadcast_scan_data()),
+            None => prepare_adv_scan_data(),
+        };
+        let extended_data = extended_adv_data(&adv_data, &scan_data);
+        let adv = if is_coded_phy() {
+            peripheral::ConnectableAdvertisement::ExtendedNonscannableUndirected { adv_data: &extended_data }
+        } else {
+            peripheral::ConnectableAdvertisement::ScannableUndirected { adv_data: &adv_data, scan_data: &scan_data }
+        };
         info!("Waiting for connection");
-        let connection = unwrap!(peripheral::advertise_connectable(sd, adv, &config).await);
-
-        //  If both conn_sup_timeout and max_conn_interval are specified, then the following constraint applies:"]
-        //  conn_sup_timeout * 4 > (1 + slave_latency) * max_conn_interval"]
-        //  that corresponds to the following Bluetooth Spec requirement:"]
-        //  The Supervision_Timeout in milliseconds shall be larger than"]
-        //  (1 + Conn_Latency) * Conn_Interval_Max * 2, where Conn_Interval_Max is given in milliseconds."]
-        if let Err(err) = connection.set_conn_params(ble_gap_conn_params_t {
-            min_conn_interval: 0,
-            max_conn_interval: 1,
-            slave_latency: 0,
-            conn_sup_timeout: 200, // 4s
-        }) {
-            info!("Failed to set connection params {:?}", err);
-        }
+        let connection = select_biased! {
+            connection = peripheral::advertise_pairable(sd, adv, &config, &BONDER).fuse() => unwrap!(connection),
+            _ = ADVERTISING_RESTART.wait().fuse() => continue,
+            // the pairing window has been closed
+            _ = Timer::at(policy_deadline.unwrap_or(Instant::MAX)).fuse() => continue,
+        };
+
         unwrap!(spawner.spawn(handle_connection(connection.clone())));
 
         handle_expander_disconnect(&connection, &peripherals_manager.expander_pins).await;
@@ -153,53 +266,129 @@ async fn main(spawner: Spawner) {
 
 #[embassy_executor::task(pool_size = 3)]
 async fn handle_connection(connection: Connection) {
-    ble_debug!("Peer: {:?}", connection.peer_address());
+    ble_info!("Peer: {:?}", connection.peer_address());
+
+    // starts on the fast parameters for service discovery
+    CONN_PARAMS.register_connection(&connection);
 
     DEVICE_EVENT_PROCESSOR.register_connection(&connection).await;
     BME_EVENT_PROCESSOR.register_connection(&connection).await;
     ADC_EVENT_PROCESSOR.register_connection(&connection).await;
     ACCELEROMETER_EVENT_PROCESSOR.register_connection(&connection).await;
     COLOR_EVENT_PROCESSOR.register_connection(&connection).await;
+    ESS_EVENT_PROCESSOR.register_connection(&connection).await;
+    BATTERY_EVENT_PROCESSOR.register_connection(&connection).await;
+    SNAPSHOT_EVENT_PROCESSOR.register_connection(&connection).await;
+    BOOT_CONNECTED.signal(());
 
-    let server_fut = gatt_server::run(&connection, SERVER.get(), |e| match e {
-        BleServerEvent::Dis(event) => {
-            if DI_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
-                ble_debug!("Failed to send DI service event")
+    let server_fut = gatt_server::run(&connection, SERVER.get(), |e| {
+        CONN_PARAMS.touch(&connection);
+        match e {
+            // Device Information Service is read only
+            BleServerEvent::Dis(_) => {}
+            BleServerEvent::Diagnostics(event) => {
+                match event {
+                    DiagnosticsServiceEvent::ConnectionPhyWrite(phys) => request_connection_phy(&connection, phys),
+                    DiagnosticsServiceEvent::ConnectionProfileWrite(profile) => {
+                        CONN_PARAMS.request_profile(&connection, profile)
+                    }
+                    DiagnosticsServiceEvent::LogWrite(ref value) => {
+                        // the value is replaced with the page before the client reads it back
+                        let sequence = value.as_slice().try_into().map(u32::from_le_bytes).unwrap_or(0);
+                        if let Err(err) = SERVER.get().diagnostics.log_set(&LOG.page(sequence)) {
+                            info!("Failed to set log page {:?}", err);
+                        }
+                    }
+                    _ => {}
+                }
+                if DIAGNOSTICS_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send Diagnostics service event")
+                }
             }
-        }
-        BleServerEvent::Adc(event) => {
-            if ADC_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
-                ble_debug!("Failed to send ADC service event")
+            BleServerEvent::Adc(event) => {
+                if ADC_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send ADC service event")
+                }
             }
-        }
-        BleServerEvent::Bme280(event) => {
-            if BME_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
-                ble_debug!("Failed to send BME service event")
+            BleServerEvent::Bme280(event) => {
+                if BME_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send BME service event")
+                }
             }
-        }
-        BleServerEvent::Accelerometer(event) => {
-            if ACCELEROMETER_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
-                ble_debug!("Failed to send Accelerometer service event")
+            BleServerEvent::Accelerometer(event) => {
+                if ACCELEROMETER_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send Accelerometer service event")
+                }
             }
-        }
-        BleServerEvent::Color(event) => {
-            if COLOR_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
-                ble_debug!("Failed to send Color service event")
+            BleServerEvent::Color(event) => {
+                if COLOR_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send Color service event")
+                }
             }
-        }
-        BleServerEvent::Expander(event) => {
-            if SPI_EXPANDER_EVENTS.try_send((connection.clone(), event)).is_err() {
-                ble_debug!("Failed to send SpiExpander service event")
+            BleServerEvent::Ess(event) => {
+                if ESS_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send ESS event")
+                }
+            }
+            BleServerEvent::Bas(event) => {
+                if BATTERY_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send Battery service event")
+                }
+            }
+            BleServerEvent::Snapshot(event) => {
+                if SNAPSHOT_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send Snapshot service event")
+                }
+            }
+            BleServerEvent::Broadcast(event) => {
+                if BROADCAST_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send Broadcast service event")
+                }
+            }
+            BleServerEvent::Cts(CurrentTimeServiceEvent::CurrentTimeWrite(value)) => handle_current_time_write(&value),
+            BleServerEvent::Cts(CurrentTimeServiceEvent::CurrentTimeCccdWrite { .. }) => {}
+            BleServerEvent::History(event) => {
+                if HISTORY_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send History service event")
+                }
+            }
+            BleServerEvent::Nus(event) => {
+                if SHELL_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send NUS event")
+                }
+            }
+            BleServerEvent::Dfu(event) => {
+                // a dropped chunk is reported as an unexpected offset of the next one
+                if DFU_SERVICE_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send DFU event")
+                }
+            }
+            BleServerEvent::Expander(event) => {
+                if SPI_EXPANDER_EVENTS.try_send((connection.clone(), event)).is_err() {
+                    ble_warn!("Failed to send SpiExpander service event")
+                }
             }
         }
     });
 
+    // the central's clock is read while the server is already running, it has to answer our requests
+    #[cfg(feature = "ble-gatt-client")]
+    let (_error, _) = futures::future::join(server_fut, sync_time_from_central(&connection)).await;
+    #[cfg(not(feature = "ble-gatt-client"))]
     let _error = server_fut.await;
+    CONN_PARAMS.drop_connection(&connection);
     DEVICE_EVENT_PROCESSOR.drop_connection(&connection).await;
     BME_EVENT_PROCESSOR.drop_connection(&connection).await;
     ADC_EVENT_PROCESSOR.drop_connection(&connection).await;
     ACCELEROMETER_EVENT_PROCESSOR.drop_connection(&connection).await;
     COLOR_EVENT_PROCESSOR.drop_connection(&connection).await;
+    ESS_EVENT_PROCESSOR.drop_connection(&connection).await;
+    BATTERY_EVENT_PROCESSOR.drop_connection(&connection).await;
+    SNAPSHOT_EVENT_PROCESSOR.drop_connection(&connection).await;
+    update_external_sensor_demand().await;
+    sync_gap_device_name().await;
+    // pairing could have been aborted while the passkey is still on the screen
+    BONDER.clear_passkey();
 
     info!("Connection closed");
 }
@@ -0,0 +1,265 @@
+//! Register models of the sensors on the bit-banged bus, just enough of them for the drivers
+
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation, SevenBitAddress};
+
+use crate::common::device::bme280::{
+    BME280_CHIP_ID, BME280_CHIP_ID_ADDR, BME280_CONFIG_ADDR, BME280_CTRL_HUM_ADDR, BME280_CTRL_MEAS_ADDR,
+    BME280_DATA_ADDR, BME280_FORCED_MODE, BME280_H_CALIB_DATA_ADDR, BME280_I2C_ADDR_PRIMARY,
+    BME280_P_T_CALIB_DATA_ADDR, BME280_RESET_ADDR, BME280_SENSOR_MODE_MSK, BME280_SOFT_RESET_CMD,
+};
+use crate::common::device::error::CustomI2CError;
+use crate::common::device::lis2dh12::reg::{DEVICE_ID, I2C_SAD, I2C_SUB_MULTI};
+
+const VEML6040_ADDR: u8 = 0x10;
+const LIS2DH12_WHO_AM_I: u8 = 0x0F;
+const LIS2DH12_OUT_X_L: u8 = 0x28;
+
+/// Calibration words of the BME280 datasheet example, T1..T3 and P1..P9
+const BME280_PT_CALIBRATION: [i32; 12] = [27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000];
+const BME280_H1: u8 = 75;
+const BME280_H2: i16 = 370;
+const BME280_H3: u8 = 0;
+const BME280_H4: i16 = 313;
+const BME280_H5: i16 = 50;
+const BME280_H6: i8 = 30;
+
+/// Conditions drift a little on every sample, so consecutive notifications differ
+const DRIFT_PERIOD: u32 = 20;
+
+trait RegisterModel {
+    fn write(&mut self, bytes: &[u8]);
+    fn read(&mut self, buf: &mut [u8]);
+}
+
+struct Bme280Model {
+    registers: [u8; 256],
+    pointer: u8,
+    samples: u32,
+}
+
+impl Bme280Model {
+    fn new() -> Self {
+        let mut registers = [0u8; 256];
+        registers[BME280_CHIP_ID_ADDR as usize] = BME280_CHIP_ID;
+
+        let calibration = &mut registers[BME280_P_T_CALIB_DATA_ADDR as usize..];
+        for (index, word) in BME280_PT_CALIBRATION.iter().enumerate() {
+            calibration[index * 2..index * 2 + 2].copy_from_slice(&(*word as u16).to_le_bytes());
+        }
+        calibration[25] = BME280_H1;
+
+        let calibration = &mut registers[BME280_H_CALIB_DATA_ADDR as usize..];
+        calibration[0..2].copy_from_slice(&BME280_H2.to_le_bytes());
+        calibration[2] = BME280_H3;
+        calibration[3] = (BME280_H4 >> 4) as u8;
+        calibration[4] = (BME280_H4 & 0x0F) as u8 | ((BME280_H5 & 0x0F) << 4) as u8;
+        calibration[5] = (BME280_H5 >> 4) as u8;
+        calibration[6] = BME280_H6 as u8;
+
+        Self { registers, pointer: 0, samples: 0 }
+    }
+
+    /// A forced conversion completes right away and the sensor drops back to sleep
+    fn convert(&mut self) {
+        let phase = self.samples % DRIFT_PERIOD;
+        self.samples += 1;
+
+        // around 25 °C, 1006 hPa and 45 %
+        let pressure = 415148 - phase * 40;
+        let temperature = 519888 + phase * 250;
+        let humidity = 28200 + phase * 30;
+
+        let data = &mut self.registers[BME280_DATA_ADDR as usize..];
+        data[0..3].copy_from_slice(&[(pressure >> 12) as u8, (pressure >> 4) as u8, (pressure << 4) as u8]);
+        data[3..6].copy_from_slice(&[(temperature >> 12) as u8, (temperature >> 4) as u8, (temperature << 4) as u8]);
+        data[6..8].copy_from_slice(&(humidity as u16).to_be_bytes());
+
+        self.registers[BME280_CTRL_MEAS_ADDR as usize] &= !BME280_SENSOR_MODE_MSK;
+    }
+}
+
+impl RegisterModel for Bme280Model {
+    fn write(&mut self, bytes: &[u8]) {
+        let Some((&register, payload)) = bytes.split_first() else {
+            return;
+        };
+        self.pointer = register;
+        let Some(&value) = payload.first() else {
+            return;
+        };
+
+        match register {
+            BME280_RESET_ADDR if value == BME280_SOFT_RESET_CMD => {
+                for register in [BME280_CTRL_HUM_ADDR, BME280_CTRL_MEAS_ADDR, BME280_CONFIG_ADDR] {
+                    self.registers[register as usize] = 0;
+                }
+            }
+            BME280_CTRL_MEAS_ADDR => {
+                self.registers[register as usize] = value;
+                if value & BME280_SENSOR_MODE_MSK == BME280_FORCED_MODE {
+                    self.convert();
+                }
+            }
+            _ => self.registers[register as usize] = value,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.registers[self.pointer as usize];
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+}
+
+struct Lis2dh12Model {
+    registers: [u8; 128],
+    pointer: u8,
+    auto_increment: bool,
+    samples: u32,
+}
+
+impl Lis2dh12Model {
+    fn new() -> Self {
+        let mut registers = [0u8; 128];
+        registers[LIS2DH12_WHO_AM_I as usize] = DEVICE_ID;
+        Self { registers, pointer: 0, auto_increment: false, samples: 0 }
+    }
+
+    /// Lying flat and slightly rocking, 12-bit left-justified at ±2 g
+    fn sample(&mut self) {
+        let phase = (self.samples % DRIFT_PERIOD) as i16 - DRIFT_PERIOD as i16 / 2;
+        self.samples += 1;
+
+        let axes = [phase * 5, -phase * 3, 1000 - phase.abs()];
+        for (index, axis) in axes.iter().enumerate() {
+            let offset = LIS2DH12_OUT_X_L as usize + index * 2;
+            self.registers[offset..offset + 2].copy_from_slice(&(axis << 4).to_le_bytes());
+        }
+    }
+}
+
+impl RegisterModel for Lis2dh12Model {
+    fn write(&mut self, bytes: &[u8]) {
+        let Some((&sub_address, payload)) = bytes.split_first() else {
+            return;
+        };
+        self.pointer = sub_address & !I2C_SUB_MULTI;
+        self.auto_increment = sub_address & I2C_SUB_MULTI != 0;
+        if let Some(&value) = payload.first() {
+            self.registers[self.pointer as usize % self.registers.len()] = value;
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        if self.pointer == LIS2DH12_OUT_X_L {
+            self.sample();
+        }
+        for byte in buf.iter_mut() {
+            *byte = self.registers[self.pointer as usize % self.registers.len()];
+            if self.auto_increment {
+                self.pointer = self.pointer.wrapping_add(1);
+            }
+        }
+    }
+}
+
+/// 16-bit little-endian registers: CONF at 0x00, R/G/B/W data at 0x08..0x0B
+struct Veml6040Model {
+    registers: [u16; 16],
+    pointer: u8,
+    samples: u32,
+}
+
+impl Veml6040Model {
+    fn new() -> Self {
+        Self { registers: [0; 16], pointer: 0, samples: 0 }
+    }
+
+    /// Indoor light slowly getting brighter
+    fn sample(&mut self) {
+        let phase = (self.samples % DRIFT_PERIOD) as u16;
+        self.samples += 1;
+
+        let white = 2400 + phase * 40;
+        self.registers[0x08..0x0C].copy_from_slice(&[white / 3, white * 2 / 5, white / 4, white]);
+    }
+}
+
+impl RegisterModel for Veml6040Model {
+    fn write(&mut self, bytes: &[u8]) {
+        let Some((&register, payload)) = bytes.split_first() else {
+            return;
+        };
+        self.pointer = register % self.registers.len() as u8;
+        if payload.len() >= 2 {
+            self.registers[self.pointer as usize] = u16::from_le_bytes([payload[0], payload[1]]);
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        if self.pointer == 0x08 {
+            self.sample();
+        }
+        let value = self.registers[self.pointer as usize].to_le_bytes();
+        for (byte, value) in buf.iter_mut().zip(value.iter()) {
+            *byte = *value;
+        }
+    }
+}
+
+/// The sensors of the board, addressed like on the real bus
+pub(crate) struct FakeI2c {
+    bme280: Bme280Model,
+    lis2dh12: Lis2dh12Model,
+    veml6040: Veml6040Model,
+}
+
+impl FakeI2c {
+    pub(crate) fn new() -> Self {
+        Self { bme280: Bme280Model::new(), lis2dh12: Lis2dh12Model::new(), veml6040: Veml6040Model::new() }
+    }
+
+    fn device(&mut self, address: SevenBitAddress) -> Result<&mut dyn RegisterModel, CustomI2CError> {
+        match address {
+            BME280_I2C_ADDR_PRIMARY => Ok(&mut self.bme280),
+            I2C_SAD => Ok(&mut self.lis2dh12),
+            VEML6040_ADDR => Ok(&mut self.veml6040),
+            _ => Err(CustomI2CError::NoAcknowledge(address)),
+        }
+    }
+}
+
+impl ErrorType for FakeI2c {
+    type Error = CustomI2CError;
+}
+
+impl I2c for FakeI2c {
+    async fn read(&mut self, address: SevenBitAddress, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.device(address)?.read(read);
+        Ok(())
+    }
+
+    async fn write(&mut self, address: SevenBitAddress, write: &[u8]) -> Result<(), Self::Error> {
+        self.device(address)?.write(write);
+        Ok(())
+    }
+
+    async fn write_read(&mut self, address: SevenBitAddress, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        let device = self.device(address)?;
+        device.write(write);
+        device.read(read);
+        Ok(())
+    }
+
+    async fn transaction(&mut self, address: SevenBitAddress, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let device = self.device(address)?;
+        for operation in operations.iter_mut() {
+            match operation {
+                Operation::Read(buf) => device.read(buf),
+                Operation::Write(bytes) => device.write(bytes),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Mirrors `src/common.rs` for the host: the portable firmware modules are compiled from their
+//! original files, the nRF specific ones are replaced with the stand-ins below.
+
+pub(crate) mod bitbang {
+    #[path = "../../../../bitbang/i2c.rs"]
+    pub(crate) mod i2c;
+}
+
+pub(crate) mod ble {
+    use crate::common::device::dfu::ram_flash::RamFlash;
+    use crate::common::device::persistence::flash_manager::FlashManager;
+    use crate::common::util::custom_static_cell::CustomStaticCell;
+
+    #[path = "../../../../ble/bthome_payload.rs"]
+    pub(crate) mod bthome_payload;
+    #[path = "../../../../ble/helper_macro.rs"]
+    pub(crate) mod helper_macro;
+    #[path = "../../../../ble/lesc.rs"]
+    pub(crate) mod lesc;
+    #[path = "../../../../ble/notification_settings.rs"]
+    pub(crate) mod notification_settings;
+    #[path = "../../../../ble/processor.rs"]
+    pub(crate) mod processor;
+    #[path = "../../../../ble/racp.rs"]
+    pub(crate) mod racp;
+    #[path = "../../../../ble/traits.rs"]
+    pub(crate) mod traits;
+
+    /// The events the `gatt_service` macro generates on the device, exported by the build script
+    pub(crate) mod services {
+        use heapless::Vec;
+
+        use crate::common::device::config::*;
+
+        include!(concat!(env!("OUT_DIR"), "/service_events.rs"));
+    }
+
+    /// The whole nRF52840 flash, so the settings and DFU pages keep their device addresses
+    pub(crate) const SIM_FLASH_SIZE: usize = 1024 * 1024;
+
+    pub(crate) type SimFlash = RamFlash<SIM_FLASH_SIZE>;
+
+    pub(crate) static FLASH_MANAGER: CustomStaticCell<FlashManager<SimFlash>> = CustomStaticCell::new();
+}
+
+pub(crate) mod device {
+    #[path = "../../../../device/battery.rs"]
+    pub(crate) mod battery;
+    #[path = "../../../../device/bme280.rs"]
+    pub(crate) mod bme280;
+    #[path = "../../../../device/config.rs"]
+    pub(crate) mod config;
+    #[path = "../../../../device/dfu/mod.rs"]
+    pub(crate) mod dfu;
+    #[path = "../../../../device/lis2dh12/mod.rs"]
+    pub(crate) mod lis2dh12;
+    #[path = "../../../../device/veml6040/mod.rs"]
+    pub(crate) mod veml6040;
+
+    pub(crate) mod epd {
+        #[path = "../../../../../device/epd/color.rs"]
+        pub(crate) mod color;
+        #[path = "../../../../../device/epd/graphics.rs"]
+        pub(crate) mod graphics;
+    }
+
+    #[path = "../../../../device/error.rs"]
+    pub(crate) mod error;
+
+    pub(crate) mod expander {
+        #[path = "../../../../../device/expander/command.rs"]
+        pub(crate) mod command;
+        #[path = "../../../../../device/expander/expander_state.rs"]
+        pub(crate) mod expander_state;
+    }
+
+    pub(crate) mod persistence {
+        #[path = "../../../../../device/persistence/adc_label_storage.rs"]
+        pub(crate) mod adc_label_storage;
+        #[path = "../../../../../device/persistence/altitude_storage.rs"]
+        pub(crate) mod altitude_storage;
+        #[path = "../../../../../device/persistence/bme_config_storage.rs"]
+        pub(crate) mod bme_config_storage;
+        #[path = "../../../../../device/persistence/boot_health_storage.rs"]
+        pub(crate) mod boot_health_storage;
+        #[path = "../../../../../device/persistence/broadcast_storage.rs"]
+        pub(crate) mod broadcast_storage;
+        #[path = "../../../../../device/persistence/dfu_transfer_storage.rs"]
+        pub(crate) mod dfu_transfer_storage;
+        #[path = "../../../../../device/persistence/flash_manager.rs"]
+        pub(crate) mod flash_manager;
+        #[path = "../../../../../device/persistence/history_storage.rs"]
+        pub(crate) mod history_storage;
+        #[path = "../../../../../device/persistence/identity_storage.rs"]
+        pub(crate) mod identity_storage;
+        #[path = "../../../../../device/persistence/log_storage.rs"]
+        pub(crate) mod log_storage;
+    }
+
+    pub(crate) mod ui {
+        #[path = "../../../../../device/ui/device_ui.rs"]
+        pub(crate) mod device_ui;
+        #[path = "../../../../../device/ui/error.rs"]
+        pub(crate) mod error;
+        #[path = "../../../../../device/ui/text_repr.rs"]
+        pub(crate) mod text_repr;
+        #[path = "../../../../../device/ui/ui_store.rs"]
+        pub(crate) mod ui_store;
+        #[macro_use]
+        #[path = "../../../../../device/ui/ui_macro.rs"]
+        pub(crate) mod ui_macro;
+    }
+}
+
+pub(crate) mod util {
+    #[path = "../../../../util/barometer.rs"]
+    pub(crate) mod barometer;
+    #[path = "../../../../util/climate.rs"]
+    pub(crate) mod climate;
+    #[path = "../../../../util/condition.rs"]
+    pub(crate) mod condition;
+    #[path = "../../../../util/custom_static_cell.rs"]
+    pub(crate) mod custom_static_cell;
+    #[path = "../../../../util/history_log.rs"]
+    pub(crate) mod history_log;
+    #[path = "../../../../util/log_buffer.rs"]
+    pub(crate) mod log_buffer;
+    #[path = "../../../../util/wall_clock.rs"]
+    pub(crate) mod wall_clock;
+
+    pub(crate) mod ble_debugger {
+        use core::fmt;
+
+        use embassy_time::Instant;
+
+        use crate::common::util::log_buffer::{LOG, LogLevel};
+
+        /// Prints the message and appends it to the log ring, there are no connections to address on the host
+        pub(crate) fn ble_log_push(level: LogLevel, args: fmt::Arguments) {
+            let message = fmt::format(args);
+            println!("[{:>7}ms] ble_{}: {}", Instant::now().as_millis(), level.as_str(), message);
+            LOG.push(level, message.as_bytes());
+        }
+
+        #[macro_export]
+        macro_rules! ble_log {
+            (
+                $level:expr, $($t:tt)*
+            ) => {
+                $crate::common::util::ble_debugger::ble_log_push($level, format_args!($($t)*))
+            };
+        }
+
+        #[macro_export]
+        macro_rules! ble_error {
+            (
+                $($t:tt)*
+            ) => {
+                $crate::ble_log!($crate::common::util::log_buffer::LogLevel::Error, $($t)*)
+            };
+        }
+
+        #[macro_export]
+        macro_rules! ble_warn {
+            (
+                $($t:tt)*
+            ) => {
+                $crate::ble_log!($crate::common::util::log_buffer::LogLevel::Warn, $($t)*)
+            };
+        }
+
+        #[macro_export]
+        macro_rules! ble_info {
+            (
+                $($t:tt)*
+            ) => {
+                $crate::ble_log!($crate::common::util::log_buffer::LogLevel::Info, $($t)*)
+            };
+        }
+
+        #[macro_export]
+        macro_rules! ble_debug {
+            (
+                $($t:tt)*
+            ) => {
+                $crate::ble_log!($crate::common::util::log_buffer::LogLevel::Debug, $($t)*)
+            };
+        }
+    }
+}
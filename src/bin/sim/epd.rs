@@ -0,0 +1,43 @@
+//! Renders the UI into the EPD frame buffer and dumps it as a PBM image instead of sending it over SPIM
+
+use std::fs;
+use std::io;
+
+use crate::common::device::epd::color::Color;
+use crate::common::device::epd::graphics::{Display, DisplayRotation};
+use crate::common::device::ui::device_ui::Ui;
+use crate::common::device::ui::text_repr::TextRepr;
+use crate::common::device::ui::ui_store::UiStore;
+use crate::common::util::wall_clock::CLOCK;
+
+/// Same panel as `Epd2in13`
+const EPD_WIDTH: u32 = 122;
+const EPD_HEIGHT: u32 = 250;
+const EPD_LINE_BYTES: usize = (EPD_WIDTH as usize + 7) / 8;
+
+type SimDisplay = Display<EPD_WIDTH, EPD_HEIGHT, false, { EPD_LINE_BYTES * EPD_HEIGHT as usize }, Color>;
+
+pub(crate) fn render(store: &UiStore, path: &str) -> io::Result<()> {
+    let mut display = SimDisplay::default();
+    display.set_rotation(DisplayRotation::Rotate90);
+
+    Ui::new(&mut display, Color::Black, Color::White)
+        .draw(TextRepr::from(store).with_time(CLOCK.now()))
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{err}")))?;
+
+    fs::write(path, to_pbm(display.buffer()))
+}
+
+/// Landscape, as the panel is mounted: the buffer is in the panel's portrait orientation
+fn to_pbm(buffer: &[u8]) -> String {
+    let mut pbm = format!("P1\n{} {}\n", EPD_HEIGHT, EPD_WIDTH);
+    for row in 0..EPD_WIDTH as usize {
+        for col in 0..EPD_HEIGHT as usize {
+            let (x, y) = (EPD_WIDTH as usize - 1 - row, col);
+            let is_white = buffer[y * EPD_LINE_BYTES + x / 8] & (0x80 >> (x % 8)) != 0;
+            pbm.push(if is_white { '0' } else { '1' });
+        }
+        pbm.push('\n');
+    }
+    pbm
+}
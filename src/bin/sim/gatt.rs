@@ -0,0 +1,63 @@
+//! In-process stand-in for the softdevice GATT server: centrals are plain ids, writes are events
+//! fed to the very same `EventProcessor`, notifications are printed.
+
+use std::sync::Mutex;
+
+use crate::common::ble::notification_settings::BmeNotificationSettings;
+use crate::common::ble::processor::EventProcessor;
+use crate::common::ble::services::Bme280ServiceEvent;
+use crate::common::ble::traits::{SettingsEventConsumer, TimeoutEventCharacteristic};
+use crate::{impl_set_notification, impl_settings_event_consumer, impl_timeout_event_characteristic};
+
+/// Takes the place of the softdevice `Connection`
+pub(crate) type Central = u16;
+
+// Only the subscriptions: the firmware consumer also writes the offsets, the altitude and the config to flash
+impl_settings_event_consumer!(
+    BmeNotificationSettings,
+    Bme280ServiceEvent,
+    Temperature,
+    Humidity,
+    Pressure,
+    DewPoint,
+    FrostPoint,
+    AbsoluteHumidity,
+    HeatIndex,
+    Humidex,
+    VapourPressureDeficit,
+    SeaLevelPressure,
+    PressureAltitude,
+    PressureTendency,
+    PressureTrend,
+    Forecast
+);
+impl_timeout_event_characteristic!(Bme280ServiceEvent);
+
+pub(crate) static BME_EVENT_PROCESSOR: EventProcessor<BmeNotificationSettings, Bme280ServiceEvent, Central, 1> =
+    EventProcessor::new(Some("bme280"));
+
+static CENTRALS: Mutex<Vec<Central>> = Mutex::new(Vec::new());
+
+pub(crate) async fn connect(central: Central) {
+    println!("central {central}: connected");
+    CENTRALS.lock().unwrap().push(central);
+    BME_EVENT_PROCESSOR.register_connection(&central).await;
+}
+
+pub(crate) async fn disconnect(central: Central) {
+    println!("central {central}: disconnected");
+    CENTRALS.lock().unwrap().retain(|connected| *connected != central);
+    BME_EVENT_PROCESSOR.drop_connection(&central).await;
+}
+
+pub(crate) async fn write(central: Central, event: Bme280ServiceEvent) {
+    BME_EVENT_PROCESSOR.process_event(central, event).await;
+}
+
+pub(crate) fn connected_centrals() -> Vec<Central> {
+    CENTRALS.lock().unwrap().clone()
+}
+
+pub(crate) fn notify(central: Central, characteristic: &str, value: impl core::fmt::Display) {
+    println!("central {central}: notify {characteristic} = {value}");
+}
@@ -0,0 +1,579 @@
+//! Host simulator: runs the portable firmware modules against simulated sensors, an in-memory flash
+//! and in-process centrals, then renders the EPD into a PBM image.
+//!
+//! `cargo sim [epd-image-path]`
+
+#![feature(type_alias_impl_trait)]
+#![feature(async_fn_in_trait)]
+
+extern crate alloc;
+
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use embassy_boot::State;
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+use sha2::{Digest, Sha256};
+
+use crate::bus::FakeI2c;
+use crate::common::ble::FLASH_MANAGER;
+use crate::common::ble::services::Bme280ServiceEvent;
+use crate::common::device::bme280::{self, BME280_SLEEP_MODE};
+use crate::common::device::config::{
+    BLE_DFU_CHUNK_LEN, BLE_EXPANDER_BUF_SIZE, BLE_EXPANDER_CONTROL_BYTES_SIZE, HISTORY_FLASH_PAGES,
+    PRESSURE_TREND_SAMPLE_INTERVAL, PRESSURE_TREND_SAMPLES,
+};
+use crate::common::device::dfu::ram_flash::RamFlash;
+use crate::common::device::dfu::updater::{DfuError, DfuImage, DfuUpdater};
+use crate::common::device::expander::command::Command;
+use crate::common::device::expander::expander_state::{ExpanderState, ExpanderType};
+use crate::common::device::error::ExpanderError;
+use crate::common::device::lis2dh12::{Lis2dh12, SlaveAddr};
+use crate::common::device::persistence::adc_label_storage::AdcLabels;
+use crate::common::device::persistence::altitude_storage::AltitudeSettings;
+use crate::common::device::persistence::bme_config_storage::BmeSettings;
+use crate::common::device::persistence::dfu_transfer_storage::DfuTransferRecord;
+use crate::common::device::persistence::flash_manager::{CalibrationData, FlashManager};
+use crate::common::device::persistence::history_storage::{HISTORY_RECORDS_PER_PAGE, HistoryRecord};
+use crate::common::device::ui::ui_store::UiStore;
+use crate::common::device::veml6040;
+use crate::common::util::barometer::{self, PRESSURE_HISTORY, PressureTrend};
+use crate::common::util::climate::ClimateMetrics;
+use crate::common::util::history_log::{HISTORY, restore_history_from_flash};
+use crate::common::util::log_buffer::{LOG, persist_log_task, restore_log_from_flash};
+use crate::common::util::wall_clock::{ADJUST_EXTERNAL_REFERENCE, CalendarTime, CLOCK};
+use crate::gatt::BME_EVENT_PROCESSOR;
+
+#[allow(dead_code)]
+mod common;
+mod bus;
+mod epd;
+mod gatt;
+
+/// How long the scenario runs before the EPD is rendered
+const SIM_DURATION: Duration = Duration::from_secs(8);
+const DEFAULT_EPD_IMAGE_PATH: &str = "sim-epd.pbm";
+/// Signs the generated image, the updater is created with the matching public key
+const SIM_DFU_SEED: [u8; 32] = [0x5E; 32];
+const SIM_DFU_IMAGE_LEN: usize = 10000;
+
+static UI_STORE: Mutex<ThreadModeRawMutex, UiStore> = Mutex::new(UiStore {
+    nrf_adc_voltages: [0.0; 8],
+    bat_voltage: 3.9,
+    bat_level: 80,
+    bat_charging_estimate: false,
+    adc_voltages: [0.0; 8],
+    r: 0,
+    g: 0,
+    b: 0,
+    w: 0,
+    cct: 0,
+    lux: 0.0,
+    temperature: 0.0,
+    humidity: 0.0,
+    pressure: 0.0,
+    dew_point: None,
+    forecast: None,
+    x: 0.0,
+    y: 0.0,
+    z: 0.0,
+    num_connections: 0,
+    name: String::new(),
+    location: String::new(),
+});
+
+/// defmt output of the shared modules is dropped, the simulator prints through `ble_*!` instead
+#[defmt::global_logger]
+struct NoopLogger;
+
+unsafe impl defmt::Logger for NoopLogger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+#[defmt::panic_handler]
+fn panic() -> ! {
+    panic!("defmt panic")
+}
+
+fn init_flash() {
+    FLASH_MANAGER.init_ro(FlashManager::new(RamFlash::new()));
+}
+
+async fn run_calibration() {
+    let flash_manager = FLASH_MANAGER.get();
+    if let Err(err) = flash_manager.init().await {
+        ble_error!("Failed to init flash: {}", err);
+        return;
+    }
+
+    let data = CalibrationData { version: 1, bme_temperature: -0.5, bme_humidity: 2.0, bme_pressure: 0.0 };
+    if let Err(err) = flash_manager.write_calibration_data(&data).await {
+        ble_error!("Failed to write calibration data: {}", err);
+    }
+
+    // a client holding an old version must not overwrite newer data
+    let stale = CalibrationData { version: 1, bme_temperature: 3.0, ..data };
+    match flash_manager.write_calibration_data(&stale).await {
+        Err(err) => ble_info!("Stale calibration rejected: {}", err),
+        Ok(()) => ble_error!("Stale calibration was accepted"),
+    }
+
+    match flash_manager.read_calibration_data().await {
+        Ok(data) => ble_info!(
+            "Calibration v{}: t={} h={} p={}",
+            data.version, data.bme_temperature, data.bme_humidity, data.bme_pressure
+        ),
+        Err(err) => ble_error!("Failed to read calibration data: {}", err),
+    }
+}
+
+async fn run_adc_labels() {
+    let flash_manager = FLASH_MANAGER.get();
+    let mut labels = AdcLabels::default();
+    labels.labels[3] = heapless::Vec::from_slice(b"soil moisture").unwrap_or_default();
+
+    if let Err(err) = flash_manager.write_setting(&labels).await {
+        ble_error!("Failed to write ADC labels: {}", err);
+        return;
+    }
+    let restored: Result<Option<AdcLabels>, _> = flash_manager.read_setting().await;
+    match restored {
+        Ok(Some(restored)) if restored == labels => {
+            ble_info!("ADC channel 3: {}", core::str::from_utf8(&restored.labels[3]).unwrap_or_default())
+        }
+        Ok(_) => ble_error!("ADC labels did not survive the flash round trip"),
+        Err(err) => ble_error!("Failed to read ADC labels: {}", err),
+    }
+}
+
+/// A fast HVAC style configuration survives the flash, the combinations the compensation can not handle are rejected
+async fn run_bme_config() {
+    let flash_manager = FLASH_MANAGER.get();
+    for bits in [[0, 1, 1, 0, 0], [0, 0, 0, 0, 0], [1, 6, 1, 0, 0]] {
+        if let Ok(configuration) = bme280::Configuration::from_bits(bits) {
+            ble_error!("BME configuration {:?} should have been rejected: {:?}", bits, configuration);
+            return;
+        }
+    }
+
+    let configuration = match bme280::Configuration::from_bits([2, 3, 0, 1, 0]) {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            ble_error!("BME configuration rejected: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = flash_manager.write_setting(&BmeSettings { configuration }).await {
+        ble_error!("Failed to write BME settings: {}", err);
+        return;
+    }
+    let restored: Result<Option<BmeSettings>, _> = flash_manager.read_setting().await;
+    match restored {
+        Ok(Some(restored)) if restored.configuration.to_bits() == configuration.to_bits() => ble_info!(
+            "BME configuration {:?}, conversion {} us",
+            configuration.to_bits(),
+            configuration.measurement_time().as_micros()
+        ),
+        Ok(_) => ble_error!("BME settings did not survive the flash round trip"),
+        Err(err) => ble_error!("Failed to read BME settings: {}", err),
+    }
+}
+
+/// Three hours of a falling barometer at the sampling interval, the reference altitude goes through flash
+async fn run_barometer() {
+    let flash_manager = FLASH_MANAGER.get();
+    let settings = AltitudeSettings { reference_altitude: 250 };
+    if let Err(err) = flash_manager.write_setting(&settings).await {
+        ble_error!("Failed to write altitude settings: {}", err);
+        return;
+    }
+    let restored: Result<Option<AltitudeSettings>, _> = flash_manager.read_setting().await;
+    let altitude = match restored {
+        Ok(Some(restored)) if restored.reference_altitude == settings.reference_altitude => restored.reference_altitude,
+        Ok(_) => {
+            ble_error!("Altitude settings did not survive the flash round trip");
+            return;
+        }
+        Err(err) => {
+            ble_error!("Failed to read altitude settings: {}", err);
+            return;
+        }
+    };
+
+    let start = Instant::now();
+    let pressure_at = |sample: u32| 98_000.0 - 50.0 * sample as f32;
+    for sample in 0..PRESSURE_TREND_SAMPLES as u32 {
+        PRESSURE_HISTORY.record(start + PRESSURE_TREND_SAMPLE_INTERVAL * sample, pressure_at(sample));
+    }
+    let pressure = pressure_at(PRESSURE_TREND_SAMPLES as u32 - 1);
+
+    let trend = PRESSURE_HISTORY.trend();
+    let sea_level = barometer::sea_level_pressure(pressure, altitude as f32, 15.0);
+    let forecast = barometer::zambretti(sea_level, trend);
+    if trend != PressureTrend::FallingFast || forecast.is_none() {
+        ble_error!("A 900 Pa drop in 3 hours should fall fast, got {:?}", trend);
+        return;
+    }
+    ble_info!(
+        "Barometer: {:.1} hPa at {} m, {:.1} hPa at the sea level, {:.0} m pressure altitude, {:?}, forecast {}",
+        pressure / 100.0,
+        altitude,
+        sea_level / 100.0,
+        barometer::pressure_altitude(pressure),
+        trend,
+        forecast.map(|letter| (b'A' + letter - 1) as char).unwrap_or('?')
+    );
+    UI_STORE.lock().await.forecast = forecast;
+}
+
+/// The host clock plays the central's Current Time Service
+fn run_wall_clock() {
+    let unix_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as u64).unwrap_or(0);
+    let current_time = CalendarTime::from_unix_millis(unix_ms).to_current_time(ADJUST_EXTERNAL_REFERENCE);
+    match CalendarTime::parse_current_time(&current_time).and_then(|(time, _)| time.to_unix_millis()) {
+        Some(parsed) => {
+            CLOCK.sync(parsed, ADJUST_EXTERNAL_REFERENCE);
+            ble_info!("Clock set to {}", CLOCK.now().map(|now| now.to_string()).unwrap_or_default());
+        }
+        None => ble_error!("Current Time did not survive the round trip"),
+    }
+}
+
+/// Fills the history region past its end, so the oldest page is erased, and restores it as after a reboot
+async fn run_history() {
+    let records = HISTORY_FLASH_PAGES * HISTORY_RECORDS_PER_PAGE + HISTORY_RECORDS_PER_PAGE / 2;
+    for index in 0..records {
+        let record = HistoryRecord {
+            time: CLOCK.now_seconds() + index as u32 * 600,
+            bme: Some((2150 + (index % 100) as i16, 4500, 1_013_250)),
+            ..Default::default()
+        };
+        if let Err(err) = HISTORY.append(record).await {
+            ble_error!("Failed to append history record: {}", err);
+            return;
+        }
+    }
+
+    let (oldest, next) = (HISTORY.oldest_sequence(), HISTORY.next_sequence());
+    if let Err(err) = restore_history_from_flash().await {
+        ble_error!("Failed to restore history: {}", err);
+        return;
+    }
+    if (HISTORY.oldest_sequence(), HISTORY.next_sequence()) != (oldest, next) {
+        ble_error!("History did not survive the restore");
+        return;
+    }
+    match HISTORY.read(oldest).await {
+        Ok(Some(record)) => ble_info!("History: records {} - {}, oldest at {}", oldest, next, record.time),
+        Ok(None) => {
+            ble_error!("Oldest history record {} is missing", oldest);
+            return;
+        }
+        Err(err) => {
+            ble_error!("Failed to read history: {}", err);
+            return;
+        }
+    }
+
+    // a deleted record is gone for good, its neighbours stay
+    let deleted = (HISTORY.delete(oldest).await, HISTORY.delete(oldest).await);
+    let reads = (HISTORY.read(oldest).await, HISTORY.read(oldest + 1).await);
+    match (deleted, reads) {
+        ((Ok(true), Ok(false)), (Ok(None), Ok(Some(_)))) => ble_info!("History: deleted record {}", oldest),
+        _ => ble_error!("History deletion of {} failed", oldest),
+    }
+
+    // a failed write leaves its slot erased and the next record lands after it; the restore resumes after
+    // that record instead of programming the hole's neighbour a second time
+    let page = (records / HISTORY_RECORDS_PER_PAGE) % HISTORY_FLASH_PAGES;
+    let index = records % HISTORY_RECORDS_PER_PAGE;
+    let after_hole = HistoryRecord { sequence: next + 1, time: CLOCK.now_seconds(), ..Default::default() };
+    if let Err(err) = FLASH_MANAGER.get().write_history_record(page, index + 1, &after_hole).await {
+        ble_error!("Failed to write history record: {}", err);
+        return;
+    }
+    if let Err(err) = restore_history_from_flash().await {
+        ble_error!("Failed to restore history: {}", err);
+        return;
+    }
+    let appended = HISTORY.append(HistoryRecord { time: CLOCK.now_seconds(), ..Default::default() }).await;
+    let reads = (HISTORY.read(next).await, HISTORY.read(next + 1).await);
+    match (appended, reads) {
+        (Ok(sequence), (Ok(None), Ok(Some(record)))) if sequence == next + 2 && record.time == after_hole.time => {
+            ble_info!("History: resumed after the hole at {}", next)
+        }
+        _ => ble_error!("History did not resume after the hole at {}", next),
+    }
+}
+
+async fn run_dfu() {
+    let image: Vec<u8> = (0..SIM_DFU_IMAGE_LEN).map(|index| (index * 7 % 251) as u8).collect();
+    let key_pair = ed25519_compact::KeyPair::from_seed(ed25519_compact::Seed::new(SIM_DFU_SEED));
+    let digest: [u8; 32] = Sha256::digest(&image).into();
+    let announce = DfuImage { size: image.len() as u32, digest, signature: *key_pair.sk.sign(digest, None) };
+
+    let flash_manager = FLASH_MANAGER.get();
+    let chunks: Vec<&[u8]> = image.chunks(BLE_DFU_CHUNK_LEN).collect();
+    let interrupt_at = chunks.len() / 2;
+
+    let result = async {
+        let mut updater = DfuUpdater::new(flash_manager.dfu_partition(), *key_pair.pk);
+        let mut offset = updater.start(announce.clone())?;
+        flash_manager
+            .write_setting(&DfuTransferRecord { image: announce.clone(), pages: 0 })
+            .await
+            .map_err(|_| DfuError::Flash)?;
+        for chunk in &chunks[..interrupt_at] {
+            let completed_pages = updater.completed_pages();
+            offset = updater.write(offset, chunk).await?;
+            for page in completed_pages..updater.completed_pages() {
+                flash_manager.record_dfu_progress(page as usize).await.map_err(|_| DfuError::Flash)?;
+            }
+        }
+        ble_info!("DFU interrupted at {}", offset);
+
+        // the device reboots, the transfer continues from the last completely written page
+        let record: Option<DfuTransferRecord> = flash_manager.read_setting().await.map_err(|_| DfuError::Flash)?;
+        let record = record.ok_or(DfuError::NotStarted)?;
+        let mut updater = DfuUpdater::new(flash_manager.dfu_partition(), *key_pair.pk);
+        updater.restore(record.image, record.pages)?;
+
+        // the client reconnects and announces the same image again
+        let mut offset = updater.start(announce)?;
+        ble_info!("DFU resumed at {} after {} pages", offset, record.pages);
+        for chunk in image[offset as usize..].chunks(BLE_DFU_CHUNK_LEN) {
+            offset = updater.write(offset, chunk).await?;
+        }
+
+        updater.verify().await?;
+        updater.ensure_verified()
+    }
+    .await;
+
+    if let Err(err) = result {
+        ble_error!("DFU failed: {}", err);
+        return;
+    }
+    ble_info!("DFU image of {} bytes verified", image.len());
+
+    let result = async {
+        flash_manager.mark_boot_pending().await?;
+        flash_manager.mark_dfu_updated().await?;
+        flash_manager.clear_dfu_transfer().await
+    }
+    .await;
+    if let Err(err) = result {
+        ble_error!("Failed to apply the DFU image: {}", err);
+    }
+}
+
+/// The "reset" into the new image: the bootloader state says it is still to be confirmed, then it confirms itself
+async fn run_boot_health() {
+    let flash_manager = FLASH_MANAGER.get();
+    let result = async {
+        let record = flash_manager.read_boot_health().await?;
+        let state = flash_manager.dfu_boot_state().await?;
+        ble_info!("Boot health: pending={} swapped={}", record.pending, state == State::Swap);
+
+        flash_manager.mark_dfu_booted().await?;
+        flash_manager.confirm_boot(None).await?;
+
+        let record = flash_manager.read_boot_health().await?;
+        let state = flash_manager.dfu_boot_state().await?;
+        ble_info!("Boot confirmed: pending={} swapped={}", record.pending, state == State::Swap);
+        Ok::<(), common::device::error::FlashManagerError>(())
+    }
+    .await;
+
+    if let Err(err) = result {
+        ble_error!("Boot health failed: {}", err);
+    }
+}
+
+/// Reads WHO_AM_I of the accelerometer through an expander I2C bundle, as a central would
+async fn run_expander(i2c: &mut FakeI2c) {
+    use embedded_hal_async::i2c::I2c;
+
+    let mut bundle = [0u8; BLE_EXPANDER_BUF_SIZE + BLE_EXPANDER_CONTROL_BYTES_SIZE];
+    // lock, command, address, size_read, size_write and mosi
+    bundle[0] = 0b1001_1111;
+    bundle[2] = 2;
+    bundle[7] = 0x02;
+    bundle[8] = common::device::lis2dh12::reg::I2C_SAD;
+    bundle[9..11].copy_from_slice(&1u16.to_le_bytes());
+    bundle[11..13].copy_from_slice(&1u16.to_le_bytes());
+    bundle[BLE_EXPANDER_CONTROL_BYTES_SIZE] = 0x0F;
+
+    let result = async {
+        let state = ExpanderState::try_from(bundle)?;
+        let (Some(ExpanderType::I2c), Some(Command::Transfer), Some(address), Some(size_write), Some(size_read)) = (
+            state.flags.expander_lock_type,
+            state.flags.command,
+            state.flags.address,
+            state.flags.size_write,
+            state.flags.size_read,
+        ) else {
+            return Err(ExpanderError::IncompleteData);
+        };
+
+        let mut miso = [0u8; BLE_EXPANDER_BUF_SIZE];
+        i2c.write_read(address, &state.mosi[..size_write], &mut miso[..size_read]).await?;
+        Ok(miso[0])
+    }
+    .await;
+
+    match result {
+        Ok(who_am_i) => ble_info!("Expander read WHO_AM_I: {:#x}", who_am_i),
+        Err(err) => ble_error!("Expander failed: {}", err),
+    }
+}
+
+async fn read_sensors(i2c: &mut FakeI2c) -> Result<(), String> {
+    let measurements = {
+        let mut bme = bme280::Bme280::new_primary(i2c);
+        let bme_config = bme280::Configuration::default()
+            .with_humidity_oversampling(bme280::Oversampling::Oversampling1X)
+            .with_temperature_oversampling(bme280::Oversampling::Oversampling1X)
+            .with_pressure_oversampling(bme280::Oversampling::Oversampling1X);
+        bme.init(bme_config).await.map_err(|err| err.to_string())?;
+        let measurements = bme.measure().await.map_err(|err| err.to_string())?;
+        bme.set_mode(BME280_SLEEP_MODE).await.map_err(|err| err.to_string())?;
+        measurements
+    };
+    let calibration = FLASH_MANAGER.get().get_last_calibration_data().await;
+    let temperature = measurements.temperature + calibration.bme_temperature;
+    let humidity = measurements.humidity + calibration.bme_humidity;
+    let pressure = measurements.pressure + calibration.bme_pressure;
+    let climate = ClimateMetrics::from_measurements(&bme280::Measurements { temperature, pressure, humidity });
+
+    let accel = {
+        let mut lis = Lis2dh12::new(&mut *i2c, SlaveAddr::Default).await.map_err(|err| format!("{err:?}"))?;
+        lis.accel_norm().await.map_err(|err| format!("{err:?}"))?
+    };
+
+    let color = {
+        let mut veml = veml6040::Veml6040::new(&mut *i2c);
+        veml.set_measurement_mode(veml6040::MeasurementMode::Auto).await.map_err(|err| format!("{err:?}"))?;
+        veml.read_all_channels_with_oversampling(veml6040::IntegrationTime::_160ms, 1)
+            .await
+            .map_err(|err| format!("{err:?}"))?
+    };
+
+    {
+        let mut store = UI_STORE.lock().await;
+        store.temperature = temperature;
+        store.humidity = humidity;
+        store.pressure = pressure;
+        store.dew_point = climate.map(|climate| climate.dew_point);
+        store.x = accel.x;
+        store.y = accel.y;
+        store.z = accel.z;
+        store.lux = color.ambient_light(veml6040::IntegrationTime::_160ms);
+        store.cct = color.compute_cct().unwrap_or(0.0) as u16;
+        store.r = color.red;
+        store.g = color.green;
+        store.b = color.blue;
+        store.w = color.white;
+        store.num_connections = gatt::connected_centrals().len() as u8;
+    }
+
+    // same cadence rules as `notify_all!`
+    for central in gatt::connected_centrals() {
+        let Some((settings, true)) = BME_EVENT_PROCESSOR.get_due_connection_settings(&central, "bme").await else {
+            continue;
+        };
+        if settings.temperature {
+            gatt::notify(central, "temperature", format!("{temperature:.2} °C"));
+        }
+        if settings.humidity {
+            gatt::notify(central, "humidity", format!("{humidity:.2} %"));
+        }
+        if settings.pressure {
+            gatt::notify(central, "pressure", format!("{:.2} hPa", pressure / 100.0));
+        }
+    }
+
+    Ok(())
+}
+
+#[embassy_executor::task]
+async fn sensor_task() {
+    let mut i2c = FakeI2c::new();
+    run_expander(&mut i2c).await;
+
+    loop {
+        let _token = BME_EVENT_PROCESSOR.wait_for_condition().await;
+        if let Err(err) = read_sensors(&mut i2c).await {
+            ble_error!("Failed to read sensors: {}", err);
+        }
+        Timer::after(BME_EVENT_PROCESSOR.get_timeout_duration()).await;
+    }
+}
+
+/// Central 1 follows temperature and pressure at the default cadence, central 2 humidity every 3 s
+#[embassy_executor::task]
+async fn central_task() {
+    gatt::connect(1).await;
+    gatt::write(1, Bme280ServiceEvent::TemperatureCccdWrite { notifications: true }).await;
+    gatt::write(1, Bme280ServiceEvent::PressureCccdWrite { notifications: true }).await;
+
+    Timer::after(Duration::from_secs(1)).await;
+    gatt::connect(2).await;
+    gatt::write(2, Bme280ServiceEvent::TimeoutWrite(3000)).await;
+    gatt::write(2, Bme280ServiceEvent::HumidityCccdWrite { notifications: true }).await;
+
+    Timer::after(Duration::from_secs(4)).await;
+    gatt::disconnect(1).await;
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let epd_image_path = env::args().nth(1).unwrap_or_else(|| DEFAULT_EPD_IMAGE_PATH.to_string());
+
+    init_flash();
+    run_wall_clock();
+    run_calibration().await;
+    run_adc_labels().await;
+    run_bme_config().await;
+    run_barometer().await;
+    run_history().await;
+
+    let log_index = match restore_log_from_flash().await {
+        Ok(index) => index,
+        Err(err) => {
+            ble_error!("Failed to restore log: {}", err);
+            0
+        }
+    };
+    spawner.must_spawn(persist_log_task(log_index));
+
+    run_dfu().await;
+    run_boot_health().await;
+
+    {
+        let mut store = UI_STORE.lock().await;
+        store.name = "sim".to_string();
+        store.location = "host".to_string();
+    }
+
+    spawner.must_spawn(sensor_task());
+    spawner.must_spawn(central_task());
+
+    Timer::after(SIM_DURATION).await;
+
+    match epd::render(&*UI_STORE.lock().await, &epd_image_path) {
+        Ok(()) => println!("EPD rendered to {epd_image_path}"),
+        Err(err) => println!("Failed to render EPD: {err}"),
+    }
+
+    let page = LOG.page(0);
+    println!("Log page: {} bytes, {} entries", page.len(), page[4]);
+
+    std::process::exit(0);
+}
@@ -1,9 +1,12 @@
 use core::fmt::Formatter;
+#[cfg(target_os = "none")]
 use embassy_nrf::gpio::{AnyPin, Flex, Output, Pin as GpioPin};
-use embassy_time::{Duration, Timer};
-use embedded_hal_async::i2c::{
-    Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress,
-};
+use embassy_time::Duration;
+#[cfg(target_os = "none")]
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{Error, ErrorKind, NoAcknowledgeSource};
+#[cfg(target_os = "none")]
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation, SevenBitAddress};
 
 #[derive(Copy, Clone)]
 pub struct Config {
@@ -31,6 +34,7 @@ impl core::fmt::Display for BitbangI2CError {
     }
 }
 
+#[cfg(target_os = "none")]
 pub struct BitbangI2C<'d, SCL = AnyPin, SDA = AnyPin>
 where
     SCL: GpioPin + 'd,
@@ -41,6 +45,7 @@ where
     config: Config,
 }
 
+#[cfg(target_os = "none")]
 impl<'d, SCL, SDA> BitbangI2C<'d, SCL, SDA>
 where
     SCL: GpioPin + 'd,
@@ -165,6 +170,7 @@ where
     }
 }
 
+#[cfg(target_os = "none")]
 impl<'d, SCL, SDA> ErrorType for BitbangI2C<'d, SCL, SDA>
 where
     SCL: 'd + GpioPin,
@@ -185,6 +191,7 @@ impl Error for BitbangI2CError {
     }
 }
 
+#[cfg(target_os = "none")]
 impl<'d, SCL, SDA> I2c for BitbangI2C<'d, SCL, SDA>
 where
     SCL: GpioPin + 'd,
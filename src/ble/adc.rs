@@ -0,0 +1,259 @@
+use core::cell::RefCell;
+use core::str;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use heapless::Vec;
+use nrf_softdevice::ble::{Connection, SecurityMode, Uuid};
+use nrf_softdevice::ble::gatt_server::{self, NotifyValueError, RegisterError, Service, SetValueError};
+use nrf_softdevice::ble::gatt_server::builder::ServiceBuilder;
+use nrf_softdevice::ble::gatt_server::characteristic::Attribute;
+use nrf_softdevice::Softdevice;
+
+use crate::ble_debug;
+use crate::common::ble::{FLASH_MANAGER, SERVER};
+use crate::common::ble::gatt_builder::{add_characteristic, gatt_uuid};
+use crate::common::ble::gatt_table::{ADC_SERVICE, CharacteristicSpec, USER_DESCRIPTION_UUID};
+use crate::common::device::config::{BLE_ADC_CHANNELS, BLE_ADC_LABEL_LEN};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::adc_label_storage::{AdcLabel, AdcLabels};
+
+// Built by hand like the ESS: the macro does not report writes to descriptors, and the channel
+// labels are writable User Descriptions.
+
+const SAMPLES: usize = BLE_ADC_CHANNELS;
+const ELAPSED: usize = SAMPLES + 1;
+const TIMEOUT: usize = ELAPSED + 1;
+const ADC_CHARACTERISTICS: usize = TIMEOUT + 1;
+
+const _: () = assert!(ADC_SERVICE.characteristics.len() == ADC_CHARACTERISTICS, "ADC_SERVICE does not match");
+
+/// Labels of the channels as declared in `ADC_SERVICE`, until the user names them
+fn default_label(channel: usize) -> &'static str {
+    ADC_SERVICE.characteristics[channel].user_description.unwrap_or_default()
+}
+
+#[derive(Default, Copy, Clone)]
+struct CharacteristicHandles {
+    value: u16,
+    cccd: u16,
+    /// User Description, the label of a voltage channel
+    description: u16,
+}
+
+/// In the order of `ADC_SERVICE`: the voltage channels, the samples, the elapsed time and the timeout
+pub(crate) struct AdcService {
+    characteristics: [CharacteristicHandles; ADC_CHARACTERISTICS],
+}
+
+pub(crate) enum AdcServiceEvent {
+    Voltage0CccdWrite { notifications: bool },
+    Voltage1CccdWrite { notifications: bool },
+    Voltage2CccdWrite { notifications: bool },
+    Voltage3CccdWrite { notifications: bool },
+    Voltage4CccdWrite { notifications: bool },
+    Voltage5CccdWrite { notifications: bool },
+    Voltage6CccdWrite { notifications: bool },
+    SamplesCccdWrite { notifications: bool },
+    ElapsedCccdWrite { notifications: bool },
+    TimeoutCccdWrite { notifications: bool },
+    TimeoutWrite(u32),
+    /// User Description of a voltage characteristic
+    DescriptionWrite { channel: usize, value: AdcLabel },
+}
+
+impl AdcServiceEvent {
+    fn voltage_cccd_write(channel: usize, notifications: bool) -> Self {
+        match channel {
+            0 => Self::Voltage0CccdWrite { notifications },
+            1 => Self::Voltage1CccdWrite { notifications },
+            2 => Self::Voltage2CccdWrite { notifications },
+            3 => Self::Voltage3CccdWrite { notifications },
+            4 => Self::Voltage4CccdWrite { notifications },
+            5 => Self::Voltage5CccdWrite { notifications },
+            _ => Self::Voltage6CccdWrite { notifications },
+        }
+    }
+}
+
+/// The value as declared in `ADC_SERVICE`, followed by its User Description
+fn register_characteristic(
+    service_builder: &mut ServiceBuilder,
+    spec: &CharacteristicSpec,
+) -> Result<CharacteristicHandles, RegisterError> {
+    let mut characteristic_builder = add_characteristic(service_builder, spec)?;
+
+    let description = match spec.user_description {
+        Some(description) => {
+            let attr = Attribute::new(description.as_bytes());
+            let attr = if spec.user_description_writable {
+                attr.write_security(SecurityMode::LescMitm).variable_len(BLE_ADC_LABEL_LEN as u16)
+            } else {
+                attr.write_security(SecurityMode::NoAccess)
+            };
+            characteristic_builder.add_descriptor(Uuid::new_16(USER_DESCRIPTION_UUID), attr)?.handle()
+        }
+        None => 0,
+    };
+
+    let handles = characteristic_builder.build();
+
+    Ok(CharacteristicHandles { value: handles.value_handle, cccd: handles.cccd_handle, description })
+}
+
+macro_rules! voltage_accessors {
+    ($($characteristic:ident = $channel:literal),+) => {
+        paste::paste! {
+            $(
+                pub(crate) fn [<$characteristic _notify>](&self, conn: &Connection, value: &u16) -> Result<(), NotifyValueError> {
+                    self.notify_value(conn, $channel, &value.to_le_bytes())
+                }
+
+                pub(crate) fn [<$characteristic _set>](&self, value: &u16) -> Result<(), SetValueError> {
+                    self.set_value($channel, &value.to_le_bytes())
+                }
+            )+
+        }
+    };
+}
+
+impl AdcService {
+    pub(crate) fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+        let mut service_builder = ServiceBuilder::new(sd, gatt_uuid(ADC_SERVICE.uuid))?;
+
+        let mut characteristics = [CharacteristicHandles::default(); ADC_CHARACTERISTICS];
+        for (handles, spec) in characteristics.iter_mut().zip(ADC_SERVICE.characteristics) {
+            *handles = register_characteristic(&mut service_builder, spec)?;
+        }
+
+        let _ = service_builder.build();
+
+        Ok(Self { characteristics })
+    }
+
+    fn notify_value(&self, conn: &Connection, index: usize, value: &[u8]) -> Result<(), NotifyValueError> {
+        gatt_server::notify_value(conn, self.characteristics[index].value, value)
+    }
+
+    fn set_value(&self, index: usize, value: &[u8]) -> Result<(), SetValueError> {
+        gatt_server::set_value(unsafe { Softdevice::steal() }, self.characteristics[index].value, value)
+    }
+
+    voltage_accessors!(
+        voltage0 = 0,
+        voltage1 = 1,
+        voltage2 = 2,
+        voltage3 = 3,
+        voltage4 = 4,
+        voltage5 = 5,
+        voltage6 = 6
+    );
+
+    pub(crate) fn samples_notify(&self, conn: &Connection, value: &u16) -> Result<(), NotifyValueError> {
+        self.notify_value(conn, SAMPLES, &value.to_le_bytes())
+    }
+
+    pub(crate) fn samples_set(&self, value: &u16) -> Result<(), SetValueError> {
+        self.set_value(SAMPLES, &value.to_le_bytes())
+    }
+
+    pub(crate) fn elapsed_notify(&self, conn: &Connection, value: &u64) -> Result<(), NotifyValueError> {
+        self.notify_value(conn, ELAPSED, &value.to_le_bytes())
+    }
+
+    pub(crate) fn elapsed_set(&self, value: &u64) -> Result<(), SetValueError> {
+        self.set_value(ELAPSED, &value.to_le_bytes())
+    }
+
+    pub(crate) fn timeout_set(&self, value: &u32) -> Result<(), SetValueError> {
+        self.set_value(TIMEOUT, &value.to_le_bytes())
+    }
+
+    /// Reflects the label in effect back to the descriptor, i.e. after a rejected write
+    pub(crate) fn description_set(&self, channel: usize, label: &[u8]) -> Result<(), SetValueError> {
+        gatt_server::set_value(unsafe { Softdevice::steal() }, self.characteristics[channel].description, label)
+    }
+}
+
+impl Service for AdcService {
+    type Event = AdcServiceEvent;
+
+    fn on_write(&self, handle: u16, data: &[u8]) -> Option<Self::Event> {
+        let notifications = data.first().map(|flags| flags & 0x01 != 0).unwrap_or(false);
+
+        for (channel, handles) in self.characteristics[..BLE_ADC_CHANNELS].iter().enumerate() {
+            if handle == handles.description {
+                return Some(AdcServiceEvent::DescriptionWrite { channel, value: Vec::from_slice(data).ok()? });
+            }
+            if handle == handles.cccd {
+                return Some(AdcServiceEvent::voltage_cccd_write(channel, notifications));
+            }
+        }
+
+        if handle == self.characteristics[SAMPLES].cccd {
+            return Some(AdcServiceEvent::SamplesCccdWrite { notifications });
+        }
+        if handle == self.characteristics[ELAPSED].cccd {
+            return Some(AdcServiceEvent::ElapsedCccdWrite { notifications });
+        }
+        if handle == self.characteristics[TIMEOUT].cccd {
+            return Some(AdcServiceEvent::TimeoutCccdWrite { notifications });
+        }
+        if handle == self.characteristics[TIMEOUT].value {
+            return Some(AdcServiceEvent::TimeoutWrite(u32::from_le_bytes(data.try_into().ok()?)));
+        }
+
+        None
+    }
+}
+
+static ADC_LABELS: Mutex<ThreadModeRawMutex, RefCell<Option<AdcLabels>>> = Mutex::new(RefCell::new(None));
+
+fn default_labels() -> AdcLabels {
+    let mut labels = AdcLabels::default();
+    for (channel, label) in labels.labels.iter_mut().enumerate() {
+        *label = Vec::from_slice(default_label(channel).as_bytes()).unwrap_or_default();
+    }
+    labels
+}
+
+pub(crate) fn adc_labels() -> AdcLabels {
+    ADC_LABELS.lock(|labels| labels.borrow().clone()).unwrap_or_else(default_labels)
+}
+
+fn apply_adc_labels(labels: AdcLabels) {
+    let server = SERVER.get();
+    for (channel, label) in labels.labels.iter().enumerate() {
+        if let Err(err) = server.adc.description_set(channel, label) {
+            info!("Failed to set ADC channel {} description {:?}", channel, err);
+        }
+    }
+    ADC_LABELS.lock(|current| *current.borrow_mut() = Some(labels));
+}
+
+pub(crate) async fn restore_adc_labels() -> Result<(), FlashManagerError> {
+    let labels: Option<AdcLabels> = FLASH_MANAGER.get().read_setting().await?;
+    apply_adc_labels(labels.unwrap_or_else(default_labels));
+    Ok(())
+}
+
+/// An empty label falls back to the pin name
+pub(crate) async fn set_adc_label(channel: usize, value: AdcLabel) {
+    let mut labels = adc_labels();
+    if str::from_utf8(&value).is_err() {
+        ble_debug!("ADC channel label must be UTF-8");
+        let _ = SERVER.get().adc.description_set(channel, &labels.labels[channel]);
+        return;
+    }
+
+    labels.labels[channel] = if value.is_empty() { default_labels().labels[channel].clone() } else { value };
+    if labels == adc_labels() {
+        return;
+    }
+
+    if let Err(err) = FLASH_MANAGER.get().write_setting(&labels).await {
+        info!("Failed to persist ADC channel labels: {:?}", err);
+    }
+    apply_adc_labels(labels);
+}
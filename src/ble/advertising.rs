@@ -0,0 +1,133 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+use heapless::Vec;
+use nrf_softdevice::ble::{Address, Connection, Phy, PhySet, set_device_identities_list, set_whitelist};
+use nrf_softdevice::ble::peripheral::FilterPolicy;
+use nrf_softdevice::Softdevice;
+
+use crate::common::ble::BONDER;
+use crate::common::ble::security::BOND_STORE_SIGNAL;
+use crate::common::device::config::{BLE_EXTENDED_ADV_DATA_LEN, BLE_MAX_BONDS, BLE_PAIRING_WINDOW};
+
+static ACCEPT_LIST_ONLY: AtomicBool = AtomicBool::new(false);
+static CODED_PHY: AtomicBool = AtomicBool::new(false);
+static PAIRING_WINDOW_END: Mutex<ThreadModeRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+/// Makes the advertising loop drop the current advertisement and start over with a fresh policy
+pub(crate) static ADVERTISING_RESTART: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+pub(crate) fn is_accept_list_only() -> bool {
+    ACCEPT_LIST_ONLY.load(Ordering::Relaxed)
+}
+
+pub(crate) fn is_coded_phy() -> bool {
+    CODED_PHY.load(Ordering::Relaxed)
+}
+
+/// Used when restoring the flags from flash, does not trigger persistence
+pub(crate) fn restore_advertising_policy(accept_list_only: bool, coded_phy: bool) {
+    ACCEPT_LIST_ONLY.store(accept_list_only, Ordering::Relaxed);
+    CODED_PHY.store(coded_phy, Ordering::Relaxed);
+}
+
+pub(crate) fn set_accept_list_only(value: bool) {
+    if ACCEPT_LIST_ONLY.swap(value, Ordering::Relaxed) != value {
+        info!("Accept list only advertising: {}", value);
+        BOND_STORE_SIGNAL.signal(());
+        ADVERTISING_RESTART.signal(());
+    }
+}
+
+/// Switches between legacy advertising on 1M PHY and extended advertising on LE Coded PHY
+pub(crate) fn set_coded_phy(value: bool) {
+    if CODED_PHY.swap(value, Ordering::Relaxed) != value {
+        info!("Coded PHY advertising: {}", value);
+        BOND_STORE_SIGNAL.signal(());
+        ADVERTISING_RESTART.signal(());
+    }
+}
+
+/// Coded PHY advertising uses S8 coding: 4 times the range of 1M PHY at 125 kbps.
+/// Centrals that do not support Coded PHY will not see the device at all in this mode.
+pub(crate) fn advertising_phys() -> (Phy, Phy) {
+    if is_coded_phy() {
+        (Phy::Coded, Phy::Coded)
+    } else {
+        (Phy::M1, Phy::M1)
+    }
+}
+
+/// Extended connectable advertisements cannot be scanned, so the scan response goes into
+/// the advertising data, which is not limited to 31 bytes in this case
+pub(crate) fn extended_adv_data(adv_data: &[u8], scan_data: &[u8]) -> Vec<u8, BLE_EXTENDED_ADV_DATA_LEN> {
+    let mut data: Vec<u8, BLE_EXTENDED_ADV_DATA_LEN> = Vec::new();
+    let _ = data.extend_from_slice(adv_data);
+    let _ = data.extend_from_slice(scan_data);
+    data
+}
+
+/// Asks the central to switch the connection to the given PHYs,
+/// a bitmask of 1 - LE 1M, 2 - LE 2M, 4 - LE Coded
+pub(crate) fn request_connection_phy(connection: &Connection, phys: u8) {
+    let phy_set = match phys & 0b111 {
+        0b001 => PhySet::M1,
+        0b010 => PhySet::M2,
+        0b011 => PhySet::M1M2,
+        0b100 => PhySet::Coded,
+        0b101 => PhySet::M1Coded,
+        0b110 => PhySet::M2Coded,
+        0b111 => PhySet::M1M2Coded,
+        _ => {
+            info!("Unsupported connection PHY {}", phys);
+            return;
+        }
+    };
+
+    let mut connection = connection.clone();
+    if let Err(err) = connection.phy_update(phy_set, phy_set) {
+        info!("Failed to request PHY update: {:?}", err);
+    }
+}
+
+/// Temporarily lets any central connect, so a new one can pair while accept-list mode is on
+pub(crate) fn open_pairing_window() {
+    info!("Opening pairing window");
+    PAIRING_WINDOW_END.lock(|end| end.set(Some(Instant::now() + BLE_PAIRING_WINDOW)));
+    ADVERTISING_RESTART.signal(());
+}
+
+/// Loads bonded identities into the softdevice accept list and returns the filter policy for the
+/// next advertisement, along with the instant when the policy must be re-evaluated.
+/// Without any bonds the accept list would lock everyone out, so the filter is not applied.
+pub(crate) fn prepare_filter_policy(sd: &Softdevice) -> (FilterPolicy, Option<Instant>) {
+    let now = Instant::now();
+    let pairing_window_end = PAIRING_WINDOW_END.lock(|end| end.get()).filter(|end| *end > now);
+
+    if !is_accept_list_only() || pairing_window_end.is_some() {
+        return (FilterPolicy::Default, pairing_window_end);
+    }
+
+    let identities = BONDER.identities();
+    if identities.is_empty() {
+        info!("Accept list only mode is on, but there are no bonds");
+        return (FilterPolicy::Default, None);
+    }
+
+    let addresses: Vec<Address, BLE_MAX_BONDS> = identities.iter().map(|identity| identity.addr).collect();
+    let result = set_device_identities_list(sd, &identities, None)
+        .and_then(|_| set_whitelist(sd, &addresses));
+
+    match result {
+        Ok(()) => (FilterPolicy::Both, None),
+        Err(err) => {
+            info!("Failed to set accept list: {:?}", err);
+            (FilterPolicy::Default, None)
+        }
+    }
+}
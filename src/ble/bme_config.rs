@@ -0,0 +1,103 @@
+use core::cell::Cell;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use nrf_softdevice::ble::Connection;
+
+use crate::ble_debug;
+use crate::common::ble::{FLASH_MANAGER, SERVER};
+use crate::common::device::bme280::{
+    BME280_CONFIGURATION_LEN, Configuration, ConfigurationError, IIRFilter, Oversampling, StandbyTime,
+};
+use crate::common::device::config::BLE_BME_CONFIG_LEN;
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::bme_config_storage::BmeSettings;
+
+const _: () = assert!(BLE_BME_CONFIG_LEN == BME280_CONFIGURATION_LEN);
+
+/// Configuration result codes, negative ones reject the write
+const RESULT_APPLIED: i8 = 0;
+/// Applied, but lost on the next reboot
+const RESULT_NOT_PERSISTED: i8 = -6;
+
+fn result_code(err: ConfigurationError) -> i8 {
+    match err {
+        ConfigurationError::InvalidOversampling => -1,
+        ConfigurationError::InvalidFilter => -2,
+        ConfigurationError::InvalidStandby => -3,
+        ConfigurationError::TemperatureSkipped => -4,
+        ConfigurationError::NothingMeasured => -5,
+    }
+}
+
+/// None until a configuration has been written or restored
+static CONFIGURATION: Mutex<ThreadModeRawMutex, Cell<Option<Configuration>>> = Mutex::new(Cell::new(None));
+/// Picked up by the BME task, which reconfigures the running sensor
+pub(crate) static BME_CONFIG_CHANGED: Signal<ThreadModeRawMutex, Configuration> = Signal::new();
+
+/// Suits weather logging: a conversion every ~1.06 s, so the IIR filter settles
+fn default_configuration() -> Configuration {
+    Configuration::default()
+        .with_humidity_oversampling(Oversampling::Oversampling8X)
+        .with_temperature_oversampling(Oversampling::Oversampling8X)
+        .with_pressure_oversampling(Oversampling::Oversampling8X)
+        .with_iir_filter(IIRFilter::Coefficient8)
+        .with_standby(StandbyTime::Ms1000)
+}
+
+pub(crate) fn bme_configuration() -> Configuration {
+    CONFIGURATION.lock(|configuration| configuration.get()).unwrap_or_else(default_configuration)
+}
+
+fn reflect_configuration() {
+    if let Err(err) = SERVER.get().bme280.config_set(&bme_configuration().to_bits()) {
+        info!("Failed to set BME configuration {:?}", err);
+    }
+}
+
+fn report(result: i8) {
+    let bme280 = &SERVER.get().bme280;
+    let _ = bme280.config_result_set(&result);
+    for connection in Connection::iter() {
+        // fails for the connections that have not subscribed
+        let _ = bme280.config_result_notify(&connection, &result);
+    }
+}
+
+pub(crate) async fn restore_bme_config() -> Result<(), FlashManagerError> {
+    let settings: Option<BmeSettings> = FLASH_MANAGER.get().read_setting().await?;
+    if let Some(settings) = settings {
+        CONFIGURATION.lock(|configuration| configuration.set(Some(settings.configuration)));
+    }
+    reflect_configuration();
+
+    Ok(())
+}
+
+/// Applies the configuration to the running sensor and persists it; an invalid one is replaced
+/// with the configuration in effect
+pub(crate) async fn set_bme_config(bits: [u8; BLE_BME_CONFIG_LEN]) {
+    let configuration = match Configuration::from_bits(bits) {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            ble_debug!("Rejected BME configuration: {}", err);
+            reflect_configuration();
+            report(result_code(err));
+            return;
+        }
+    };
+
+    CONFIGURATION.lock(|current| current.set(Some(configuration)));
+    BME_CONFIG_CHANGED.signal(configuration);
+    reflect_configuration();
+
+    match FLASH_MANAGER.get().write_setting(&BmeSettings { configuration }).await {
+        Ok(()) => report(RESULT_APPLIED),
+        Err(err) => {
+            info!("Failed to persist BME settings: {:?}", err);
+            report(RESULT_NOT_PERSISTED);
+        }
+    }
+}
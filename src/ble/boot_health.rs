@@ -0,0 +1,109 @@
+use defmt::info;
+use embassy_boot::State;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use nrf_softdevice::raw;
+
+use crate::common::ble::{FLASH_MANAGER, SERVER};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::boot_health_storage::{BootHealthRecord, Rollback, RollbackReason};
+use crate::{ble_error, ble_info};
+
+/// POWER.RESETREAS: reset from the watchdog
+const RESET_REASON_DOG: u32 = 1 << 1;
+
+/// Signalled on every established connection, the last step of a healthy boot
+pub(crate) static BOOT_CONNECTED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+#[derive(Copy, Clone, defmt::Format)]
+#[repr(u8)]
+pub(crate) enum BootState {
+    Confirmed = 0,
+    Pending = 1,
+    /// The previous image is running again after the new one failed
+    RolledBack = 2,
+}
+
+/// The reason register accumulates until cleared, so it is read once per boot
+pub(crate) fn take_reset_reason() -> u32 {
+    let mut reason = 0u32;
+    unsafe {
+        raw::sd_power_reset_reason_get(&mut reason);
+        raw::sd_power_reset_reason_clr(reason);
+    }
+    reason
+}
+
+/// [state: u8][rollback reason: u8][reset reason: u32]
+fn publish(state: BootState, record: &BootHealthRecord, reset_reason: u32) {
+    let mut value = [0u8; 6];
+    value[0] = state as u8;
+    if let Some(rollback) = record.rollback {
+        value[1] = rollback.reason as u8;
+    }
+    value[2..6].copy_from_slice(&reset_reason.to_le_bytes());
+
+    if let Err(err) = SERVER.get().diagnostics.boot_state_set(&value) {
+        info!("Failed to set boot state {:?}", err);
+    }
+}
+
+/// The bootloader swaps a new image in once and reverts it on the next reset unless it is marked booted;
+/// returns the record if the running image still has to be confirmed
+pub(crate) async fn check_boot_health(reset_reason: u32) -> Result<Option<BootHealthRecord>, FlashManagerError> {
+    if !cfg!(feature = "dfu-bootloader") {
+        // the stock bootloader neither swaps nor reverts images, the running one is the only one
+        publish(BootState::Confirmed, &BootHealthRecord::default(), reset_reason);
+        return Ok(None);
+    }
+
+    let flash_manager = FLASH_MANAGER.get();
+    let mut record = flash_manager.read_boot_health().await?;
+    let state = flash_manager.dfu_boot_state().await?;
+    info!("Boot health: {:?}, bootloader state: {:?}, reset reason: {:x}", record, state, reset_reason);
+
+    if state == State::Swap {
+        publish(BootState::Pending, &record, reset_reason);
+        ble_info!("Pending image, waiting for a connection to confirm it");
+        return Ok(Some(record));
+    }
+
+    if record.pending {
+        // the image that requested the update is back: the new one reset before it was confirmed, and the reset
+        // reason register still holds the cause, nothing has cleared it since
+        let reason = if reset_reason & RESET_REASON_DOG != 0 {
+            RollbackReason::Watchdog
+        } else {
+            RollbackReason::Reset
+        };
+        ble_error!("Rolled back a new image: {:?}", reason);
+        record.pending = false;
+        record.rollback = Some(Rollback { reason });
+        flash_manager.confirm_boot(record.rollback).await?;
+        publish(BootState::RolledBack, &record, reset_reason);
+        return Ok(None);
+    }
+
+    publish(BootState::Confirmed, &record, reset_reason);
+    Ok(None)
+}
+
+/// The softdevice, the sensor tasks and advertising are started before the first connection
+#[embassy_executor::task]
+pub(crate) async fn confirm_boot_task(mut record: BootHealthRecord, reset_reason: u32) {
+    BOOT_CONNECTED.wait().await;
+
+    let flash_manager = FLASH_MANAGER.get();
+    // the bootloader first, the pending token left behind would report a rollback that never happened
+    if let Err(err) = flash_manager.mark_dfu_booted().await {
+        ble_error!("Failed to mark the running image booted: {:?}", err);
+        return;
+    }
+    if let Err(err) = flash_manager.confirm_boot(record.rollback).await {
+        ble_error!("Failed to confirm the running image: {:?}", err);
+        return;
+    }
+    record.pending = false;
+    publish(BootState::Confirmed, &record, reset_reason);
+    ble_info!("Confirmed the running image");
+}
@@ -0,0 +1,235 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU8, Ordering};
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+use futures::{FutureExt, select_biased};
+use heapless::Vec;
+use nrf_softdevice::{raw, Softdevice};
+use nrf_softdevice::ble::get_address;
+
+use crate::{ble_debug, ble_error};
+use crate::common::ble::{BROADCAST_SERVICE_EVENTS, FLASH_MANAGER, SERVER, update_external_sensor_demand};
+use crate::common::ble::advertising::ADVERTISING_RESTART;
+use crate::common::ble::bthome_payload::{ADV_DATA_LEN, BlockCipher, BroadcastReadings, build_adv_data, Encryption};
+use crate::common::ble::services::BroadcastServiceEvent;
+use crate::common::ble::snapshot::{SNAPSHOT, Snapshot};
+use crate::common::device::config::{BTHOME_KEY_LEN, BTHOME_MIN_REBUILD_INTERVAL};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::broadcast_storage::BroadcastSettings;
+
+/// The next block is reserved while this many counters of the current one are left
+const COUNTER_RESERVE_MARGIN: u32 = 64;
+
+#[derive(Copy, Clone, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub(crate) enum BroadcastMode {
+    Off = 0,
+    Plain = 1,
+    Encrypted = 2,
+}
+
+impl BroadcastMode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Off),
+            1 => Some(Self::Plain),
+            2 => Some(Self::Encrypted),
+            _ => None,
+        }
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(BroadcastMode::Off as u8);
+static KEY: Mutex<ThreadModeRawMutex, Cell<[u8; BTHOME_KEY_LEN]>> = Mutex::new(Cell::new([0; BTHOME_KEY_LEN]));
+/// BTHome encryption counter, handed out from blocks of 2^16 that are persisted as used before the first one
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+/// End of the reserved block, 0 until a block is reserved
+static COUNTER_LIMIT: AtomicU32 = AtomicU32::new(0);
+/// Upper half of the next block nobody has used with the current key
+static COUNTER_BASE: AtomicU16 = AtomicU16::new(0);
+static COUNTER_RESERVE: Signal<ThreadModeRawMutex, ()> = Signal::new();
+static PACKET_ID: AtomicU8 = AtomicU8::new(0);
+static LAST_REBUILD: Mutex<ThreadModeRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+fn mode() -> BroadcastMode {
+    BroadcastMode::from_u8(MODE.load(Ordering::Relaxed)).unwrap_or(BroadcastMode::Off)
+}
+
+pub(crate) fn is_broadcast_enabled() -> bool {
+    mode() != BroadcastMode::Off
+}
+
+fn current_settings() -> BroadcastSettings {
+    BroadcastSettings {
+        mode: MODE.load(Ordering::Relaxed),
+        counter_base: COUNTER_BASE.load(Ordering::Relaxed),
+        key: KEY.lock(|key| key.get()),
+    }
+}
+
+/// None if no block is reserved or it is used up, the broadcast task reserves the next one ahead of time
+fn next_counter() -> Option<u32> {
+    let limit = COUNTER_LIMIT.load(Ordering::Relaxed);
+    let counter =
+        COUNTER.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |counter| (counter < limit).then_some(counter + 1));
+    if limit != 0 && counter.map_or(true, |counter| counter + COUNTER_RESERVE_MARGIN >= limit) {
+        COUNTER_RESERVE.signal(());
+    }
+    counter.ok()
+}
+
+/// Persists the next block as used before handing it out, so neither a reboot nor a mode switch
+/// can repeat a nonce; once the blocks run out nothing is encrypted until a new key is written
+async fn reserve_counters() -> Result<(), FlashManagerError> {
+    let base = COUNTER_BASE.load(Ordering::Relaxed);
+    let Some(next) = base.checked_add(1) else {
+        COUNTER_LIMIT.store(0, Ordering::Relaxed);
+        ble_error!("BTHome encryption counters are used up, the key has to be replaced");
+        return Ok(());
+    };
+
+    COUNTER_BASE.store(next, Ordering::Relaxed);
+    FLASH_MANAGER.get().write_setting(&current_settings()).await?;
+    COUNTER.store((base as u32) << 16, Ordering::Relaxed);
+    COUNTER_LIMIT.store((next as u32) << 16, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Sensor tasks report new readings through the snapshot store; the advertisement is restarted
+/// with fresh service data, but not more often than BTHOME_MIN_REBUILD_INTERVAL
+pub(crate) fn on_readings_updated() {
+    if !is_broadcast_enabled() {
+        return;
+    }
+
+    let last_rebuild = LAST_REBUILD.lock(|last| last.get());
+    if last_rebuild.map_or(true, |last| last.elapsed() >= BTHOME_MIN_REBUILD_INTERVAL) {
+        ADVERTISING_RESTART.signal(());
+    }
+}
+
+/// AES-128 on the ECB peripheral, which is owned by the softdevice
+struct SoftdeviceEcb {
+    key: [u8; BTHOME_KEY_LEN],
+}
+
+impl BlockCipher for SoftdeviceEcb {
+    fn encrypt_block(&self, block: &[u8; 16]) -> Option<[u8; 16]> {
+        let mut data = raw::nrf_ecb_hal_data_t { key: self.key, cleartext: *block, ciphertext: [0; 16] };
+        let ret = unsafe { raw::sd_ecb_block_encrypt(&mut data) };
+        (ret == raw::NRF_SUCCESS).then_some(data.ciphertext)
+    }
+}
+
+fn broadcast_readings(snapshot: &Snapshot) -> BroadcastReadings {
+    BroadcastReadings {
+        battery: snapshot.battery,
+        bme: snapshot.bme,
+        illuminance: snapshot.color.map(|color| color[5]),
+    }
+}
+
+/// Builds the advertising data with BTHome v2 service data from the latest readings,
+/// returns None if broadcasting is off or cannot be done
+pub(crate) fn prepare_broadcast_adv_data(sd: &Softdevice) -> Option<Vec<u8, ADV_DATA_LEN>> {
+    let mode = mode();
+    if mode == BroadcastMode::Off {
+        return None;
+    }
+    LAST_REBUILD.lock(|last| last.set(Some(Instant::now())));
+
+    let readings = broadcast_readings(&SNAPSHOT.latest());
+    let packet_id = PACKET_ID.fetch_add(1, Ordering::Relaxed);
+    if mode == BroadcastMode::Plain {
+        return build_adv_data::<SoftdeviceEcb>(&readings, packet_id, None);
+    }
+
+    let key = KEY.lock(|key| key.get());
+    if key == [0; BTHOME_KEY_LEN] {
+        info!("BTHome encryption is on, but the key is not set");
+        return None;
+    }
+    let Some(counter) = next_counter() else {
+        info!("No BTHome encryption counter is reserved");
+        return None;
+    };
+
+    let cipher = SoftdeviceEcb { key };
+    let encryption = Encryption { cipher: &cipher, address: get_address(sd).bytes(), counter };
+    let adv_data = build_adv_data(&readings, packet_id, Some(encryption));
+    if adv_data.is_none() {
+        ble_error!("BTHome encryption failed");
+    }
+    adv_data
+}
+
+pub(crate) async fn restore_broadcast_settings() -> Result<(), FlashManagerError> {
+    let settings: BroadcastSettings = FLASH_MANAGER.get().read_setting().await?.unwrap_or_else(|| {
+        info!("Broadcast settings are not initialized");
+        BroadcastSettings::default()
+    });
+    MODE.store(settings.mode, Ordering::Relaxed);
+    KEY.lock(|key| key.set(settings.key));
+    COUNTER_BASE.store(settings.counter_base, Ordering::Relaxed);
+
+    if let Err(err) = SERVER.get().broadcast.mode_set(&settings.mode) {
+        info!("Failed to set broadcast mode {:?}", err);
+    }
+
+    // the counters of the previous boot are never known to be unused, a page write per boot is cheap enough
+    reserve_counters().await
+}
+
+#[embassy_executor::task]
+pub(crate) async fn read_broadcast_events_channel() {
+    loop {
+        let (_connection, event) = select_biased! {
+            event = BROADCAST_SERVICE_EVENTS.receive().fuse() => event,
+            _ = COUNTER_RESERVE.wait().fuse() => {
+                if let Err(err) = reserve_counters().await {
+                    info!("Failed to reserve BTHome encryption counters: {:?}", err);
+                }
+                continue;
+            }
+        };
+
+        let mut reserve = false;
+        match event {
+            BroadcastServiceEvent::ModeWrite(value) => {
+                let Some(mode) = BroadcastMode::from_u8(value) else {
+                    ble_debug!("Unsupported broadcast mode {}", value);
+                    let _ = SERVER.get().broadcast.mode_set(&MODE.load(Ordering::Relaxed));
+                    continue;
+                };
+                info!("Broadcast mode: {:?}", mode);
+                reserve = mode == BroadcastMode::Encrypted && self::mode() != BroadcastMode::Encrypted;
+                MODE.store(mode as u8, Ordering::Relaxed);
+            }
+            BroadcastServiceEvent::KeyWrite(value) => {
+                // the counters start over with a new key, a key must never be used again
+                if KEY.lock(|key| key.replace(value)) != value {
+                    COUNTER_BASE.store(0, Ordering::Relaxed);
+                    reserve = true;
+                }
+            }
+        }
+
+        // reserving persists the rest of the settings along with the counter base
+        let persisted = if reserve {
+            reserve_counters().await
+        } else {
+            FLASH_MANAGER.get().write_setting(&current_settings()).await
+        };
+        if let Err(err) = persisted {
+            info!("Failed to persist broadcast settings: {:?}", err);
+        }
+
+        update_external_sensor_demand().await;
+        ADVERTISING_RESTART.signal(());
+    }
+}
@@ -0,0 +1,353 @@
+//! BTHome v2 advertising data, built from the readings and encrypted with AES-CCM on top of any AES-128
+//! block cipher; the firmware runs it on the ECB peripheral of the softdevice, see `bthome`.
+
+use heapless::Vec;
+
+pub(crate) const ADV_DATA_LEN: usize = 31;
+
+const ADV_FLAGS_LE_ONLY_GENERAL_DISC_MODE: u8 = 0x06;
+const AD_TYPE_FLAGS: u8 = 0x01;
+const AD_TYPE_SERVICE_DATA: u8 = 0x16;
+
+const BTHOME_UUID: [u8; 2] = [0xD2, 0xFC];
+const BTHOME_VERSION_2: u8 = 2 << 5;
+const BTHOME_ENCRYPTED: u8 = 1;
+
+const FLAGS_LEN: usize = 3;
+/// [len][service data type][uuid: 2][device information]
+const SERVICE_DATA_HEADER_LEN: usize = 5;
+const MIC_LEN: usize = 4;
+/// [counter: u32][mic: 4]
+const ENCRYPTION_OVERHEAD: usize = 4 + MIC_LEN;
+const NONCE_LEN: usize = 13;
+const BLOCK_LEN: usize = 16;
+
+const OBJECT_PACKET_ID: u8 = 0x00;
+const OBJECT_BATTERY: u8 = 0x01;
+const OBJECT_TEMPERATURE: u8 = 0x02;
+const OBJECT_HUMIDITY: u8 = 0x03;
+const OBJECT_PRESSURE: u8 = 0x04;
+const OBJECT_ILLUMINANCE: u8 = 0x05;
+const OBJECT_VOLTAGE: u8 = 0x0C;
+
+pub(crate) trait BlockCipher {
+    /// AES-128 of a single block with the key of the cipher, None if the block could not be encrypted
+    fn encrypt_block(&self, block: &[u8; BLOCK_LEN]) -> Option<[u8; BLOCK_LEN]>;
+}
+
+/// The broadcast readings, in the encoding of the snapshot
+#[derive(Copy, Clone, Default)]
+pub(crate) struct BroadcastReadings {
+    /// 1/64 V, %
+    pub(crate) battery: Option<(u16, u8)>,
+    /// 0.01 °C, 0.01 %, 0.1 Pa
+    pub(crate) bme: Option<(i16, u16, u32)>,
+    /// lux
+    pub(crate) illuminance: Option<u16>,
+}
+
+/// Keys the encryption of a single advertisement
+pub(crate) struct Encryption<'a, C: BlockCipher> {
+    pub(crate) cipher: &'a C,
+    /// As the softdevice reports it, least significant byte first
+    pub(crate) address: [u8; 6],
+    pub(crate) counter: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Object {
+    id: u8,
+    value: [u8; 3],
+    len: usize,
+}
+
+impl Object {
+    fn new(id: u8, bytes: &[u8]) -> Self {
+        let mut value = [0u8; 3];
+        value[..bytes.len()].copy_from_slice(bytes);
+        Self { id, value, len: bytes.len() }
+    }
+
+    fn u24(id: u8, value: u32) -> Self {
+        Self::new(id, &value.to_le_bytes()[..3])
+    }
+
+    fn encoded_len(&self) -> usize {
+        1 + self.len
+    }
+}
+
+/// Objects in the order of importance: whatever does not fit into the advertisement is dropped
+fn collect_objects(readings: &BroadcastReadings, packet_id: Option<u8>) -> Vec<Object, 8> {
+    let mut objects: Vec<Object, 8> = Vec::new();
+
+    if let Some(packet_id) = packet_id {
+        let _ = objects.push(Object::new(OBJECT_PACKET_ID, &[packet_id]));
+    }
+    if let Some((_, level)) = readings.battery {
+        let _ = objects.push(Object::new(OBJECT_BATTERY, &[level]));
+    }
+    if let Some((temperature, humidity, pressure)) = readings.bme {
+        // both are already in 0.01 units
+        let _ = objects.push(Object::new(OBJECT_TEMPERATURE, &temperature.to_le_bytes()));
+        let _ = objects.push(Object::new(OBJECT_HUMIDITY, &humidity.to_le_bytes()));
+        // 0.1 Pa -> 0.01 hPa
+        let _ = objects.push(Object::u24(OBJECT_PRESSURE, pressure / 10));
+    }
+    if let Some(illuminance) = readings.illuminance {
+        // lux -> 0.01 lux
+        let _ = objects.push(Object::u24(OBJECT_ILLUMINANCE, illuminance as u32 * 100));
+    }
+    if let Some((voltage, _)) = readings.battery {
+        // 1/64 V -> mV
+        let millivolts = (voltage as u32 * 1000 / 64) as u16;
+        let _ = objects.push(Object::new(OBJECT_VOLTAGE, &millivolts.to_le_bytes()));
+    }
+
+    objects
+}
+
+/// The objects that fit into `budget` bytes, in the ascending order of their ids that receivers expect
+fn fit_objects(objects: Vec<Object, 8>, mut budget: usize) -> Vec<Object, 8> {
+    let mut fitting: Vec<Object, 8> = Vec::new();
+    for object in objects {
+        if object.encoded_len() <= budget {
+            budget -= object.encoded_len();
+            let _ = fitting.push(object);
+        }
+    }
+    fitting.sort_unstable_by_key(|object| object.id);
+    fitting
+}
+
+fn nonce(address: &[u8; 6], device_info: u8, counter: u32) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    // the MAC address goes in the most significant byte first order
+    nonce[0..6].iter_mut().zip(address.iter().rev()).for_each(|(nonce, byte)| *nonce = *byte);
+    nonce[6..8].copy_from_slice(&BTHOME_UUID);
+    nonce[8] = device_info;
+    nonce[9..13].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+/// Flags and BTHome service data; the packet id is left out of encrypted advertisements, the counter makes it
+/// redundant. None if the encryption fails.
+pub(crate) fn build_adv_data<C: BlockCipher>(
+    readings: &BroadcastReadings,
+    packet_id: u8,
+    encryption: Option<Encryption<C>>,
+) -> Option<Vec<u8, ADV_DATA_LEN>> {
+    let encrypted = encryption.is_some();
+    let device_info = BTHOME_VERSION_2 | if encrypted { BTHOME_ENCRYPTED } else { 0 };
+
+    let mut budget = ADV_DATA_LEN - FLAGS_LEN - SERVICE_DATA_HEADER_LEN;
+    if encrypted {
+        budget -= ENCRYPTION_OVERHEAD;
+    }
+    let objects = collect_objects(readings, (!encrypted).then_some(packet_id));
+
+    let mut payload: Vec<u8, ADV_DATA_LEN> = Vec::new();
+    for object in fit_objects(objects, budget).iter() {
+        let _ = payload.push(object.id);
+        let _ = payload.extend_from_slice(&object.value[..object.len]);
+    }
+
+    if let Some(Encryption { cipher, address, counter }) = encryption {
+        let nonce = nonce(&address, device_info, counter);
+        let mic: [u8; MIC_LEN] = ccm_encrypt(cipher, &nonce, &[], &mut payload)?;
+        let _ = payload.extend_from_slice(&counter.to_le_bytes());
+        let _ = payload.extend_from_slice(&mic);
+    }
+
+    let mut adv_data: Vec<u8, ADV_DATA_LEN> = Vec::new();
+    let _ = adv_data.extend_from_slice(&[0x02, AD_TYPE_FLAGS, ADV_FLAGS_LE_ONLY_GENERAL_DISC_MODE]);
+    let _ = adv_data.extend_from_slice(&[
+        (payload.len() + SERVICE_DATA_HEADER_LEN - 1) as u8,
+        AD_TYPE_SERVICE_DATA,
+        BTHOME_UUID[0],
+        BTHOME_UUID[1],
+        device_info,
+    ]);
+    let _ = adv_data.extend_from_slice(&payload);
+
+    Some(adv_data)
+}
+
+/// AES-CCM (RFC 3610) with a 13 byte nonce and an `M` byte MIC; the associated data must fit into the first
+/// block. The payload is encrypted in place, the MIC is returned.
+fn ccm_encrypt<C: BlockCipher, const M: usize>(
+    cipher: &C,
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    payload: &mut [u8],
+) -> Option<[u8; M]> {
+    // L = 2 bytes for the message length
+    let length_field_flags = 0x01u8;
+    let aad_flag = if aad.is_empty() { 0 } else { 1 << 6 };
+
+    let mut b0 = [0u8; BLOCK_LEN];
+    b0[0] = aad_flag | ((((M - 2) / 2) as u8) << 3) | length_field_flags;
+    b0[1..14].copy_from_slice(nonce);
+    b0[14..16].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+
+    let mut mac = cipher.encrypt_block(&b0)?;
+    if !aad.is_empty() {
+        let mut b1 = [0u8; BLOCK_LEN];
+        b1[0..2].copy_from_slice(&(aad.len() as u16).to_be_bytes());
+        b1[2..2 + aad.len()].copy_from_slice(aad);
+        mac.iter_mut().zip(b1).for_each(|(mac, byte)| *mac ^= byte);
+        mac = cipher.encrypt_block(&mac)?;
+    }
+    for chunk in payload.chunks(BLOCK_LEN) {
+        mac.iter_mut().zip(chunk).for_each(|(mac, byte)| *mac ^= byte);
+        mac = cipher.encrypt_block(&mac)?;
+    }
+
+    let mut counter_block = [0u8; BLOCK_LEN];
+    counter_block[0] = length_field_flags;
+    counter_block[1..14].copy_from_slice(nonce);
+
+    for (index, chunk) in payload.chunks_mut(BLOCK_LEN).enumerate() {
+        counter_block[14..16].copy_from_slice(&((index + 1) as u16).to_be_bytes());
+        let stream = cipher.encrypt_block(&counter_block)?;
+        chunk.iter_mut().zip(stream).for_each(|(byte, stream)| *byte ^= stream);
+    }
+
+    counter_block[14..16].copy_from_slice(&0u16.to_be_bytes());
+    let stream = cipher.encrypt_block(&counter_block)?;
+
+    let mut mic = [0u8; M];
+    mic.iter_mut().zip(mac.iter().zip(stream)).for_each(|(mic, (mac, stream))| *mic = mac ^ stream);
+    Some(mic)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes::Aes128;
+    use aes::cipher::{BlockEncrypt, KeyInit};
+
+    use super::*;
+
+    struct SoftwareAes(Aes128);
+
+    impl SoftwareAes {
+        fn new(key: &[u8; 16]) -> Self {
+            Self(Aes128::new(key.into()))
+        }
+    }
+
+    impl BlockCipher for SoftwareAes {
+        fn encrypt_block(&self, block: &[u8; BLOCK_LEN]) -> Option<[u8; BLOCK_LEN]> {
+            let mut block = (*block).into();
+            self.0.encrypt_block(&mut block);
+            Some(block.into())
+        }
+    }
+
+    fn hex(text: &str) -> std::vec::Vec<u8> {
+        (0..text.len()).step_by(2).map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap()).collect()
+    }
+
+    const RFC_3610_KEY: [u8; 16] = [
+        0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xCB, 0xCC, 0xCD, 0xCE, 0xCF,
+    ];
+
+    /// Packet vectors #1 and #2 have an 8 byte MIC, #9 a 10 byte one
+    #[test]
+    fn rfc_3610_packet_vectors() {
+        let cipher = SoftwareAes::new(&RFC_3610_KEY);
+
+        let nonce = hex("00000003020100a0a1a2a3a4a5").try_into().unwrap();
+        let mut payload = hex("08090a0b0c0d0e0f101112131415161718191a1b1c1d1e");
+        let mic: [u8; 8] = ccm_encrypt(&cipher, &nonce, &hex("0001020304050607"), &mut payload).unwrap();
+        assert_eq!(payload, hex("588c979a61c663d2f066d0c2c0f989806d5f6b61dac384"));
+        assert_eq!(mic.as_slice(), hex("17e8d12cfdf926e0"));
+
+        let nonce = hex("00000004030201a0a1a2a3a4a5").try_into().unwrap();
+        let mut payload = hex("08090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let mic: [u8; 8] = ccm_encrypt(&cipher, &nonce, &hex("0001020304050607"), &mut payload).unwrap();
+        assert_eq!(payload, hex("72c91a36e135f8cf291ca894085c87e3cc15c439c9e43a3b"));
+        assert_eq!(mic.as_slice(), hex("a091d56e10400916"));
+
+        let nonce = hex("00000009080706a0a1a2a3a4a5").try_into().unwrap();
+        let mut payload = hex("08090a0b0c0d0e0f101112131415161718191a1b1c1d1e");
+        let mic: [u8; 10] = ccm_encrypt(&cipher, &nonce, &hex("0001020304050607"), &mut payload).unwrap();
+        assert_eq!(payload, hex("0135d1b2c95f41d5d1d4fec185d166b8094e999dfed96c"));
+        assert_eq!(mic.as_slice(), hex("048c56602c97acbb7490"));
+    }
+
+    /// The example of the BTHome encryption docs: 25.06 °C and 50.55 % from 54:48:E6:8F:80:A5
+    #[test]
+    fn bthome_example() {
+        let cipher = SoftwareAes::new(&hex("231d39c1d7cc1ab1aee224cd096db932").try_into().unwrap());
+        let address = [0xA5, 0x80, 0x8F, 0xE6, 0x48, 0x54];
+        let nonce = nonce(&address, BTHOME_VERSION_2 | BTHOME_ENCRYPTED, 0x3322_1100);
+        assert_eq!(nonce.as_slice(), hex("5448e68f80a5d2fc4100112233"));
+
+        let mut payload = hex("02ca0903bf13");
+        let mic: [u8; MIC_LEN] = ccm_encrypt(&cipher, &nonce, &[], &mut payload).unwrap();
+        assert_eq!(payload, hex("a47266c95f73"));
+        assert_eq!(mic.as_slice(), hex("78237214"));
+    }
+
+    #[test]
+    fn encrypted_adv_data() {
+        let cipher = SoftwareAes::new(&hex("231d39c1d7cc1ab1aee224cd096db932").try_into().unwrap());
+        let readings = BroadcastReadings { bme: Some((2506, 5055, 1_013_250)), ..Default::default() };
+        let address = [0xA5, 0x80, 0x8F, 0xE6, 0x48, 0x54];
+        let encryption = Encryption { cipher: &cipher, address, counter: 0x3322_1100 };
+
+        let adv_data = build_adv_data(&readings, 7, Some(encryption)).unwrap();
+        assert_eq!(adv_data.as_slice(), hex("0201061616d2fc41a47266c95f7305a104d900112233735cee7b"));
+    }
+
+    #[test]
+    fn plain_adv_data() {
+        let readings = BroadcastReadings { bme: Some((-1250, 5055, 1_013_250)), ..Default::default() };
+
+        let adv_data = build_adv_data::<SoftwareAes>(&readings, 7, None).unwrap();
+        assert_eq!(adv_data.as_slice(), hex("0201061016d2fc400007021efb03bf1304cd8b01"));
+    }
+
+    #[test]
+    fn collect_objects_scales_the_readings() {
+        let readings = BroadcastReadings {
+            battery: Some((200, 87)),
+            bme: Some((2506, 5055, 1_013_250)),
+            illuminance: Some(1234),
+        };
+        let objects = collect_objects(&readings, Some(3));
+        let expected = [
+            Object::new(OBJECT_PACKET_ID, &[3]),
+            Object::new(OBJECT_BATTERY, &[87]),
+            Object::new(OBJECT_TEMPERATURE, &2506i16.to_le_bytes()),
+            Object::new(OBJECT_HUMIDITY, &5055u16.to_le_bytes()),
+            Object::u24(OBJECT_PRESSURE, 101_325),
+            Object::u24(OBJECT_ILLUMINANCE, 123_400),
+            Object::new(OBJECT_VOLTAGE, &3125u16.to_le_bytes()),
+        ];
+        assert_eq!(objects.as_slice(), expected.as_slice());
+
+        assert!(collect_objects(&BroadcastReadings::default(), None).is_empty());
+    }
+
+    #[test]
+    fn objects_that_do_not_fit_are_dropped() {
+        let readings = BroadcastReadings {
+            battery: Some((200, 87)),
+            bme: Some((2506, 5055, 1_013_250)),
+            illuminance: Some(1234),
+        };
+
+        // all of them fit unencrypted
+        let ids = |objects: Vec<Object, 8>| objects.iter().map(|object| object.id).collect::<std::vec::Vec<_>>();
+        let budget = ADV_DATA_LEN - FLAGS_LEN - SERVICE_DATA_HEADER_LEN;
+        let objects = fit_objects(collect_objects(&readings, Some(3)), budget);
+        assert_eq!(ids(objects), [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x0C]);
+
+        // the illuminance does not fit next to the counter and the MIC, the voltage still does
+        let budget = budget - ENCRYPTION_OVERHEAD;
+        let objects = fit_objects(collect_objects(&readings, None), budget);
+        assert_eq!(ids(objects), [0x01, 0x02, 0x03, 0x04, 0x0C]);
+    }
+}
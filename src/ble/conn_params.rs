@@ -0,0 +1,276 @@
+use alloc::collections::BTreeMap;
+use core::cell::RefCell;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Instant, Timer};
+use futures::{FutureExt, select_biased};
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::raw::ble_gap_conn_params_t;
+
+use crate::{ble_debug, ble_warn};
+use crate::common::ble::SPI_EXPANDER_LOCK_OWNER;
+use crate::common::device::config::{
+    CONN_PARAMS_IDLE_TIMEOUT, CONN_PARAMS_NEGOTIATION_TIMEOUT, CONN_PARAMS_POLL_INTERVAL,
+    CONN_PARAMS_REJECT_BACKOFF,
+};
+
+/// Connection parameters in the softdevice units: intervals in 1.25 ms, supervision timeout in 10 ms.
+/// Every set satisfies both the spec (timeout > (1 + latency) * max interval * 2) and the Apple
+/// accessory guidelines (min interval >= 15 ms, max interval >= min interval + 15 ms,
+/// max interval * (1 + latency) <= 2 s and * 3 < timeout, 2 s <= timeout <= 6 s).
+const fn params(min_interval: u16, max_interval: u16, latency: u16, timeout: u16) -> ble_gap_conn_params_t {
+    ble_gap_conn_params_t {
+        min_conn_interval: min_interval,
+        max_conn_interval: max_interval,
+        slave_latency: latency,
+        conn_sup_timeout: timeout,
+    }
+}
+
+/// 15 - 30 ms, then 30 - 45 ms; iOS rejects anything below 15 ms
+const FAST: [ble_gap_conn_params_t; 2] = [params(12, 24, 0, 400), params(24, 36, 0, 400)];
+/// 30 - 50 ms, then 50 - 100 ms
+const BALANCED: [ble_gap_conn_params_t; 2] = [params(24, 40, 0, 400), params(40, 80, 0, 400)];
+/// 400 - 500 ms with 2 skipped events, then 100 - 200 ms with 4 skipped events
+const LOW_POWER: [ble_gap_conn_params_t; 2] = [params(320, 400, 2, 600), params(80, 160, 4, 600)];
+
+const _: () = assert!(is_compliant(&FAST) && is_compliant(&BALANCED) && is_compliant(&LOW_POWER));
+
+/// The rules above in microseconds
+const fn is_compliant(sets: &[ble_gap_conn_params_t]) -> bool {
+    let mut index = 0;
+    while index < sets.len() {
+        let min_interval = sets[index].min_conn_interval as u32 * 1250;
+        let max_interval = sets[index].max_conn_interval as u32 * 1250;
+        let period = max_interval * (1 + sets[index].slave_latency as u32);
+        let timeout = sets[index].conn_sup_timeout as u32 * 10_000;
+        if min_interval < 15_000
+            || max_interval < min_interval + 15_000
+            || period > 2_000_000
+            || period * 3 >= timeout
+            || timeout < 2_000_000
+            || timeout > 6_000_000
+        {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub(crate) enum ConnectionProfile {
+    /// Fast during service discovery, expander sessions and other GATT activity, low power when idle
+    Auto = 0,
+    Fast = 1,
+    Balanced = 2,
+    LowPower = 3,
+}
+
+impl ConnectionProfile {
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Auto),
+            1 => Some(Self::Fast),
+            2 => Some(Self::Balanced),
+            3 => Some(Self::LowPower),
+            _ => None,
+        }
+    }
+
+    /// The first set is requested first, the rest are fallbacks in case the central rejects it
+    fn candidates(self) -> &'static [ble_gap_conn_params_t] {
+        match self {
+            Self::Auto | Self::Fast => &FAST,
+            Self::Balanced => &BALANCED,
+            Self::LowPower => &LOW_POWER,
+        }
+    }
+}
+
+struct ConnectionParamsState {
+    /// Requested by the client, Auto unless written
+    requested: ConnectionProfile,
+    last_activity: Instant,
+    /// The concrete profile the policy wants right now, never Auto
+    target: Option<ConnectionProfile>,
+    /// Index of the candidate being negotiated or in use
+    candidate: usize,
+    /// Set while waiting for the central to answer the update request
+    negotiation_deadline: Option<Instant>,
+    /// Set after all the candidates have been rejected
+    backoff_until: Option<Instant>,
+}
+
+impl ConnectionParamsState {
+    fn new(now: Instant) -> Self {
+        Self {
+            requested: ConnectionProfile::Auto,
+            last_activity: now,
+            target: None,
+            candidate: 0,
+            negotiation_deadline: None,
+            backoff_until: None,
+        }
+    }
+
+    fn desired_profile(&self, is_expander_owner: bool, now: Instant) -> ConnectionProfile {
+        match self.requested {
+            ConnectionProfile::Auto if is_expander_owner => ConnectionProfile::Fast,
+            ConnectionProfile::Auto if now.duration_since(self.last_activity) < CONN_PARAMS_IDLE_TIMEOUT => {
+                ConnectionProfile::Fast
+            }
+            ConnectionProfile::Auto => ConnectionProfile::LowPower,
+            profile => profile,
+        }
+    }
+
+    fn evaluate(&mut self, connection: &Connection, is_expander_owner: bool, now: Instant) {
+        let desired = self.desired_profile(is_expander_owner, now);
+        if self.target != Some(desired) {
+            self.target = Some(desired);
+            self.candidate = 0;
+            self.negotiation_deadline = None;
+            self.backoff_until = None;
+            self.request(connection, now);
+            return;
+        }
+
+        if self.backoff_until.is_some_and(|until| now >= until) {
+            self.backoff_until = None;
+            self.candidate = 0;
+            self.request(connection, now);
+            return;
+        }
+
+        let Some(deadline) = self.negotiation_deadline else {
+            return;
+        };
+        if now < deadline {
+            return;
+        }
+        self.negotiation_deadline = None;
+
+        let candidates = desired.candidates();
+        if is_accepted(&connection.conn_params(), &candidates[self.candidate]) {
+            info!("Connection parameters accepted: {:?}, candidate {}", desired, self.candidate);
+            return;
+        }
+
+        self.candidate += 1;
+        if self.candidate < candidates.len() {
+            info!("Connection parameters rejected, trying a fallback for {:?}", desired);
+            self.request(connection, now);
+        } else {
+            ble_warn!("Central rejected all connection parameters for {:?}", desired);
+            self.candidate = 0;
+            self.backoff_until = Some(now + CONN_PARAMS_REJECT_BACKOFF);
+        }
+    }
+
+    fn request(&mut self, connection: &Connection, now: Instant) {
+        let Some(target) = self.target else {
+            return;
+        };
+
+        match connection.set_conn_params(target.candidates()[self.candidate]) {
+            Ok(()) => self.negotiation_deadline = Some(now + CONN_PARAMS_NEGOTIATION_TIMEOUT),
+            Err(err) => {
+                // most likely another procedure is in progress; retried on the next poll
+                info!("Failed to request connection params {:?}", err);
+                self.target = None;
+            }
+        }
+    }
+}
+
+/// The central is free to pick any interval within the requested range
+fn is_accepted(current: &ble_gap_conn_params_t, requested: &ble_gap_conn_params_t) -> bool {
+    (requested.min_conn_interval..=requested.max_conn_interval).contains(&current.max_conn_interval)
+        && current.slave_latency == requested.slave_latency
+}
+
+pub(crate) struct ConnectionParamsManager {
+    states: Mutex<ThreadModeRawMutex, RefCell<BTreeMap<Connection, ConnectionParamsState>>>,
+    update: Signal<ThreadModeRawMutex, ()>,
+}
+
+impl ConnectionParamsManager {
+    pub(crate) const fn new() -> Self {
+        Self { states: Mutex::new(RefCell::new(BTreeMap::new())), update: Signal::new() }
+    }
+
+    pub(crate) fn register_connection(&self, connection: &Connection) {
+        self.states.lock(|states| {
+            states.borrow_mut().insert(connection.clone(), ConnectionParamsState::new(Instant::now()));
+        });
+        self.update.signal(());
+    }
+
+    pub(crate) fn drop_connection(&self, connection: &Connection) {
+        self.states.lock(|states| states.borrow_mut().remove(connection));
+    }
+
+    /// Any GATT event keeps an Auto connection on the fast parameters
+    pub(crate) fn touch(&self, connection: &Connection) {
+        let was_idle = self.states.lock(|states| {
+            let mut states = states.borrow_mut();
+            let Some(state) = states.get_mut(connection) else {
+                return false;
+            };
+            let was_idle = state.last_activity.elapsed() >= CONN_PARAMS_IDLE_TIMEOUT;
+            state.last_activity = Instant::now();
+            was_idle
+        });
+        if was_idle {
+            self.update.signal(());
+        }
+    }
+
+    pub(crate) fn request_profile(&self, connection: &Connection, value: u8) {
+        let Some(profile) = ConnectionProfile::from_u8(value) else {
+            ble_debug!("Unsupported connection profile {}", value);
+            return;
+        };
+        info!("Connection profile requested: {:?}", profile);
+        self.states.lock(|states| {
+            if let Some(state) = states.borrow_mut().get_mut(connection) {
+                state.requested = profile;
+            }
+        });
+        self.update.signal(());
+    }
+
+    pub(crate) fn requested_profile(&self, connection: &Connection) -> Option<ConnectionProfile> {
+        self.states.lock(|states| states.borrow().get(connection).map(|state| state.requested))
+    }
+
+    /// The expander lock owner stays on the fast parameters for the whole session
+    fn evaluate(&self, expander_owner: Option<&Connection>) {
+        let now = Instant::now();
+        self.states.lock(|states| {
+            for (connection, state) in states.borrow_mut().iter_mut() {
+                state.evaluate(connection, expander_owner == Some(connection), now);
+            }
+        });
+    }
+}
+
+pub(crate) static CONN_PARAMS: ConnectionParamsManager = ConnectionParamsManager::new();
+
+#[embassy_executor::task]
+pub(crate) async fn manage_connection_params_task() {
+    loop {
+        let expander_owner = SPI_EXPANDER_LOCK_OWNER.lock().await.clone();
+        CONN_PARAMS.evaluate(expander_owner.as_ref());
+
+        select_biased! {
+            _ = CONN_PARAMS.update.wait().fuse() => {}
+            _ = Timer::after(CONN_PARAMS_POLL_INTERVAL).fuse() => {}
+        }
+    }
+}
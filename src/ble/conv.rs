@@ -1,5 +1,10 @@
 use num_traits::float::FloatCore;
 
+use crate::common::ble::encoding::{
+    ABSOLUTE_HUMIDITY, Encoding, HUMIDITY, ILLUMINANCE, LUMINOUS_FLUX, PRESSURE, PRESSURE_ALTITUDE, PRESSURE_TENDENCY,
+    TEMPERATURE, TEMPERATURE_SINT8, VOLTAGE,
+};
+
 pub trait BleScalarReprExt {
     fn ble_serialize(&self, multiplier: i32, decimal_exponent: i32, binary_exponent: i32) -> Self;
     fn ble_deserialize(&self, multiplier: i32, decimal_exponent: i32, binary_exponent: i32)
@@ -33,36 +38,65 @@ impl BleScalarReprExt for f32 {
     }
 }
 
+fn encode(value: f32, encoding: &Encoding) -> f32 {
+    value.ble_serialize(encoding.multiplier, encoding.decimal_exponent, encoding.binary_exponent)
+}
+
 pub trait ConvExt {
     fn as_voltage(&self) -> u16;
     fn as_temp(&self) -> i16;
+    fn as_temp_sint8(&self) -> i8;
     fn as_pressure(&self) -> u32;
+    fn as_pressure_tendency(&self) -> i16;
+    fn as_pressure_altitude(&self) -> i32;
     fn as_humidity(&self) -> u16;
+    fn as_absolute_humidity(&self) -> u16;
     fn as_luminous_flux(&self) -> u16;
+    fn as_illuminance(&self) -> u32;
 }
 
+/// Encodings are defined in `encoding`, the exported GATT schema is generated from the same definitions
 impl ConvExt for f32 {
     fn as_voltage(&self) -> u16 {
-        // Represented values: M = 1, d = 0, b = -6
-        self.ble_serialize(1, 0, -6) as u16
+        encode(*self, &VOLTAGE) as u16
     }
+
     fn as_temp(&self) -> i16 {
-        //  M = 1, d = -2, b = 0
-        self.ble_serialize(1, -2, 0) as i16
+        encode(*self, &TEMPERATURE) as i16
+    }
+
+    fn as_temp_sint8(&self) -> i8 {
+        // whole degrees, truncating would be off by up to a degree
+        encode(*self, &TEMPERATURE_SINT8).round() as i8
     }
 
     fn as_pressure(&self) -> u32 {
-        // M = 1, d = -1, b = 0
-        self.ble_serialize(1, -1, 0) as u32
+        encode(*self, &PRESSURE) as u32
+    }
+
+    fn as_pressure_tendency(&self) -> i16 {
+        encode(*self, &PRESSURE_TENDENCY).round() as i16
+    }
+
+    fn as_pressure_altitude(&self) -> i32 {
+        encode(*self, &PRESSURE_ALTITUDE) as i32
     }
 
     fn as_humidity(&self) -> u16 {
-        // M = 1, d = -2, b = 0
-        self.ble_serialize(1, -2, 0) as u16
+        encode(*self, &HUMIDITY) as u16
+    }
+
+    /// From g/m³
+    fn as_absolute_humidity(&self) -> u16 {
+        encode(*self / 1000.0, &ABSOLUTE_HUMIDITY) as u16
     }
 
     fn as_luminous_flux(&self) -> u16 {
-        //  M = 1, d = 0, b = 0
-        self.ble_serialize(1, 0, 0) as u16
+        encode(*self, &LUMINOUS_FLUX) as u16
+    }
+
+    fn as_illuminance(&self) -> u32 {
+        // uint24
+        encode(*self, &ILLUMINANCE) as u32
     }
 }
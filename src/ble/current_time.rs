@@ -0,0 +1,101 @@
+use defmt::info;
+use embassy_time::Timer;
+use nrf_softdevice::ble::Connection;
+
+use crate::{ble_debug, ble_info};
+use crate::common::ble::SERVER;
+use crate::common::device::config::{BLE_CURRENT_TIME_LEN, CLOCK_UPDATE_INTERVAL};
+use crate::common::device::ui::DISPLAY_REFRESH_EVENTS;
+use crate::common::device::ui::controls::DisplayRefreshType;
+use crate::common::util::wall_clock::{ADJUST_MANUAL, CalendarTime, CLOCK};
+
+/// Reads the Current Time of the central right after it connects
+#[cfg(feature = "ble-gatt-client")]
+#[nrf_softdevice::gatt_client(uuid = "1805")]
+pub(crate) struct CurrentTimeServiceClient {
+    #[characteristic(uuid = "2A2B", read, notify)]
+    current_time: [u8; BLE_CURRENT_TIME_LEN],
+}
+
+fn reflect_current_time() -> Option<[u8; BLE_CURRENT_TIME_LEN]> {
+    let now = CLOCK.now()?;
+    let value = now.to_current_time(CLOCK.adjust_reason());
+    if let Err(err) = SERVER.get().cts.current_time_set(&value) {
+        info!("Failed to set current time {:?}", err);
+    }
+    Some(value)
+}
+
+/// Current Time notifications are sent on adjustments only, not as the clock ticks; peers that have not
+/// subscribed reject them
+fn notify_current_time(value: &[u8; BLE_CURRENT_TIME_LEN]) {
+    let server = SERVER.get();
+    for connection in Connection::iter() {
+        if let Err(err) = server.cts.current_time_notify(&connection, value) {
+            ble_debug!("Current time not notified: {:?}", err);
+        }
+    }
+}
+
+/// Anchors the wall clock and notifies the subscribed peers, `adjust_reason` tells where the time came from
+pub(crate) fn set_time(unix_ms: u64, adjust_reason: u8) {
+    let was_set = CLOCK.is_set();
+    match CLOCK.sync(unix_ms, adjust_reason) {
+        Some(error) => ble_debug!("Clock synced, error {} ms, drift {} ppb", error, CLOCK.drift_ppb().unwrap_or(0)),
+        None => ble_info!("Clock set to {}", CalendarTime::from_unix_millis(unix_ms)),
+    }
+    if let Some(value) = reflect_current_time() {
+        notify_current_time(&value);
+    }
+    if !was_set {
+        let _ = DISPLAY_REFRESH_EVENTS.try_send(DisplayRefreshType::Partial);
+    }
+}
+
+/// Current Time write; an invalid date is replaced with the time in effect
+pub(crate) fn handle_current_time_write(data: &[u8]) {
+    match CalendarTime::parse_current_time(data).and_then(|(time, _)| time.to_unix_millis()) {
+        Some(unix_ms) => set_time(unix_ms, ADJUST_MANUAL),
+        None => {
+            ble_debug!("Invalid current time");
+            let _ = reflect_current_time();
+        }
+    }
+}
+
+#[cfg(feature = "ble-gatt-client")]
+pub(crate) async fn sync_time_from_central(connection: &Connection) {
+    use nrf_softdevice::ble::gatt_client;
+
+    use crate::common::util::wall_clock::ADJUST_EXTERNAL_REFERENCE;
+
+    let client: CurrentTimeServiceClient = match gatt_client::discover(connection).await {
+        Ok(client) => client,
+        Err(err) => {
+            info!("Central has no Current Time Service: {:?}", err);
+            return;
+        }
+    };
+    let value = match client.current_time_read().await {
+        Ok(value) => value,
+        Err(err) => {
+            ble_debug!("Failed to read the central's current time: {:?}", err);
+            return;
+        }
+    };
+    match CalendarTime::parse_current_time(&value).and_then(|(time, _)| time.to_unix_millis()) {
+        Some(unix_ms) => set_time(unix_ms, ADJUST_EXTERNAL_REFERENCE),
+        None => ble_debug!("Invalid current time from the central"),
+    }
+}
+
+/// Keeps the Current Time characteristic close to the wall clock while anyone may read it
+#[embassy_executor::task]
+pub(crate) async fn current_time_task() {
+    loop {
+        if Connection::iter().next().is_some() {
+            let _ = reflect_current_time();
+        }
+        Timer::after(CLOCK_UPDATE_INTERVAL).await;
+    }
+}
@@ -0,0 +1,37 @@
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+use nrf_softdevice::ble::gatt_server::SetValueError;
+
+use crate::common::ble::services::BleServer;
+use crate::common::device::config::{
+    BLE_DIS_STRING_LEN, DEVICE_HARDWARE_REVISION, DEVICE_MANUFACTURER_NAME, DEVICE_MODEL_NUMBER,
+};
+
+/// `SHBLE_GIT_HASH` is provided by build.rs
+pub(crate) const FIRMWARE_REVISION: &str = concat!(env!("CARGO_PKG_VERSION"), "-", env!("SHBLE_GIT_HASH"));
+
+/// 64-bit unique device identifier from FICR, most significant word first
+pub(crate) fn serial_number() -> String<16> {
+    let ficr = unsafe { &*nrf52840_pac::FICR::ptr() };
+    let high = ficr.deviceid[1].read().bits();
+    let low = ficr.deviceid[0].read().bits();
+
+    let mut serial = String::new();
+    let _ = write!(serial, "{:08X}{:08X}", high, low);
+    serial
+}
+
+fn to_value(text: &str) -> Vec<u8, BLE_DIS_STRING_LEN> {
+    let len = text.len().min(BLE_DIS_STRING_LEN);
+    Vec::from_slice(&text.as_bytes()[..len]).unwrap_or_default()
+}
+
+pub(crate) fn populate_device_information(server: &BleServer) -> Result<(), SetValueError> {
+    server.dis.manufacturer_name_set(&to_value(DEVICE_MANUFACTURER_NAME))?;
+    server.dis.model_number_set(&to_value(DEVICE_MODEL_NUMBER))?;
+    server.dis.serial_number_set(&to_value(&serial_number()))?;
+    server.dis.hardware_revision_set(&to_value(DEVICE_HARDWARE_REVISION))?;
+    server.dis.firmware_revision_set(&to_value(FIRMWARE_REVISION))?;
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use embassy_time::{Duration, Timer};
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::Flash;
+
+use crate::common::ble::{DFU_SERVICE_EVENTS, FLASH_MANAGER, SERVER};
+use crate::common::ble::services::DfuServiceEvent;
+use crate::common::device::dfu::updater::{DFU_PUBLIC_KEY_LEN, DfuError, DfuImage, DfuUpdater};
+use crate::common::device::persistence::dfu_transfer_storage::DfuTransferRecord;
+use crate::common::device::persistence::flash_manager::FlashPartition;
+use crate::{ble_debug, ble_error, ble_info};
+
+/// Ed25519 key the images are signed with, `SHBLE_DFU_PUBLIC_KEY` at build time
+static DFU_PUBLIC_KEY: &[u8; DFU_PUBLIC_KEY_LEN] = include_bytes!(concat!(env!("OUT_DIR"), "/dfu_public_key.bin"));
+
+const APPLY_RESET_DELAY: Duration = Duration::from_millis(500);
+
+/// Control point opcodes, followed by the arguments
+#[repr(u8)]
+enum DfuCommand {
+    /// [size: u32][sha256: 32][ed25519 signature: 64]
+    Start = 1,
+    Verify = 2,
+    /// Marks the image for the bootloader and resets, the image must be verified; needs the `dfu-bootloader` feature
+    Apply = 3,
+    Abort = 4,
+}
+
+impl DfuCommand {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Start),
+            2 => Some(Self::Verify),
+            3 => Some(Self::Apply),
+            4 => Some(Self::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// [state: u8][result: u8, 0 or `DfuError`][offset: u32]
+fn status(updater: &DfuUpdater<FlashPartition<Flash>>, result: Result<(), DfuError>) -> [u8; 6] {
+    let mut status = [0u8; 6];
+    status[0] = updater.state() as u8;
+    status[1] = match result {
+        Ok(()) => 0,
+        Err(err) => err as u8,
+    };
+    status[2..6].copy_from_slice(&updater.offset().to_le_bytes());
+    status
+}
+
+fn report(connection: &Connection, status: &[u8; 6]) {
+    let server = SERVER.get();
+    if server.dfu.status_notify(connection, status).is_err() {
+        let _ = server.dfu.status_set(status);
+    }
+}
+
+async fn execute(updater: &mut DfuUpdater<FlashPartition<Flash>>, command: &[u8]) -> Result<(), DfuError> {
+    let Some((&opcode, args)) = command.split_first() else {
+        return Err(DfuError::InvalidCommand);
+    };
+    match DfuCommand::from_u8(opcode) {
+        Some(DfuCommand::Start) => {
+            let image = DfuImage::deserialize(args).ok_or(DfuError::InvalidCommand)?;
+            let record = DfuTransferRecord { image: image.clone(), pages: 0 };
+            let offset = updater.start(image)?;
+            if offset == 0 {
+                FLASH_MANAGER.get().write_setting(&record).await.map_err(|_| DfuError::Flash)?;
+            }
+            ble_info!("DFU start: {} bytes, resuming at {}", record.image.size, offset);
+        }
+        Some(DfuCommand::Verify) => {
+            if let Err(err) = updater.verify().await {
+                ble_error!("DFU image rejected: {}", err);
+                return Err(err);
+            }
+            ble_info!("DFU image verified");
+        }
+        Some(DfuCommand::Apply) => {
+            updater.ensure_verified()?;
+            if !cfg!(feature = "dfu-bootloader") {
+                // the stock bootloader ignores the state page, the update would "succeed" without being installed
+                ble_error!("DFU apply rejected: the bootloader can't swap images");
+                return Err(DfuError::NoBootloader);
+            }
+            let flash_manager = FLASH_MANAGER.get();
+            // the new image has to confirm itself, see `boot_health`
+            flash_manager.mark_boot_pending().await.map_err(|_| DfuError::Flash)?;
+            flash_manager.mark_dfu_updated().await.map_err(|_| DfuError::Flash)?;
+            // the DFU partition holds the previous image after the swap, there is nothing to resume
+            flash_manager.clear_dfu_transfer().await.map_err(|_| DfuError::Flash)?;
+            ble_info!("DFU image marked for the swap, resetting");
+        }
+        Some(DfuCommand::Abort) => {
+            updater.abort();
+            FLASH_MANAGER.get().clear_dfu_transfer().await.map_err(|_| DfuError::Flash)?;
+        }
+        None => return Err(DfuError::InvalidCommand),
+    }
+    Ok(())
+}
+
+/// Programs the markers of the pages the chunk has completed, a reboot resumes the transfer after them
+async fn persist_progress(updater: &DfuUpdater<FlashPartition<Flash>>, completed_pages: u32) {
+    for page in completed_pages..updater.completed_pages() {
+        if let Err(err) = FLASH_MANAGER.get().record_dfu_progress(page as usize).await {
+            ble_error!("Failed to persist DFU progress: {:?}", err);
+            return;
+        }
+    }
+}
+
+/// A transfer interrupted by a reboot continues from its last completely written page
+async fn restore_transfer(updater: &mut DfuUpdater<FlashPartition<Flash>>) {
+    let record: Option<DfuTransferRecord> = match FLASH_MANAGER.get().read_setting().await {
+        Ok(record) => record,
+        Err(err) => {
+            ble_error!("Failed to read the DFU transfer: {:?}", err);
+            return;
+        }
+    };
+    if let Some(record) = record {
+        match updater.restore(record.image, record.pages) {
+            Ok(offset) => ble_info!("DFU transfer restored at {}", offset),
+            Err(err) => ble_error!("Failed to restore the DFU transfer: {}", err),
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub(crate) async fn dfu_task() {
+    // the transfer survives reconnects and reboots, a client resumes from the reported offset
+    let mut updater = DfuUpdater::new(FLASH_MANAGER.get().dfu_partition(), *DFU_PUBLIC_KEY);
+    restore_transfer(&mut updater).await;
+
+    loop {
+        let (connection, event) = DFU_SERVICE_EVENTS.receive().await;
+        let mut apply = false;
+        let result = match event {
+            DfuServiceEvent::ControlWrite(command) => {
+                apply = command.first() == Some(&(DfuCommand::Apply as u8));
+                execute(&mut updater, &command).await
+            }
+            DfuServiceEvent::DataWrite(chunk) if chunk.len() >= 4 => {
+                let offset = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                let completed_pages = updater.completed_pages();
+                let result = updater.write(offset, &chunk[4..]).await.map(|_| ());
+                persist_progress(&updater, completed_pages).await;
+                result
+            }
+            DfuServiceEvent::DataWrite(_) => Err(DfuError::InvalidCommand),
+            DfuServiceEvent::StatusCccdWrite { .. } => continue,
+        };
+
+        if let Err(err) = result {
+            ble_debug!("DFU error: {}", err);
+        }
+        report(&connection, &status(&updater, result));
+
+        if apply && result.is_ok() {
+            // let the status notification leave the queue
+            Timer::after(APPLY_RESET_DELAY).await;
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Wire encodings of the characteristic values, R = C * M * 10^d * 2^b.
+//! `ConvExt`, `gatt_table` and the Presentation Format descriptors of `services` encode with these and the build
+//! script includes this file to export the GATT schema, so it must not depend on anything outside `core`.
+//! The Characteristic Presentation Format (0x2904) has no binary exponent: the 0x2B18 based voltages are
+//! described as uint16 volts with `d` = 0, clients take the `b` = -6 from the 0x2B18 definition or the schema.
+
+/// Value format as named in the Characteristic Presentation Format
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Format {
+    Uint8,
+    Sint8,
+    Uint16,
+    Sint16,
+    Uint24,
+    Uint32,
+    Sint32,
+    Uint64,
+    Float32,
+    Utf8,
+    Bytes,
+}
+
+impl Format {
+    pub(crate) const fn name(self) -> &'static str {
+        match self {
+            Format::Uint8 => "uint8",
+            Format::Sint8 => "sint8",
+            Format::Uint16 => "uint16",
+            Format::Sint16 => "sint16",
+            Format::Uint24 => "uint24",
+            Format::Uint32 => "uint32",
+            Format::Sint32 => "sint32",
+            Format::Uint64 => "uint64",
+            Format::Float32 => "float32",
+            Format::Utf8 => "utf8",
+            Format::Bytes => "bytes",
+        }
+    }
+
+    /// Value length in bytes, None for text and raw data
+    pub(crate) const fn len(self) -> Option<usize> {
+        match self {
+            Format::Uint8 | Format::Sint8 => Some(1),
+            Format::Uint16 | Format::Sint16 => Some(2),
+            Format::Uint24 => Some(3),
+            Format::Uint32 | Format::Sint32 | Format::Float32 => Some(4),
+            Format::Uint64 => Some(8),
+            Format::Utf8 | Format::Bytes => None,
+        }
+    }
+
+    /// Format field of the Characteristic Presentation Format, raw data is an opaque `struct`
+    pub(crate) const fn gatt_format(self) -> u8 {
+        match self {
+            Format::Uint8 => 0x04,
+            Format::Sint8 => 0x0C,
+            Format::Uint16 => 0x06,
+            Format::Sint16 => 0x0E,
+            Format::Uint24 => 0x07,
+            Format::Uint32 => 0x08,
+            Format::Sint32 => 0x10,
+            Format::Uint64 => 0x0A,
+            Format::Float32 => 0x14,
+            Format::Utf8 => 0x19,
+            Format::Bytes => 0x1B,
+        }
+    }
+}
+
+/// Bluetooth SIG assigned units
+pub(crate) const UNIT_UNITLESS: u16 = 0x2700;
+pub(crate) const UNIT_METRE: u16 = 0x2701;
+pub(crate) const UNIT_SECOND: u16 = 0x2703;
+pub(crate) const UNIT_KELVIN: u16 = 0x2705;
+pub(crate) const UNIT_KILOGRAM_PER_CUBIC_METRE: u16 = 0x2715;
+pub(crate) const UNIT_PASCAL: u16 = 0x2724;
+pub(crate) const UNIT_VOLT: u16 = 0x2728;
+pub(crate) const UNIT_CELSIUS: u16 = 0x272F;
+pub(crate) const UNIT_LUMEN: u16 = 0x2730;
+pub(crate) const UNIT_LUX: u16 = 0x2731;
+pub(crate) const UNIT_PERCENTAGE: u16 = 0x27AD;
+
+/// Namespace of the Presentation Format description field
+pub(crate) const NAMESPACE_BLUETOOTH_SIG: u8 = 0x01;
+pub(crate) const DESCRIPTION_UNKNOWN: u16 = 0x0000;
+pub(crate) const PRESENTATION_FORMAT_LEN: usize = 7;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Encoding {
+    pub(crate) format: Format,
+    pub(crate) multiplier: i32,
+    pub(crate) decimal_exponent: i32,
+    pub(crate) binary_exponent: i32,
+    /// Unit of the represented value, empty for text and raw data
+    pub(crate) unit: &'static str,
+    /// Assigned number of the unit, `UNIT_UNITLESS` if there is none
+    pub(crate) gatt_unit: u16,
+}
+
+impl Encoding {
+    const fn scalar(
+        format: Format,
+        decimal_exponent: i32,
+        binary_exponent: i32,
+        unit: &'static str,
+        gatt_unit: u16,
+    ) -> Self {
+        Self { format, multiplier: 1, decimal_exponent, binary_exponent, unit, gatt_unit }
+    }
+
+    /// [format][exponent: i8][unit: u16][namespace][description: u16], `description` tells
+    /// apart characteristics of the same kind, e.g. 1 - "first", 2 - "second"
+    pub(crate) const fn presentation_format(&self, description: u16) -> [u8; PRESENTATION_FORMAT_LEN] {
+        let unit = self.gatt_unit.to_le_bytes();
+        let description = description.to_le_bytes();
+        [
+            self.format.gatt_format(),
+            self.decimal_exponent as i8 as u8,
+            unit[0],
+            unit[1],
+            NAMESPACE_BLUETOOTH_SIG,
+            description[0],
+            description[1],
+        ]
+    }
+}
+
+/// Declares the encodings and `ENCODINGS`, which the GATT schema resolves the Presentation Format
+/// descriptors of `services` with, by the name of the constant
+macro_rules! encodings {
+    ($($(#[$meta:meta])* $name:ident = $encoding:expr;)+) => {
+        $(
+            $(#[$meta])*
+            pub(crate) const $name: Encoding = $encoding;
+        )+
+
+        pub(crate) const ENCODINGS: &[(&str, Encoding)] = &[$((stringify!($name), $name)),+];
+    };
+}
+
+encodings! {
+    /// 0x2B18 Voltage, 1/64 V
+    VOLTAGE = Encoding::scalar(Format::Uint16, 0, -6, "V", UNIT_VOLT);
+    /// 0x2A6E Temperature
+    TEMPERATURE = Encoding::scalar(Format::Sint16, -2, 0, "°C", UNIT_CELSIUS);
+    /// 0x2A7B Dew Point and 0x2A7A Heat Index of the ESS, whole degrees
+    TEMPERATURE_SINT8 = Encoding::scalar(Format::Sint8, 0, 0, "°C", UNIT_CELSIUS);
+    /// 0x2A6D Pressure
+    PRESSURE = Encoding::scalar(Format::Uint32, -1, 0, "Pa", UNIT_PASCAL);
+    /// Pressure change over 3 hours in 10 Pa, i.e. 0.1 hPa as in the synoptic reports
+    PRESSURE_TENDENCY = Encoding::scalar(Format::Sint16, 1, 0, "Pa", UNIT_PASCAL);
+    /// Reference altitude of the sea-level pressure, whole metres
+    ALTITUDE = Encoding::scalar(Format::Sint16, 0, 0, "m", UNIT_METRE);
+    /// Barometric altitude in centimetres
+    PRESSURE_ALTITUDE = Encoding::scalar(Format::Sint32, -2, 0, "m", UNIT_METRE);
+    /// 0x2A6F Humidity
+    HUMIDITY = Encoding::scalar(Format::Uint16, -2, 0, "%", UNIT_PERCENTAGE);
+    /// Absolute humidity in 0.01 g/m³
+    ABSOLUTE_HUMIDITY = Encoding::scalar(Format::Uint16, -5, 0, "kg/m³", UNIT_KILOGRAM_PER_CUBIC_METRE);
+    /// 0x2AFF Luminous Flux
+    LUMINOUS_FLUX = Encoding::scalar(Format::Uint16, 0, 0, "lm", UNIT_LUMEN);
+    /// 0x2AFB Illuminance
+    ILLUMINANCE = Encoding::scalar(Format::Uint24, -2, 0, "lx", UNIT_LUX);
+    /// 0x2AE9 Correlated Color Temperature
+    CCT = Encoding::scalar(Format::Uint16, 0, 0, "K", UNIT_KELVIN);
+    /// Raw VEML6040 channel counts
+    COLOR_COUNT = Encoding::scalar(Format::Uint16, 0, 0, "", UNIT_UNITLESS);
+    /// Accelerometer axes are sent as they come from the driver; there is no assigned unit for g
+    ACCELERATION = Encoding::scalar(Format::Float32, 0, 0, "g", UNIT_UNITLESS);
+    PERCENTAGE = Encoding::scalar(Format::Uint8, 0, 0, "%", UNIT_PERCENTAGE);
+    TIMEOUT_MS = Encoding::scalar(Format::Uint32, -3, 0, "s", UNIT_SECOND);
+    INTERVAL_S = Encoding::scalar(Format::Uint32, 0, 0, "s", UNIT_SECOND);
+    ELAPSED_US = Encoding::scalar(Format::Uint64, -6, 0, "s", UNIT_SECOND);
+    COUNT = Encoding::scalar(Format::Uint16, 0, 0, "", UNIT_UNITLESS);
+    /// Calibration offsets are little endian f32 in the units of the calibrated reading
+    TEMPERATURE_OFFSET = Encoding::scalar(Format::Float32, 0, 0, "°C", UNIT_CELSIUS);
+    HUMIDITY_OFFSET = Encoding::scalar(Format::Float32, 0, 0, "%", UNIT_PERCENTAGE);
+    PRESSURE_OFFSET = Encoding::scalar(Format::Float32, 0, 0, "Pa", UNIT_PASCAL);
+    /// Flags, modes and opcodes
+    ENUMERATION = Encoding::scalar(Format::Uint8, 0, 0, "", UNIT_UNITLESS);
+    RESULT_CODE = Encoding::scalar(Format::Sint8, 0, 0, "", UNIT_UNITLESS);
+    TEXT = Encoding::scalar(Format::Utf8, 0, 0, "", UNIT_UNITLESS);
+    /// Packed records, the layout is in the characteristic documentation
+    OPAQUE = Encoding::scalar(Format::Bytes, 0, 0, "", UNIT_UNITLESS);
+}
@@ -0,0 +1,450 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_time::{Duration, Instant};
+use nrf_softdevice::ble::{Connection, SecurityMode, Uuid};
+use nrf_softdevice::ble::gatt_server::{self, NotifyValueError, RegisterError, Service, SetValueError};
+use nrf_softdevice::ble::gatt_server::builder::ServiceBuilder;
+use nrf_softdevice::ble::gatt_server::characteristic::Attribute;
+use nrf_softdevice::Softdevice;
+
+use crate::common::ble::ESS_EVENT_PROCESSOR;
+use crate::common::ble::conv::ConvExt;
+use crate::common::ble::event_processor::EventProcessor;
+use crate::common::ble::gatt_builder::{add_characteristic, gatt_uuid};
+use crate::common::ble::gatt_table::{
+    CharacteristicSpec, ES_MEASUREMENT_UUID, ES_TRIGGER_SETTING_UUID, ESS_SERVICE, VALID_RANGE_UUID,
+};
+use crate::common::device::bme280::{
+    BME280_HUMIDITY_MAX, BME280_HUMIDITY_MIN, BME280_PRESSURE_MAX, BME280_PRESSURE_MIN, BME280_TEMP_MAX,
+    BME280_TEMP_MIN,
+};
+use crate::common::device::veml6040::VEML6040_MAX_LUX;
+use crate::notify_all;
+
+/// ES Measurement: sampling function
+const SAMPLING_INSTANTANEOUS: u8 = 0x01;
+const SAMPLING_ARITHMETIC_MEAN: u8 = 0x02;
+/// ES Measurement: application
+const APPLICATION_AIR: u8 = 0x01;
+
+const TRIGGER_INACTIVE: u8 = 0x00;
+const TRIGGER_FIXED_INTERVAL: u8 = 0x01;
+const TRIGGER_MIN_INTERVAL: u8 = 0x02;
+const TRIGGER_VALUE_CHANGED: u8 = 0x03;
+
+const DEFAULT_TRIGGER: TriggerSetting = TriggerSetting::FixedInterval(1);
+
+/// Indexes `ESS_SERVICE.characteristics`
+#[derive(Debug, defmt::Format, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum EssMeasurementKind {
+    Temperature = 0,
+    Humidity = 1,
+    Pressure = 2,
+    Illuminance = 3,
+    DewPoint = 4,
+    HeatIndex = 5,
+}
+
+const ESS_MEASUREMENT_KINDS: usize = ESS_SERVICE.characteristics.len();
+
+const ESS_KINDS: [EssMeasurementKind; ESS_MEASUREMENT_KINDS] = [
+    EssMeasurementKind::Temperature,
+    EssMeasurementKind::Humidity,
+    EssMeasurementKind::Pressure,
+    EssMeasurementKind::Illuminance,
+    EssMeasurementKind::DewPoint,
+    EssMeasurementKind::HeatIndex,
+];
+
+/// Lower and upper bound of the widest value, the 32-bit pressure
+const VALID_RANGE_MAX_LEN: usize = 8;
+
+/// Everything derived from a BME280 reading, the dew point and heat index included
+pub(crate) const BME_MEASUREMENT_KINDS: [EssMeasurementKind; 5] = [
+    EssMeasurementKind::Temperature,
+    EssMeasurementKind::Humidity,
+    EssMeasurementKind::Pressure,
+    EssMeasurementKind::DewPoint,
+    EssMeasurementKind::HeatIndex,
+];
+
+/// ES Trigger Setting descriptor value; intervals are in seconds, as in the spec (uint24)
+#[derive(Debug, defmt::Format, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TriggerSetting {
+    Inactive,
+    FixedInterval(u32),
+    MinInterval(u32),
+    ValueChanged,
+}
+
+impl TriggerSetting {
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        match data {
+            [TRIGGER_INACTIVE] => Some(Self::Inactive),
+            [TRIGGER_VALUE_CHANGED] => Some(Self::ValueChanged),
+            [TRIGGER_FIXED_INTERVAL, a, b, c] => Some(Self::FixedInterval(u32::from_le_bytes([*a, *b, *c, 0]))),
+            [TRIGGER_MIN_INTERVAL, a, b, c] => Some(Self::MinInterval(u32::from_le_bytes([*a, *b, *c, 0]))),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn serialize(&self) -> ([u8; 4], usize) {
+        match *self {
+            Self::Inactive => ([TRIGGER_INACTIVE, 0, 0, 0], 1),
+            Self::ValueChanged => ([TRIGGER_VALUE_CHANGED, 0, 0, 0], 1),
+            Self::FixedInterval(seconds) => (u24_with_prefix(TRIGGER_FIXED_INTERVAL, seconds), 4),
+            Self::MinInterval(seconds) => (u24_with_prefix(TRIGGER_MIN_INTERVAL, seconds), 4),
+        }
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        match *self {
+            Self::FixedInterval(seconds) | Self::MinInterval(seconds) => {
+                Some(Duration::from_secs(seconds.max(1) as u64))
+            }
+            Self::Inactive | Self::ValueChanged => None,
+        }
+    }
+}
+
+fn u24_with_prefix(prefix: u8, value: u32) -> [u8; 4] {
+    let bytes = value.min(0xFF_FFFF).to_le_bytes();
+    [prefix, bytes[0], bytes[1], bytes[2]]
+}
+
+#[derive(Copy, Clone)]
+struct TriggerState {
+    setting: TriggerSetting,
+    last_notified: Option<(Instant, i64)>,
+}
+
+/// Keeps the trigger setting of every ESS characteristic and decides whether a fresh reading
+/// is worth a notification.
+pub(crate) struct EssTriggers {
+    states: Mutex<ThreadModeRawMutex, RefCell<[TriggerState; ESS_MEASUREMENT_KINDS]>>,
+}
+
+impl EssTriggers {
+    const fn new() -> Self {
+        Self {
+            states: Mutex::new(RefCell::new(
+                [TriggerState { setting: DEFAULT_TRIGGER, last_notified: None }; ESS_MEASUREMENT_KINDS],
+            )),
+        }
+    }
+
+    pub(crate) fn set(&self, kind: EssMeasurementKind, setting: TriggerSetting) {
+        self.states.lock(|states| {
+            states.borrow_mut()[kind as usize] = TriggerState { setting, last_notified: None };
+        });
+    }
+
+    pub(crate) fn get(&self, kind: EssMeasurementKind) -> TriggerSetting {
+        self.states.lock(|states| states.borrow()[kind as usize].setting)
+    }
+
+    pub(crate) fn should_notify(&self, kind: EssMeasurementKind, value: i64) -> bool {
+        let now = Instant::now();
+        self.states.lock(|states| {
+            let state = &mut states.borrow_mut()[kind as usize];
+            let should_notify = match (state.setting, state.last_notified) {
+                (TriggerSetting::Inactive, _) => false,
+                (_, None) => true,
+                (TriggerSetting::ValueChanged, Some((_, last_value))) => last_value != value,
+                (TriggerSetting::FixedInterval(_), Some((ts, _))) => {
+                    // readings are taken at this very interval, allow some jitter
+                    now - ts + Duration::from_millis(100) >= state.setting.interval().unwrap()
+                }
+                (TriggerSetting::MinInterval(_), Some((ts, last_value))) => {
+                    last_value != value && now - ts >= state.setting.interval().unwrap()
+                }
+            };
+            if should_notify {
+                state.last_notified = Some((now, value));
+            }
+            should_notify
+        })
+    }
+
+    /// The shortest interval the given characteristics need to be sampled at
+    pub(crate) fn sampling_interval(&self, kinds: &[EssMeasurementKind]) -> Option<Duration> {
+        self.states.lock(|states| {
+            let states = states.borrow();
+            kinds.iter().filter_map(|kind| states[*kind as usize].setting.interval()).min()
+        })
+    }
+}
+
+pub(crate) static ESS_TRIGGERS: EssTriggers = EssTriggers::new();
+
+/// Sensor tasks serve both their own service and the ESS; this picks the shortest cadence
+/// among the ones that are actually subscribed to.
+pub(crate) fn sampling_interval<S, E, const T: usize>(
+    processor: &EventProcessor<S, E, T>,
+    kinds: &[EssMeasurementKind],
+) -> Duration {
+    let ess_interval = if ESS_EVENT_PROCESSOR.is_enabled() {
+        ESS_TRIGGERS.sampling_interval(kinds)
+    } else {
+        None
+    };
+
+    match (processor.is_enabled(), ess_interval) {
+        (true, Some(interval)) => interval.min(processor.get_timeout_duration()),
+        (false, Some(interval)) => interval,
+        (_, None) => processor.get_timeout_duration(),
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+struct EssCharacteristicHandles {
+    value: u16,
+    cccd: u16,
+    trigger: u16,
+}
+
+pub(crate) struct EnvironmentalSensingService {
+    characteristics: [EssCharacteristicHandles; ESS_MEASUREMENT_KINDS],
+}
+
+#[derive(defmt::Format)]
+pub(crate) enum EnvironmentalSensingServiceEvent {
+    TemperatureCccdWrite { notifications: bool },
+    HumidityCccdWrite { notifications: bool },
+    PressureCccdWrite { notifications: bool },
+    IlluminanceCccdWrite { notifications: bool },
+    DewPointCccdWrite { notifications: bool },
+    HeatIndexCccdWrite { notifications: bool },
+    TriggerSettingWrite { kind: EssMeasurementKind, setting: Option<TriggerSetting> },
+}
+
+/// [flags: u16][sampling function][measurement period: u24][update interval: u24][application][uncertainty]
+fn es_measurement(sampling_function: u8, uncertainty: u8) -> [u8; 11] {
+    let update_interval = DEFAULT_TRIGGER.interval().unwrap().as_secs() as u8;
+    [0, 0, sampling_function, 0, 0, 0, update_interval, 0, 0, APPLICATION_AIR, uncertainty]
+}
+
+/// ES Measurement and Valid Range descriptor values
+fn measurement_descriptors(kind: EssMeasurementKind) -> ([u8; 11], ([u8; VALID_RANGE_MAX_LEN], usize)) {
+    match kind {
+        EssMeasurementKind::Temperature => (
+            es_measurement(SAMPLING_INSTANTANEOUS, 0x02),
+            concat_range(BME280_TEMP_MIN.as_temp().to_le_bytes(), BME280_TEMP_MAX.as_temp().to_le_bytes()),
+        ),
+        EssMeasurementKind::Humidity => (
+            es_measurement(SAMPLING_INSTANTANEOUS, 0x06),
+            concat_range(
+                BME280_HUMIDITY_MIN.as_humidity().to_le_bytes(),
+                BME280_HUMIDITY_MAX.as_humidity().to_le_bytes(),
+            ),
+        ),
+        EssMeasurementKind::Pressure => (
+            es_measurement(SAMPLING_INSTANTANEOUS, 0x01),
+            concat_range(
+                BME280_PRESSURE_MIN.as_pressure().to_le_bytes(),
+                BME280_PRESSURE_MAX.as_pressure().to_le_bytes(),
+            ),
+        ),
+        EssMeasurementKind::Illuminance => (
+            es_measurement(SAMPLING_ARITHMETIC_MEAN, 0x14),
+            concat_range(u24(0), u24(VEML6040_MAX_LUX.as_illuminance())),
+        ),
+        EssMeasurementKind::DewPoint => (
+            es_measurement(SAMPLING_INSTANTANEOUS, 0x02),
+            concat_range([BME280_TEMP_MIN.as_temp_sint8() as u8], [BME280_TEMP_MAX.as_temp_sint8() as u8]),
+        ),
+        EssMeasurementKind::HeatIndex => (
+            es_measurement(SAMPLING_INSTANTANEOUS, 0x02),
+            concat_range([BME280_TEMP_MIN.as_temp_sint8() as u8], [i8::MAX as u8]),
+        ),
+    }
+}
+
+/// The value as declared in `ESS_SERVICE`, followed by `ESS_SERVICE.descriptors`
+fn register_characteristic(
+    service_builder: &mut ServiceBuilder,
+    spec: &CharacteristicSpec,
+    kind: EssMeasurementKind,
+) -> Result<EssCharacteristicHandles, RegisterError> {
+    let (es_measurement, (valid_range, valid_range_len)) = measurement_descriptors(kind);
+    let mut characteristic_builder = add_characteristic(service_builder, spec)?;
+
+    characteristic_builder.add_descriptor(
+        Uuid::new_16(ES_MEASUREMENT_UUID),
+        Attribute::new(es_measurement).write_security(SecurityMode::NoAccess),
+    )?;
+
+    let (trigger, trigger_len) = DEFAULT_TRIGGER.serialize();
+    let trigger_descriptor = characteristic_builder.add_descriptor(
+        Uuid::new_16(ES_TRIGGER_SETTING_UUID),
+        Attribute::new(trigger)
+            .write_security(SecurityMode::LescMitm)
+            .variable_len(trigger_len as u16),
+    )?;
+
+    characteristic_builder.add_descriptor(
+        Uuid::new_16(VALID_RANGE_UUID),
+        Attribute::new(&valid_range[..valid_range_len]).write_security(SecurityMode::NoAccess),
+    )?;
+
+    let handles = characteristic_builder.build();
+
+    Ok(EssCharacteristicHandles {
+        value: handles.value_handle,
+        cccd: handles.cccd_handle,
+        trigger: trigger_descriptor.handle(),
+    })
+}
+
+fn concat_range<const N: usize>(lower: [u8; N], upper: [u8; N]) -> ([u8; VALID_RANGE_MAX_LEN], usize) {
+    let mut range = [0u8; VALID_RANGE_MAX_LEN];
+    range[..N].copy_from_slice(&lower);
+    range[N..2 * N].copy_from_slice(&upper);
+    (range, 2 * N)
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let bytes = value.min(0xFF_FFFF).to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+impl EnvironmentalSensingService {
+    pub(crate) fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+        let mut service_builder = ServiceBuilder::new(sd, gatt_uuid(ESS_SERVICE.uuid))?;
+
+        let mut characteristics = [EssCharacteristicHandles::default(); ESS_MEASUREMENT_KINDS];
+        for ((handles, spec), kind) in characteristics.iter_mut().zip(ESS_SERVICE.characteristics).zip(ESS_KINDS) {
+            *handles = register_characteristic(&mut service_builder, spec, kind)?;
+        }
+
+        let _ = service_builder.build();
+
+        Ok(Self { characteristics })
+    }
+
+    fn handles(&self, kind: EssMeasurementKind) -> &EssCharacteristicHandles {
+        &self.characteristics[kind as usize]
+    }
+
+    fn notify_value(&self, conn: &Connection, kind: EssMeasurementKind, value: &[u8]) -> Result<(), NotifyValueError> {
+        gatt_server::notify_value(conn, self.handles(kind).value, value)
+    }
+
+    fn set_value(&self, kind: EssMeasurementKind, value: &[u8]) -> Result<(), SetValueError> {
+        gatt_server::set_value(unsafe { Softdevice::steal() }, self.handles(kind).value, value)
+    }
+
+    /// Reflects the effective trigger setting back to the descriptor, i.e. after a rejected write
+    pub(crate) fn trigger_setting_set(&self, kind: EssMeasurementKind, setting: TriggerSetting) -> Result<(), SetValueError> {
+        let (buf, len) = setting.serialize();
+        gatt_server::set_value(unsafe { Softdevice::steal() }, self.handles(kind).trigger, &buf[..len])
+    }
+
+    pub(crate) fn temperature_notify(&self, conn: &Connection, value: &i16) -> Result<(), NotifyValueError> {
+        self.notify_value(conn, EssMeasurementKind::Temperature, &value.to_le_bytes())
+    }
+
+    pub(crate) fn temperature_set(&self, value: &i16) -> Result<(), SetValueError> {
+        self.set_value(EssMeasurementKind::Temperature, &value.to_le_bytes())
+    }
+
+    pub(crate) fn humidity_notify(&self, conn: &Connection, value: &u16) -> Result<(), NotifyValueError> {
+        self.notify_value(conn, EssMeasurementKind::Humidity, &value.to_le_bytes())
+    }
+
+    pub(crate) fn humidity_set(&self, value: &u16) -> Result<(), SetValueError> {
+        self.set_value(EssMeasurementKind::Humidity, &value.to_le_bytes())
+    }
+
+    pub(crate) fn pressure_notify(&self, conn: &Connection, value: &u32) -> Result<(), NotifyValueError> {
+        self.notify_value(conn, EssMeasurementKind::Pressure, &value.to_le_bytes())
+    }
+
+    pub(crate) fn pressure_set(&self, value: &u32) -> Result<(), SetValueError> {
+        self.set_value(EssMeasurementKind::Pressure, &value.to_le_bytes())
+    }
+
+    pub(crate) fn illuminance_notify(&self, conn: &Connection, value: &u32) -> Result<(), NotifyValueError> {
+        self.notify_value(conn, EssMeasurementKind::Illuminance, &u24(*value))
+    }
+
+    pub(crate) fn illuminance_set(&self, value: &u32) -> Result<(), SetValueError> {
+        self.set_value(EssMeasurementKind::Illuminance, &u24(*value))
+    }
+
+    pub(crate) fn dew_point_notify(&self, conn: &Connection, value: &i8) -> Result<(), NotifyValueError> {
+        self.notify_value(conn, EssMeasurementKind::DewPoint, &value.to_le_bytes())
+    }
+
+    pub(crate) fn dew_point_set(&self, value: &i8) -> Result<(), SetValueError> {
+        self.set_value(EssMeasurementKind::DewPoint, &value.to_le_bytes())
+    }
+
+    pub(crate) fn heat_index_notify(&self, conn: &Connection, value: &i8) -> Result<(), NotifyValueError> {
+        self.notify_value(conn, EssMeasurementKind::HeatIndex, &value.to_le_bytes())
+    }
+
+    pub(crate) fn heat_index_set(&self, value: &i8) -> Result<(), SetValueError> {
+        self.set_value(EssMeasurementKind::HeatIndex, &value.to_le_bytes())
+    }
+
+    pub(crate) async fn notify_bme(&self, temperature: i16, humidity: u16, pressure: u32) {
+        if ESS_TRIGGERS.should_notify(EssMeasurementKind::Temperature, temperature as i64) {
+            notify_all!(ESS_EVENT_PROCESSOR, self, temperature = &temperature);
+        }
+        if ESS_TRIGGERS.should_notify(EssMeasurementKind::Humidity, humidity as i64) {
+            notify_all!(ESS_EVENT_PROCESSOR, self, humidity = &humidity);
+        }
+        if ESS_TRIGGERS.should_notify(EssMeasurementKind::Pressure, pressure as i64) {
+            notify_all!(ESS_EVENT_PROCESSOR, self, pressure = &pressure);
+        }
+    }
+
+    /// Dew point and heat index in whole degrees, as the ESS defines them
+    pub(crate) async fn notify_climate(&self, dew_point: i8, heat_index: i8) {
+        if ESS_TRIGGERS.should_notify(EssMeasurementKind::DewPoint, dew_point as i64) {
+            notify_all!(ESS_EVENT_PROCESSOR, self, dew_point = &dew_point);
+        }
+        if ESS_TRIGGERS.should_notify(EssMeasurementKind::HeatIndex, heat_index as i64) {
+            notify_all!(ESS_EVENT_PROCESSOR, self, heat_index = &heat_index);
+        }
+    }
+
+    pub(crate) async fn notify_illuminance(&self, illuminance: u32) {
+        if ESS_TRIGGERS.should_notify(EssMeasurementKind::Illuminance, illuminance as i64) {
+            notify_all!(ESS_EVENT_PROCESSOR, self, illuminance = &illuminance);
+        }
+    }
+}
+
+impl Service for EnvironmentalSensingService {
+    type Event = EnvironmentalSensingServiceEvent;
+
+    fn on_write(&self, handle: u16, data: &[u8]) -> Option<Self::Event> {
+        let notifications = data.first().map(|flags| flags & 0x01 != 0).unwrap_or(false);
+
+        for kind in ESS_KINDS {
+            let handles = self.handles(kind);
+            if handle == handles.trigger {
+                return Some(EnvironmentalSensingServiceEvent::TriggerSettingWrite {
+                    kind,
+                    setting: TriggerSetting::parse(data),
+                });
+            }
+            if handle == handles.cccd {
+                return Some(match kind {
+                    EssMeasurementKind::Temperature => EnvironmentalSensingServiceEvent::TemperatureCccdWrite { notifications },
+                    EssMeasurementKind::Humidity => EnvironmentalSensingServiceEvent::HumidityCccdWrite { notifications },
+                    EssMeasurementKind::Pressure => EnvironmentalSensingServiceEvent::PressureCccdWrite { notifications },
+                    EssMeasurementKind::Illuminance => EnvironmentalSensingServiceEvent::IlluminanceCccdWrite { notifications },
+                    EssMeasurementKind::DewPoint => EnvironmentalSensingServiceEvent::DewPointCccdWrite { notifications },
+                    EssMeasurementKind::HeatIndex => EnvironmentalSensingServiceEvent::HeatIndexCccdWrite { notifications },
+                });
+            }
+        }
+
+        None
+    }
+}
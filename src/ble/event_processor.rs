@@ -1,148 +1,31 @@
-use alloc::collections::BTreeMap;
-use core::marker::PhantomData;
-use core::sync::atomic::{AtomicU32, Ordering};
-
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
-use embassy_sync::mutex::Mutex;
-use embassy_time::Duration;
+use defmt::info;
 use nrf_softdevice::ble::Connection;
 
 use crate::{
-    impl_is_task_enabled, impl_read_event_channel, impl_set_notification,
-    impl_settings_event_consumer, impl_timeout_event_characteristic,
+    ble_debug, impl_read_event_channel, impl_set_notification, impl_settings_event_consumer,
+    impl_timeout_event_characteristic,
 };
-use crate::common::ble::{ACCELEROMETER_EVENT_PROCESSOR, ACCELEROMETER_SERVICE_EVENTS, ADC_EVENT_PROCESSOR, ADC_SERVICE_EVENTS, BME_EVENT_PROCESSOR, BME_SERVICE_EVENTS, COLOR_EVENT_PROCESSOR, COLOR_SERVICE_EVENTS, DEVICE_EVENT_PROCESSOR, DI_SERVICE_EVENTS, FLASH_MANAGER};
-use crate::common::ble::services::{
-    AccelerometerServiceEvent, AdcServiceEvent, Bme280ServiceEvent, ColorServiceEvent,
-    DeviceInformationServiceEvent,
+use crate::common::ble::adc::{AdcServiceEvent, set_adc_label};
+use crate::common::ble::advertising::{set_accept_list_only, set_coded_phy};
+use crate::common::ble::bme_config::set_bme_config;
+use crate::common::ble::identity::{set_device_name, set_location};
+use crate::common::ble::{ACCELEROMETER_EVENT_PROCESSOR, ACCELEROMETER_SERVICE_EVENTS, ADC_EVENT_PROCESSOR, ADC_SERVICE_EVENTS, BATTERY_EVENT_PROCESSOR, BATTERY_SERVICE_EVENTS, BME_EVENT_PROCESSOR, BME_SERVICE_EVENTS, COLOR_EVENT_PROCESSOR, COLOR_SERVICE_EVENTS, DEVICE_EVENT_PROCESSOR, DIAGNOSTICS_SERVICE_EVENTS, ESS_EVENT_PROCESSOR, ESS_SERVICE_EVENTS, FLASH_MANAGER, SERVER};
+use crate::common::ble::ess::{ESS_TRIGGERS, EnvironmentalSensingServiceEvent};
+use crate::common::ble::notification_settings::{
+    AccelerometerNotificationSettings, AdcNotificationSettings, BatteryNotificationSettings, BmeNotificationSettings,
+    ColorNotificationSettings, DiagnosticsNotificationSettings, EssNotificationSettings, SnapshotNotificationSettings,
 };
-use crate::common::ble::traits::{
-    IsTaskEnabled, SettingsEventConsumer, TimeoutEventCharacteristic,
+use crate::common::ble::processor;
+use crate::common::ble::services::{
+    AccelerometerServiceEvent, BatteryServiceEvent, Bme280ServiceEvent,
+    ColorServiceEvent, DiagnosticsServiceEvent, SnapshotServiceEvent,
 };
-use crate::common::util::condition::{Condition, ConditionToken};
-
-#[derive(Default, Clone)]
-pub(crate) struct AccelerometerNotificationSettings {
-    pub(crate) x: bool,
-    pub(crate) y: bool,
-    pub(crate) z: bool,
-}
-
-#[derive(Default, Clone)]
-pub(crate) struct ColorNotificationSettings {
-    pub(crate) red: bool,
-    pub(crate) green: bool,
-    pub(crate) blue: bool,
-    pub(crate) white: bool,
-    pub(crate) cct: bool,
-    pub(crate) lux: bool,
-}
+use crate::common::ble::traits::{SettingsEventConsumer, TimeoutEventCharacteristic};
+use crate::common::ble::weather::set_reference_altitude;
+use crate::common::device::error::DeviceError;
 
-#[derive(Default, Clone)]
-pub(crate) struct AdcNotificationSettings {
-    pub(crate) voltage0: bool,
-    pub(crate) voltage1: bool,
-    pub(crate) voltage2: bool,
-    pub(crate) voltage3: bool,
-    pub(crate) voltage4: bool,
-    pub(crate) voltage5: bool,
-    pub(crate) voltage6: bool,
-    pub(crate) samples: bool,
-    pub(crate) elapsed: bool,
-}
-
-#[derive(Default, Clone)]
-pub(crate) struct BmeNotificationSettings {
-    pub(crate) temperature: bool,
-    pub(crate) humidity: bool,
-    pub(crate) pressure: bool,
-}
-
-#[derive(Default, Clone)]
-pub(crate) struct DiNotificationSettings {
-    pub(crate) temperature: bool,
-    pub(crate) battery_voltage: bool,
-    pub(crate) debug: bool,
-}
-
-pub(crate) struct EventProcessor<S, E, const T: usize> {
-    notification_settings: Mutex<ThreadModeRawMutex, BTreeMap<Connection, S>>,
-    timeout: AtomicU32,
-    condition: Condition<T>,
-    _phantom_data: PhantomData<E>,
-}
-
-impl<S, E, const T: usize> EventProcessor<S, E, T>
-    where
-        S: Default + SettingsEventConsumer<E> + IsTaskEnabled + Clone,
-        E: TimeoutEventCharacteristic,
-{
-    pub(crate) const fn new(name: Option<&'static str>) -> Self {
-        Self {
-            notification_settings: Mutex::new(BTreeMap::new()),
-            timeout: AtomicU32::new(1000),
-            condition: Condition::new(name),
-            _phantom_data: PhantomData,
-        }
-    }
-
-    pub(crate) async fn process_event(&self, connection: Connection, event: E) {
-        if let Some(timeout) = event.get_timeout() {
-            self.timeout.store(timeout, Ordering::SeqCst);
-        }
-
-        let mut settings_map = self.notification_settings.lock().await;
-        let settings = settings_map.entry(connection).or_default();
-        settings.consume(event).await;
-
-        self.set_task_enabled_state(&settings_map);
-    }
-
-    pub(crate) fn get_timeout_duration(&self) -> Duration {
-        Duration::from_millis(self.timeout.load(Ordering::Relaxed) as u64)
-    }
-
-    fn set_task_enabled_state(&self, settings: &BTreeMap<Connection, S>) {
-        let should_enable = settings.values().any(|settings| settings.is_task_enabled());
-        self.condition.set(should_enable);
-    }
-
-    pub(crate) fn fire_once(&self) {
-        self.condition.fire_once();
-    }
-
-    pub(crate) async fn register_connection(&self, connection: &Connection) {
-        _ = self.notification_settings.lock().await.entry(connection.clone()).or_default();
-    }
-
-    pub(crate) async fn drop_connection(&self, connection: &Connection) {
-        let mut settings_map = self.notification_settings.lock().await;
-        settings_map.remove(connection);
-        self.set_task_enabled_state(&settings_map);
-    }
-
-    pub(crate) async fn wait_for_condition(&self) -> ConditionToken<T> {
-        self.condition.lock().await
-    }
-
-    pub(crate) async fn get_connection_settings(&self, connection: &Connection) -> Option<S> {
-        self.notification_settings.lock().await.get(connection).cloned()
-    }
-}
-
-impl_settings_event_consumer!(
-    AdcNotificationSettings,
-    AdcServiceEvent,
-    Voltage0,
-    Voltage1,
-    Voltage2,
-    Voltage3,
-    Voltage4,
-    Voltage5,
-    Voltage6,
-    Samples,
-    Elapsed
-);
+/// Per-connection state is keyed by the softdevice connection on the device
+pub(crate) type EventProcessor<S, E, const T: usize> = processor::EventProcessor<S, E, Connection, T>;
 
 impl_settings_event_consumer!(
     AccelerometerNotificationSettings,
@@ -162,6 +45,15 @@ impl_settings_event_consumer!(
     Lux,
     Cct
 );
+
+impl_settings_event_consumer!(
+    BatteryNotificationSettings,
+    BatteryServiceEvent,
+    BatteryLevel,
+    BatteryLevelStatus
+);
+impl_settings_event_consumer!(SnapshotNotificationSettings, SnapshotServiceEvent, Snapshot);
+
 impl SettingsEventConsumer<Bme280ServiceEvent> for BmeNotificationSettings {
     async fn consume(&mut self, event: Bme280ServiceEvent) {
         let mut next_calibration_data = match event {
@@ -177,6 +69,54 @@ impl SettingsEventConsumer<Bme280ServiceEvent> for BmeNotificationSettings {
                 self.pressure = notifications;
                 return;
             }
+            Bme280ServiceEvent::DewPointCccdWrite { notifications } => {
+                self.dew_point = notifications;
+                return;
+            }
+            Bme280ServiceEvent::FrostPointCccdWrite { notifications } => {
+                self.frost_point = notifications;
+                return;
+            }
+            Bme280ServiceEvent::AbsoluteHumidityCccdWrite { notifications } => {
+                self.absolute_humidity = notifications;
+                return;
+            }
+            Bme280ServiceEvent::HeatIndexCccdWrite { notifications } => {
+                self.heat_index = notifications;
+                return;
+            }
+            Bme280ServiceEvent::HumidexCccdWrite { notifications } => {
+                self.humidex = notifications;
+                return;
+            }
+            Bme280ServiceEvent::VapourPressureDeficitCccdWrite { notifications } => {
+                self.vapour_pressure_deficit = notifications;
+                return;
+            }
+            Bme280ServiceEvent::SeaLevelPressureCccdWrite { notifications } => {
+                self.sea_level_pressure = notifications;
+                return;
+            }
+            Bme280ServiceEvent::PressureAltitudeCccdWrite { notifications } => {
+                self.pressure_altitude = notifications;
+                return;
+            }
+            Bme280ServiceEvent::PressureTendencyCccdWrite { notifications } => {
+                self.pressure_tendency = notifications;
+                return;
+            }
+            Bme280ServiceEvent::PressureTrendCccdWrite { notifications } => {
+                self.pressure_trend = notifications;
+                return;
+            }
+            Bme280ServiceEvent::ForecastCccdWrite { notifications } => {
+                self.forecast = notifications;
+                return;
+            }
+            Bme280ServiceEvent::ReferenceAltitudeWrite(altitude) => {
+                set_reference_altitude(altitude).await;
+                return;
+            }
             Bme280ServiceEvent::HumidityOffsetWrite(value) => {
                 let mut data = FLASH_MANAGER.get().get_last_calibration_data().await;
                 data.bme_humidity = f32::from_le_bytes(value);
@@ -192,6 +132,10 @@ impl SettingsEventConsumer<Bme280ServiceEvent> for BmeNotificationSettings {
                 data.bme_pressure = f32::from_le_bytes(value);
                 data
             }
+            Bme280ServiceEvent::ConfigWrite(value) => {
+                set_bme_config(value).await;
+                return;
+            }
             _ => return
         };
 
@@ -201,41 +145,132 @@ impl SettingsEventConsumer<Bme280ServiceEvent> for BmeNotificationSettings {
     }
 }
 
-impl_settings_event_consumer!(
-    DiNotificationSettings,
-    DeviceInformationServiceEvent,
-    BatteryVoltage,
-    Temperature,
-    Debug
-);
+pub(crate) async fn copy_calibration_data_from_flash() -> Result<(), DeviceError> {
+    let server = SERVER.get();
+    let calibration_data = FLASH_MANAGER.get().get_last_calibration_data().await;
+    server.bme280.humidity_offset_set(&calibration_data.bme_humidity.to_le_bytes())?;
+    server.bme280.pressure_offset_set(&calibration_data.bme_pressure.to_le_bytes())?;
+    server.bme280.temperature_offset_set(&calibration_data.bme_temperature.to_le_bytes())?;
 
-impl_is_task_enabled!(BmeNotificationSettings, humidity, pressure, temperature);
-impl_is_task_enabled!(DiNotificationSettings, debug, battery_voltage, temperature);
-impl_is_task_enabled!(
-    AdcNotificationSettings,
-    voltage0,
-    voltage1,
-    voltage2,
-    voltage3,
-    voltage4,
-    voltage5,
-    voltage6,
-    elapsed,
-    samples
-);
-impl_is_task_enabled!(ColorNotificationSettings, red, green, blue, white);
-impl_is_task_enabled!(AccelerometerNotificationSettings, x, y, z);
+    info!("Calibration data copied from flash: {:?}", calibration_data);
+
+    Ok(())
+}
+
+impl SettingsEventConsumer<DiagnosticsServiceEvent> for DiagnosticsNotificationSettings {
+    async fn consume(&mut self, event: DiagnosticsServiceEvent) {
+        match event {
+            DiagnosticsServiceEvent::AcceptListOnlyWrite(value) => {
+                set_accept_list_only(value != 0);
+                return;
+            }
+            DiagnosticsServiceEvent::CodedPhyWrite(value) => {
+                set_coded_phy(value != 0);
+                return;
+            }
+            DiagnosticsServiceEvent::DeviceNameWrite(value) => {
+                set_device_name(value).await;
+                return;
+            }
+            DiagnosticsServiceEvent::LocationWrite(value) => {
+                set_location(value).await;
+                return;
+            }
+            // handled right away, they need the connection
+            DiagnosticsServiceEvent::ConnectionPhyWrite(_) => return,
+            DiagnosticsServiceEvent::ConnectionProfileWrite(_) => return,
+            DiagnosticsServiceEvent::LogWrite(_) => return,
+            _ => {}
+        }
+
+        impl_set_notification!(
+            DiagnosticsServiceEvent,
+            event,
+            self,
+            BatteryVoltage,
+            Temperature,
+            Debug
+        );
+    }
+}
+
+impl SettingsEventConsumer<AdcServiceEvent> for AdcNotificationSettings {
+    async fn consume(&mut self, event: AdcServiceEvent) {
+        if let AdcServiceEvent::DescriptionWrite { channel, value } = event {
+            set_adc_label(channel, value).await;
+            return;
+        }
+
+        impl_set_notification!(
+            AdcServiceEvent,
+            event,
+            self,
+            Voltage0,
+            Voltage1,
+            Voltage2,
+            Voltage3,
+            Voltage4,
+            Voltage5,
+            Voltage6,
+            Samples,
+            Elapsed
+        );
+    }
+}
+
+impl SettingsEventConsumer<EnvironmentalSensingServiceEvent> for EssNotificationSettings {
+    async fn consume(&mut self, event: EnvironmentalSensingServiceEvent) {
+        if let EnvironmentalSensingServiceEvent::TriggerSettingWrite { kind, setting } = event {
+            match setting {
+                Some(setting) => ESS_TRIGGERS.set(kind, setting),
+                None => ble_debug!("Unsupported ESS trigger setting for {:?}", kind),
+            }
+            // the descriptor always reflects the trigger that is in effect
+            let _ = SERVER.get().ess.trigger_setting_set(kind, ESS_TRIGGERS.get(kind));
+            return;
+        }
+
+        impl_set_notification!(
+            EnvironmentalSensingServiceEvent,
+            event,
+            self,
+            Temperature,
+            Humidity,
+            Pressure,
+            Illuminance,
+            DewPoint,
+            HeatIndex
+        );
+    }
+}
+
+impl TimeoutEventCharacteristic for EnvironmentalSensingServiceEvent {
+    fn get_timeout(&self) -> Option<u32> {
+        // ESS cadence is controlled by ES Trigger Setting descriptors
+        None
+    }
+}
+
+impl TimeoutEventCharacteristic for BatteryServiceEvent {
+    fn get_timeout(&self) -> Option<u32> {
+        // Battery Service has no timeout characteristic, see BATTERY_LEVEL_INTERVAL
+        None
+    }
+}
 
 impl_timeout_event_characteristic!(AdcServiceEvent);
 impl_timeout_event_characteristic!(Bme280ServiceEvent);
-impl_timeout_event_characteristic!(DeviceInformationServiceEvent);
+impl_timeout_event_characteristic!(DiagnosticsServiceEvent);
 impl_timeout_event_characteristic!(ColorServiceEvent);
 impl_timeout_event_characteristic!(AccelerometerServiceEvent);
+impl_timeout_event_characteristic!(SnapshotServiceEvent);
 
 impl_read_event_channel!("adc", ADC_SERVICE_EVENTS, ADC_EVENT_PROCESSOR);
 impl_read_event_channel!("bme", BME_SERVICE_EVENTS, BME_EVENT_PROCESSOR);
-impl_read_event_channel!("di", DI_SERVICE_EVENTS, DEVICE_EVENT_PROCESSOR);
+impl_read_event_channel!("diagnostics", DIAGNOSTICS_SERVICE_EVENTS, DEVICE_EVENT_PROCESSOR);
 impl_read_event_channel!("color", COLOR_SERVICE_EVENTS, COLOR_EVENT_PROCESSOR);
+impl_read_event_channel!("ess", ESS_SERVICE_EVENTS, ESS_EVENT_PROCESSOR);
+impl_read_event_channel!("battery", BATTERY_SERVICE_EVENTS, BATTERY_EVENT_PROCESSOR);
 impl_read_event_channel!(
     "accelerometer",
     ACCELEROMETER_SERVICE_EVENTS,
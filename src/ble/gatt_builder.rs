@@ -0,0 +1,53 @@
+use nrf_softdevice::ble::{SecurityMode, Uuid};
+use nrf_softdevice::ble::gatt_server::builder::{CharacteristicBuilder, ServiceBuilder};
+use nrf_softdevice::ble::gatt_server::characteristic::{Attribute, Metadata, Properties};
+use nrf_softdevice::ble::gatt_server::RegisterError;
+
+use crate::common::ble::gatt_table::{CharacteristicSpec, GattUuid, PRESENTATION_FORMAT_UUID, uuid_128, WriteSecurity};
+
+/// Largest scalar value of the table, a u64
+const VALUE_MAX_LEN: usize = 8;
+
+pub(crate) fn gatt_uuid(uuid: GattUuid) -> Uuid {
+    match uuid {
+        GattUuid::Sig(uuid) => Uuid::new_16(uuid),
+        GattUuid::Vendor(uuid) => Uuid::new_128(&uuid_128(uuid)),
+    }
+}
+
+fn write_security(security: WriteSecurity) -> SecurityMode {
+    match security {
+        WriteSecurity::NoAccess => SecurityMode::NoAccess,
+        WriteSecurity::LescMitm => SecurityMode::LescMitm,
+    }
+}
+
+/// Adds the zeroed value and the Presentation Format of `spec`, the caller adds the rest of the descriptors
+pub(crate) fn add_characteristic<'a>(
+    service_builder: &'a mut ServiceBuilder,
+    spec: &CharacteristicSpec,
+) -> Result<CharacteristicBuilder<'a>, RegisterError> {
+    let initial_value = [0u8; VALUE_MAX_LEN];
+    let attr = Attribute::new(&initial_value[..spec.value_len()]).write_security(write_security(spec.write_security));
+
+    let mut properties = Properties::new();
+    if spec.properties.read {
+        properties = properties.read();
+    }
+    if spec.properties.write {
+        properties = properties.write();
+    }
+    if spec.properties.notify {
+        properties = properties.notify();
+    }
+
+    let mut characteristic_builder =
+        service_builder.add_characteristic(gatt_uuid(spec.uuid), attr, Metadata::new(properties))?;
+    if let Some(description) = spec.presentation_format {
+        characteristic_builder.add_descriptor(
+            Uuid::new_16(PRESENTATION_FORMAT_UUID),
+            Attribute::new(spec.encoding.presentation_format(description)).write_security(SecurityMode::NoAccess),
+        )?;
+    }
+    Ok(characteristic_builder)
+}
@@ -0,0 +1,189 @@
+//! Characteristics of the services registered through the `ServiceBuilder`: `ess` and `adc` register them
+//! from these tables and the build script exports them to the GATT schema, so this must not depend on anything
+//! outside `core` and `encoding`.
+//! The `#[nrf_softdevice::gatt_service]` structs in `services` are declared by their attributes instead.
+
+use super::encoding::{
+    Encoding, COUNT, DESCRIPTION_UNKNOWN, ELAPSED_US, HUMIDITY, ILLUMINANCE, PRESSURE, TEMPERATURE,
+    TEMPERATURE_SINT8, TIMEOUT_MS, VOLTAGE,
+};
+
+pub(crate) const ES_MEASUREMENT_UUID: u16 = 0x290C;
+pub(crate) const ES_TRIGGER_SETTING_UUID: u16 = 0x290D;
+pub(crate) const VALID_RANGE_UUID: u16 = 0x2906;
+pub(crate) const USER_DESCRIPTION_UUID: u16 = 0x2901;
+pub(crate) const PRESENTATION_FORMAT_UUID: u16 = 0x2904;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum GattUuid {
+    Sig(u16),
+    /// "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx"
+    Vendor(&'static str),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Properties {
+    pub(crate) read: bool,
+    pub(crate) write: bool,
+    pub(crate) notify: bool,
+}
+
+pub(crate) const READ_NOTIFY: Properties = Properties { read: true, write: false, notify: true };
+pub(crate) const READ_WRITE_NOTIFY: Properties = Properties { read: true, write: true, notify: true };
+
+/// Values are readable without a bond, writes are rejected or need an authenticated LESC link
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum WriteSecurity {
+    NoAccess,
+    LescMitm,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CharacteristicSpec {
+    /// Field name in the schema and the event names
+    pub(crate) name: &'static str,
+    pub(crate) uuid: GattUuid,
+    pub(crate) properties: Properties,
+    pub(crate) write_security: WriteSecurity,
+    pub(crate) encoding: Encoding,
+    /// Description field of the Presentation Format (0x2904), None if there is no such descriptor
+    pub(crate) presentation_format: Option<u16>,
+    /// Initial User Description (0x2901), None if there is no such descriptor
+    pub(crate) user_description: Option<&'static str>,
+    pub(crate) user_description_writable: bool,
+}
+
+impl CharacteristicSpec {
+    /// Values registered by the builders are scalars; text and raw data are declared in `services`
+    pub(crate) const fn value_len(&self) -> usize {
+        match self.encoding.format.len() {
+            Some(len) => len,
+            None => panic!("variable length characteristic"),
+        }
+    }
+}
+
+pub(crate) struct ServiceSpec {
+    pub(crate) name: &'static str,
+    pub(crate) uuid: GattUuid,
+    pub(crate) doc: &'static str,
+    /// Added by the service builder to every characteristic, next to the 0x2904 and 0x2901 of the spec
+    pub(crate) descriptors: &'static [u16],
+    pub(crate) characteristics: &'static [CharacteristicSpec],
+}
+
+const fn ess_measurement(name: &'static str, uuid: u16, encoding: Encoding) -> CharacteristicSpec {
+    CharacteristicSpec {
+        name,
+        uuid: GattUuid::Sig(uuid),
+        properties: READ_NOTIFY,
+        write_security: WriteSecurity::NoAccess,
+        encoding,
+        presentation_format: None,
+        user_description: None,
+        user_description_writable: false,
+    }
+}
+
+/// In the order of `EssMeasurementKind`
+pub(crate) const ESS_SERVICE: ServiceSpec = ServiceSpec {
+    name: "EnvironmentalSensingService",
+    uuid: GattUuid::Sig(0x181A),
+    doc: "Environmental Sensing Service, notifications follow the ES Trigger Setting of each characteristic",
+    descriptors: &[ES_MEASUREMENT_UUID, ES_TRIGGER_SETTING_UUID, VALID_RANGE_UUID],
+    characteristics: &[
+        ess_measurement("temperature", 0x2A6E, TEMPERATURE),
+        ess_measurement("humidity", 0x2A6F, HUMIDITY),
+        ess_measurement("pressure", 0x2A6D, PRESSURE),
+        ess_measurement("illuminance", 0x2AFB, ILLUMINANCE),
+        ess_measurement("dew_point", 0x2A7B, TEMPERATURE_SINT8),
+        ess_measurement("heat_index", 0x2A7A, TEMPERATURE_SINT8),
+    ],
+};
+
+/// The description field tells the channels apart: 1 - "first", 2 - "second", ..; the label defaults to the pin
+/// as wired in `PeripheralsManager`, until the user names the channel
+const fn adc_voltage(name: &'static str, uuid: &'static str, channel: u16, pin: &'static str) -> CharacteristicSpec {
+    CharacteristicSpec {
+        name,
+        uuid: GattUuid::Vendor(uuid),
+        properties: READ_NOTIFY,
+        write_security: WriteSecurity::NoAccess,
+        encoding: VOLTAGE,
+        presentation_format: Some(channel + 1),
+        user_description: Some(pin),
+        user_description_writable: true,
+    }
+}
+
+const fn adc_characteristic(
+    name: &'static str,
+    uuid: &'static str,
+    writable: bool,
+    encoding: Encoding,
+    description: &'static str,
+) -> CharacteristicSpec {
+    let (properties, write_security) =
+        if writable { (READ_WRITE_NOTIFY, WriteSecurity::LescMitm) } else { (READ_NOTIFY, WriteSecurity::NoAccess) };
+    CharacteristicSpec {
+        name,
+        uuid: GattUuid::Vendor(uuid),
+        properties,
+        write_security,
+        encoding,
+        presentation_format: Some(DESCRIPTION_UNKNOWN),
+        user_description: Some(description),
+        user_description_writable: false,
+    }
+}
+
+/// The voltage channels first, then the samples, the elapsed time and the timeout
+pub(crate) const ADC_SERVICE: ServiceSpec = ServiceSpec {
+    name: "AdcService",
+    uuid: GattUuid::Vendor("5c853275-723b-4754-a329-969d8bc8121d"),
+    doc: "SAADC channel voltages, the channel labels are writable User Descriptions",
+    descriptors: &[],
+    characteristics: &[
+        adc_voltage("voltage0", "00002b18-0000-1000-8000-00805f9b34fb", 0, "AIN0"),
+        adc_voltage("voltage1", "00002b18-0001-1000-8000-00805f9b34fb", 1, "AIN2"),
+        adc_voltage("voltage2", "00002b18-0002-1000-8000-00805f9b34fb", 2, "AIN3"),
+        adc_voltage("voltage3", "00002b18-0003-1000-8000-00805f9b34fb", 3, "AIN4"),
+        adc_voltage("voltage4", "00002b18-0004-1000-8000-00805f9b34fb", 4, "AIN5"),
+        adc_voltage("voltage5", "00002b18-0005-1000-8000-00805f9b34fb", 5, "AIN6"),
+        adc_voltage("voltage6", "00002b18-0006-1000-8000-00805f9b34fb", 6, "AIN7"),
+        adc_characteristic("samples", "A0E4D2BA-0000-8000-0000-00805f9b34fb", false, COUNT, "Samples"),
+        adc_characteristic("elapsed", "A0E4D2BA-0001-8000-0000-00805f9b34fb", false, ELAPSED_US, "Sampling time"),
+        adc_characteristic(
+            "timeout",
+            "a0e4d2ba-0002-8000-0000-00805f9b34fb",
+            true,
+            TIMEOUT_MS,
+            "Notification interval",
+        ),
+    ],
+};
+
+/// Little endian bytes of a "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx" UUID, as the softdevice takes them
+pub(crate) const fn uuid_128(uuid: &str) -> [u8; 16] {
+    let text = uuid.as_bytes();
+    let mut bytes = [0u8; 16];
+    let mut index = 0;
+    let mut nibbles = 0;
+    while index < text.len() {
+        let nibble = match text[index] {
+            b'-' => {
+                index += 1;
+                continue;
+            }
+            c @ b'0'..=b'9' => c - b'0',
+            c @ b'a'..=b'f' => c - b'a' + 10,
+            c @ b'A'..=b'F' => c - b'A' + 10,
+            _ => panic!("invalid UUID"),
+        };
+        bytes[15 - nibbles / 2] |= if nibbles % 2 == 0 { nibble << 4 } else { nibble };
+        nibbles += 1;
+        index += 1;
+    }
+    assert!(nibbles == 32, "invalid UUID");
+    bytes
+}
@@ -0,0 +1,364 @@
+use core::future::Future;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use futures::{FutureExt, pin_mut, select_biased};
+use heapless::Vec;
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::ble::gatt_server::{IndicateValueError, NotifyValueError};
+use nrf_softdevice::RawError;
+
+use crate::{ble_debug, ble_error};
+use crate::common::ble::{FLASH_MANAGER, HISTORY_SERVICE_EVENTS, SERVER, trigger_all_sensor_update};
+use crate::common::ble::racp;
+use crate::common::ble::racp::{RacpRequest, RecordFilter, ResponseCode};
+use crate::common::ble::services::HistoryServiceEvent;
+use crate::common::ble::snapshot::{SNAPSHOT, Snapshot};
+use crate::common::device::config::{
+    ALL_TASK_COMPLETION_INTERVAL, BLE_HISTORY_CHUNK_LEN, BLE_RACP_LEN, DEFAULT_HISTORY_INTERVAL, HISTORY_MIN_INTERVAL,
+};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::history_storage::{HISTORY_RECORD_LEN, HistoryRecord, HistorySettings};
+use crate::common::util::history_log::HISTORY;
+use crate::common::util::wall_clock::CLOCK;
+
+const NOTIFY_RETRIES: usize = 10;
+const NOTIFY_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+static INTERVAL: AtomicU32 = AtomicU32::new(DEFAULT_HISTORY_INTERVAL);
+static INTERVAL_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+type HistoryEvent = (Connection, HistoryServiceEvent);
+
+fn record_from_snapshot(snapshot: &Snapshot) -> HistoryRecord {
+    HistoryRecord {
+        sequence: 0,
+        time: CLOCK.now_seconds(),
+        bme: snapshot.bme,
+        battery: snapshot.battery,
+        luminous_flux: snapshot.color.map(|color| color[5]),
+        nrf_temperature: snapshot.nrf_temperature,
+        deleted: false,
+    }
+}
+
+fn reflect_range() {
+    let mut range = [0u8; 8];
+    range[0..4].copy_from_slice(&HISTORY.oldest_sequence().to_le_bytes());
+    range[4..8].copy_from_slice(&HISTORY.next_sequence().to_le_bytes());
+    if let Err(err) = SERVER.get().history.range_set(&range) {
+        info!("Failed to set history range {:?}", err);
+    }
+}
+
+pub(crate) fn history_interval() -> u32 {
+    INTERVAL.load(Ordering::Relaxed)
+}
+
+pub(crate) async fn restore_history_settings() -> Result<(), FlashManagerError> {
+    let settings: Option<HistorySettings> = FLASH_MANAGER.get().read_setting().await?;
+    if let Some(settings) = settings {
+        INTERVAL.store(settings.interval, Ordering::Relaxed);
+    }
+    if let Err(err) = SERVER.get().history.interval_set(&history_interval()) {
+        info!("Failed to set history interval {:?}", err);
+    }
+    reflect_range();
+
+    Ok(())
+}
+
+/// Seconds between the samples, 0 turns the logger off
+pub(crate) async fn set_history_interval(interval: u32) {
+    if interval != 0 && interval < HISTORY_MIN_INTERVAL {
+        ble_debug!("History interval must be 0 or at least {} s", HISTORY_MIN_INTERVAL);
+        let _ = SERVER.get().history.interval_set(&history_interval());
+        return;
+    }
+
+    INTERVAL.store(interval, Ordering::Relaxed);
+    INTERVAL_CHANGED.signal(());
+    let _ = SERVER.get().history.interval_set(&interval);
+    if let Err(err) = FLASH_MANAGER.get().write_setting(&HistorySettings { interval }).await {
+        info!("Failed to persist history settings: {:?}", err);
+    }
+}
+
+/// Samples every sensor at the history interval, whether anyone is connected or not
+#[embassy_executor::task]
+pub(crate) async fn history_task() {
+    loop {
+        let interval = history_interval();
+        if interval == 0 {
+            INTERVAL_CHANGED.wait().await;
+            continue;
+        }
+        select_biased! {
+            _ = INTERVAL_CHANGED.wait().fuse() => continue,
+            _ = Timer::after(Duration::from_secs(interval as u64)).fuse() => {}
+        }
+
+        trigger_all_sensor_update();
+        // the color sensor is the slowest one
+        Timer::after(ALL_TASK_COMPLETION_INTERVAL).await;
+
+        match HISTORY.append(record_from_snapshot(&SNAPSHOT.latest())).await {
+            Ok(sequence) => info!("Stored history record {}", sequence),
+            Err(err) => ble_error!("Failed to store history record: {:?}", err),
+        }
+        reflect_range();
+    }
+}
+
+async fn notify(connection: &Connection, chunk: &Vec<u8, BLE_HISTORY_CHUNK_LEN>) -> Result<(), NotifyValueError> {
+    let mut retries = 0;
+    loop {
+        match SERVER.get().history.records_notify(connection, chunk) {
+            // the softdevice TX queue is full
+            Err(NotifyValueError::Raw(RawError::Resources)) if retries < NOTIFY_RETRIES => {
+                retries += 1;
+                Timer::after(NOTIFY_RETRY_INTERVAL).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The records a transfer or a RACP procedure walks through, `[from, to)` in sequence order
+struct Selection {
+    from: u32,
+    to: u32,
+    /// Inclusive wall clock seconds, records stored before the clock was set never match
+    time: Option<(u32, u32)>,
+    limit: usize,
+}
+
+impl Selection {
+    fn from_sequence(sequence: u32) -> Self {
+        Self { from: sequence.max(HISTORY.oldest_sequence()), to: u32::MAX, time: None, limit: usize::MAX }
+    }
+
+    async fn from_filter(filter: RecordFilter) -> Self {
+        let oldest = HISTORY.oldest_sequence();
+        let next = HISTORY.next_sequence();
+        let mut selection = Self { from: oldest, to: next, time: None, limit: usize::MAX };
+        match filter {
+            RecordFilter::All => {}
+            RecordFilter::Sequence(min, max) => {
+                selection.from = min.max(oldest);
+                selection.to = max.saturating_add(1).min(next);
+            }
+            RecordFilter::Time(min, max) => selection.time = Some((min, max)),
+            RecordFilter::First => selection.limit = 1,
+            RecordFilter::Last(count) => {
+                // deleted records leave holes, so count the live ones back from the newest
+                let mut found = 0;
+                selection.from = next;
+                while found < count && selection.from > oldest {
+                    selection.from -= 1;
+                    if matches!(HISTORY.read(selection.from).await, Ok(Some(_))) {
+                        found += 1;
+                    }
+                }
+            }
+        }
+        selection
+    }
+
+    /// Records appended in the meantime are taken as well unless the range has an upper bound
+    async fn next(&mut self) -> Option<HistoryRecord> {
+        while self.limit > 0 && self.from < self.to.min(HISTORY.next_sequence()) {
+            let sequence = self.from;
+            self.from += 1;
+            match HISTORY.read(sequence).await {
+                Ok(Some(record)) if self.matches(&record) => {
+                    self.limit -= 1;
+                    return Some(record);
+                }
+                Ok(_) => {}
+                Err(err) => info!("Failed to read history record {}: {:?}", sequence, err),
+            }
+        }
+        None
+    }
+
+    fn matches(&self, record: &HistoryRecord) -> bool {
+        self.time.map_or(true, |(min, max)| record.time != 0 && (min..=max).contains(&record.time))
+    }
+}
+
+/// Notifies the selected records, as many per notification as the ATT_MTU allows;
+/// returns the number of records sent
+async fn send_records(connection: &Connection, selection: &mut Selection) -> Result<usize, NotifyValueError> {
+    let chunk_len = (connection.att_mtu() as usize).saturating_sub(3).min(BLE_HISTORY_CHUNK_LEN);
+    if chunk_len < HISTORY_RECORD_LEN {
+        ble_debug!("ATT_MTU is too small for history records");
+        return Ok(0);
+    }
+
+    let mut chunk: Vec<u8, BLE_HISTORY_CHUNK_LEN> = Vec::new();
+    let mut sent = 0;
+    while let Some(record) = selection.next().await {
+        if chunk.len() + HISTORY_RECORD_LEN > chunk_len {
+            notify(connection, &chunk).await?;
+            chunk.clear();
+        }
+        let _ = chunk.extend_from_slice(&record.serialize());
+        sent += 1;
+    }
+    if !chunk.is_empty() {
+        notify(connection, &chunk).await?;
+    }
+    Ok(sent)
+}
+
+async fn indicate(connection: &Connection, value: &[u8]) {
+    let Ok(value) = Vec::<u8, BLE_RACP_LEN>::from_slice(value) else {
+        return;
+    };
+    let history = &SERVER.get().history;
+    // a client that has not enabled the indications still finds the response in the value
+    let _ = history.racp_set(&value);
+
+    let mut retries = 0;
+    loop {
+        match history.racp_indicate(connection, &value) {
+            // the previous indication has not been confirmed yet
+            Err(IndicateValueError::Raw(RawError::Busy | RawError::Resources)) if retries < NOTIFY_RETRIES => {
+                retries += 1;
+                Timer::after(NOTIFY_RETRY_INTERVAL).await;
+            }
+            Err(err) => {
+                info!("Failed to indicate RACP response: {:?}", err);
+                return;
+            }
+            Ok(()) => return,
+        }
+    }
+}
+
+async fn respond(connection: &Connection, request_opcode: u8, code: ResponseCode) {
+    indicate(connection, &racp::response(request_opcode, code)).await
+}
+
+/// Deletions and counts run to completion, reports go through `run_transfer`
+async fn handle_racp(connection: &Connection, request: RacpRequest, next_event: &mut Option<HistoryEvent>) {
+    let opcode = request.opcode();
+    match request {
+        RacpRequest::Report(filter) => {
+            let mut selection = Selection::from_filter(filter).await;
+            match run_transfer(connection, send_records(connection, &mut selection), next_event).await {
+                Some(Ok(0)) => respond(connection, opcode, ResponseCode::NoRecordsFound).await,
+                Some(Ok(_)) => respond(connection, opcode, ResponseCode::Success).await,
+                Some(Err(err)) => info!("History report stopped: {:?}", err),
+                None => {}
+            }
+        }
+        RacpRequest::Delete(filter) => {
+            let mut selection = Selection::from_filter(filter).await;
+            let mut deleted = 0;
+            while let Some(record) = selection.next().await {
+                match HISTORY.delete(record.sequence).await {
+                    Ok(true) => deleted += 1,
+                    Ok(false) => {}
+                    Err(err) => info!("Failed to delete history record {}: {:?}", record.sequence, err),
+                }
+            }
+            ble_debug!("Deleted {} history records", deleted);
+            let code = if deleted > 0 { ResponseCode::Success } else { ResponseCode::NoRecordsFound };
+            respond(connection, opcode, code).await;
+        }
+        RacpRequest::Count(filter) => {
+            let mut selection = Selection::from_filter(filter).await;
+            let mut count = 0usize;
+            while selection.next().await.is_some() {
+                count += 1;
+            }
+            indicate(connection, &racp::number_of_records(count.min(u16::MAX as usize) as u16)).await;
+        }
+        // there is nothing to abort
+        RacpRequest::Abort => respond(connection, opcode, ResponseCode::Success).await,
+    }
+}
+
+/// Runs a transfer while serving the events that arrive meanwhile; returns None if it has been
+/// stopped: by a RACP abort, by the client turning the notifications off or by a new download request
+async fn run_transfer<F>(
+    connection: &Connection,
+    transfer: F,
+    next_event: &mut Option<HistoryEvent>,
+) -> Option<Result<usize, NotifyValueError>>
+where
+    F: Future<Output = Result<usize, NotifyValueError>>,
+{
+    let transfer = transfer.fuse();
+    pin_mut!(transfer);
+    loop {
+        let (event_connection, event) = select_biased! {
+            result = transfer => return Some(result),
+            event = HISTORY_SERVICE_EVENTS.receive().fuse() => event,
+        };
+        match event {
+            HistoryServiceEvent::IntervalWrite(interval) => set_history_interval(interval).await,
+            HistoryServiceEvent::RacpWrite(data) => match RacpRequest::parse(&data) {
+                Ok(RacpRequest::Abort) => {
+                    respond(&event_connection, RacpRequest::Abort.opcode(), ResponseCode::Success).await;
+                    return None;
+                }
+                Ok(request) => respond(&event_connection, request.opcode(), ResponseCode::ProcedureNotCompleted).await,
+                Err((opcode, code)) => respond(&event_connection, opcode, code).await,
+            },
+            HistoryServiceEvent::RecordsWrite(_) => {
+                *next_event = Some((event_connection, event));
+                return None;
+            }
+            HistoryServiceEvent::RecordsCccdWrite { notifications: false }
+                if event_connection.handle() == connection.handle() =>
+            {
+                return None;
+            }
+            HistoryServiceEvent::RecordsCccdWrite { .. } | HistoryServiceEvent::RacpCccdWrite { .. } => {}
+        }
+    }
+}
+
+/// Write [sequence: u32] to records for a plain download, the client resumes from the last received
+/// sequence + 1; RACP procedures are served one at a time as well
+#[embassy_executor::task]
+pub(crate) async fn history_transfer_task() {
+    let mut next_event = None;
+
+    loop {
+        let (connection, event) = match next_event.take() {
+            Some(event) => event,
+            None => HISTORY_SERVICE_EVENTS.receive().await,
+        };
+        match event {
+            HistoryServiceEvent::IntervalWrite(interval) => set_history_interval(interval).await,
+            HistoryServiceEvent::RecordsWrite(request) => {
+                let Ok(sequence) = request.as_slice().try_into().map(u32::from_le_bytes) else {
+                    ble_debug!("History request must be a u32 sequence");
+                    continue;
+                };
+
+                let mut selection = Selection::from_sequence(sequence);
+                // an empty notification marks the end
+                let transfer = async {
+                    let sent = send_records(&connection, &mut selection).await?;
+                    notify(&connection, &Vec::new()).await.map(|_| sent)
+                };
+                if let Some(Err(err)) = run_transfer(&connection, transfer, &mut next_event).await {
+                    info!("History transfer stopped: {:?}", err);
+                }
+            }
+            HistoryServiceEvent::RacpWrite(data) => match RacpRequest::parse(&data) {
+                Ok(request) => handle_racp(&connection, request, &mut next_event).await,
+                Err((opcode, code)) => respond(&connection, opcode, code).await,
+            },
+            HistoryServiceEvent::RecordsCccdWrite { .. } | HistoryServiceEvent::RacpCccdWrite { .. } => {}
+        }
+    }
+}
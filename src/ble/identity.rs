@@ -0,0 +1,138 @@
+use alloc::string::{String, ToString};
+use core::cell::RefCell;
+use core::str;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use heapless::Vec;
+use nrf_softdevice::raw;
+
+use crate::ble_debug;
+use crate::common::ble::{FLASH_MANAGER, SERVER};
+use crate::common::ble::advertising::ADVERTISING_RESTART;
+use crate::common::device::config::{BLE_DEVICE_NAME_LEN, BLE_LOCATION_LEN, DEFAULT_DEVICE_NAME};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::identity_storage::DeviceIdentity;
+use crate::common::device::ui::{DISPLAY_REFRESH_EVENTS, UI_STORE};
+use crate::common::device::ui::controls::DisplayRefreshType;
+
+static IDENTITY: Mutex<ThreadModeRawMutex, RefCell<DeviceIdentity>> =
+    Mutex::new(RefCell::new(DeviceIdentity::new()));
+
+pub(crate) fn device_identity() -> DeviceIdentity {
+    IDENTITY.lock(|identity| identity.borrow().clone())
+}
+
+fn default_identity() -> DeviceIdentity {
+    DeviceIdentity {
+        name: Vec::from_slice(DEFAULT_DEVICE_NAME.as_bytes()).unwrap_or_default(),
+        location: Vec::new(),
+    }
+}
+
+/// Only peers paired with a passkey may write the GAP Device Name characteristic
+pub(crate) fn device_name_write_perm() -> raw::ble_gap_conn_sec_mode_t {
+    raw::ble_gap_conn_sec_mode_t {
+        // security mode 1, level 3: encrypted link with MITM protection
+        _bitfield_1: raw::ble_gap_conn_sec_mode_t::new_bitfield_1(1, 3),
+    }
+}
+
+fn set_gap_device_name(name: &[u8]) {
+    let write_perm = device_name_write_perm();
+    let ret = unsafe { raw::sd_ble_gap_device_name_set(&write_perm, name.as_ptr(), name.len() as u16) };
+    if ret != raw::NRF_SUCCESS {
+        info!("Failed to set GAP device name: {}", ret);
+    }
+}
+
+fn get_gap_device_name() -> Option<Vec<u8, BLE_DEVICE_NAME_LEN>> {
+    let mut buf = [0u8; BLE_DEVICE_NAME_LEN];
+    let mut len = BLE_DEVICE_NAME_LEN as u16;
+    let ret = unsafe { raw::sd_ble_gap_device_name_get(buf.as_mut_ptr(), &mut len) };
+    if ret != raw::NRF_SUCCESS {
+        info!("Failed to get GAP device name: {}", ret);
+        return None;
+    }
+    Vec::from_slice(&buf[..(len as usize).min(BLE_DEVICE_NAME_LEN)]).ok()
+}
+
+/// Pushes the identity to the softdevice, the diagnostics characteristics and the EPD
+async fn apply_identity(identity: DeviceIdentity) {
+    set_gap_device_name(&identity.name);
+
+    let server = SERVER.get();
+    if let Err(err) = server.diagnostics.device_name_set(&identity.name) {
+        info!("Failed to set device name characteristic {:?}", err);
+    }
+    if let Err(err) = server.diagnostics.location_set(&identity.location) {
+        info!("Failed to set location characteristic {:?}", err);
+    }
+
+    {
+        let mut store = UI_STORE.lock().await;
+        store.name = as_text(&identity.name);
+        store.location = as_text(&identity.location);
+    }
+
+    IDENTITY.lock(|current| *current.borrow_mut() = identity);
+}
+
+fn as_text(value: &[u8]) -> String {
+    str::from_utf8(value).unwrap_or_default().to_string()
+}
+
+async fn update_identity(update: impl FnOnce(&mut DeviceIdentity)) {
+    let mut identity = device_identity();
+    update(&mut identity);
+    if identity == device_identity() {
+        return;
+    }
+
+    if let Err(err) = FLASH_MANAGER.get().write_setting(&identity).await {
+        info!("Failed to persist device identity: {:?}", err);
+    }
+    apply_identity(identity).await;
+
+    ADVERTISING_RESTART.signal(());
+    let _ = DISPLAY_REFRESH_EVENTS.try_send(DisplayRefreshType::Partial);
+}
+
+pub(crate) async fn restore_device_identity() -> Result<(), FlashManagerError> {
+    let identity: Option<DeviceIdentity> = FLASH_MANAGER.get().read_setting().await?;
+    apply_identity(identity.unwrap_or_else(default_identity)).await;
+    Ok(())
+}
+
+/// An empty name falls back to the default one, so the device never advertises without a name
+pub(crate) async fn set_device_name(value: Vec<u8, BLE_DEVICE_NAME_LEN>) {
+    if str::from_utf8(&value).is_err() {
+        ble_debug!("Device name must be UTF-8");
+        let _ = SERVER.get().diagnostics.device_name_set(&device_identity().name);
+        return;
+    }
+    let name = if value.is_empty() { default_identity().name } else { value };
+    update_identity(|identity| identity.name = name).await;
+}
+
+pub(crate) async fn set_location(value: Vec<u8, BLE_LOCATION_LEN>) {
+    if str::from_utf8(&value).is_err() {
+        ble_debug!("Location must be UTF-8");
+        let _ = SERVER.get().diagnostics.location_set(&device_identity().location);
+        return;
+    }
+    update_identity(|identity| identity.location = value).await;
+}
+
+/// Centrals can also rename the device through the GAP service, which is handled by
+/// the softdevice itself; the new name is picked up once the connection is closed
+pub(crate) async fn sync_gap_device_name() {
+    let Some(name) = get_gap_device_name() else {
+        return;
+    };
+    if name != device_identity().name {
+        info!("GAP device name has been changed by a peer");
+        set_device_name(name).await;
+    }
+}
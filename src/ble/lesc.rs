@@ -0,0 +1,121 @@
+//! LE Secure Connections: the P-256 key pair of the device and the Diffie-Hellman key the softdevice asks for
+//! during pairing. The softdevice keeps the coordinates and the DHKey little-endian, SEC1 is big-endian.
+
+use p256::ecdh::diffie_hellman;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+
+pub(crate) const LESC_PUBLIC_KEY_LEN: usize = 64;
+pub(crate) const LESC_DHKEY_LEN: usize = 32;
+const COORDINATE_LEN: usize = 32;
+
+pub(crate) struct LescKeyPair {
+    secret: SecretKey,
+    /// [x: 32][y: 32], little-endian each
+    public_key: [u8; LESC_PUBLIC_KEY_LEN],
+}
+
+impl LescKeyPair {
+    /// None if the random bytes are not a valid private key (zero or not below the curve order), draw new ones then
+    pub(crate) fn from_random(bytes: &[u8; COORDINATE_LEN]) -> Option<Self> {
+        let secret = SecretKey::from_slice(bytes).ok()?;
+        let point = secret.public_key().to_encoded_point(false);
+
+        let mut public_key = [0u8; LESC_PUBLIC_KEY_LEN];
+        public_key[..COORDINATE_LEN].copy_from_slice(point.x()?);
+        public_key[COORDINATE_LEN..].copy_from_slice(point.y()?);
+        public_key[..COORDINATE_LEN].reverse();
+        public_key[COORDINATE_LEN..].reverse();
+
+        Some(Self { secret, public_key })
+    }
+
+    pub(crate) fn public_key(&self) -> &[u8; LESC_PUBLIC_KEY_LEN] {
+        &self.public_key
+    }
+
+    /// None if the peer key is not a point of the curve, the pairing has to fail then
+    pub(crate) fn dhkey(&self, peer_public_key: &[u8; LESC_PUBLIC_KEY_LEN]) -> Option<[u8; LESC_DHKEY_LEN]> {
+        let mut sec1 = [0u8; 1 + LESC_PUBLIC_KEY_LEN];
+        // uncompressed point
+        sec1[0] = 0x04;
+        sec1[1..].copy_from_slice(peer_public_key);
+        sec1[1..1 + COORDINATE_LEN].reverse();
+        sec1[1 + COORDINATE_LEN..].reverse();
+        let peer_public_key = PublicKey::from_sec1_bytes(&sec1).ok()?;
+
+        let shared_secret = diffie_hellman(self.secret.to_nonzero_scalar(), peer_public_key.as_affine());
+        let mut dhkey = [0u8; LESC_DHKEY_LEN];
+        dhkey.copy_from_slice(shared_secret.raw_secret_bytes());
+        dhkey.reverse();
+        Some(dhkey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Big-endian hex as printed in the specification
+    fn be(hex: &str) -> [u8; COORDINATE_LEN] {
+        let hex: String = hex.split_whitespace().collect();
+        core::array::from_fn(|index| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).unwrap())
+    }
+
+    /// The byte order of the softdevice
+    fn le(hex: &str) -> [u8; COORDINATE_LEN] {
+        let mut bytes = be(hex);
+        bytes.reverse();
+        bytes
+    }
+
+    fn public_key(x: &str, y: &str) -> [u8; LESC_PUBLIC_KEY_LEN] {
+        let mut key = [0u8; LESC_PUBLIC_KEY_LEN];
+        key[..COORDINATE_LEN].copy_from_slice(&le(x));
+        key[COORDINATE_LEN..].copy_from_slice(&le(y));
+        key
+    }
+
+    // Bluetooth Core Specification, Vol 3, Part H, 2.3.5.6.1 P-256 sample data
+    const PRIVATE_A: &str = "3f49f6d4 a3c55f38 74c9b3e3 d2103f50 4aff607b eb40b799 5899b8a6 cd3c1abd";
+    const PUBLIC_A_X: &str = "20b003d2 f297be2c 5e2c83a7 e9f9a5b9 eff49111 acf4fddb cc030148 0e359de6";
+    const PUBLIC_A_Y: &str = "dc809c49 652aeb6d 63329abf 5a52155c 766345c2 8fed3024 741c8ed0 1589d28b";
+    const PRIVATE_B: &str = "55188b3d 32f6bb9a 900afcfb eed4e72a 59cb9ac2 f19d7cfb 6b4fdd49 f47fc5fd";
+    const PUBLIC_B_X: &str = "1ea1f0f0 1faf1d96 09592284 f19e4c00 47b58afd 8615a69f 559077b2 2faaa190";
+    const PUBLIC_B_Y: &str = "4c55f33e 429dad37 7356703a 9ab85160 472d1130 e28e3676 5f89aff9 15b1214a";
+    const DHKEY: &str = "ec0234a3 57c8ad05 341010a6 0a397d9b 99796b13 b4f866f1 868d34f3 73bfa698";
+
+    #[test]
+    fn public_key_of_the_specification_sample() {
+        let key_pair = LescKeyPair::from_random(&be(PRIVATE_A)).unwrap();
+        assert_eq!(key_pair.public_key(), &public_key(PUBLIC_A_X, PUBLIC_A_Y));
+
+        let key_pair = LescKeyPair::from_random(&be(PRIVATE_B)).unwrap();
+        assert_eq!(key_pair.public_key(), &public_key(PUBLIC_B_X, PUBLIC_B_Y));
+    }
+
+    #[test]
+    fn dhkey_of_the_specification_sample() {
+        let a = LescKeyPair::from_random(&be(PRIVATE_A)).unwrap();
+        let b = LescKeyPair::from_random(&be(PRIVATE_B)).unwrap();
+
+        assert_eq!(a.dhkey(b.public_key()), Some(le(DHKEY)));
+        assert_eq!(b.dhkey(a.public_key()), Some(le(DHKEY)));
+    }
+
+    #[test]
+    fn rejects_peer_key_off_the_curve() {
+        let a = LescKeyPair::from_random(&be(PRIVATE_A)).unwrap();
+        let mut peer_public_key = public_key(PUBLIC_B_X, PUBLIC_B_Y);
+        peer_public_key[COORDINATE_LEN] ^= 0x01;
+
+        assert_eq!(a.dhkey(&peer_public_key), None);
+        assert_eq!(a.dhkey(&[0u8; LESC_PUBLIC_KEY_LEN]), None);
+    }
+
+    #[test]
+    fn rejects_invalid_private_key() {
+        assert!(LescKeyPair::from_random(&[0u8; COORDINATE_LEN]).is_none());
+        assert!(LescKeyPair::from_random(&[0xFF; COORDINATE_LEN]).is_none());
+    }
+}
@@ -2,25 +2,56 @@ use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::mutex::Mutex;
 use nrf_softdevice::ble::Connection;
+use nrf_softdevice::Flash;
 
-use crate::common::ble::event_processor::{
-    AccelerometerNotificationSettings, AdcNotificationSettings, BmeNotificationSettings,
-    ColorNotificationSettings, DiNotificationSettings, EventProcessor,
+use crate::common::ble::adc::AdcServiceEvent;
+use crate::common::ble::security::Bonder;
+use crate::common::ble::ess::EnvironmentalSensingServiceEvent;
+use crate::common::ble::event_processor::EventProcessor;
+use crate::common::ble::notification_settings::{
+    AccelerometerNotificationSettings, AdcNotificationSettings, BatteryNotificationSettings, BmeNotificationSettings,
+    ColorNotificationSettings, DiagnosticsNotificationSettings, EssNotificationSettings, SnapshotNotificationSettings,
 };
-use crate::common::ble::services::{AccelerometerServiceEvent, AdcServiceEvent, BleServer, Bme280ServiceEvent, ColorServiceEvent, DeviceInformationServiceEvent, ExpanderServiceEvent};
-use crate::common::device::config::NUM_CONNECTIONS;
+use crate::common::ble::services::{AccelerometerServiceEvent, BatteryServiceEvent, BleServer, BroadcastServiceEvent, Bme280ServiceEvent, ColorServiceEvent, DfuServiceEvent, DiagnosticsServiceEvent, ExpanderServiceEvent, HistoryServiceEvent, NusServiceEvent, SnapshotServiceEvent};
+use crate::common::ble::bthome::is_broadcast_enabled;
+use crate::common::device::config::{BLE_DFU_QUEUE_LEN, BLE_SHELL_QUEUE_LEN, BTHOME_SAMPLING_INTERVAL, NUM_CONNECTIONS};
 use crate::common::device::persistence::flash_manager::FlashManager;
 use crate::common::util::custom_static_cell::CustomStaticCell;
 
+pub(crate) mod adc;
+pub(crate) mod advertising;
+pub(crate) mod bme_config;
+pub(crate) mod boot_health;
+pub(crate) mod bthome;
+pub(crate) mod bthome_payload;
+pub(crate) mod conn_params;
 pub(crate) mod conv;
+pub(crate) mod current_time;
+pub(crate) mod device_info;
+pub(crate) mod dfu;
+pub(crate) mod encoding;
+pub(crate) mod ess;
 pub(crate) mod event_processor;
+pub(crate) mod gatt_builder;
+pub(crate) mod gatt_table;
 pub(crate) mod helper_macro;
+pub(crate) mod history;
+pub(crate) mod identity;
+pub(crate) mod lesc;
+pub(crate) mod notification_settings;
+pub(crate) mod processor;
+pub(crate) mod racp;
+pub(crate) mod security;
 pub(crate) mod services;
+pub(crate) mod shell;
+pub(crate) mod snapshot;
 pub(crate) mod softdevice;
 pub(crate) mod traits;
+pub(crate) mod weather;
 
 pub(crate) static SERVER: CustomStaticCell<BleServer> = CustomStaticCell::new();
-pub(crate) static FLASH_MANAGER: CustomStaticCell<FlashManager> = CustomStaticCell::new();
+pub(crate) static FLASH_MANAGER: CustomStaticCell<FlashManager<Flash>> = CustomStaticCell::new();
+pub(crate) static BONDER: Bonder = Bonder::new();
 
 pub(crate) static ADC_SERVICE_EVENTS: Channel<
     ThreadModeRawMutex,
@@ -34,9 +65,9 @@ pub(crate) static BME_SERVICE_EVENTS: Channel<
     NUM_CONNECTIONS,
 > = Channel::new();
 
-pub(crate) static DI_SERVICE_EVENTS: Channel<
+pub(crate) static DIAGNOSTICS_SERVICE_EVENTS: Channel<
     ThreadModeRawMutex,
-    (Connection, DeviceInformationServiceEvent),
+    (Connection, DiagnosticsServiceEvent),
     NUM_CONNECTIONS,
 > = Channel::new();
 
@@ -52,6 +83,48 @@ pub(crate) static COLOR_SERVICE_EVENTS: Channel<
     NUM_CONNECTIONS,
 > = Channel::new();
 
+pub(crate) static ESS_SERVICE_EVENTS: Channel<
+    ThreadModeRawMutex,
+    (Connection, EnvironmentalSensingServiceEvent),
+    NUM_CONNECTIONS,
+> = Channel::new();
+
+pub(crate) static BATTERY_SERVICE_EVENTS: Channel<
+    ThreadModeRawMutex,
+    (Connection, BatteryServiceEvent),
+    NUM_CONNECTIONS,
+> = Channel::new();
+
+pub(crate) static SNAPSHOT_SERVICE_EVENTS: Channel<
+    ThreadModeRawMutex,
+    (Connection, SnapshotServiceEvent),
+    NUM_CONNECTIONS,
+> = Channel::new();
+
+pub(crate) static BROADCAST_SERVICE_EVENTS: Channel<
+    ThreadModeRawMutex,
+    (Connection, BroadcastServiceEvent),
+    NUM_CONNECTIONS,
+> = Channel::new();
+
+pub(crate) static HISTORY_SERVICE_EVENTS: Channel<
+    ThreadModeRawMutex,
+    (Connection, HistoryServiceEvent),
+    NUM_CONNECTIONS,
+> = Channel::new();
+
+pub(crate) static SHELL_EVENTS: Channel<
+    ThreadModeRawMutex,
+    (Connection, NusServiceEvent),
+    BLE_SHELL_QUEUE_LEN,
+> = Channel::new();
+
+pub(crate) static DFU_SERVICE_EVENTS: Channel<
+    ThreadModeRawMutex,
+    (Connection, DfuServiceEvent),
+    BLE_DFU_QUEUE_LEN,
+> = Channel::new();
+
 pub(crate) static SPI_EXPANDER_EVENTS: Channel<
     ThreadModeRawMutex,
     (Connection, ExpanderServiceEvent),
@@ -61,8 +134,8 @@ pub(crate) static SPI_EXPANDER_EVENTS: Channel<
 pub(crate) static SPI_EXPANDER_LOCK_OWNER: Mutex<ThreadModeRawMutex, Option<Connection>> = Mutex::new(None);
 
 pub(crate) static DEVICE_EVENT_PROCESSOR: EventProcessor<
-    DiNotificationSettings,
-    DeviceInformationServiceEvent,
+    DiagnosticsNotificationSettings,
+    DiagnosticsServiceEvent,
     2,
 > = EventProcessor::new(Some("device"));
 pub(crate) static BME_EVENT_PROCESSOR: EventProcessor<
@@ -83,6 +156,47 @@ pub(crate) static COLOR_EVENT_PROCESSOR: EventProcessor<
     1,
 > = EventProcessor::new(Some("color"));
 
+/// ESS readings come from both the BME280 and the VEML6040 tasks
+pub(crate) static ESS_EVENT_PROCESSOR: EventProcessor<
+    EssNotificationSettings,
+    EnvironmentalSensingServiceEvent,
+    2,
+> = EventProcessor::new(Some("ess"));
+
+pub(crate) static BATTERY_EVENT_PROCESSOR: EventProcessor<
+    BatteryNotificationSettings,
+    BatteryServiceEvent,
+    1,
+> = EventProcessor::new(Some("battery"));
+
+pub(crate) static SNAPSHOT_EVENT_PROCESSOR: EventProcessor<
+    SnapshotNotificationSettings,
+    SnapshotServiceEvent,
+    1,
+> = EventProcessor::new(Some("snapshot"));
+
+/// Snapshot subscribers and the BTHome broadcast need readings without subscribing to the
+/// individual characteristics, so they keep the sensor tasks running on their own cadence.
+pub(crate) async fn update_external_sensor_demand() {
+    let snapshot = if SNAPSHOT_EVENT_PROCESSOR.is_enabled() {
+        Some(SNAPSHOT_EVENT_PROCESSOR.get_timeout_duration())
+    } else {
+        None
+    };
+    let broadcast = if is_broadcast_enabled() { Some(BTHOME_SAMPLING_INTERVAL) } else { None };
+    let combined = match (snapshot, broadcast) {
+        (Some(snapshot), Some(broadcast)) => Some(snapshot.min(broadcast)),
+        (snapshot, broadcast) => snapshot.or(broadcast),
+    };
+
+    // BTHome carries battery, BME and illuminance readings only;
+    // the battery task runs whenever the device processor is enabled
+    DEVICE_EVENT_PROCESSOR.set_external_timeout(combined).await;
+    BME_EVENT_PROCESSOR.set_external_timeout(combined).await;
+    COLOR_EVENT_PROCESSOR.set_external_timeout(combined).await;
+    ADC_EVENT_PROCESSOR.set_external_timeout(snapshot).await;
+    ACCELEROMETER_EVENT_PROCESSOR.set_external_timeout(snapshot).await;
+}
 
 pub(crate) fn trigger_all_sensor_update() {
     // Fire event twice, since one event will be consumed by NRF temperature task
@@ -0,0 +1,116 @@
+//! What each connection has subscribed to, per service; the event processors keep one per connection.
+
+use crate::common::ble::traits::IsTaskEnabled;
+use crate::impl_is_task_enabled;
+
+#[derive(Default, Clone)]
+pub(crate) struct AccelerometerNotificationSettings {
+    pub(crate) x: bool,
+    pub(crate) y: bool,
+    pub(crate) z: bool,
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct ColorNotificationSettings {
+    pub(crate) red: bool,
+    pub(crate) green: bool,
+    pub(crate) blue: bool,
+    pub(crate) white: bool,
+    pub(crate) cct: bool,
+    pub(crate) lux: bool,
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct AdcNotificationSettings {
+    pub(crate) voltage0: bool,
+    pub(crate) voltage1: bool,
+    pub(crate) voltage2: bool,
+    pub(crate) voltage3: bool,
+    pub(crate) voltage4: bool,
+    pub(crate) voltage5: bool,
+    pub(crate) voltage6: bool,
+    pub(crate) samples: bool,
+    pub(crate) elapsed: bool,
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct BmeNotificationSettings {
+    pub(crate) temperature: bool,
+    pub(crate) humidity: bool,
+    pub(crate) pressure: bool,
+    pub(crate) dew_point: bool,
+    pub(crate) frost_point: bool,
+    pub(crate) absolute_humidity: bool,
+    pub(crate) heat_index: bool,
+    pub(crate) humidex: bool,
+    pub(crate) vapour_pressure_deficit: bool,
+    pub(crate) sea_level_pressure: bool,
+    pub(crate) pressure_altitude: bool,
+    pub(crate) pressure_tendency: bool,
+    pub(crate) pressure_trend: bool,
+    pub(crate) forecast: bool,
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct EssNotificationSettings {
+    pub(crate) temperature: bool,
+    pub(crate) humidity: bool,
+    pub(crate) pressure: bool,
+    pub(crate) illuminance: bool,
+    pub(crate) dew_point: bool,
+    pub(crate) heat_index: bool,
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct BatteryNotificationSettings {
+    pub(crate) battery_level: bool,
+    pub(crate) battery_level_status: bool,
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct SnapshotNotificationSettings {
+    pub(crate) snapshot: bool,
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct DiagnosticsNotificationSettings {
+    pub(crate) temperature: bool,
+    pub(crate) battery_voltage: bool,
+    pub(crate) debug: bool,
+}
+
+impl_is_task_enabled!(BatteryNotificationSettings, battery_level, battery_level_status);
+impl_is_task_enabled!(SnapshotNotificationSettings, snapshot);
+impl_is_task_enabled!(
+    BmeNotificationSettings,
+    humidity,
+    pressure,
+    temperature,
+    dew_point,
+    frost_point,
+    absolute_humidity,
+    heat_index,
+    humidex,
+    vapour_pressure_deficit,
+    sea_level_pressure,
+    pressure_altitude,
+    pressure_tendency,
+    pressure_trend,
+    forecast
+);
+impl_is_task_enabled!(EssNotificationSettings, temperature, humidity, pressure, illuminance, dew_point, heat_index);
+impl_is_task_enabled!(DiagnosticsNotificationSettings, debug, battery_voltage, temperature);
+impl_is_task_enabled!(
+    AdcNotificationSettings,
+    voltage0,
+    voltage1,
+    voltage2,
+    voltage3,
+    voltage4,
+    voltage5,
+    voltage6,
+    elapsed,
+    samples
+);
+impl_is_task_enabled!(ColorNotificationSettings, red, green, blue, white);
+impl_is_task_enabled!(AccelerometerNotificationSettings, x, y, z);
@@ -0,0 +1,161 @@
+use alloc::collections::BTreeMap;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use crate::common::ble::traits::{IsTaskEnabled, SettingsEventConsumer, TimeoutEventCharacteristic};
+use crate::common::device::config::{DEFAULT_NOTIFICATION_TIMEOUT, NOTIFICATION_TIMEOUT_TOLERANCE};
+use crate::common::util::condition::{Condition, ConditionToken};
+
+/// Notification settings and the notification cadence of a single connection
+struct ConnectionState<S> {
+    settings: S,
+    /// Set once the connection writes its own timeout; until then it is notified on every sample
+    timeout: Option<Duration>,
+    /// Last notification instant per `notify_all!` call site
+    last_notified: BTreeMap<&'static str, Instant>,
+}
+
+impl<S: Default> Default for ConnectionState<S> {
+    fn default() -> Self {
+        Self { settings: S::default(), timeout: None, last_notified: BTreeMap::new() }
+    }
+}
+
+impl<S> ConnectionState<S> {
+    fn effective_timeout(&self) -> Duration {
+        self.timeout.unwrap_or(DEFAULT_NOTIFICATION_TIMEOUT)
+    }
+
+    fn is_due(&mut self, key: &'static str, now: Instant) -> bool {
+        let Some(timeout) = self.timeout else {
+            return true;
+        };
+
+        if let Some(last_notified) = self.last_notified.get(key) {
+            // samples are taken at the shortest interval, so they never land exactly on this one
+            if now.duration_since(*last_notified) + NOTIFICATION_TIMEOUT_TOLERANCE < timeout {
+                return false;
+            }
+        }
+
+        self.last_notified.insert(key, now);
+        true
+    }
+}
+
+/// `K` identifies a connection, the softdevice `Connection` on the device
+pub(crate) struct EventProcessor<S, E, K, const T: usize> {
+    notification_settings: Mutex<ThreadModeRawMutex, BTreeMap<K, ConnectionState<S>>>,
+    /// The shortest timeout among connections that have notifications enabled
+    timeout: AtomicU32,
+    /// Timeout requested on behalf of another service's subscribers, 0 if there are none
+    external_timeout: AtomicU32,
+    condition: Condition<T>,
+    _phantom_data: PhantomData<E>,
+}
+
+impl<S, E, K, const T: usize> EventProcessor<S, E, K, T>
+    where
+        S: Default + SettingsEventConsumer<E> + IsTaskEnabled + Clone,
+        E: TimeoutEventCharacteristic,
+        K: Ord + Clone,
+{
+    pub(crate) const fn new(name: Option<&'static str>) -> Self {
+        Self {
+            notification_settings: Mutex::new(BTreeMap::new()),
+            timeout: AtomicU32::new(DEFAULT_NOTIFICATION_TIMEOUT.as_millis() as u32),
+            external_timeout: AtomicU32::new(0),
+            condition: Condition::new(name),
+            _phantom_data: PhantomData,
+        }
+    }
+
+    pub(crate) async fn process_event(&self, connection: K, event: E) {
+        let mut settings_map = self.notification_settings.lock().await;
+        let state = settings_map.entry(connection).or_default();
+        if let Some(timeout) = event.get_timeout() {
+            state.timeout = Some(Duration::from_millis(timeout as u64));
+        }
+        state.settings.consume(event).await;
+
+        self.set_task_enabled_state(&settings_map);
+    }
+
+    /// Sensor tasks sample at this interval, connections with longer timeouts skip samples
+    pub(crate) fn get_timeout_duration(&self) -> Duration {
+        Duration::from_millis(self.timeout.load(Ordering::Relaxed) as u64)
+    }
+
+    fn set_task_enabled_state(&self, settings: &BTreeMap<K, ConnectionState<S>>) {
+        let external_timeout = match self.external_timeout.load(Ordering::SeqCst) {
+            0 => None,
+            timeout => Some(Duration::from_millis(timeout as u64)),
+        };
+
+        let should_enable = external_timeout.is_some()
+            || settings.values().any(|state| state.settings.is_task_enabled());
+        self.condition.set(should_enable);
+
+        let timeout = settings
+            .values()
+            .filter(|state| state.settings.is_task_enabled())
+            .map(|state| state.effective_timeout())
+            .chain(external_timeout)
+            .min()
+            .unwrap_or(DEFAULT_NOTIFICATION_TIMEOUT);
+        self.timeout.store(timeout.as_millis() as u32, Ordering::SeqCst);
+    }
+
+    /// Keeps the sensor task running for subscribers of another service, i.e. the snapshot
+    pub(crate) async fn set_external_timeout(&self, timeout: Option<Duration>) {
+        let timeout = timeout.map(|timeout| (timeout.as_millis() as u32).max(1)).unwrap_or(0);
+        self.external_timeout.store(timeout, Ordering::SeqCst);
+
+        let settings_map = self.notification_settings.lock().await;
+        self.set_task_enabled_state(&settings_map);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.condition.is_enabled()
+    }
+
+    pub(crate) fn fire_once(&self) {
+        self.condition.fire_once();
+    }
+
+    pub(crate) async fn register_connection(&self, connection: &K) {
+        _ = self.notification_settings.lock().await.entry(connection.clone()).or_default();
+    }
+
+    pub(crate) async fn drop_connection(&self, connection: &K) {
+        let mut settings_map = self.notification_settings.lock().await;
+        settings_map.remove(connection);
+        self.set_task_enabled_state(&settings_map);
+    }
+
+    pub(crate) async fn wait_for_condition(&self) -> ConditionToken<T> {
+        self.condition.lock().await
+    }
+
+    pub(crate) async fn get_connection_settings(&self, connection: &K) -> Option<S> {
+        self.notification_settings.lock().await.get(connection).map(|state| state.settings.clone())
+    }
+
+    /// Returns connection settings along with a flag telling whether the connection's own timeout
+    /// has elapsed since the last notification from the `key` call site.
+    /// A positive answer counts as a notification.
+    pub(crate) async fn get_due_connection_settings(
+        &self,
+        connection: &K,
+        key: &'static str,
+    ) -> Option<(S, bool)> {
+        let mut settings_map = self.notification_settings.lock().await;
+        let state = settings_map.get_mut(connection)?;
+        let is_due = state.is_due(key, Instant::now());
+        Some((state.settings.clone(), is_due))
+    }
+}
@@ -0,0 +1,274 @@
+//! Record Access Control Point (0x2A52) as in the Glucose and CGM profiles, over the offline history.
+//! Requests are [opcode][operator][operand..], responses are indicated on the same characteristic.
+
+/// Requests take an operator, the responses carry the null operator
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Opcode {
+    ReportRecords = 0x01,
+    DeleteRecords = 0x02,
+    Abort = 0x03,
+    ReportNumberOfRecords = 0x04,
+    /// [count: u16]
+    NumberOfRecordsResponse = 0x05,
+    /// [request opcode][response code]
+    ResponseCode = 0x06,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Self::ReportRecords),
+            0x02 => Some(Self::DeleteRecords),
+            0x03 => Some(Self::Abort),
+            0x04 => Some(Self::ReportNumberOfRecords),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, defmt::Format)]
+#[repr(u8)]
+pub(crate) enum ResponseCode {
+    Success = 0x01,
+    OpcodeNotSupported = 0x02,
+    InvalidOperator = 0x03,
+    OperatorNotSupported = 0x04,
+    InvalidOperand = 0x05,
+    NoRecordsFound = 0x06,
+    AbortUnsuccessful = 0x07,
+    ProcedureNotCompleted = 0x08,
+    OperandNotSupported = 0x09,
+}
+
+const OPERATOR_NULL: u8 = 0x00;
+const OPERATOR_ALL: u8 = 0x01;
+const OPERATOR_LESS_OR_EQUAL: u8 = 0x02;
+const OPERATOR_GREATER_OR_EQUAL: u8 = 0x03;
+const OPERATOR_WITHIN_RANGE: u8 = 0x04;
+const OPERATOR_FIRST: u8 = 0x05;
+const OPERATOR_LAST: u8 = 0x06;
+
+/// Operands of the comparison operators start with the filter type
+const FILTER_SEQUENCE: u8 = 0x01;
+/// Wall clock seconds as stored in the records, see `wall_clock`
+const FILTER_TIME: u8 = 0x02;
+
+/// Both bounds are inclusive
+#[derive(Copy, Clone, PartialEq, Eq, Debug, defmt::Format)]
+pub(crate) enum RecordFilter {
+    All,
+    Sequence(u32, u32),
+    Time(u32, u32),
+    First,
+    /// The last N records; the count is an extension, a plain "last record" has no operand
+    Last(u16),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, defmt::Format)]
+pub(crate) enum RacpRequest {
+    Report(RecordFilter),
+    Delete(RecordFilter),
+    Count(RecordFilter),
+    Abort,
+}
+
+impl RacpRequest {
+    /// On failure returns the opcode to respond to along with the response code
+    pub(crate) fn parse(data: &[u8]) -> Result<Self, (u8, ResponseCode)> {
+        let (&opcode, rest) = data.split_first().ok_or((0, ResponseCode::OpcodeNotSupported))?;
+        let fail = |code| (opcode, code);
+        let Some(parsed_opcode) = Opcode::from_u8(opcode) else {
+            return Err(fail(ResponseCode::OpcodeNotSupported));
+        };
+        let (&operator, operand) = rest.split_first().ok_or(fail(ResponseCode::InvalidOperator))?;
+
+        if parsed_opcode == Opcode::Abort {
+            return match operator {
+                OPERATOR_NULL if operand.is_empty() => Ok(Self::Abort),
+                OPERATOR_NULL => Err(fail(ResponseCode::InvalidOperand)),
+                _ => Err(fail(ResponseCode::InvalidOperator)),
+            };
+        }
+
+        let filter = parse_filter(operator, operand).map_err(fail)?;
+        Ok(match parsed_opcode {
+            Opcode::ReportRecords => Self::Report(filter),
+            Opcode::DeleteRecords => Self::Delete(filter),
+            _ => Self::Count(filter),
+        })
+    }
+
+    pub(crate) fn opcode(&self) -> u8 {
+        match self {
+            Self::Report(_) => Opcode::ReportRecords as u8,
+            Self::Delete(_) => Opcode::DeleteRecords as u8,
+            Self::Count(_) => Opcode::ReportNumberOfRecords as u8,
+            Self::Abort => Opcode::Abort as u8,
+        }
+    }
+}
+
+fn parse_filter(operator: u8, operand: &[u8]) -> Result<RecordFilter, ResponseCode> {
+    let u32_at = |offset: usize| -> Result<u32, ResponseCode> {
+        operand
+            .get(offset..offset + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or(ResponseCode::InvalidOperand)
+    };
+
+    let filter = match operator {
+        OPERATOR_ALL | OPERATOR_FIRST if !operand.is_empty() => return Err(ResponseCode::InvalidOperand),
+        OPERATOR_ALL => return Ok(RecordFilter::All),
+        OPERATOR_FIRST => return Ok(RecordFilter::First),
+        OPERATOR_LAST => {
+            return match operand {
+                [] => Ok(RecordFilter::Last(1)),
+                [low, high] => match u16::from_le_bytes([*low, *high]) {
+                    0 => Err(ResponseCode::InvalidOperand),
+                    count => Ok(RecordFilter::Last(count)),
+                },
+                _ => Err(ResponseCode::InvalidOperand),
+            };
+        }
+        OPERATOR_LESS_OR_EQUAL | OPERATOR_GREATER_OR_EQUAL if operand.len() != 5 => {
+            return Err(ResponseCode::InvalidOperand)
+        }
+        OPERATOR_LESS_OR_EQUAL => (0, u32_at(1)?),
+        OPERATOR_GREATER_OR_EQUAL => (u32_at(1)?, u32::MAX),
+        OPERATOR_WITHIN_RANGE if operand.len() != 9 => return Err(ResponseCode::InvalidOperand),
+        OPERATOR_WITHIN_RANGE => match (u32_at(1)?, u32_at(5)?) {
+            (min, max) if min > max => return Err(ResponseCode::InvalidOperand),
+            range => range,
+        },
+        OPERATOR_NULL => return Err(ResponseCode::InvalidOperator),
+        _ => return Err(ResponseCode::OperatorNotSupported),
+    };
+
+    match operand[0] {
+        FILTER_SEQUENCE => Ok(RecordFilter::Sequence(filter.0, filter.1)),
+        FILTER_TIME => Ok(RecordFilter::Time(filter.0, filter.1)),
+        _ => Err(ResponseCode::OperandNotSupported),
+    }
+}
+
+/// [response code opcode][null operator][request opcode][response code]
+pub(crate) fn response(request_opcode: u8, code: ResponseCode) -> [u8; 4] {
+    [Opcode::ResponseCode as u8, OPERATOR_NULL, request_opcode, code as u8]
+}
+
+/// [number of records opcode][null operator][count: u16]
+pub(crate) fn number_of_records(count: u16) -> [u8; 4] {
+    let count = count.to_le_bytes();
+    [Opcode::NumberOfRecordsResponse as u8, OPERATOR_NULL, count[0], count[1]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: u8 = Opcode::ReportRecords as u8;
+    const DELETE: u8 = Opcode::DeleteRecords as u8;
+    const ABORT: u8 = Opcode::Abort as u8;
+    const COUNT: u8 = Opcode::ReportNumberOfRecords as u8;
+
+    fn parse(data: &[u8]) -> Result<RacpRequest, (u8, ResponseCode)> {
+        RacpRequest::parse(data)
+    }
+
+    #[test]
+    fn opcodes() {
+        assert_eq!(parse(&[REPORT, OPERATOR_ALL]), Ok(RacpRequest::Report(RecordFilter::All)));
+        assert_eq!(parse(&[DELETE, OPERATOR_ALL]), Ok(RacpRequest::Delete(RecordFilter::All)));
+        assert_eq!(parse(&[COUNT, OPERATOR_ALL]), Ok(RacpRequest::Count(RecordFilter::All)));
+        assert_eq!(parse(&[ABORT, OPERATOR_NULL]), Ok(RacpRequest::Abort));
+
+        assert_eq!(RacpRequest::Report(RecordFilter::First).opcode(), REPORT);
+        assert_eq!(RacpRequest::Delete(RecordFilter::First).opcode(), DELETE);
+        assert_eq!(RacpRequest::Count(RecordFilter::First).opcode(), COUNT);
+        assert_eq!(RacpRequest::Abort.opcode(), ABORT);
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(parse(&[REPORT, OPERATOR_FIRST]), Ok(RacpRequest::Report(RecordFilter::First)));
+        assert_eq!(parse(&[REPORT, OPERATOR_LAST]), Ok(RacpRequest::Report(RecordFilter::Last(1))));
+        assert_eq!(parse(&[REPORT, OPERATOR_LAST, 0x2C, 0x01]), Ok(RacpRequest::Report(RecordFilter::Last(300))));
+        assert_eq!(
+            parse(&[REPORT, OPERATOR_LESS_OR_EQUAL, FILTER_SEQUENCE, 0x10, 0x00, 0x00, 0x00]),
+            Ok(RacpRequest::Report(RecordFilter::Sequence(0, 16)))
+        );
+        assert_eq!(
+            parse(&[DELETE, OPERATOR_GREATER_OR_EQUAL, FILTER_SEQUENCE, 0x10, 0x00, 0x00, 0x00]),
+            Ok(RacpRequest::Delete(RecordFilter::Sequence(16, u32::MAX)))
+        );
+        assert_eq!(
+            parse(&[COUNT, OPERATOR_GREATER_OR_EQUAL, FILTER_TIME, 0x00, 0xF1, 0x53, 0x65]),
+            Ok(RacpRequest::Count(RecordFilter::Time(1_700_000_000, u32::MAX)))
+        );
+        assert_eq!(
+            parse(&[REPORT, OPERATOR_WITHIN_RANGE, FILTER_TIME, 0x00, 0xF1, 0x53, 0x65, 0x10, 0xFF, 0x53, 0x65]),
+            Ok(RacpRequest::Report(RecordFilter::Time(1_700_000_000, 1_700_003_600)))
+        );
+        // a single record
+        assert_eq!(
+            parse(&[REPORT, OPERATOR_WITHIN_RANGE, FILTER_SEQUENCE, 0x07, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00]),
+            Ok(RacpRequest::Report(RecordFilter::Sequence(7, 7)))
+        );
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let invalid = |data: &[u8]| {
+            assert_eq!(parse(data), Err((data[0], ResponseCode::InvalidOperand)), "{:02x?}", data);
+        };
+
+        invalid(&[REPORT, OPERATOR_ALL, FILTER_SEQUENCE]);
+        invalid(&[REPORT, OPERATOR_FIRST, FILTER_SEQUENCE]);
+        invalid(&[REPORT, OPERATOR_LAST, 0x01]);
+        invalid(&[REPORT, OPERATOR_LAST, 0x01, 0x00, 0x00]);
+        invalid(&[REPORT, OPERATOR_LAST, 0x00, 0x00]);
+        invalid(&[REPORT, OPERATOR_LESS_OR_EQUAL]);
+        invalid(&[REPORT, OPERATOR_LESS_OR_EQUAL, FILTER_SEQUENCE, 0x10, 0x00, 0x00]);
+        invalid(&[DELETE, OPERATOR_GREATER_OR_EQUAL, FILTER_SEQUENCE, 0x10, 0x00, 0x00, 0x00, 0x00]);
+        invalid(&[COUNT, OPERATOR_WITHIN_RANGE, FILTER_SEQUENCE, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00]);
+        invalid(&[COUNT, OPERATOR_WITHIN_RANGE, FILTER_SEQUENCE, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00]);
+        // the range is reversed
+        invalid(&[REPORT, OPERATOR_WITHIN_RANGE, FILTER_SEQUENCE, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        invalid(&[ABORT, OPERATOR_NULL, 0x00]);
+    }
+
+    #[test]
+    fn unsupported_operators() {
+        assert_eq!(parse(&[REPORT]), Err((REPORT, ResponseCode::InvalidOperator)));
+        assert_eq!(parse(&[REPORT, OPERATOR_NULL]), Err((REPORT, ResponseCode::InvalidOperator)));
+        assert_eq!(parse(&[ABORT]), Err((ABORT, ResponseCode::InvalidOperator)));
+        assert_eq!(parse(&[ABORT, OPERATOR_ALL]), Err((ABORT, ResponseCode::InvalidOperator)));
+        assert_eq!(parse(&[DELETE, 0x07]), Err((DELETE, ResponseCode::OperatorNotSupported)));
+        assert_eq!(parse(&[COUNT, 0xFF, FILTER_SEQUENCE]), Err((COUNT, ResponseCode::OperatorNotSupported)));
+        assert_eq!(
+            parse(&[REPORT, OPERATOR_LESS_OR_EQUAL, 0x03, 0x10, 0x00, 0x00, 0x00]),
+            Err((REPORT, ResponseCode::OperandNotSupported))
+        );
+    }
+
+    #[test]
+    fn unsupported_opcodes() {
+        for opcode in [0x00, Opcode::NumberOfRecordsResponse as u8, Opcode::ResponseCode as u8, 0x07, 0xFF] {
+            assert_eq!(parse(&[opcode, OPERATOR_ALL]), Err((opcode, ResponseCode::OpcodeNotSupported)));
+            assert_eq!(parse(&[opcode]), Err((opcode, ResponseCode::OpcodeNotSupported)));
+        }
+    }
+
+    #[test]
+    fn empty_request() {
+        assert_eq!(parse(&[]), Err((0, ResponseCode::OpcodeNotSupported)));
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(response(DELETE, ResponseCode::NoRecordsFound), [0x06, 0x00, 0x02, 0x06]);
+        assert_eq!(number_of_records(300), [0x05, 0x00, 0x2C, 0x01]);
+    }
+}
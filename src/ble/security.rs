@@ -0,0 +1,228 @@
+use core::cell::{Cell, RefCell};
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use heapless::Vec;
+use nrf_softdevice::ble::{Connection, EncryptionInfo, gatt_server, IdentityKey, MasterId, SecurityMode};
+use nrf_softdevice::ble::security::{IoCapabilities, SecurityHandler};
+use nrf_softdevice::Softdevice;
+
+use crate::ble_error;
+use crate::common::ble::advertising::{is_accept_list_only, is_coded_phy, restore_advertising_policy};
+use crate::common::ble::lesc::{LESC_DHKEY_LEN, LESC_PUBLIC_KEY_LEN, LescKeyPair};
+use crate::common::ble::{BONDER, FLASH_MANAGER};
+use crate::common::device::config::{BLE_MAX_BONDS, BLE_SYS_ATTRS_LEN};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::bond_storage::BondPage;
+use crate::common::device::ui::controls::DisplayRefreshType;
+use crate::common::device::ui::DISPLAY_REFRESH_EVENTS;
+
+/// Fired whenever bonds or the advertising policy change and need to be written to flash
+pub(crate) static BOND_STORE_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+#[derive(Clone)]
+pub(crate) struct Bond {
+    pub(crate) master_id: MasterId,
+    pub(crate) key: EncryptionInfo,
+    pub(crate) peer_id: IdentityKey,
+    pub(crate) sys_attrs: Vec<u8, BLE_SYS_ATTRS_LEN>,
+}
+
+/// Softdevice RNG pool refills while the key pair is drawn
+const LESC_RANDOM_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Keeps LTKs, peer identities and CCCD states of bonded centrals.
+/// The device has an e-paper display, so it acts as a display-only device during pairing:
+/// the passkey is rendered on the screen and must be typed on the central.
+/// Pairing uses LE Secure Connections, the P-256 key pair is drawn once per boot.
+pub(crate) struct Bonder {
+    bonds: Mutex<ThreadModeRawMutex, RefCell<Vec<Bond, BLE_MAX_BONDS>>>,
+    passkey: Mutex<ThreadModeRawMutex, Cell<Option<[u8; 6]>>>,
+    lesc_key_pair: Mutex<ThreadModeRawMutex, RefCell<Option<LescKeyPair>>>,
+}
+
+impl Bonder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            bonds: Mutex::new(RefCell::new(Vec::new())),
+            passkey: Mutex::new(Cell::new(None)),
+            lesc_key_pair: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Has to run before advertising starts, without a key pair the central can only pair with legacy pairing,
+    /// which the `LescMitm` characteristics reject
+    pub(crate) async fn generate_lesc_key_pair(&self, sd: &Softdevice) {
+        let mut random = [0u8; 32];
+        let key_pair = loop {
+            match nrf_softdevice::random_bytes(sd, &mut random) {
+                Ok(()) => {
+                    if let Some(key_pair) = LescKeyPair::from_random(&random) {
+                        break key_pair;
+                    }
+                }
+                Err(err) => info!("Waiting for the RNG pool: {:?}", err),
+            }
+            Timer::after(LESC_RANDOM_RETRY_INTERVAL).await;
+        };
+        random.fill(0);
+        self.lesc_key_pair.lock(|current| *current.borrow_mut() = Some(key_pair));
+    }
+
+    /// Passkey that is currently being displayed for an ongoing pairing
+    pub(crate) fn pending_passkey(&self) -> Option<[u8; 6]> {
+        self.passkey.lock(|passkey| passkey.get())
+    }
+
+    pub(crate) fn clear_passkey(&self) {
+        if self.passkey.lock(|passkey| passkey.take()).is_some() {
+            let _ = DISPLAY_REFRESH_EVENTS.try_send(DisplayRefreshType::Partial);
+        }
+    }
+
+    pub(crate) fn bonds(&self) -> Vec<Bond, BLE_MAX_BONDS> {
+        self.bonds.lock(|bonds| bonds.borrow().clone())
+    }
+
+    pub(crate) fn identities(&self) -> Vec<IdentityKey, BLE_MAX_BONDS> {
+        self.bonds.lock(|bonds| bonds.borrow().iter().map(|bond| bond.peer_id).collect())
+    }
+
+    pub(crate) fn restore(&self, restored: Vec<Bond, BLE_MAX_BONDS>) {
+        self.bonds.lock(|bonds| *bonds.borrow_mut() = restored);
+    }
+
+    fn upsert_bond(&self, bond: Bond) {
+        self.bonds.lock(|bonds| {
+            let mut bonds = bonds.borrow_mut();
+            bonds.retain(|existing| existing.peer_id.addr != bond.peer_id.addr);
+            if bonds.is_full() {
+                // the oldest bond is evicted
+                bonds.remove(0);
+            }
+            let _ = bonds.push(bond);
+        });
+    }
+}
+
+impl SecurityHandler for Bonder {
+    fn io_capabilities(&self) -> IoCapabilities {
+        IoCapabilities::DisplayOnly
+    }
+
+    fn can_bond(&self, _conn: &Connection) -> bool {
+        true
+    }
+
+    /// Some sets the LESC flag in the pairing parameters
+    fn lesc_public_key(&self) -> Option<[u8; LESC_PUBLIC_KEY_LEN]> {
+        self.lesc_key_pair.lock(|key_pair| key_pair.borrow().as_ref().map(|key_pair| *key_pair.public_key()))
+    }
+
+    /// Replied to the DHKey request of the softdevice, None fails the pairing
+    fn lesc_dhkey(
+        &self,
+        _conn: &Connection,
+        peer_public_key: &[u8; LESC_PUBLIC_KEY_LEN],
+    ) -> Option<[u8; LESC_DHKEY_LEN]> {
+        let dhkey = self.lesc_key_pair.lock(|key_pair| key_pair.borrow().as_ref()?.dhkey(peer_public_key));
+        if dhkey.is_none() {
+            ble_error!("Rejected the pairing: the LESC public key of the central is not on the curve");
+        }
+        dhkey
+    }
+
+    fn display_passkey(&self, passkey: &[u8; 6]) {
+        info!("Pairing passkey: {:a}", passkey);
+        self.passkey.lock(|current| current.set(Some(*passkey)));
+        let _ = DISPLAY_REFRESH_EVENTS.try_send(DisplayRefreshType::Partial);
+    }
+
+    fn on_security_update(&self, conn: &Connection, security_mode: SecurityMode) {
+        info!("Security mode for {:?} changed to {:?}", conn.peer_address(), security_mode);
+        self.clear_passkey();
+    }
+
+    fn on_bonded(&self, _conn: &Connection, master_id: MasterId, key: EncryptionInfo, peer_id: IdentityKey) {
+        info!("Bonded with {:?}", peer_id.addr);
+        self.upsert_bond(Bond { master_id, key, peer_id, sys_attrs: Vec::new() });
+        self.clear_passkey();
+        BOND_STORE_SIGNAL.signal(());
+    }
+
+    fn get_key(&self, _conn: &Connection, master_id: MasterId) -> Option<EncryptionInfo> {
+        self.bonds.lock(|bonds| {
+            bonds.borrow().iter().find(|bond| bond.master_id == master_id).map(|bond| bond.key)
+        })
+    }
+
+    fn save_sys_attrs(&self, conn: &Connection) {
+        let address = conn.peer_address();
+        self.bonds.lock(|bonds| {
+            let mut bonds = bonds.borrow_mut();
+            let Some(bond) = bonds.iter_mut().find(|bond| bond.peer_id.is_match(address)) else {
+                return;
+            };
+
+            let mut sys_attrs: Vec<u8, BLE_SYS_ATTRS_LEN> = Vec::new();
+            let _ = sys_attrs.resize(BLE_SYS_ATTRS_LEN, 0);
+            match gatt_server::get_sys_attrs(conn, &mut sys_attrs) {
+                Ok(len) => sys_attrs.truncate(len),
+                Err(err) => {
+                    // the CCCDs of the peer are lost on the next connection
+                    ble_error!("Failed to get sys attrs: {:?}", err);
+                    return;
+                }
+            }
+
+            // CCCD states are stored on every disconnect, avoid wearing out the flash
+            if bond.sys_attrs != sys_attrs {
+                bond.sys_attrs = sys_attrs;
+                BOND_STORE_SIGNAL.signal(());
+            }
+        });
+    }
+
+    fn load_sys_attrs(&self, conn: &Connection) {
+        let address = conn.peer_address();
+        self.bonds.lock(|bonds| {
+            let bonds = bonds.borrow();
+            let sys_attrs = bonds
+                .iter()
+                .find(|bond| bond.peer_id.is_match(address))
+                .filter(|bond| !bond.sys_attrs.is_empty())
+                .map(|bond| bond.sys_attrs.as_slice());
+
+            if let Err(err) = gatt_server::set_sys_attrs(conn, sys_attrs) {
+                info!("Failed to set sys attrs: {:?}", err);
+            }
+        });
+    }
+}
+
+/// Bonds share the page with the advertising policy, both are restored at once
+pub(crate) async fn restore_bonds_from_flash() -> Result<(), FlashManagerError> {
+    let page = FLASH_MANAGER.get().read_bond_page().await?;
+    restore_advertising_policy(page.accept_list_only, page.coded_phy);
+    BONDER.restore(page.bonds);
+    Ok(())
+}
+
+#[embassy_executor::task]
+pub(crate) async fn persist_bonds_task() {
+    loop {
+        BOND_STORE_SIGNAL.wait().await;
+
+        let page = BondPage {
+            accept_list_only: is_accept_list_only(),
+            coded_phy: is_coded_phy(),
+            bonds: BONDER.bonds(),
+        };
+        if let Err(err) = FLASH_MANAGER.get().write_bond_page(&page).await {
+            info!("Failed to persist bonds: {:?}", err);
+        }
+    }
+}
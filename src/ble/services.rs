@@ -1,65 +1,164 @@
-use crate::common::device::config::{BLE_DEBUG_ARRAY_LEN, BLE_EXPANDER_BUF_SIZE, BLE_EXPANDER_CONTROL_BYTES_SIZE};
-
+use heapless::Vec;
+
+use crate::common::ble::adc::AdcService;
+use crate::common::ble::encoding::{
+    ABSOLUTE_HUMIDITY, ACCELERATION, ALTITUDE, CCT, COLOR_COUNT, DESCRIPTION_UNKNOWN, ENUMERATION, HUMIDITY,
+    HUMIDITY_OFFSET, INTERVAL_S, LUMINOUS_FLUX, OPAQUE, PERCENTAGE, PRESSURE, PRESSURE_ALTITUDE, PRESSURE_OFFSET,
+    PRESSURE_TENDENCY, RESULT_CODE, TEMPERATURE, TEMPERATURE_OFFSET, TEXT, TIMEOUT_MS, VOLTAGE,
+};
+use crate::common::ble::ess::EnvironmentalSensingService;
+use crate::common::device::config::{
+    BLE_BME_CONFIG_LEN, BLE_CURRENT_TIME_LEN, BLE_DEBUG_ARRAY_LEN, BLE_DEVICE_NAME_LEN, BLE_DFU_CONTROL_LEN,
+    BLE_DFU_DATA_LEN, BLE_DIS_STRING_LEN, BLE_EXPANDER_BUF_SIZE, BLE_EXPANDER_CONTROL_BYTES_SIZE, BLE_HISTORY_CHUNK_LEN,
+    BLE_LOCATION_LEN, BLE_LOG_PAGE_LEN, BLE_NUS_CHUNK_LEN, BLE_RACP_LEN, BLE_SNAPSHOT_LEN, BTHOME_KEY_LEN,
+};
+
+// Writable characteristics use `security = "LescMitm"`: the softdevice rejects access from peers
+// that have not paired over LE Secure Connections with a passkey with ATT "Insufficient Authentication".
+// Every characteristic carries a Presentation Format (0x2904) built from the same `encoding` the value
+// is encoded with, and a read-only User Description (0x2901); the ADC channel labels are writable, see `adc`.
+
+/// Static strings, filled once on startup, see `device_info::populate_device_information`
 #[nrf_softdevice::gatt_service(uuid = "180A")]
 pub(crate) struct DeviceInformationService {
+    #[characteristic(uuid = "2A29", read)]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Manufacturer")]
+    pub(crate) manufacturer_name: Vec<u8, BLE_DIS_STRING_LEN>,
+
+    #[characteristic(uuid = "2A24", read)]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Model")]
+    pub(crate) model_number: Vec<u8, BLE_DIS_STRING_LEN>,
+
+    /// Hex encoded FICR DEVICEID
+    #[characteristic(uuid = "2A25", read)]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Serial number")]
+    pub(crate) serial_number: Vec<u8, BLE_DIS_STRING_LEN>,
+
+    #[characteristic(uuid = "2A27", read)]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Hardware revision")]
+    pub(crate) hardware_revision: Vec<u8, BLE_DIS_STRING_LEN>,
+
+    /// Crate version and git hash of the build
+    #[characteristic(uuid = "2A26", read)]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Firmware revision")]
+    pub(crate) firmware_revision: Vec<u8, BLE_DIS_STRING_LEN>,
+}
+
+#[nrf_softdevice::gatt_service(uuid = "5c853275-623b-4754-a329-969d8bc8121d")]
+pub(crate) struct DiagnosticsService {
     #[characteristic(uuid = "00002b18-0000-1000-8999-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = VOLTAGE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Battery voltage")]
     pub(crate) battery_voltage: u16,
 
     #[characteristic(uuid = "2A6E", read, notify)]
+    #[descriptor(uuid = "2904", value = TEMPERATURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "nRF temperature")]
     pub(crate) temperature: i16,
 
     #[characteristic(uuid = "2BDE", read, notify)]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Debug messages")]
     pub(crate) debug: [u8; BLE_DEBUG_ARRAY_LEN],
 
-    #[characteristic(uuid = "a0e4d2ba-0002-8000-8789-00805f9b34fb", read, write, notify)]
+    #[characteristic(uuid = "a0e4d2ba-0002-8000-8789-00805f9b34fb", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = TIMEOUT_MS.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Notification interval")]
     pub(crate) timeout: u32,
-}
-
-#[nrf_softdevice::gatt_service(uuid = "5c853275-723b-4754-a329-969d8bc8121d")]
-pub(crate) struct AdcService {
-    #[characteristic(uuid = "00002b18-0000-1000-8000-00805f9b34fb", read, notify)]
-    pub(crate) voltage0: u16,
-
-    #[characteristic(uuid = "00002b18-0001-1000-8000-00805f9b34fb", read, notify)]
-    pub(crate) voltage1: u16,
-
-    #[characteristic(uuid = "00002b18-0002-1000-8000-00805f9b34fb", read, notify)]
-    pub(crate) voltage2: u16,
 
-    #[characteristic(uuid = "00002b18-0003-1000-8000-00805f9b34fb", read, notify)]
-    pub(crate) voltage3: u16,
-
-    #[characteristic(uuid = "00002b18-0004-1000-8000-00805f9b34fb", read, notify)]
-    pub(crate) voltage4: u16,
-
-    #[characteristic(uuid = "00002b18-0005-1000-8000-00805f9b34fb", read, notify)]
-    pub(crate) voltage5: u16,
-
-    #[characteristic(uuid = "00002b18-0006-1000-8000-00805f9b34fb", read, notify)]
-    pub(crate) voltage6: u16,
-
-    #[characteristic(uuid = "A0E4D2BA-0000-8000-0000-00805f9b34fb", read, notify)]
-    pub(crate) samples: u16,
-
-    #[characteristic(uuid = "A0E4D2BA-0001-8000-0000-00805f9b34fb", read, notify)]
-    pub(crate) elapsed: u64,
+    /// 1 - only bonded centrals can connect, 0 - anyone can connect
+    #[characteristic(uuid = "a0e4d2ba-0003-8000-8789-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Bonded centrals only")]
+    pub(crate) accept_list_only: u8,
+
+    /// 1 - extended advertising on LE Coded PHY (S8), 0 - legacy advertising on LE 1M PHY
+    #[characteristic(uuid = "a0e4d2ba-0004-8000-8789-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Coded PHY advertising")]
+    pub(crate) coded_phy: u8,
+
+    /// Requests a PHY update for the writing connection: 1 - LE 1M, 2 - LE 2M, 4 - LE Coded
+    #[characteristic(uuid = "a0e4d2ba-0005-8000-8789-00805f9b34fb", write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Connection PHY")]
+    pub(crate) connection_phy: u8,
+
+    /// Connection parameters profile of the writing connection:
+    /// 0 - auto (fast while active, low power when idle), 1 - fast, 2 - balanced, 3 - low power
+    #[characteristic(uuid = "a0e4d2ba-0008-8000-8789-00805f9b34fb", write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Connection profile")]
+    pub(crate) connection_profile: u8,
+
+    /// UTF-8 GAP device name, persisted and advertised; mirrors the GAP Device Name characteristic
+    #[characteristic(uuid = "a0e4d2ba-0006-8000-8789-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Device name")]
+    pub(crate) device_name: Vec<u8, BLE_DEVICE_NAME_LEN>,
+
+    /// UTF-8 location label, e.g. "Greenhouse 2", persisted and sent in the scan response
+    #[characteristic(uuid = "a0e4d2ba-0007-8000-8789-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Location")]
+    pub(crate) location: Vec<u8, BLE_LOCATION_LEN>,
+
+    /// Log backlog page; write a u32 start sequence, then read the entries from it on
+    #[characteristic(uuid = "a0e4d2ba-0009-8000-8789-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Log page")]
+    pub(crate) log: Vec<u8, BLE_LOG_PAGE_LEN>,
+
+    /// [state: 0 confirmed, 1 pending, 2 rolled back][last rollback reason: 0 none, 1 watchdog, 2 other reset]
+    /// [reset reason: u32]
+    #[characteristic(uuid = "a0e4d2ba-000a-8000-8789-00805f9b34fb", read)]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Boot state")]
+    pub(crate) boot_state: [u8; 6],
+}
 
-    #[characteristic(uuid = "a0e4d2ba-0002-8000-0000-00805f9b34fb", read, write, notify)]
-    pub(crate) timeout: u32,
+#[nrf_softdevice::gatt_service(uuid = "180F")]
+pub(crate) struct BatteryService {
+    /// State of charge in percent, derived from the LiPo discharge curve
+    #[characteristic(uuid = "2A19", read, notify)]
+    #[descriptor(uuid = "2904", value = PERCENTAGE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Battery level")]
+    pub(crate) battery_level: u8,
+
+    /// [flags][power state: u16][battery level], see `BatteryState::level_status`
+    #[characteristic(uuid = "2BED", read, notify)]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Battery level status")]
+    pub(crate) battery_level_status: [u8; 4],
 }
 
 #[nrf_softdevice::gatt_service(uuid = "5c853275-723b-4754-a329-969d4bc8121e")]
 pub(crate) struct Bme280Service {
     #[characteristic(uuid = "2A6E", read, notify)]
+    #[descriptor(uuid = "2904", value = TEMPERATURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Temperature")]
     pub(crate) temperature: i16,
 
     #[characteristic(uuid = "2A6F", read, notify)]
+    #[descriptor(uuid = "2904", value = HUMIDITY.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Humidity")]
     pub(crate) humidity: u16,
 
     #[characteristic(uuid = "2A6D", read, notify)]
+    #[descriptor(uuid = "2904", value = PRESSURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Pressure")]
     pub(crate) pressure: u32,
 
-    #[characteristic(uuid = "a0e4a2ba-0000-8000-0000-00805f9b34fb", read, write, notify)]
+    /// Interval of this service's notifications only; the ESS temperature, humidity and pressure follow
+    /// their ES Trigger Settings instead. Neither overrides the other, the BME280 is sampled at the shorter one
+    #[characteristic(uuid = "a0e4a2ba-0000-8000-0000-00805f9b34fb", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = TIMEOUT_MS.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Notification interval")]
     pub(crate) timeout: u32,
 
     // if it's represented as f32 and you write to it from a client, there's a
@@ -68,43 +167,145 @@ pub(crate) struct Bme280Service {
     //       <exception entry>
     //   (HOST) WARN  call stack was corrupted; unwinding could not be completed
     //   (HOST) ERROR the program panicked
-    #[characteristic(uuid = "a0e4a2ba-1234-4321-0001-00805f9b34fb", read, write, notify)]
+    #[characteristic(uuid = "a0e4a2ba-1234-4321-0001-00805f9b34fb", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = HUMIDITY_OFFSET.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Humidity offset")]
     pub(crate) humidity_offset: [u8; 4],
 
-    #[characteristic(uuid = "a0e4a2ba-1234-4321-0002-00805f9b34fb", read, write, notify)]
+    #[characteristic(uuid = "a0e4a2ba-1234-4321-0002-00805f9b34fb", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = TEMPERATURE_OFFSET.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Temperature offset")]
     pub(crate) temperature_offset: [u8; 4],
 
-    #[characteristic(uuid = "a0e4a2ba-1234-4321-0003-00805f9b34fb", read, write, notify)]
+    #[characteristic(uuid = "a0e4a2ba-1234-4321-0003-00805f9b34fb", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = PRESSURE_OFFSET.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Pressure offset")]
     pub(crate) pressure_offset: [u8; 4],
+
+    /// [temperature, pressure, humidity oversampling][IIR filter][standby] as the BME280 register fields:
+    /// oversampling 0 - skipped, 1..5 - 1x..16x; applied to the running sensor and kept in flash
+    #[characteristic(uuid = "a0e4a2bb-1234-4321-0001-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Sensor configuration")]
+    pub(crate) config: [u8; BLE_BME_CONFIG_LEN],
+
+    /// Outcome of the last configuration write: 0 - applied, negative - rejected, see `bme_config`
+    #[characteristic(uuid = "a0e4a2bc-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = RESULT_CODE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Sensor configuration result")]
+    pub(crate) config_result: i8,
+
+    #[characteristic(uuid = "a0e4a2bd-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = TEMPERATURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Dew point")]
+    pub(crate) dew_point: i16,
+
+    /// Equals the dew point above 0 °C
+    #[characteristic(uuid = "a0e4a2be-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = TEMPERATURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Frost point")]
+    pub(crate) frost_point: i16,
+
+    #[characteristic(uuid = "a0e4a2bf-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = ABSOLUTE_HUMIDITY.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Absolute humidity")]
+    pub(crate) absolute_humidity: u16,
+
+    #[characteristic(uuid = "a0e4a2c0-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = TEMPERATURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Heat index")]
+    pub(crate) heat_index: i16,
+
+    #[characteristic(uuid = "a0e4a2c1-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = TEMPERATURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Humidex")]
+    pub(crate) humidex: i16,
+
+    #[characteristic(uuid = "a0e4a2c2-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = PRESSURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Vapour pressure deficit")]
+    pub(crate) vapour_pressure_deficit: u32,
+
+    /// Metres above the sea level the device is installed at, kept in flash
+    #[characteristic(uuid = "a0e4a2c3-1234-4321-0001-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ALTITUDE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Reference altitude")]
+    pub(crate) reference_altitude: i16,
+
+    /// Station pressure reduced to the sea level at the reference altitude (QNH)
+    #[characteristic(uuid = "a0e4a2c4-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = PRESSURE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Sea-level pressure")]
+    pub(crate) sea_level_pressure: u32,
+
+    /// Altitude over the standard 1013.25 hPa
+    #[characteristic(uuid = "a0e4a2c5-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = PRESSURE_ALTITUDE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Pressure altitude")]
+    pub(crate) pressure_altitude: i32,
+
+    /// Change of the station pressure over 3 hours
+    #[characteristic(uuid = "a0e4a2c6-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = PRESSURE_TENDENCY.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Pressure tendency")]
+    pub(crate) pressure_tendency: i16,
+
+    /// 0 - unknown, 1 - falling fast, 2 - falling, 3 - steady, 4 - rising, 5 - rising fast
+    #[characteristic(uuid = "a0e4a2c7-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Pressure trend")]
+    pub(crate) pressure_trend: u8,
+
+    /// Zambretti forecast letter, 1 - `A` (settled fine) .. 26 - `Z` (stormy), 0 - unknown
+    #[characteristic(uuid = "a0e4a2c8-1234-4321-0001-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Forecast")]
+    pub(crate) forecast: u8,
 }
 
 #[nrf_softdevice::gatt_service(uuid = "5c853275-823b-4754-a329-969d4bc8121e")]
 pub(crate) struct AccelerometerService {
     #[characteristic(uuid = "eaeaeaea-0000-0000-0000-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = ACCELERATION.presentation_format(1))]
+    #[descriptor(uuid = "2901", value = "Acceleration X, g")]
     pub(crate) x: f32,
 
     #[characteristic(uuid = "eaeaeaea-0000-1000-0000-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = ACCELERATION.presentation_format(2))]
+    #[descriptor(uuid = "2901", value = "Acceleration Y, g")]
     pub(crate) y: f32,
 
     #[characteristic(uuid = "eaeaeaea-0000-2000-0000-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = ACCELERATION.presentation_format(3))]
+    #[descriptor(uuid = "2901", value = "Acceleration Z, g")]
     pub(crate) z: f32,
 
-    #[characteristic(uuid = "a0e4a2ba-0000-8000-0000-00805f9b34fb", read, write, notify)]
+    #[characteristic(uuid = "a0e4a2ba-0000-8000-0000-00805f9b34fb", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = TIMEOUT_MS.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Notification interval")]
     pub(crate) timeout: u32,
 }
 
 #[nrf_softdevice::gatt_service(uuid = "5c853275-923b-4754-a329-969d4bc8121e")]
 pub(crate) struct ColorService {
     #[characteristic(uuid = "ebbbbaea-a000-0000-0000-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = COLOR_COUNT.presentation_format(1))]
+    #[descriptor(uuid = "2901", value = "Red")]
     pub(crate) red: u16,
 
     #[characteristic(uuid = "eaeaeaea-b000-1000-0000-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = COLOR_COUNT.presentation_format(2))]
+    #[descriptor(uuid = "2901", value = "Green")]
     pub(crate) green: u16,
 
     #[characteristic(uuid = "eaeaeaea-c000-2000-0000-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = COLOR_COUNT.presentation_format(3))]
+    #[descriptor(uuid = "2901", value = "Blue")]
     pub(crate) blue: u16,
 
     #[characteristic(uuid = "eaeaeaea-d000-3000-0000-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = COLOR_COUNT.presentation_format(4))]
+    #[descriptor(uuid = "2901", value = "White")]
     pub(crate) white: u16,
 
     /// uuid: 0x2AE9
@@ -118,6 +319,8 @@ pub(crate) struct ColorService {
     /// A value of 0xFFFF represents ’value is not known’.
     /// uint16
     #[characteristic(uuid = "2AE9", read, notify)]
+    #[descriptor(uuid = "2904", value = CCT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Color temperature")]
     pub(crate) cct: u16,
 
     /// uuid: 0x2AFF
@@ -132,12 +335,138 @@ pub(crate) struct ColorService {
     /// All other values are Prohibited.
     /// uint16
     #[characteristic(uuid = "2AFF", read, notify)]
+    #[descriptor(uuid = "2904", value = LUMINOUS_FLUX.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Luminous flux")]
     pub(crate) lux: u16,
 
-    #[characteristic(uuid = "a0e4a2ba-0000-8000-0000-00805f9b34fb", read, write, notify)]
+    /// Interval of this service's notifications only; the ESS illuminance follows its ES Trigger Setting
+    /// instead. Neither overrides the other, the VEML6040 is sampled at the shorter one
+    #[characteristic(uuid = "a0e4a2ba-0000-8000-0000-00805f9b34fb", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = TIMEOUT_MS.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Notification interval")]
+    pub(crate) timeout: u32,
+}
+
+/// All the latest readings in a single notification, subscribing to it keeps every sensor task running
+#[nrf_softdevice::gatt_service(uuid = "5c853275-a23b-4754-a329-969d4bc8121e")]
+pub(crate) struct SnapshotService {
+    /// [version][presence: u16][sequence: u32][present fields..], see `snapshot::SnapshotField`
+    #[characteristic(uuid = "a0e4d2ba-0000-8000-5350-00805f9b34fb", read, notify)]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Snapshot")]
+    pub(crate) snapshot: Vec<u8, BLE_SNAPSHOT_LEN>,
+
+    #[characteristic(uuid = "a0e4a2ba-0000-8000-0000-00805f9b34fb", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = TIMEOUT_MS.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Notification interval")]
     pub(crate) timeout: u32,
 }
 
+/// Connectionless BTHome v2 broadcast of the latest readings
+#[nrf_softdevice::gatt_service(uuid = "5c853275-b23b-4754-a329-969d4bc8121e")]
+pub(crate) struct BroadcastService {
+    /// 0 - off, 1 - BTHome v2, 2 - BTHome v2 encrypted with AES-CCM
+    #[characteristic(uuid = "a0e4d2ba-0000-8000-4254-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Broadcast mode")]
+    pub(crate) mode: u8,
+
+    /// AES-128 bind key, write only
+    #[characteristic(uuid = "a0e4d2ba-0001-8000-4254-00805f9b34fb", write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Broadcast key")]
+    pub(crate) key: [u8; BTHOME_KEY_LEN],
+}
+
+/// Calendar time, written by the central or read from the central's own Current Time Service and notified
+/// on every adjustment, see `current_time`
+#[nrf_softdevice::gatt_service(uuid = "1805")]
+pub(crate) struct CurrentTimeService {
+    /// [year: u16][month][day][hours][minutes][seconds][day of week][fractions256][adjust reason]
+    #[characteristic(uuid = "2A2B", read, write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Current time")]
+    pub(crate) current_time: [u8; BLE_CURRENT_TIME_LEN],
+}
+
+/// Readings sampled at a fixed interval regardless of connections and kept in flash, see `history`
+#[nrf_softdevice::gatt_service(uuid = "5c853276-c23b-4754-a329-969d4bc8121e")]
+pub(crate) struct HistoryService {
+    /// Seconds between the samples, 0 - off
+    #[characteristic(uuid = "a0e4d2ba-0000-8000-4853-00805f9b34fb", read, write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = INTERVAL_S.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "History interval")]
+    pub(crate) interval: u32,
+
+    /// [oldest sequence: u32][next sequence: u32], equal while the history is empty
+    #[characteristic(uuid = "a0e4d2ba-0001-8000-4853-00805f9b34fb", read)]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "History range")]
+    pub(crate) range: [u8; 8],
+
+    /// Write [sequence: u32] to download the records from it on; every notification carries whole
+    /// records, see `history_storage::HistoryRecord`, an empty one ends the transfer
+    #[characteristic(uuid = "a0e4d2ba-0002-8000-4853-00805f9b34fb", write, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "History records")]
+    pub(crate) records: Vec<u8, BLE_HISTORY_CHUNK_LEN>,
+
+    /// Record Access Control Point, see `racp`; reported records go out as `records` notifications
+    #[characteristic(uuid = "2A52", write, indicate, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Record access control point")]
+    pub(crate) racp: Vec<u8, BLE_RACP_LEN>,
+}
+
+/// Nordic UART Service compatible line based shell, see `shell::shell_task`
+#[nrf_softdevice::gatt_service(uuid = "6E400001-B5A3-F393-E0A9-E50E24DCCA9E")]
+pub(crate) struct NusService {
+    /// Commands from the client, terminated with '\n'; a command may span several writes
+    #[characteristic(
+        uuid = "6E400002-B5A3-F393-E0A9-E50E24DCCA9E",
+        write,
+        write_without_response,
+        security = "LescMitm"
+    )]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Shell input")]
+    pub(crate) rx: Vec<u8, BLE_NUS_CHUNK_LEN>,
+
+    /// Responses, split into ATT_MTU - 3 sized notifications; every response ends with '\n'
+    #[characteristic(uuid = "6E400003-B5A3-F393-E0A9-E50E24DCCA9E", notify)]
+    #[descriptor(uuid = "2904", value = TEXT.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Shell output")]
+    pub(crate) tx: Vec<u8, BLE_NUS_CHUNK_LEN>,
+}
+
+/// Firmware update: announce the image on the control point, write the chunks, verify and apply;
+/// see `dfu::dfu_task` for the opcodes
+#[nrf_softdevice::gatt_service(uuid = "5c853275-c23b-4754-a329-969d4bc8121e")]
+pub(crate) struct DfuService {
+    /// [opcode: u8][arguments..]
+    #[characteristic(uuid = "a0e4d2ba-0000-8000-4446-00805f9b34fb", write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "DFU control point")]
+    pub(crate) control: Vec<u8, BLE_DFU_CONTROL_LEN>,
+
+    /// [offset: u32][image bytes..], chunks are a multiple of 4 bytes except the last one
+    #[characteristic(
+        uuid = "a0e4d2ba-0001-8000-4446-00805f9b34fb",
+        write,
+        write_without_response,
+        security = "LescMitm"
+    )]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "DFU data")]
+    pub(crate) data: Vec<u8, BLE_DFU_DATA_LEN>,
+
+    /// [state: u8][result: u8][offset: u32], notified after every control and data write
+    #[characteristic(uuid = "a0e4d2ba-0002-8000-4446-00805f9b34fb", read, notify, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "DFU status")]
+    pub(crate) status: [u8; 6],
+}
+
 #[nrf_softdevice::gatt_service(uuid = "ac866789-aaaa-eeee-a329-969d4bc8621e")]
 pub(crate) struct ExpanderService {
     /// First byte is control bits
@@ -161,22 +490,34 @@ pub(crate) struct ExpanderService {
     ///     ..mosi
     /// ]
 
-    #[characteristic(uuid = "0000A001-0000-1000-8000-00805F9B34FB", write)]
+    #[characteristic(uuid = "0000A001-0000-1000-8000-00805F9B34FB", write, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Expander transfer")]
     pub(crate) data_bundle: [u8; BLE_EXPANDER_BUF_SIZE + BLE_EXPANDER_CONTROL_BYTES_SIZE],
 
     #[characteristic(uuid = "0000A002-0000-1000-8000-00805F9B34FB", read)]
+    #[descriptor(uuid = "2904", value = OPAQUE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Expander MISO")]
     pub(crate) miso: [u8; BLE_EXPANDER_BUF_SIZE],
 
-    #[characteristic(uuid = "0000A003-0000-1000-8000-00805F9B34FB", write, read)]
+    #[characteristic(uuid = "0000A003-0000-1000-8000-00805F9B34FB", write, read, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Expander chip select")]
     pub(crate) cs: u8,
 
-    #[characteristic(uuid = "0000A004-0000-1000-8000-00805F9B34FB", write, read)]
+    #[characteristic(uuid = "0000A004-0000-1000-8000-00805F9B34FB", write, read, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Expander lock")]
     pub(crate) lock: u8,
 
-    #[characteristic(uuid = "0000A005-0000-1000-8000-00805F9B34FB", write, read)]
+    #[characteristic(uuid = "0000A005-0000-1000-8000-00805F9B34FB", write, read, security = "LescMitm")]
+    #[descriptor(uuid = "2904", value = ENUMERATION.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Expander power")]
     pub(crate) power: u8,
 
     #[characteristic(uuid = "0000A006-0000-1000-8000-00805F9B34FB", notify)]
+    #[descriptor(uuid = "2904", value = RESULT_CODE.presentation_format(DESCRIPTION_UNKNOWN))]
+    #[descriptor(uuid = "2901", value = "Expander result")]
     pub(crate) result: i8,
 }
 
@@ -195,9 +536,18 @@ impl ExpanderServiceEvent {
 #[nrf_softdevice::gatt_server]
 pub(crate) struct BleServer {
     pub(crate) dis: DeviceInformationService,
+    pub(crate) diagnostics: DiagnosticsService,
     pub(crate) adc: AdcService,
     pub(crate) bme280: Bme280Service,
     pub(crate) accelerometer: AccelerometerService,
     pub(crate) color: ColorService,
     pub(crate) expander: ExpanderService,
+    pub(crate) ess: EnvironmentalSensingService,
+    pub(crate) bas: BatteryService,
+    pub(crate) snapshot: SnapshotService,
+    pub(crate) broadcast: BroadcastService,
+    pub(crate) cts: CurrentTimeService,
+    pub(crate) history: HistoryService,
+    pub(crate) nus: NusService,
+    pub(crate) dfu: DfuService,
 }
@@ -0,0 +1,517 @@
+use alloc::collections::BTreeMap;
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Write;
+use core::str;
+
+use defmt::info;
+use embassy_nrf::peripherals;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Timer};
+use heapless::{String, Vec};
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::ble::gatt_server::NotifyValueError;
+use nrf_softdevice::RawError;
+use rclite::Arc;
+
+use crate::common::ble::{
+    ACCELEROMETER_EVENT_PROCESSOR, ACCELEROMETER_SERVICE_EVENTS, ADC_EVENT_PROCESSOR, ADC_SERVICE_EVENTS,
+    BME_EVENT_PROCESSOR, BME_SERVICE_EVENTS, BROADCAST_SERVICE_EVENTS, COLOR_EVENT_PROCESSOR, COLOR_SERVICE_EVENTS,
+    DEVICE_EVENT_PROCESSOR, DIAGNOSTICS_SERVICE_EVENTS, FLASH_MANAGER, SERVER, SHELL_EVENTS,
+    SNAPSHOT_EVENT_PROCESSOR, SNAPSHOT_SERVICE_EVENTS, SPI_EXPANDER_LOCK_OWNER, trigger_all_sensor_update,
+};
+use crate::common::ble::adc::AdcServiceEvent;
+use crate::common::ble::advertising::{is_accept_list_only, is_coded_phy, set_accept_list_only, set_coded_phy};
+use crate::common::ble::conn_params::{CONN_PARAMS, ConnectionProfile};
+use crate::common::ble::current_time::set_time;
+use crate::common::ble::history::{history_interval, set_history_interval};
+use crate::common::ble::identity::{device_identity, set_device_name, set_location};
+use crate::common::ble::services::{
+    AccelerometerServiceEvent, Bme280ServiceEvent, BroadcastServiceEvent, ColorServiceEvent, DiagnosticsServiceEvent,
+    NusServiceEvent, SnapshotServiceEvent,
+};
+use crate::common::ble::weather::{reference_altitude, set_reference_altitude};
+use crate::common::device::config::{
+    ALL_TASK_COMPLETION_INTERVAL, BLE_DEBUG_ARRAY_LEN, BLE_NUS_CHUNK_LEN, BLE_SHELL_LINE_LEN, BLE_SHELL_RESPONSE_LEN,
+    NUM_CONNECTIONS,
+};
+use crate::common::device::error::{ExpanderError, ShellError};
+use crate::common::device::expander::{handle_i2c_exec, handle_power};
+use crate::common::device::expander::command::Command;
+use crate::common::device::peripherals_manager::ExpanderPins;
+use crate::common::device::ui::UI_STORE;
+use crate::common::device::ui::ui_store::UiStore;
+use crate::common::util::history_log::HISTORY;
+use crate::common::util::log_buffer::LOG;
+use crate::common::util::wall_clock::{ADJUST_MANUAL, CalendarTime, CLOCK};
+
+type ExpanderPinsMutex = Arc<Mutex<ThreadModeRawMutex, ExpanderPins<peripherals::SPI3, peripherals::TWISPI1>>>;
+type Response = String<BLE_SHELL_RESPONSE_LEN>;
+
+/// The default ATT_MTU of 23 bytes, used until the central negotiates a larger one
+const MIN_CHUNK_LEN: usize = 20;
+const NOTIFY_RETRIES: usize = 10;
+const NOTIFY_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+const EXPANDER_POWER_WAIT: Duration = Duration::from_millis(50);
+
+const HELP: &str = "get <key>\n\
+set <key> <value>\n\
+read <bme|adc|nrf|accel|color|battery|all>\n\
+i2cscan\n\
+calib [<humidity|temperature|pressure> <offset>]\n\
+reboot\n\
+log <on|off>\n\
+log from <sequence>\n\
+keys: name location accept_list_only coded_phy broadcast profile time history altitude \
+timeout.<device|adc|bme|accel|color|snapshot>";
+
+/// Connections that get debug messages mirrored to the shell
+static LOG_SUBSCRIBERS: BlockingMutex<ThreadModeRawMutex, RefCell<Vec<Connection, NUM_CONNECTIONS>>> =
+    BlockingMutex::new(RefCell::new(Vec::new()));
+
+/// Notifications can carry up to ATT_MTU - 3 bytes of the negotiated MTU
+fn chunk_len(connection: &Connection) -> usize {
+    (connection.att_mtu() as usize).saturating_sub(3).clamp(MIN_CHUNK_LEN, BLE_NUS_CHUNK_LEN)
+}
+
+async fn send(connection: &Connection, data: &[u8]) -> Result<(), NotifyValueError> {
+    let server = SERVER.get();
+
+    for chunk in data.chunks(chunk_len(connection)) {
+        let value: Vec<u8, BLE_NUS_CHUNK_LEN> = Vec::from_slice(chunk).unwrap_or_default();
+        let mut retries = 0;
+        loop {
+            match server.nus.tx_notify(connection, &value) {
+                // the softdevice TX queue is full
+                Err(NotifyValueError::Raw(RawError::Resources)) if retries < NOTIFY_RETRIES => {
+                    retries += 1;
+                    Timer::after(NOTIFY_RETRY_INTERVAL).await;
+                }
+                result => {
+                    result?;
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits off the first whitespace separated word, the rest is trimmed
+fn split_word(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (line, ""),
+    }
+}
+
+fn parse<T: str::FromStr>(value: &str) -> Result<T, ShellError> {
+    if value.is_empty() {
+        return Err(ShellError::MissingArgument);
+    }
+    value.parse().map_err(|_| ShellError::InvalidArgument)
+}
+
+fn parse_flag(value: &str) -> Result<bool, ShellError> {
+    match value {
+        "1" | "on" | "true" => Ok(true),
+        "0" | "off" | "false" => Ok(false),
+        "" => Err(ShellError::MissingArgument),
+        _ => Err(ShellError::InvalidArgument),
+    }
+}
+
+fn bytes_to_str(value: &[u8]) -> &str {
+    str::from_utf8(value).unwrap_or("")
+}
+
+/// Timeout writes go through the same channels as the GATT writes, so they end up in the
+/// connection state of the corresponding `EventProcessor`
+fn send_timeout(connection: &Connection, service: &str, timeout: u32) -> Result<(), ShellError> {
+    let server = SERVER.get();
+    let connection = connection.clone();
+    let result = match service {
+        "device" => {
+            server.diagnostics.timeout_set(&timeout)?;
+            DIAGNOSTICS_SERVICE_EVENTS.try_send((connection, DiagnosticsServiceEvent::TimeoutWrite(timeout))).is_ok()
+        }
+        "adc" => {
+            server.adc.timeout_set(&timeout)?;
+            ADC_SERVICE_EVENTS.try_send((connection, AdcServiceEvent::TimeoutWrite(timeout))).is_ok()
+        }
+        "bme" => {
+            server.bme280.timeout_set(&timeout)?;
+            BME_SERVICE_EVENTS.try_send((connection, Bme280ServiceEvent::TimeoutWrite(timeout))).is_ok()
+        }
+        "accel" => {
+            server.accelerometer.timeout_set(&timeout)?;
+            ACCELEROMETER_SERVICE_EVENTS
+                .try_send((connection, AccelerometerServiceEvent::TimeoutWrite(timeout)))
+                .is_ok()
+        }
+        "color" => {
+            server.color.timeout_set(&timeout)?;
+            COLOR_SERVICE_EVENTS.try_send((connection, ColorServiceEvent::TimeoutWrite(timeout))).is_ok()
+        }
+        "snapshot" => {
+            server.snapshot.timeout_set(&timeout)?;
+            SNAPSHOT_SERVICE_EVENTS.try_send((connection, SnapshotServiceEvent::TimeoutWrite(timeout))).is_ok()
+        }
+        _ => return Err(ShellError::UnknownKey),
+    };
+
+    if result { Ok(()) } else { Err(ShellError::Busy) }
+}
+
+/// Sampling interval of the sensor task, the shortest timeout among the subscribers
+fn sampling_interval(service: &str) -> Result<Duration, ShellError> {
+    Ok(match service {
+        "device" => DEVICE_EVENT_PROCESSOR.get_timeout_duration(),
+        "adc" => ADC_EVENT_PROCESSOR.get_timeout_duration(),
+        "bme" => BME_EVENT_PROCESSOR.get_timeout_duration(),
+        "accel" => ACCELEROMETER_EVENT_PROCESSOR.get_timeout_duration(),
+        "color" => COLOR_EVENT_PROCESSOR.get_timeout_duration(),
+        "snapshot" => SNAPSHOT_EVENT_PROCESSOR.get_timeout_duration(),
+        _ => return Err(ShellError::UnknownKey),
+    })
+}
+
+fn get(connection: &Connection, key: &str, response: &mut Response) -> Result<(), ShellError> {
+    let identity = device_identity();
+    match key {
+        "name" => write!(response, "{}", bytes_to_str(&identity.name)),
+        "location" => write!(response, "{}", bytes_to_str(&identity.location)),
+        "accept_list_only" => write!(response, "{}", is_accept_list_only() as u8),
+        "coded_phy" => write!(response, "{}", is_coded_phy() as u8),
+        "broadcast" => write!(response, "{}", SERVER.get().broadcast.mode_get()?),
+        "profile" => {
+            let profile = CONN_PARAMS.requested_profile(connection).unwrap_or(ConnectionProfile::Auto);
+            write!(response, "{}", profile as u8)
+        }
+        "history" => write!(
+            response,
+            "interval={} records={}-{}",
+            history_interval(),
+            HISTORY.oldest_sequence(),
+            HISTORY.next_sequence()
+        ),
+        "altitude" => write!(response, "{}", reference_altitude()),
+        "time" => match CLOCK.now() {
+            Some(now) => write!(response, "{} drift {} ppb", now, CLOCK.drift_ppb().unwrap_or(0)),
+            None => write!(response, "unset"),
+        },
+        "" => return Err(ShellError::MissingArgument),
+        key => match key.strip_prefix("timeout.") {
+            Some(service) => write!(response, "{}", sampling_interval(service)?.as_millis()),
+            None => return Err(ShellError::UnknownKey),
+        },
+    }
+    .map_err(|_| ShellError::LineTooLong)
+}
+
+async fn set(connection: &Connection, args: &str) -> Result<(), ShellError> {
+    let (key, value) = split_word(args);
+    match key {
+        "name" => set_device_name(Vec::from_slice(value.as_bytes()).map_err(|_| ShellError::InvalidArgument)?).await,
+        "location" => set_location(Vec::from_slice(value.as_bytes()).map_err(|_| ShellError::InvalidArgument)?).await,
+        "accept_list_only" => {
+            let value = parse_flag(value)?;
+            SERVER.get().diagnostics.accept_list_only_set(&(value as u8))?;
+            set_accept_list_only(value);
+        }
+        "coded_phy" => {
+            let value = parse_flag(value)?;
+            SERVER.get().diagnostics.coded_phy_set(&(value as u8))?;
+            set_coded_phy(value);
+        }
+        "broadcast" => {
+            let mode: u8 = parse(value)?;
+            SERVER.get().broadcast.mode_set(&mode)?;
+            BROADCAST_SERVICE_EVENTS
+                .try_send((connection.clone(), BroadcastServiceEvent::ModeWrite(mode)))
+                .map_err(|_| ShellError::Busy)?;
+        }
+        "profile" => CONN_PARAMS.request_profile(connection, parse(value)?),
+        "history" => set_history_interval(parse(value)?).await,
+        // metres above the sea level, for the sea-level pressure
+        "altitude" => set_reference_altitude(parse(value)?).await,
+        // seconds since 1970-01-01 in local time
+        "time" => set_time(parse::<u64>(value)? * 1000, ADJUST_MANUAL),
+        "" => return Err(ShellError::MissingArgument),
+        key => match key.strip_prefix("timeout.") {
+            Some(service) => send_timeout(connection, service, parse(value)?)?,
+            None => return Err(ShellError::UnknownKey),
+        },
+    }
+
+    Ok(())
+}
+
+/// Wakes the sensor task once and reports the readings it leaves in the UI store
+async fn read(sensor: &str, response: &mut Response) -> Result<(), ShellError> {
+    match sensor {
+        "bme" => BME_EVENT_PROCESSOR.fire_once(),
+        "adc" => ADC_EVENT_PROCESSOR.fire_once(),
+        "accel" => ACCELEROMETER_EVENT_PROCESSOR.fire_once(),
+        "color" => COLOR_EVENT_PROCESSOR.fire_once(),
+        // the nRF temperature and the battery tasks share the device processor
+        "nrf" | "battery" => {
+            DEVICE_EVENT_PROCESSOR.fire_once();
+            DEVICE_EVENT_PROCESSOR.fire_once();
+        }
+        "all" => trigger_all_sensor_update(),
+        "" => return Err(ShellError::MissingArgument),
+        _ => return Err(ShellError::UnknownKey),
+    }
+    // the color sensor is the slowest one
+    Timer::after(ALL_TASK_COMPLETION_INTERVAL).await;
+
+    let store = UI_STORE.lock().await;
+    write_readings(&store, sensor, response).map_err(|_| ShellError::LineTooLong)?;
+    // responses end with a single new line
+    if response.ends_with('\n') {
+        response.pop();
+    }
+
+    Ok(())
+}
+
+fn write_readings(store: &UiStore, sensor: &str, response: &mut Response) -> fmt::Result {
+    let all = sensor == "all";
+    if all || sensor == "bme" {
+        writeln!(
+            response,
+            "temperature={:.2} humidity={:.2} pressure={:.1}",
+            store.temperature, store.humidity, store.pressure / 100.0
+        )?;
+    }
+    if all || sensor == "adc" {
+        write!(response, "adc=")?;
+        write_list(response, &store.adc_voltages)?;
+    }
+    if all || sensor == "nrf" {
+        write!(response, "nrf_adc=")?;
+        write_list(response, &store.nrf_adc_voltages)?;
+    }
+    if all || sensor == "accel" {
+        writeln!(response, "x={:.3} y={:.3} z={:.3}", store.x, store.y, store.z)?;
+    }
+    if all || sensor == "color" {
+        writeln!(
+            response,
+            "r={} g={} b={} w={} cct={} lux={:.1}",
+            store.r, store.g, store.b, store.w, store.cct, store.lux
+        )?;
+    }
+    if all || sensor == "battery" {
+        writeln!(
+            response,
+            "voltage={:.2} level={} charging_estimate={}",
+            store.bat_voltage, store.bat_level, store.bat_charging_estimate as u8
+        )?;
+    }
+    Ok(())
+}
+
+fn write_list(response: &mut Response, values: &[f32]) -> fmt::Result {
+    for (index, value) in values.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        write!(response, "{}{:.3}", separator, value)?;
+    }
+    writeln!(response)
+}
+
+/// Scans the expander I2C bus, the expander must not be locked by anyone, this connection included.
+/// The owner stays locked for the whole scan instead of being set, so the GATT lock, a session piggybacking
+/// on it and the timeout release can't interleave and there is no lock to release afterwards.
+async fn i2c_scan(connection: &Connection, pins: &ExpanderPinsMutex, response: &mut Response) -> Result<(), ShellError> {
+    let owner = SPI_EXPANDER_LOCK_OWNER.lock().await;
+    match owner.as_ref() {
+        Some(owning_connection) if owning_connection == connection => {
+            return Err(ExpanderError::MutexAcquireTwiceSameClient.into());
+        }
+        Some(_) => return Err(ExpanderError::MutexAcquiredByOtherClient.into()),
+        None => {}
+    }
+
+    handle_power(pins, true).await;
+    Timer::after(EXPANDER_POWER_WAIT).await;
+
+    let result = handle_i2c_exec(pins, 0, Command::I2cScan, &[], 0).await;
+
+    handle_power(pins, false).await;
+    drop(owner);
+
+    if let Some(addresses) = result? {
+        // found addresses are packed at the start of the buffer
+        for address in addresses.iter().take_while(|&&address| address != 0) {
+            write!(response, "0x{:02x} ", address).map_err(|_| ShellError::LineTooLong)?;
+        }
+    }
+    if response.is_empty() {
+        let _ = write!(response, "no devices");
+    }
+
+    Ok(())
+}
+
+async fn calib(connection: &Connection, args: &str, response: &mut Response) -> Result<(), ShellError> {
+    let (field, value) = split_word(args);
+    if field.is_empty() {
+        let data = FLASH_MANAGER.get().get_last_calibration_data().await;
+        return write!(
+            response,
+            "version={} humidity={} temperature={} pressure={}",
+            data.version, data.bme_humidity, data.bme_temperature, data.bme_pressure
+        )
+        .map_err(|_| ShellError::LineTooLong);
+    }
+
+    let offset = parse::<f32>(value)?.to_le_bytes();
+    let server = SERVER.get();
+    // the BME280 consumer persists the offset exactly like a GATT write
+    let event = match field {
+        "humidity" => {
+            server.bme280.humidity_offset_set(&offset)?;
+            Bme280ServiceEvent::HumidityOffsetWrite(offset)
+        }
+        "temperature" => {
+            server.bme280.temperature_offset_set(&offset)?;
+            Bme280ServiceEvent::TemperatureOffsetWrite(offset)
+        }
+        "pressure" => {
+            server.bme280.pressure_offset_set(&offset)?;
+            Bme280ServiceEvent::PressureOffsetWrite(offset)
+        }
+        _ => return Err(ShellError::UnknownKey),
+    };
+
+    BME_SERVICE_EVENTS.try_send((connection.clone(), event)).map_err(|_| ShellError::Busy)
+}
+
+fn set_log_subscription(connection: &Connection, enabled: bool) {
+    LOG_SUBSCRIBERS.lock(|subscribers| {
+        let mut subscribers = subscribers.borrow_mut();
+        subscribers.retain(|subscriber| subscriber != connection && subscriber.handle().is_some());
+        if enabled {
+            let _ = subscribers.push(connection.clone());
+        }
+    });
+}
+
+/// Dumps the log entries starting at `sequence`, as many as fit into the response;
+/// the next request continues from the last printed sequence + 1
+fn log_backlog(sequence: u32, response: &mut Response) {
+    LOG.for_each_from(sequence, |entry| {
+        let mut line: String<{ BLE_DEBUG_ARRAY_LEN + 52 }> = String::new();
+        let marker = if entry.is_from_previous_boot() { "*" } else { "" };
+        let _ = write!(line, "#{} {}{} ", entry.sequence, entry.timestamp, marker);
+        if entry.time != 0 {
+            let _ = write!(line, "{} ", CalendarTime::from_unix_millis(entry.time as u64 * 1000));
+        }
+        let _ = writeln!(line, "{} {}", entry.level().as_str(), bytes_to_str(&entry.message));
+        response.push_str(&line).is_ok()
+    });
+    // the trailing newline is added by `respond`
+    if response.ends_with('\n') {
+        response.pop();
+    }
+}
+
+/// Forwards a debug message to the connections that enabled `log`
+pub(crate) async fn mirror_log(message: &[u8]) {
+    let subscribers = LOG_SUBSCRIBERS.lock(|subscribers| subscribers.borrow().clone());
+    let len = message.iter().position(|&byte| byte == 0).unwrap_or(message.len());
+
+    for connection in subscribers.iter() {
+        let _ = send(connection, &message[..len]).await;
+        let _ = send(connection, b"\n").await;
+    }
+}
+
+async fn execute(
+    connection: &Connection,
+    line: &str,
+    pins: &ExpanderPinsMutex,
+    response: &mut Response,
+) -> Result<(), ShellError> {
+    let (command, args) = split_word(line);
+    match command {
+        "help" => {
+            let _ = response.push_str(HELP);
+        }
+        "get" => get(connection, args, response)?,
+        "set" => set(connection, args).await?,
+        "read" => read(args, response).await?,
+        "i2cscan" => i2c_scan(connection, pins, response).await?,
+        "calib" => calib(connection, args, response).await?,
+        "reboot" => {
+            let _ = send(connection, b"OK rebooting\n").await;
+            // let the notification leave the queue
+            Timer::after(Duration::from_millis(500)).await;
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        "log" => match split_word(args) {
+            ("from", sequence) => log_backlog(parse(sequence)?, response),
+            _ => set_log_subscription(connection, parse_flag(args)?),
+        },
+        _ => return Err(ShellError::UnknownCommand),
+    }
+
+    Ok(())
+}
+
+async fn respond(connection: &Connection, result: Result<(), ShellError>, response: &mut Response) {
+    if let Err(err) = result {
+        response.clear();
+        let _ = write!(response, "ERR {}", err);
+    } else if response.is_empty() {
+        let _ = response.push_str("OK");
+    }
+    let _ = response.push('\n');
+
+    if let Err(err) = send(connection, response.as_bytes()).await {
+        info!("Failed to send shell response: {:?}", err);
+    }
+}
+
+#[embassy_executor::task]
+pub(crate) async fn shell_task(pins: ExpanderPinsMutex) {
+    let mut lines: BTreeMap<Connection, Vec<u8, BLE_SHELL_LINE_LEN>> = BTreeMap::new();
+
+    loop {
+        let (connection, event) = SHELL_EVENTS.receive().await;
+        lines.retain(|connection, _| connection.handle().is_some());
+
+        let NusServiceEvent::RxWrite(data) = event else {
+            continue;
+        };
+
+        for &byte in data.iter() {
+            let line = lines.entry(connection.clone()).or_default();
+            match byte {
+                b'\r' => {}
+                b'\n' => {
+                    let command = core::mem::take(line);
+                    let mut response = Response::new();
+                    let result = match str::from_utf8(&command) {
+                        Ok(command) if command.trim().is_empty() => continue,
+                        Ok(command) => execute(&connection, command, &pins, &mut response).await,
+                        Err(_) => Err(ShellError::InvalidArgument),
+                    };
+                    respond(&connection, result, &mut response).await;
+                }
+                byte => {
+                    if line.push(byte).is_err() {
+                        line.clear();
+                        respond(&connection, Err(ShellError::LineTooLong), &mut Response::new()).await;
+                    }
+                }
+            }
+        }
+    }
+}
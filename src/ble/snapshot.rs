@@ -0,0 +1,184 @@
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_time::Timer;
+use heapless::Vec;
+
+use crate::common::ble::{
+    SERVER, SNAPSHOT_EVENT_PROCESSOR, SNAPSHOT_SERVICE_EVENTS, update_external_sensor_demand,
+};
+use crate::common::ble::bthome::on_readings_updated;
+use crate::common::device::config::{BLE_ATT_MTU, BLE_SNAPSHOT_LEN};
+use crate::notify_all;
+
+/// Bump whenever the layout of an existing field changes; new fields only take a new presence bit
+pub(crate) const SNAPSHOT_VERSION: u8 = 1;
+
+/// [version: 1][presence: u16][sequence: u32]
+const SNAPSHOT_HEADER_LEN: usize = 7;
+const SNAPSHOT_MAX_LEN: usize = SNAPSHOT_HEADER_LEN
+    + SnapshotField::AdcVoltages.len()
+    + SnapshotField::Bme.len()
+    + SnapshotField::Accelerometer.len()
+    + SnapshotField::Color.len()
+    + SnapshotField::Battery.len()
+    + SnapshotField::NrfTemperature.len();
+
+const _: () = assert!(SNAPSHOT_MAX_LEN <= BLE_SNAPSHOT_LEN);
+// 3 bytes of the ATT payload are taken by the notification header
+const _: () = assert!(BLE_SNAPSHOT_LEN <= BLE_ATT_MTU as usize - 3);
+
+/// Presence bits; present fields follow the header in the bit order, all values are little endian
+/// and use the same encoding as the dedicated characteristics.
+#[derive(Copy, Clone)]
+#[repr(u16)]
+pub(crate) enum SnapshotField {
+    /// 7 x u16, nRF ADC channels 0-6
+    AdcVoltages = 1 << 0,
+    /// i16 temperature, u16 humidity, u32 pressure
+    Bme = 1 << 1,
+    /// 3 x f32, x, y, z
+    Accelerometer = 1 << 2,
+    /// 6 x u16, red, green, blue, white, cct, luminous flux
+    Color = 1 << 3,
+    /// u16 battery voltage, u8 battery level
+    Battery = 1 << 4,
+    /// i16 nRF die temperature
+    NrfTemperature = 1 << 5,
+}
+
+impl SnapshotField {
+    const fn len(self) -> usize {
+        match self {
+            SnapshotField::AdcVoltages => 14,
+            SnapshotField::Bme => 8,
+            SnapshotField::Accelerometer => 12,
+            SnapshotField::Color => 12,
+            SnapshotField::Battery => 3,
+            SnapshotField::NrfTemperature => 2,
+        }
+    }
+}
+
+/// The latest already encoded readings of every sensor task
+#[derive(Clone)]
+pub(crate) struct Snapshot {
+    pub(crate) adc_voltages: Option<[u16; 7]>,
+    pub(crate) bme: Option<(i16, u16, u32)>,
+    pub(crate) accelerometer: Option<[f32; 3]>,
+    pub(crate) color: Option<[u16; 6]>,
+    pub(crate) battery: Option<(u16, u8)>,
+    pub(crate) nrf_temperature: Option<i16>,
+}
+
+impl Snapshot {
+    const fn new() -> Self {
+        Self {
+            adc_voltages: None,
+            bme: None,
+            accelerometer: None,
+            color: None,
+            battery: None,
+            nrf_temperature: None,
+        }
+    }
+
+    fn serialize(&self, sequence: u32) -> Vec<u8, BLE_SNAPSHOT_LEN> {
+        let mut presence = 0u16;
+        let mut body: Vec<u8, BLE_SNAPSHOT_LEN> = Vec::new();
+
+        if let Some(voltages) = self.adc_voltages {
+            presence |= SnapshotField::AdcVoltages as u16;
+            voltages.iter().for_each(|voltage| push(&mut body, &voltage.to_le_bytes()));
+        }
+        if let Some((temperature, humidity, pressure)) = self.bme {
+            presence |= SnapshotField::Bme as u16;
+            push(&mut body, &temperature.to_le_bytes());
+            push(&mut body, &humidity.to_le_bytes());
+            push(&mut body, &pressure.to_le_bytes());
+        }
+        if let Some(axes) = self.accelerometer {
+            presence |= SnapshotField::Accelerometer as u16;
+            axes.iter().for_each(|axis| push(&mut body, &axis.to_le_bytes()));
+        }
+        if let Some(channels) = self.color {
+            presence |= SnapshotField::Color as u16;
+            channels.iter().for_each(|channel| push(&mut body, &channel.to_le_bytes()));
+        }
+        if let Some((voltage, level)) = self.battery {
+            presence |= SnapshotField::Battery as u16;
+            push(&mut body, &voltage.to_le_bytes());
+            push(&mut body, &[level]);
+        }
+        if let Some(temperature) = self.nrf_temperature {
+            presence |= SnapshotField::NrfTemperature as u16;
+            push(&mut body, &temperature.to_le_bytes());
+        }
+
+        let mut frame: Vec<u8, BLE_SNAPSHOT_LEN> = Vec::new();
+        push(&mut frame, &[SNAPSHOT_VERSION]);
+        push(&mut frame, &presence.to_le_bytes());
+        push(&mut frame, &sequence.to_le_bytes());
+        push(&mut frame, &body);
+        frame
+    }
+}
+
+fn push(buf: &mut Vec<u8, BLE_SNAPSHOT_LEN>, bytes: &[u8]) {
+    // capacity is checked at compile time against SNAPSHOT_MAX_LEN
+    let _ = buf.extend_from_slice(bytes);
+}
+
+pub(crate) struct SnapshotStore {
+    snapshot: Mutex<ThreadModeRawMutex, RefCell<Snapshot>>,
+    sequence: AtomicU32,
+}
+
+impl SnapshotStore {
+    const fn new() -> Self {
+        Self { snapshot: Mutex::new(RefCell::new(Snapshot::new())), sequence: AtomicU32::new(0) }
+    }
+
+    pub(crate) fn update(&self, f: impl FnOnce(&mut Snapshot)) {
+        self.snapshot.lock(|snapshot| f(&mut snapshot.borrow_mut()));
+        on_readings_updated();
+    }
+
+    pub(crate) fn latest(&self) -> Snapshot {
+        self.snapshot.lock(|snapshot| snapshot.borrow().clone())
+    }
+
+    /// Every serialized frame gets a new sequence number, so clients can detect missed notifications
+    pub(crate) fn next_frame(&self) -> Vec<u8, BLE_SNAPSHOT_LEN> {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        self.snapshot.lock(|snapshot| snapshot.borrow().serialize(sequence))
+    }
+}
+
+pub(crate) static SNAPSHOT: SnapshotStore = SnapshotStore::new();
+
+#[embassy_executor::task]
+pub(crate) async fn read_snapshot_notification_settings_channel() {
+    loop {
+        let (connection, event) = SNAPSHOT_SERVICE_EVENTS.receive().await;
+        SNAPSHOT_EVENT_PROCESSOR.process_event(connection, event).await;
+        // a client may subscribe to the snapshot alone
+        update_external_sensor_demand().await;
+    }
+}
+
+#[embassy_executor::task]
+pub(crate) async fn notify_snapshot_task() {
+    let server = SERVER.get();
+
+    loop {
+        let _token = SNAPSHOT_EVENT_PROCESSOR.wait_for_condition().await;
+        // give sensor tasks one cycle to fill in the readings
+        Timer::after(SNAPSHOT_EVENT_PROCESSOR.get_timeout_duration()).await;
+
+        let frame = SNAPSHOT.next_frame();
+        notify_all!(SNAPSHOT_EVENT_PROCESSOR, server.snapshot, snapshot = &frame);
+    }
+}
@@ -1,8 +1,23 @@
-use core::mem;
-
+use heapless::Vec;
 use nrf_softdevice::{raw, Config};
 
-use crate::common::device::config::NUM_CONNECTIONS;
+use crate::common::ble::bthome_payload::ADV_DATA_LEN;
+use crate::common::ble::gatt_table::uuid_128;
+use crate::common::ble::identity::{device_identity, device_name_write_perm};
+use crate::common::device::config::{
+    BLE_ATT_MTU, BLE_DEVICE_NAME_LEN, BLE_LOCATION_LEN, BLE_VS_UUID_COUNT, DEFAULT_DEVICE_NAME, NUM_CONNECTIONS,
+};
+
+const AD_TYPE_FLAGS: u8 = 0x01;
+const AD_TYPE_COMPLETE_16BIT_UUIDS: u8 = 0x03;
+const AD_TYPE_SHORTENED_LOCAL_NAME: u8 = 0x08;
+const AD_TYPE_COMPLETE_LOCAL_NAME: u8 = 0x09;
+const AD_TYPE_SERVICE_DATA_128BIT_UUID: u8 = 0x21;
+
+/// Environmental Sensing Service
+const ESS_UUID: [u8; 2] = [0x1A, 0x18];
+/// The diagnostics service, which carries the location characteristic
+const LOCATION_SERVICE_UUID: [u8; 16] = uuid_128("5c853275-623b-4754-a329-969d8bc8121d");
 
 pub(crate) fn prepare_softdevice_config() -> Config {
     Config {
@@ -18,9 +33,9 @@ pub(crate) fn prepare_softdevice_config() -> Config {
         }),
         common_vs_uuid: Some(raw::ble_common_cfg_vs_uuid_t {
             // sd_ble_uuid_vs_add err NoMem
-            vs_uuid_count: 50,
+            vs_uuid_count: BLE_VS_UUID_COUNT as u8,
         }),
-        conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: 256 }),
+        conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: BLE_ATT_MTU }),
         gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t { attr_tab_size: 32768 }),
         gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
             adv_set_count: 1,
@@ -29,11 +44,12 @@ pub(crate) fn prepare_softdevice_config() -> Config {
             central_sec_count: 0,
             _bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
         }),
+        // the persisted name replaces the default one once the flash is available
         gap_device_name: Some(raw::ble_gap_cfg_device_name_t {
-            p_value: b"Sensor Hub BLE" as *const u8 as _,
-            current_len: 14,
-            max_len: 14,
-            write_perm: unsafe { mem::zeroed() },
+            p_value: DEFAULT_DEVICE_NAME.as_ptr() as _,
+            current_len: DEFAULT_DEVICE_NAME.len() as u16,
+            max_len: BLE_DEVICE_NAME_LEN as u16,
+            write_perm: device_name_write_perm(),
             _bitfield_1: raw::ble_gap_cfg_device_name_t::new_bitfield_1(
                 raw::BLE_GATTS_VLOC_STACK as u8,
             ),
@@ -42,15 +58,62 @@ pub(crate) fn prepare_softdevice_config() -> Config {
     }
 }
 
-#[rustfmt::skip]
-pub(crate) fn prepare_adv_scan_data() -> (&'static [u8], &'static [u8]) {
-    static ADV_DATA: [u8; 23] = [
-        0x02, 0x01, raw::BLE_GAP_ADV_FLAGS_LE_ONLY_GENERAL_DISC_MODE as u8,
-        0x03, 0x03, 0x09, 0x18,
-        0x0F, 0x09, b'S', b'e', b'n', b's', b'o', b'r', b' ', b'H', b'u', b'b', b' ', b'B', b'L', b'E',
-    ];
-    // scan_rsp_data
-    static SCAN_DATA: [u8; 4] = [0x03, 0x03, 0x09, 0x18];
-
-    (&ADV_DATA, &SCAN_DATA)
+/// Appends an AD structure, as much of `value` as fits is kept when `truncate` is set
+fn push_ad_structure(data: &mut Vec<u8, ADV_DATA_LEN>, ad_type: u8, value: &[u8], truncate: bool) -> bool {
+    let available = ADV_DATA_LEN.saturating_sub(data.len() + 2);
+    if value.is_empty() || available == 0 || (!truncate && value.len() > available) {
+        return false;
+    }
+    let len = value.len().min(available);
+    let _ = data.extend_from_slice(&[len as u8 + 1, ad_type]);
+    let _ = data.extend_from_slice(&value[..len]);
+    true
+}
+
+/// Falls back to the shortened local name if the complete one does not fit
+fn push_name(data: &mut Vec<u8, ADV_DATA_LEN>, name: &[u8]) {
+    if !push_ad_structure(data, AD_TYPE_COMPLETE_LOCAL_NAME, name, false) {
+        push_ad_structure(data, AD_TYPE_SHORTENED_LOCAL_NAME, name, true);
+    }
+}
+
+/// The location label goes as service data under the UUID of the diagnostics service, which carries the
+/// location characteristic; the 128-bit UUID leaves room for the first 13 bytes of the label in an empty packet,
+/// the label is left out rather than the UUID truncated
+fn push_location(data: &mut Vec<u8, ADV_DATA_LEN>, location: &[u8]) {
+    let available = ADV_DATA_LEN.saturating_sub(data.len() + 2);
+    if location.is_empty() || available <= LOCATION_SERVICE_UUID.len() {
+        return;
+    }
+    let mut value: Vec<u8, { 16 + BLE_LOCATION_LEN }> = Vec::new();
+    let _ = value.extend_from_slice(&LOCATION_SERVICE_UUID);
+    let _ = value.extend_from_slice(location);
+    push_ad_structure(data, AD_TYPE_SERVICE_DATA_128BIT_UUID, &value, true);
+}
+
+/// Flags, the ESS UUID and the device name go into the advertising data, the location label
+/// into the scan response on its own
+pub(crate) fn prepare_adv_scan_data() -> (Vec<u8, ADV_DATA_LEN>, Vec<u8, ADV_DATA_LEN>) {
+    let identity = device_identity();
+
+    let mut adv_data: Vec<u8, ADV_DATA_LEN> = Vec::new();
+    push_ad_structure(&mut adv_data, AD_TYPE_FLAGS, &[raw::BLE_GAP_ADV_FLAGS_LE_ONLY_GENERAL_DISC_MODE as u8], false);
+    push_ad_structure(&mut adv_data, AD_TYPE_COMPLETE_16BIT_UUIDS, &ESS_UUID, false);
+    push_name(&mut adv_data, &identity.name);
+
+    let mut scan_data: Vec<u8, ADV_DATA_LEN> = Vec::new();
+    push_location(&mut scan_data, &identity.location);
+
+    (adv_data, scan_data)
+}
+
+/// Advertising data is taken by BTHome service data while broadcasting, so the name moves here
+pub(crate) fn prepare_broadcast_scan_data() -> Vec<u8, ADV_DATA_LEN> {
+    let identity = device_identity();
+
+    let mut scan_data: Vec<u8, ADV_DATA_LEN> = Vec::new();
+    push_name(&mut scan_data, &identity.name);
+    push_location(&mut scan_data, &identity.location);
+
+    scan_data
 }
@@ -0,0 +1,97 @@
+use core::sync::atomic::{AtomicI16, Ordering};
+
+use defmt::info;
+use embassy_time::{Instant, Timer};
+
+use crate::ble_debug;
+use crate::common::ble::{BME_EVENT_PROCESSOR, FLASH_MANAGER, SERVER};
+use crate::common::device::bme280::Measurements;
+use crate::common::device::config::{MAX_REFERENCE_ALTITUDE, MIN_REFERENCE_ALTITUDE, PRESSURE_TREND_SAMPLE_INTERVAL};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::altitude_storage::AltitudeSettings;
+use crate::common::util::barometer::{pressure_altitude, PRESSURE_HISTORY, PressureTrend, sea_level_pressure, zambretti};
+
+/// Metres, the sea-level pressure equals the station pressure until it is set
+static REFERENCE_ALTITUDE: AtomicI16 = AtomicI16::new(0);
+
+/// Everything derived from the station pressure of a single reading
+#[derive(Copy, Clone, Debug, defmt::Format)]
+pub(crate) struct Weather {
+    /// Pa
+    pub(crate) sea_level_pressure: f32,
+    /// m, over the ISA sea-level pressure
+    pub(crate) pressure_altitude: f32,
+    /// Pa per 3 hours, None until the history spans an hour
+    pub(crate) tendency: Option<f32>,
+    pub(crate) trend: PressureTrend,
+    /// Zambretti letter, 1 - `A` .. 26 - `Z`
+    pub(crate) forecast: Option<u8>,
+}
+
+impl Weather {
+    /// Keeps the station pressure for the tendency; None if the pressure is skipped
+    pub(crate) fn observe(measurements: &Measurements) -> Option<Self> {
+        if measurements.pressure <= 0.0 {
+            return None;
+        }
+
+        PRESSURE_HISTORY.record(Instant::now(), measurements.pressure);
+        let tendency = PRESSURE_HISTORY.tendency();
+        let trend = PressureTrend::from_tendency(tendency);
+        let sea_level_pressure =
+            sea_level_pressure(measurements.pressure, reference_altitude() as f32, measurements.temperature);
+
+        Some(Self {
+            sea_level_pressure,
+            pressure_altitude: pressure_altitude(measurements.pressure),
+            tendency,
+            trend,
+            forecast: zambretti(sea_level_pressure, trend),
+        })
+    }
+}
+
+pub(crate) fn reference_altitude() -> i16 {
+    REFERENCE_ALTITUDE.load(Ordering::Relaxed)
+}
+
+fn reflect_reference_altitude() {
+    if let Err(err) = SERVER.get().bme280.reference_altitude_set(&reference_altitude()) {
+        info!("Failed to set reference altitude {:?}", err);
+    }
+}
+
+pub(crate) async fn restore_reference_altitude() -> Result<(), FlashManagerError> {
+    let settings: Option<AltitudeSettings> = FLASH_MANAGER.get().read_setting().await?;
+    if let Some(settings) = settings {
+        REFERENCE_ALTITUDE.store(settings.reference_altitude, Ordering::Relaxed);
+    }
+    reflect_reference_altitude();
+
+    Ok(())
+}
+
+/// Metres above the sea level, applies to the next reading
+pub(crate) async fn set_reference_altitude(altitude: i16) {
+    if !(MIN_REFERENCE_ALTITUDE..=MAX_REFERENCE_ALTITUDE).contains(&altitude) {
+        ble_debug!("Reference altitude must be within {}..={} m", MIN_REFERENCE_ALTITUDE, MAX_REFERENCE_ALTITUDE);
+        reflect_reference_altitude();
+        return;
+    }
+
+    REFERENCE_ALTITUDE.store(altitude, Ordering::Relaxed);
+    reflect_reference_altitude();
+    let settings = AltitudeSettings { reference_altitude: altitude };
+    if let Err(err) = FLASH_MANAGER.get().write_setting(&settings).await {
+        info!("Failed to persist altitude settings: {:?}", err);
+    }
+}
+
+/// The pressure tendency needs readings while nobody is connected
+#[embassy_executor::task]
+pub(crate) async fn pressure_trend_task() {
+    loop {
+        BME_EVENT_PROCESSOR.fire_once();
+        Timer::after(PRESSURE_TREND_SAMPLE_INTERVAL).await;
+    }
+}
@@ -0,0 +1,198 @@
+/// Resting voltage of a single LiPo cell vs. state of charge, descending.
+/// The curve is flat between 3.7V and 3.9V, so most of the capacity is in the middle of the table.
+const LIPO_DISCHARGE_CURVE: [(f32, u8); 12] = [
+    (4.20, 100),
+    (4.10, 90),
+    (4.00, 80),
+    (3.93, 70),
+    (3.87, 60),
+    (3.82, 50),
+    (3.79, 40),
+    (3.77, 30),
+    (3.73, 20),
+    (3.68, 10),
+    (3.50, 5),
+    (3.20, 0),
+];
+
+/// While the battery is not thought to be charged, the level only goes up by rises of at least this much:
+/// the voltage recovers a bit when the radio and sensors are idle.
+const LEVEL_HYSTERESIS: u8 = 5;
+
+/// Above this voltage the cell can only be sitting on a charger
+const CHARGER_VOLTAGE: f32 = 4.25;
+/// A sustained rise of the filtered voltage is taken as charging, the board has no charger status pin to tell.
+/// A load drop (radio, display, sensors going idle) looks the same, so a false positive clears once the voltage sags.
+const CHARGE_RISE_THRESHOLD: f32 = 0.05;
+const CHARGE_STOP_THRESHOLD: f32 = 0.03;
+
+const FILTER_ALPHA: f32 = 0.3;
+
+pub(crate) const BATTERY_LEVEL_CRITICAL: u8 = 5;
+pub(crate) const BATTERY_LEVEL_LOW: u8 = 15;
+
+#[derive(Debug, defmt::Format, Copy, Clone, Default, PartialEq, Eq)]
+pub(crate) struct BatteryState {
+    pub(crate) level: u8,
+    /// Guessed from the voltage trend, not read from the charger
+    pub(crate) charging_estimate: bool,
+    /// The voltage is at least `CHARGER_VOLTAGE`, the only charge state known for certain
+    pub(crate) on_charger: bool,
+}
+
+impl BatteryState {
+    pub(crate) fn is_critical(&self) -> bool {
+        self.level <= BATTERY_LEVEL_CRITICAL
+    }
+
+    pub(crate) fn is_low(&self) -> bool {
+        self.level <= BATTERY_LEVEL_LOW
+    }
+
+    /// Battery Level Status (0x2BED): [flags][power state: u16][battery level]
+    pub(crate) fn level_status(&self) -> [u8; 4] {
+        // flags: battery level present
+        let flags = 0b0000_0010u8;
+
+        // bit 0: battery present
+        let mut power_state = 0b1u16;
+        // bits 5-6: battery charge state: 0 - unknown, 1 - charging; the voltage trend is too weak a hint to
+        // report discharging, see `BatteryGauge`
+        power_state |= if self.on_charger { 1 } else { 0 } << 5;
+        // bits 7-8: battery charge level: 1 - good, 2 - low, 3 - critical
+        let charge_level = if self.is_critical() {
+            3
+        } else if self.is_low() {
+            2
+        } else {
+            1
+        };
+        power_state |= charge_level << 7;
+
+        let power_state = power_state.to_le_bytes();
+        [flags, power_state[0], power_state[1], self.level]
+    }
+}
+
+/// Turns divider-corrected battery voltage readings into a state of charge and a charging estimate
+#[derive(Default)]
+pub(crate) struct BatteryGauge {
+    filtered_voltage: Option<f32>,
+    min_voltage: f32,
+    max_voltage: f32,
+    state: Option<BatteryState>,
+}
+
+impl BatteryGauge {
+    pub(crate) fn update(&mut self, voltage: f32) -> BatteryState {
+        let filtered = match self.filtered_voltage {
+            Some(previous) => previous + FILTER_ALPHA * (voltage - previous),
+            None => {
+                self.min_voltage = voltage;
+                self.max_voltage = voltage;
+                voltage
+            }
+        };
+        self.filtered_voltage = Some(filtered);
+
+        let was_charging = self.state.map(|state| state.charging_estimate).unwrap_or(false);
+        let on_charger = voltage >= CHARGER_VOLTAGE;
+        let charging = if on_charger {
+            true
+        } else if was_charging {
+            filtered > self.max_voltage - CHARGE_STOP_THRESHOLD
+        } else {
+            filtered > self.min_voltage + CHARGE_RISE_THRESHOLD
+        };
+
+        if charging != was_charging {
+            self.min_voltage = filtered;
+            self.max_voltage = filtered;
+        }
+        self.min_voltage = self.min_voltage.min(filtered);
+        self.max_voltage = self.max_voltage.max(filtered);
+
+        let computed = voltage_to_level(filtered);
+        let level = match self.state {
+            Some(previous) if !charging && computed > previous.level => {
+                if computed - previous.level >= LEVEL_HYSTERESIS {
+                    computed
+                } else {
+                    previous.level
+                }
+            }
+            _ => computed,
+        };
+
+        let state = BatteryState { level, charging_estimate: charging, on_charger };
+        self.state = Some(state);
+        state
+    }
+}
+
+pub(crate) fn voltage_to_level(voltage: f32) -> u8 {
+    let (max_voltage, max_level) = LIPO_DISCHARGE_CURVE[0];
+    if voltage >= max_voltage {
+        return max_level;
+    }
+
+    for window in LIPO_DISCHARGE_CURVE.windows(2) {
+        let (upper_voltage, upper_level) = window[0];
+        let (lower_voltage, lower_level) = window[1];
+        if voltage >= lower_voltage {
+            let ratio = (voltage - lower_voltage) / (upper_voltage - lower_voltage);
+            return lower_level + (ratio * (upper_level - lower_level) as f32) as u8;
+        }
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charge_state(state: &BatteryState) -> u16 {
+        let status = state.level_status();
+        u16::from_le_bytes([status[1], status[2]]) >> 5 & 0b11
+    }
+
+    #[test]
+    fn charge_state_is_unknown_off_the_charger() {
+        let mut gauge = BatteryGauge::default();
+        let state = gauge.update(3.80);
+        assert_eq!(charge_state(&state), 0);
+
+        // a rising voltage is only an estimate
+        let state = (0..20).map(|_| gauge.update(4.0)).last().unwrap();
+        assert!(state.charging_estimate);
+        assert_eq!(charge_state(&state), 0);
+
+        let state = gauge.update(CHARGER_VOLTAGE);
+        assert!(state.on_charger);
+        assert_eq!(charge_state(&state), 1);
+    }
+
+    #[test]
+    fn level_status() {
+        let state = BatteryState { level: 12, charging_estimate: false, on_charger: false };
+        // battery present, unknown charge state, low
+        assert_eq!(state.level_status(), [0b10, 0b0000_0001, 0b1, 12]);
+
+        let state = BatteryState { level: 80, charging_estimate: true, on_charger: true };
+        // battery present, charging, good
+        assert_eq!(state.level_status(), [0b10, 0b1010_0001, 0b0, 80]);
+    }
+
+    #[test]
+    fn small_rises_are_ignored_while_discharging() {
+        let mut gauge = BatteryGauge::default();
+        let level = gauge.update(3.82).level;
+        assert_eq!(level, 50);
+
+        // the voltage recovers slightly as the load drops, the filtered 3.835 V alone would read 53 %
+        assert_eq!(gauge.update(3.87).level, level);
+        // and the level follows it down again
+        assert!(gauge.update(3.70).level < level);
+    }
+}
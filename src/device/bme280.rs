@@ -1,4 +1,4 @@
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use thiserror_no_std::Error;
 
 use crate::common::bitbang;
@@ -11,6 +11,7 @@ pub(crate) const BME280_PWR_CTRL_ADDR: u8 = 0xF4;
 pub(crate) const BME280_CTRL_HUM_ADDR: u8 = 0xF2;
 pub(crate) const BME280_CTRL_MEAS_ADDR: u8 = 0xF4;
 pub(crate) const BME280_CONFIG_ADDR: u8 = 0xF5;
+pub(crate) const BME280_STATUS_ADDR: u8 = 0xF3;
 
 pub(crate) const BME280_RESET_ADDR: u8 = 0xE0;
 pub(crate) const BME280_SOFT_RESET_CMD: u8 = 0xB6;
@@ -43,6 +44,10 @@ pub(crate) const BME280_NORMAL_MODE: u8 = 0x03;
 
 pub(crate) const BME280_SENSOR_MODE_MSK: u8 = 0x03;
 
+/// Set while a conversion is running, cleared once the results are in the data registers
+pub(crate) const BME280_STATUS_MEASURING: u8 = 0x08;
+const BME280_STATUS_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
 pub(crate) const BME280_CTRL_HUM_MSK: u8 = 0x07;
 
 pub(crate) const BME280_CTRL_PRESS_MSK: u8 = 0x1C;
@@ -59,6 +64,17 @@ pub(crate) const BME280_FILTER_COEFF_4: u8 = 0x02;
 pub(crate) const BME280_FILTER_COEFF_8: u8 = 0x03;
 pub(crate) const BME280_FILTER_COEFF_16: u8 = 0x04;
 
+pub(crate) const BME280_STANDBY_MSK: u8 = 0xE0;
+pub(crate) const BME280_STANDBY_POS: u8 = 0x05;
+
+/// Output of a skipped channel, see section 4 of the datasheet
+const BME280_SKIPPED_P_T: u32 = 0x80000;
+const BME280_SKIPPED_H: u32 = 0x8000;
+
+/// Oversampling of temperature, pressure and humidity, the IIR filter and the standby, each as its register field
+pub(crate) const BME280_CONFIGURATION_LEN: usize = 5;
+
+pub(crate) const BME280_OVERSAMPLING_SKIPPED: u8 = 0x00;
 pub(crate) const BME280_OVERSAMPLING_1X: u8 = 0x01;
 pub(crate) const BME280_OVERSAMPLING_2X: u8 = 0x02;
 pub(crate) const BME280_OVERSAMPLING_4X: u8 = 0x03;
@@ -81,6 +97,7 @@ pub(crate) struct Bme280<'a, I: embedded_hal_async::i2c::I2c> {
     address: u8,
     interface: &'a mut I,
     calibration: Option<CalibrationData>,
+    config: Configuration,
 }
 
 impl<'a, I: embedded_hal_async::i2c::I2c> Bme280<'a, I>
@@ -88,7 +105,7 @@ where
     Bme280Error: From<<I as embedded_hal_async::i2c::ErrorType>::Error>,
 {
     pub fn new(interface: &'a mut I, address: u8) -> Self {
-        Self { address, interface, calibration: None }
+        Self { address, interface, calibration: None, config: Configuration::default() }
     }
 
     pub fn new_primary(interface: &'a mut I) -> Self {
@@ -170,11 +187,9 @@ where
         Ok(data)
     }
 
+    /// Leaves the sensor in the sleep mode, the config register may ignore writes in the normal mode
     pub async fn configure(&mut self, config: Configuration) -> Result<(), Bme280Error> {
-        match self.mode().await? {
-            SensorMode::Sleep => {}
-            _ => self.soft_reset().await?,
-        };
+        self.set_mode(BME280_SLEEP_MODE).await?;
 
         self.write_register(
             BME280_CTRL_HUM_ADDR,
@@ -201,7 +216,11 @@ where
 
         let data = self.read_register(BME280_CONFIG_ADDR).await?;
         let data = set_bits!(data, BME280_FILTER_MSK, BME280_FILTER_POS, config.iir_filter.bits());
-        self.write_register(BME280_CONFIG_ADDR, data).await
+        let data = set_bits!(data, BME280_STANDBY_MSK, BME280_STANDBY_POS, config.standby.bits());
+        self.write_register(BME280_CONFIG_ADDR, data).await?;
+
+        self.config = config;
+        Ok(())
     }
 
     pub async fn mode(&mut self) -> Result<SensorMode, Bme280Error> {
@@ -218,20 +237,49 @@ where
         self.set_mode(BME280_FORCED_MODE).await
     }
 
+    /// Conversions run continuously, `config.standby` apart; the IIR filter keeps its state between them
+    pub async fn normal(&mut self) -> Result<(), Bme280Error> {
+        self.set_mode(BME280_NORMAL_MODE).await?;
+        self.wait_for_measurement().await
+    }
+
+    /// Switching between the forced and the normal mode goes through the sleep mode,
+    /// the oversampling settings in the same register are kept
     pub async fn set_mode(&mut self, mode: u8) -> Result<(), Bme280Error> {
-        match self.mode().await? {
-            SensorMode::Sleep => {}
-            _ => self.soft_reset().await?,
-        };
         let data = self.read_register(BME280_PWR_CTRL_ADDR).await?;
+        if data & BME280_SENSOR_MODE_MSK != BME280_SLEEP_MODE && mode != BME280_SLEEP_MODE {
+            let data = set_bits!(data, BME280_SENSOR_MODE_MSK, 0, BME280_SLEEP_MODE);
+            self.write_register(BME280_PWR_CTRL_ADDR, data).await?;
+        }
         let data = set_bits!(data, BME280_SENSOR_MODE_MSK, 0, mode);
         self.write_register(BME280_PWR_CTRL_ADDR, data).await
     }
 
-    /// Captures and processes sensor data for temperature, pressure, and humidity
+    /// Waits out the conversion time of the current configuration, then polls the status register
+    async fn wait_for_measurement(&mut self) -> Result<(), Bme280Error> {
+        let measurement_time = self.config.measurement_time();
+        Timer::after(measurement_time).await;
+
+        let deadline = Instant::now() + measurement_time;
+        while self.read_register(BME280_STATUS_ADDR).await? & BME280_STATUS_MEASURING != 0 {
+            if Instant::now() > deadline {
+                return Err(Bme280Error::MeasurementTimeout);
+            }
+            Timer::after(BME280_STATUS_POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /// Captures and processes sensor data for temperature, pressure, and humidity in the forced mode
     pub async fn measure(&mut self) -> Result<Measurements, Bme280Error> {
         self.forced().await?;
-        Timer::after(Duration::from_millis(40)).await;
+        self.wait_for_measurement().await?;
+        self.read_measurements().await
+    }
+
+    /// The latest conversion of the normal mode; the data registers are shadowed during a burst read,
+    /// so a conversion running meanwhile can not mix into the result
+    pub async fn read_measurements(&mut self) -> Result<Measurements, Bme280Error> {
         let measurements = self.read_data(BME280_DATA_ADDR).await?;
         match self.calibration.as_mut() {
             Some(calibration) => {
@@ -317,6 +365,7 @@ pub struct Configuration {
     pressure_oversampling: Oversampling,
     humidity_oversampling: Oversampling,
     iir_filter: IIRFilter,
+    standby: StandbyTime,
 }
 
 impl Configuration {
@@ -343,15 +392,73 @@ impl Configuration {
         self.iir_filter = filter;
         self
     }
+
+    /// Sets the inactive duration between the conversions of the normal mode.
+    pub fn with_standby(mut self, standby: StandbyTime) -> Self {
+        self.standby = standby;
+        self
+    }
+
+    /// Maximum duration of a single conversion, see appendix B of the datasheet; skipped channels take no time
+    pub fn measurement_time(&self) -> Duration {
+        let channel = |oversampling: Oversampling, setup: u32| match oversampling.factor() {
+            0 => 0,
+            factor => 2300 * factor + setup,
+        };
+        let micros = 1250
+            + channel(self.temperature_oversampling, 0)
+            + channel(self.pressure_oversampling, 575)
+            + channel(self.humidity_oversampling, 575);
+        Duration::from_micros(micros as u64)
+    }
+
+    /// The register fields, see `BME280_CONFIGURATION_LEN`
+    pub fn to_bits(&self) -> [u8; BME280_CONFIGURATION_LEN] {
+        [
+            self.temperature_oversampling.bits(),
+            self.pressure_oversampling.bits(),
+            self.humidity_oversampling.bits(),
+            self.iir_filter.bits(),
+            self.standby.bits(),
+        ]
+    }
+
+    /// Rejects out of range fields and the combinations the compensation can not handle
+    pub fn from_bits(bits: [u8; BME280_CONFIGURATION_LEN]) -> Result<Self, ConfigurationError> {
+        let oversampling = |bits| Oversampling::from_bits(bits).ok_or(ConfigurationError::InvalidOversampling);
+        let config = Self {
+            temperature_oversampling: oversampling(bits[0])?,
+            pressure_oversampling: oversampling(bits[1])?,
+            humidity_oversampling: oversampling(bits[2])?,
+            iir_filter: IIRFilter::from_bits(bits[3]).ok_or(ConfigurationError::InvalidFilter)?,
+            standby: StandbyTime::from_bits(bits[4]).ok_or(ConfigurationError::InvalidStandby)?,
+        };
+
+        let is_skipped = |oversampling: Oversampling| matches!(oversampling, Oversampling::Skipped);
+        if is_skipped(config.temperature_oversampling) {
+            // pressure and humidity are compensated with the temperature (t_fine)
+            return if is_skipped(config.pressure_oversampling) && is_skipped(config.humidity_oversampling) {
+                Err(ConfigurationError::NothingMeasured)
+            } else {
+                Err(ConfigurationError::TemperatureSkipped)
+            };
+        }
+        Ok(config)
+    }
+
+    /// How often the normal mode produces a new measurement
+    pub fn normal_mode_period(&self) -> Duration {
+        self.measurement_time() + self.standby.duration()
+    }
 }
 
 #[derive(Debug, defmt::Format)]
 pub struct Measurements {
     /// temperature in degrees celsius
     pub temperature: f32,
-    /// pressure in pascals
+    /// pressure in pascals (`0` if skipped)
     pub pressure: f32,
-    /// percent relative humidity (`0` with BMP280)
+    /// percent relative humidity (`0` with BMP280 or if skipped)
     pub humidity: f32,
 }
 
@@ -375,8 +482,14 @@ impl Measurements {
         let humidity = data_msb | data_lsb;
 
         let temperature = Measurements::compensate_temperature(temperature, calibration)?;
-        let pressure = Measurements::compensate_pressure(pressure, calibration)?;
-        let humidity = Measurements::compensate_humidity(humidity, calibration)?;
+        let pressure = match pressure {
+            BME280_SKIPPED_P_T => 0.0,
+            pressure => Measurements::compensate_pressure(pressure, calibration)?,
+        };
+        let humidity = match humidity {
+            BME280_SKIPPED_H => 0.0,
+            humidity => Measurements::compensate_humidity(humidity, calibration)?,
+        };
 
         Ok(Measurements { temperature, pressure, humidity })
     }
@@ -463,6 +576,9 @@ impl Measurements {
 /// The default is 1x, i.e., no oversampling.
 #[derive(Debug, Copy, Clone)]
 pub enum Oversampling {
+    /// Disables the channel, its measurement reads as `0`.
+    /// The temperature can not be skipped, the other channels are compensated with it.
+    Skipped,
     /// Disables oversampling.
     /// Without IIR filtering, this sets the resolution of temperature and pressure measurements
     /// to 16 bits.
@@ -488,6 +604,7 @@ pub enum Oversampling {
 impl Oversampling {
     fn bits(&self) -> u8 {
         match self {
+            Oversampling::Skipped => BME280_OVERSAMPLING_SKIPPED,
             Oversampling::Oversampling1X => BME280_OVERSAMPLING_1X,
             Oversampling::Oversampling2X => BME280_OVERSAMPLING_2X,
             Oversampling::Oversampling4X => BME280_OVERSAMPLING_4X,
@@ -495,6 +612,26 @@ impl Oversampling {
             Oversampling::Oversampling16X => BME280_OVERSAMPLING_16X,
         }
     }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            BME280_OVERSAMPLING_SKIPPED => Some(Oversampling::Skipped),
+            BME280_OVERSAMPLING_1X => Some(Oversampling::Oversampling1X),
+            BME280_OVERSAMPLING_2X => Some(Oversampling::Oversampling2X),
+            BME280_OVERSAMPLING_4X => Some(Oversampling::Oversampling4X),
+            BME280_OVERSAMPLING_8X => Some(Oversampling::Oversampling8X),
+            BME280_OVERSAMPLING_16X => Some(Oversampling::Oversampling16X),
+            _ => None,
+        }
+    }
+
+    /// Samples taken per conversion
+    fn factor(&self) -> u32 {
+        match self.bits() {
+            BME280_OVERSAMPLING_SKIPPED => 0,
+            bits => 1 << (bits - 1),
+        }
+    }
 }
 
 impl Default for Oversampling {
@@ -539,6 +676,67 @@ impl IIRFilter {
             IIRFilter::Coefficient16 => BME280_FILTER_COEFF_16,
         }
     }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            BME280_FILTER_COEFF_OFF => Some(IIRFilter::Off),
+            BME280_FILTER_COEFF_2 => Some(IIRFilter::Coefficient2),
+            BME280_FILTER_COEFF_4 => Some(IIRFilter::Coefficient4),
+            BME280_FILTER_COEFF_8 => Some(IIRFilter::Coefficient8),
+            BME280_FILTER_COEFF_16 => Some(IIRFilter::Coefficient16),
+            _ => None,
+        }
+    }
+}
+
+/// Inactive duration between the conversions in the normal mode (t_sb).
+/// See section 3.6.3 of the datasheet; the BMP280 takes the two last settings as 2 s and 4 s.
+/// The default is 1 s.
+#[derive(Debug, Copy, Clone, Default)]
+pub enum StandbyTime {
+    Ms0_5,
+    Ms62_5,
+    Ms125,
+    Ms250,
+    Ms500,
+    #[default]
+    Ms1000,
+    Ms10,
+    Ms20,
+}
+
+impl StandbyTime {
+    fn bits(&self) -> u8 {
+        *self as u8
+    }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        [
+            StandbyTime::Ms0_5,
+            StandbyTime::Ms62_5,
+            StandbyTime::Ms125,
+            StandbyTime::Ms250,
+            StandbyTime::Ms500,
+            StandbyTime::Ms1000,
+            StandbyTime::Ms10,
+            StandbyTime::Ms20,
+        ]
+        .get(bits as usize)
+        .copied()
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_micros(match self {
+            StandbyTime::Ms0_5 => 500,
+            StandbyTime::Ms62_5 => 62_500,
+            StandbyTime::Ms125 => 125_000,
+            StandbyTime::Ms250 => 250_000,
+            StandbyTime::Ms500 => 500_000,
+            StandbyTime::Ms1000 => 1_000_000,
+            StandbyTime::Ms10 => 10_000,
+            StandbyTime::Ms20 => 20_000,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -548,6 +746,25 @@ pub enum SensorMode {
     Normal,
 }
 
+/// Why `Configuration::from_bits` rejected a configuration
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum ConfigurationError {
+    #[error("Oversampling must be 0 (skipped) to 5 (16x)")]
+    InvalidOversampling,
+
+    #[error("IIR filter must be 0 (off) to 4 (16)")]
+    InvalidFilter,
+
+    #[error("Standby must be 0 to 7")]
+    InvalidStandby,
+
+    #[error("Pressure and humidity need the temperature")]
+    TemperatureSkipped,
+
+    #[error("All channels are skipped")]
+    NothingMeasured,
+}
+
 /// BME280 errors
 #[derive(Error, Debug)]
 pub enum Bme280Error {
@@ -573,4 +790,7 @@ pub enum Bme280Error {
 
     #[error("Delay error")]
     Delay,
+
+    #[error("Measurement did not complete in time")]
+    MeasurementTimeout,
 }
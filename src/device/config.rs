@@ -2,19 +2,141 @@ use embassy_time::Duration;
 
 pub(crate) const NUM_CONNECTIONS: usize = 3;
 
+pub(crate) const BLE_ATT_MTU: u16 = 256;
+pub(crate) const BLE_SNAPSHOT_LEN: usize = 64;
+
+pub(crate) const BLE_MAX_BONDS: usize = 4;
+// Notifiable and indicatable characteristics of the GATT table, the build fails if there are more
+pub(crate) const BLE_CCCD_COUNT: usize = 64;
+// CCCD values of all notifiable characteristics of a single peer: [handle: u16][len: u16][value: u16] each
+// and the CRC of the table
+pub(crate) const BLE_SYS_ATTRS_LEN: usize = BLE_CCCD_COUNT * 6 + 4;
+// 128-bit UUID bases the softdevice can hold, the build fails if the GATT table needs more
+pub(crate) const BLE_VS_UUID_COUNT: usize = 50;
+pub(crate) const BLE_EXTENDED_ADV_DATA_LEN: usize = 64;
+pub(crate) const BLE_PAIRING_WINDOW: Duration = Duration::from_secs(60);
+
+// Auto profile connections drop to the low power parameters after this long without GATT activity
+pub(crate) const CONN_PARAMS_IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+// The central has to answer the update request within this time, otherwise it is considered rejected
+pub(crate) const CONN_PARAMS_NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(5);
+// Once all the candidates of a profile have been rejected, the central is left alone for a while
+pub(crate) const CONN_PARAMS_REJECT_BACKOFF: Duration = Duration::from_secs(60);
+pub(crate) const CONN_PARAMS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Used by connections that did not write their own timeout
+pub(crate) const DEFAULT_NOTIFICATION_TIMEOUT: Duration = Duration::from_millis(1000);
+pub(crate) const NOTIFICATION_TIMEOUT_TOLERANCE: Duration = Duration::from_millis(50);
+
+pub(crate) const BLE_DIS_STRING_LEN: usize = 32;
+// Both fit into a single legacy advertising packet along with the flags and the service UUID
+pub(crate) const BLE_DEVICE_NAME_LEN: usize = 20;
+pub(crate) const BLE_LOCATION_LEN: usize = 20;
+// ADC channels exposed over BLE (the battery channel is in the diagnostics service) and their
+// user assigned labels, e.g. "soil moisture"
+pub(crate) const BLE_ADC_CHANNELS: usize = 7;
+pub(crate) const BLE_ADC_LABEL_LEN: usize = 24;
+pub(crate) const DEFAULT_DEVICE_NAME: &str = "Sensor Hub BLE";
+pub(crate) const DEVICE_MANUFACTURER_NAME: &str = "night-crawler";
+pub(crate) const DEVICE_MODEL_NUMBER: &str = "Sensor Hub BLE";
+// Schematics revision of the board, MS88SF3 module
+pub(crate) const DEVICE_HARDWARE_REVISION: &str = "2023-10-26 MS88SF3";
+
+// Nordic UART Service shell: a notification carries up to ATT_MTU - 3 bytes
+pub(crate) const BLE_NUS_CHUNK_LEN: usize = BLE_ATT_MTU as usize - 3;
+pub(crate) const BLE_SHELL_LINE_LEN: usize = 128;
+pub(crate) const BLE_SHELL_RESPONSE_LEN: usize = 512;
+pub(crate) const BLE_SHELL_QUEUE_LEN: usize = 4;
+
 pub(crate) const BLE_DEBUG_QUEUE_LEN: usize = 2;
 pub(crate) const BLE_DEBUG_ARRAY_LEN: usize = 128;
+// Entries kept in RAM, older ones are overwritten
+pub(crate) const LOG_RING_LEN: usize = 32;
+// A page of the log backlog characteristic, fits into a single notification as well
+pub(crate) const BLE_LOG_PAGE_LEN: usize = BLE_NUS_CHUNK_LEN;
+// Severe entries are written to flash in batches, at most this often, to bound page erases
+pub(crate) const LOG_PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+pub(crate) const LOG_PERSIST_BATCH_LEN: usize = 4;
 
 pub(crate) const BLE_EXPANDER_CONTROL_BYTES_SIZE: usize = 16;
 pub(crate) const BLE_EXPANDER_BUF_SIZE: usize = 512 - BLE_EXPANDER_CONTROL_BYTES_SIZE;
 pub(crate) const BLE_EXPANDER_LOCK_TIMEOUT: Duration = Duration::from_secs(20);
 pub(crate) const BLE_EXPANDER_EXEC_TIMEOUT: Duration = Duration::from_millis(2000);
 
+// Battery voltage changes slowly, no need to sample it more often for the Battery Service alone
+pub(crate) const BATTERY_LEVEL_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) const BTHOME_KEY_LEN: usize = 16;
+// Sensors are sampled at least this often while broadcasting
+pub(crate) const BTHOME_SAMPLING_INTERVAL: Duration = Duration::from_secs(30);
+// Restarting advertising on every reading would keep the radio busy with subscribed clients
+pub(crate) const BTHOME_MIN_REBUILD_INTERVAL: Duration = Duration::from_secs(5);
+
 pub(crate) const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(50);
 
 // Color sensor oversampling takes a lot of time
 pub(crate) const ALL_TASK_COMPLETION_INTERVAL: Duration = Duration::from_millis(3000);
 
 pub(crate) const FLASH_PAGE_SIZE: usize = 4096;
+pub(crate) const FLASH_WRITE_ALIGNMENT: usize = 4;
 pub(crate) const CONFIG_FLASH_SIZE: usize = FLASH_PAGE_SIZE - 4;
 pub(crate) const INIT_TOKEN: [u8; 4] = [0xBB, 0x3D, 0x12, 0x3A];
+
+// Flash layout, keep in sync with memory.x and bootloader/memory.x, build.rs checks them: the bootloader swaps the
+// application (ACTIVE) with the DFU partition, which is a page larger for the swap, and keeps its progress in the state
+// page; the settings pages and the history region follow
+pub(crate) const ACTIVE_OFFSET: u32 = 0x27000;
+pub(crate) const ACTIVE_SIZE: u32 = 77 * FLASH_PAGE_SIZE as u32;
+pub(crate) const DFU_OFFSET: u32 = ACTIVE_OFFSET + ACTIVE_SIZE;
+pub(crate) const DFU_SIZE: u32 = ACTIVE_SIZE + FLASH_PAGE_SIZE as u32;
+pub(crate) const BOOTLOADER_STATE_OFFSET: u32 = DFU_OFFSET + DFU_SIZE;
+pub(crate) const BOOTLOADER_STATE_SIZE: u32 = FLASH_PAGE_SIZE as u32;
+pub(crate) const SETTINGS_FLASH_OFFSET: u32 = BOOTLOADER_STATE_OFFSET + BOOTLOADER_STATE_SIZE;
+pub(crate) const SETTINGS_FLASH_PAGES: u32 = 16;
+// The in-tree bootloader, the MBR parameter page follows it
+pub(crate) const BOOTLOADER_OFFSET: u32 = 0xF4000;
+// [offset: u32][image bytes], a multiple of the flash write size that fits into a single write
+pub(crate) const BLE_DFU_CHUNK_LEN: usize = 244;
+pub(crate) const BLE_DFU_DATA_LEN: usize = 4 + BLE_DFU_CHUNK_LEN;
+pub(crate) const BLE_DFU_CONTROL_LEN: usize = 128;
+pub(crate) const BLE_DFU_QUEUE_LEN: usize = 4;
+// Only with the `dfu-bootloader` feature; the watchdog keeps running across soft resets, the bootloader starts it with
+// the same timeout and feeds it
+pub(crate) const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const WATCHDOG_FEED_INTERVAL: Duration = Duration::from_secs(2);
+
+// Drift is only estimated from syncs that are at least this far apart, the central's clock has
+// a resolution of a second at best
+pub(crate) const CLOCK_DRIFT_MIN_INTERVAL: Duration = Duration::from_secs(30 * 60);
+// 32.768 kHz crystal tolerance is ~20 ppm, the internal RC oscillator ~500 ppm between calibrations
+pub(crate) const CLOCK_MAX_DRIFT_PPM: i64 = 500;
+// [year: u16][month][day][hours][minutes][seconds][day of week][fractions256][adjust reason]
+pub(crate) const BLE_CURRENT_TIME_LEN: usize = 10;
+// How often the Current Time characteristic is refreshed while connected
+pub(crate) const CLOCK_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Offline history: records are sampled regardless of connections and appended to a dedicated region
+// after the settings pages, the oldest page is erased once the region is full
+pub(crate) const HISTORY_FLASH_OFFSET: u32 = SETTINGS_FLASH_OFFSET + SETTINGS_FLASH_PAGES * FLASH_PAGE_SIZE as u32;
+pub(crate) const HISTORY_FLASH_PAGES: usize = 32;
+// 0 turns the logger off; the sensors need ALL_TASK_COMPLETION_INTERVAL for a single round
+pub(crate) const DEFAULT_HISTORY_INTERVAL: u32 = 600;
+pub(crate) const HISTORY_MIN_INTERVAL: u32 = 30;
+// A notification of the bulk download, as many whole records as fit into the negotiated ATT_MTU
+pub(crate) const BLE_HISTORY_CHUNK_LEN: usize = BLE_NUS_CHUNK_LEN;
+// [opcode][operator][filter type][min: u32][max: u32] is the longest RACP request
+pub(crate) const BLE_RACP_LEN: usize = 11;
+
+// BME280 oversampling of the three channels, IIR filter and standby as the register fields
+pub(crate) const BLE_BME_CONFIG_LEN: usize = 5;
+
+// Pressure tendency: the BME280 is woken this often regardless of connections, a 3 hour window
+// is the synoptic tendency period
+pub(crate) const PRESSURE_TREND_SAMPLE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+pub(crate) const PRESSURE_TREND_WINDOW: Duration = Duration::from_secs(3 * 60 * 60);
+pub(crate) const PRESSURE_TREND_SAMPLES: usize = 19;
+// The tendency is extrapolated to the window once the samples span at least this much
+pub(crate) const PRESSURE_TREND_MIN_SPAN: Duration = Duration::from_secs(60 * 60);
+// Reference altitude of the sea-level pressure, m; the Dead Sea shore to the highest settlements
+pub(crate) const MIN_REFERENCE_ALTITUDE: i16 = -500;
+pub(crate) const MAX_REFERENCE_ALTITUDE: i16 = 6000;
@@ -0,0 +1,4 @@
+// Host stand-in for the simulator and the tests
+#[cfg(not(target_os = "none"))]
+pub(crate) mod ram_flash;
+pub(crate) mod updater;
@@ -0,0 +1,64 @@
+//! In-memory NOR flash stand-in for running the DFU state machine on a host
+
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+/// Behaves like the nRF52840 flash: erased bytes read as 0xFF and writes can only clear bits
+pub(crate) struct RamFlash<const N: usize> {
+    pub(crate) data: [u8; N],
+}
+
+impl<const N: usize> RamFlash<N> {
+    pub(crate) const fn new() -> Self {
+        Self { data: [0xFF; N] }
+    }
+
+    fn check(offset: u32, len: usize, alignment: usize) -> Result<(), NorFlashErrorKind> {
+        if offset as usize % alignment != 0 || len % alignment != 0 {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        if offset as usize + len > N {
+            return Err(NorFlashErrorKind::OutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> ErrorType for RamFlash<N> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<const N: usize> ReadNorFlash for RamFlash<N> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        Self::check(offset, bytes.len(), Self::READ_SIZE)?;
+        bytes.copy_from_slice(&self.data[offset as usize..offset as usize + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> NorFlash for RamFlash<N> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 4096;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if to < from {
+            return Err(NorFlashErrorKind::OutOfBounds);
+        }
+        Self::check(from, (to - from) as usize, Self::ERASE_SIZE)?;
+        self.data[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        Self::check(offset, bytes.len(), Self::WRITE_SIZE)?;
+        for (cell, byte) in self.data[offset as usize..].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
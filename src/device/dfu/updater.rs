@@ -0,0 +1,428 @@
+//! Chunked firmware image receiver. It only depends on `NorFlash`, so the state machine runs on a host
+//! against `RamFlash` as well as on the device against the DFU partition.
+
+use ed25519_compact::{PublicKey, Signature};
+use embedded_storage_async::nor_flash::NorFlash;
+use sha2::{Digest, Sha256};
+use thiserror_no_std::Error;
+
+pub(crate) const DFU_DIGEST_LEN: usize = 32;
+pub(crate) const DFU_SIGNATURE_LEN: usize = 64;
+pub(crate) const DFU_PUBLIC_KEY_LEN: usize = 32;
+/// Largest chunk accepted by `write`, the final chunk is padded up to the flash write size
+pub(crate) const DFU_MAX_CHUNK_LEN: usize = 256;
+
+const HASH_BUF_LEN: usize = 256;
+
+#[repr(align(4))]
+struct AlignedBuf<const N: usize>([u8; N]);
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum DfuError {
+    #[error("no image started")]
+    NotStarted = 1,
+
+    #[error("image does not fit into the DFU partition")]
+    ImageTooLarge = 2,
+
+    #[error("unexpected chunk offset")]
+    UnexpectedOffset = 3,
+
+    #[error("chunk is not aligned to the flash write size")]
+    Misaligned = 4,
+
+    #[error("image is incomplete")]
+    Incomplete = 5,
+
+    #[error("SHA-256 digest mismatch")]
+    DigestMismatch = 6,
+
+    #[error("invalid signature")]
+    InvalidSignature = 7,
+
+    #[error("image is not verified")]
+    NotVerified = 8,
+
+    #[error("flash error")]
+    Flash = 9,
+
+    #[error("invalid command")]
+    InvalidCommand = 10,
+
+    #[error("no bootloader to swap the image")]
+    NoBootloader = 11,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub(crate) enum DfuState {
+    Idle = 0,
+    Receiving = 1,
+    Verified = 2,
+}
+
+/// Announced by the client before the first chunk; the signature is Ed25519 over the SHA-256 digest
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct DfuImage {
+    pub(crate) size: u32,
+    pub(crate) digest: [u8; DFU_DIGEST_LEN],
+    pub(crate) signature: [u8; DFU_SIGNATURE_LEN],
+}
+
+impl DfuImage {
+    pub(crate) const LEN: usize = 4 + DFU_DIGEST_LEN + DFU_SIGNATURE_LEN;
+
+    pub(crate) fn serialize(&self) -> [u8; Self::LEN] {
+        let mut buf = [0u8; Self::LEN];
+        buf[0..4].copy_from_slice(&self.size.to_le_bytes());
+        buf[4..4 + DFU_DIGEST_LEN].copy_from_slice(&self.digest);
+        buf[4 + DFU_DIGEST_LEN..].copy_from_slice(&self.signature);
+        buf
+    }
+
+    /// [size: u32][sha256: 32][ed25519 signature: 64]
+    pub(crate) fn deserialize(buf: &[u8]) -> Option<Self> {
+        if buf.len() != Self::LEN {
+            return None;
+        }
+        Some(Self {
+            size: u32::from_le_bytes(buf[0..4].try_into().ok()?),
+            digest: buf[4..4 + DFU_DIGEST_LEN].try_into().ok()?,
+            signature: buf[4 + DFU_DIGEST_LEN..].try_into().ok()?,
+        })
+    }
+}
+
+pub(crate) struct DfuUpdater<F: NorFlash> {
+    flash: F,
+    public_key: [u8; DFU_PUBLIC_KEY_LEN],
+    image: Option<DfuImage>,
+    state: DfuState,
+    /// Everything below has been written
+    offset: u32,
+    /// Pages are erased right before the first write into them
+    erased_until: u32,
+}
+
+impl<F: NorFlash> DfuUpdater<F> {
+    pub(crate) fn new(flash: F, public_key: [u8; DFU_PUBLIC_KEY_LEN]) -> Self {
+        Self { flash, public_key, image: None, state: DfuState::Idle, offset: 0, erased_until: 0 }
+    }
+
+    pub(crate) fn state(&self) -> DfuState {
+        self.state
+    }
+
+    pub(crate) fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Pages of the image written completely, a transfer interrupted by a reboot resumes after them
+    pub(crate) fn completed_pages(&self) -> u32 {
+        let page_size = F::ERASE_SIZE as u32;
+        match (&self.image, self.state) {
+            (Some(image), DfuState::Receiving | DfuState::Verified) if self.offset == image.size => {
+                image.size.div_ceil(page_size)
+            }
+            (Some(_), DfuState::Receiving) => self.offset / page_size,
+            _ => 0,
+        }
+    }
+
+    fn check_size(&self, image: &DfuImage) -> Result<(), DfuError> {
+        if image.size == 0 || image.size as usize > self.flash.capacity() {
+            return Err(DfuError::ImageTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Starts receiving an image and returns the offset the client has to continue from:
+    /// announcing the same image again resumes an interrupted transfer.
+    pub(crate) fn start(&mut self, image: DfuImage) -> Result<u32, DfuError> {
+        self.check_size(&image)?;
+        if self.image.as_ref() == Some(&image) && self.state != DfuState::Idle {
+            return Ok(self.offset);
+        }
+
+        self.image = Some(image);
+        self.state = DfuState::Receiving;
+        self.offset = 0;
+        self.erased_until = 0;
+        Ok(0)
+    }
+
+    /// Continues a transfer after a reboot from the persisted number of completed pages; whatever was written past
+    /// them is erased and received again. Returns the offset the client has to continue from.
+    pub(crate) fn restore(&mut self, image: DfuImage, pages: u32) -> Result<u32, DfuError> {
+        self.check_size(&image)?;
+        let page_size = F::ERASE_SIZE as u32;
+        let erased_until = pages.min(image.size.div_ceil(page_size)) * page_size;
+
+        self.offset = erased_until.min(image.size);
+        self.erased_until = erased_until;
+        self.image = Some(image);
+        self.state = DfuState::Receiving;
+        Ok(self.offset)
+    }
+
+    pub(crate) fn abort(&mut self) {
+        self.image = None;
+        self.state = DfuState::Idle;
+        self.offset = 0;
+        self.erased_until = 0;
+    }
+
+    /// Chunks have to arrive in order; a repeated chunk below the current offset is acknowledged
+    /// without writing, so a client can blindly retry the last chunk after a reconnect.
+    pub(crate) async fn write(&mut self, offset: u32, data: &[u8]) -> Result<u32, DfuError> {
+        let size = match (&self.image, self.state) {
+            (Some(image), DfuState::Receiving) => image.size,
+            _ => return Err(DfuError::NotStarted),
+        };
+        let end = offset.checked_add(data.len() as u32).ok_or(DfuError::ImageTooLarge)?;
+        if end > size {
+            return Err(DfuError::ImageTooLarge);
+        }
+        if end <= self.offset {
+            return Ok(self.offset);
+        }
+        if offset != self.offset {
+            return Err(DfuError::UnexpectedOffset);
+        }
+        if data.len() > DFU_MAX_CHUNK_LEN {
+            return Err(DfuError::ImageTooLarge);
+        }
+
+        let write_size = F::WRITE_SIZE as u32;
+        // only the final chunk may end in the middle of a write unit
+        if offset % write_size != 0 || (end != size && data.len() as u32 % write_size != 0) {
+            return Err(DfuError::Misaligned);
+        }
+        let padded_len = (data.len() as u32).div_ceil(write_size) * write_size;
+
+        self.erase_until(offset + padded_len).await?;
+
+        // the softdevice flash driver needs a word aligned source buffer
+        let mut buf = AlignedBuf([0xFF; DFU_MAX_CHUNK_LEN + 4]);
+        buf.0[..data.len()].copy_from_slice(data);
+        self.flash.write(offset, &buf.0[..padded_len as usize]).await.map_err(|_| DfuError::Flash)?;
+
+        self.offset = end;
+        Ok(end)
+    }
+
+    async fn erase_until(&mut self, end: u32) -> Result<(), DfuError> {
+        if end <= self.erased_until {
+            return Ok(());
+        }
+        let page_size = F::ERASE_SIZE as u32;
+        let to = end.div_ceil(page_size) * page_size;
+        self.flash.erase(self.erased_until, to).await.map_err(|_| DfuError::Flash)?;
+        self.erased_until = to;
+        Ok(())
+    }
+
+    /// Hashes the received image back from flash and checks the signature of the announced digest
+    pub(crate) async fn verify(&mut self) -> Result<(), DfuError> {
+        let image = match (&self.image, self.state) {
+            (Some(image), DfuState::Receiving | DfuState::Verified) => image.clone(),
+            _ => return Err(DfuError::NotStarted),
+        };
+        if self.offset != image.size {
+            return Err(DfuError::Incomplete);
+        }
+
+        let mut hasher = Sha256::new();
+        let mut buf = AlignedBuf([0u8; HASH_BUF_LEN]);
+        let mut position = 0;
+        while position < image.size {
+            let len = (image.size - position).min(HASH_BUF_LEN as u32) as usize;
+            self.flash.read(position, &mut buf.0[..len]).await.map_err(|_| DfuError::Flash)?;
+            hasher.update(&buf.0[..len]);
+            position += len as u32;
+        }
+        if hasher.finalize().as_slice() != image.digest {
+            return Err(DfuError::DigestMismatch);
+        }
+
+        let public_key = PublicKey::from_slice(&self.public_key).map_err(|_| DfuError::InvalidSignature)?;
+        let signature = Signature::from_slice(&image.signature).map_err(|_| DfuError::InvalidSignature)?;
+        public_key.verify(image.digest, &signature).map_err(|_| DfuError::InvalidSignature)?;
+
+        self.state = DfuState::Verified;
+        Ok(())
+    }
+
+    /// Only a verified image may be handed over to the bootloader
+    pub(crate) fn ensure_verified(&self) -> Result<(), DfuError> {
+        match self.state {
+            DfuState::Verified => Ok(()),
+            _ => Err(DfuError::NotVerified),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    use ed25519_compact::{KeyPair, Seed};
+
+    use super::*;
+    use crate::common::device::dfu::ram_flash::RamFlash;
+
+    const PAGE_LEN: usize = 4096;
+    const FLASH_LEN: usize = 4 * PAGE_LEN;
+    const IMAGE_LEN: usize = 1001;
+    const CHUNK_LEN: usize = 244;
+
+    type TestUpdater = DfuUpdater<RamFlash<FLASH_LEN>>;
+
+    fn noop_clone(_: *const ()) -> RawWaker {
+        RawWaker::new(core::ptr::null(), &NOOP_WAKER)
+    }
+
+    fn noop(_: *const ()) {}
+
+    static NOOP_WAKER: RawWakerVTable = RawWakerVTable::new(noop_clone, noop, noop, noop);
+
+    /// `RamFlash` completes every operation on the first poll
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        let waker = unsafe { Waker::from_raw(noop_clone(core::ptr::null())) };
+        match pin!(future).poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(value) => value,
+            Poll::Pending => panic!("RamFlash operation pending"),
+        }
+    }
+
+    fn key_pair(seed: u8) -> KeyPair {
+        KeyPair::from_seed(Seed::new([seed; 32]))
+    }
+
+    fn image() -> [u8; IMAGE_LEN] {
+        core::array::from_fn(|index| (index * 7 % 251) as u8)
+    }
+
+    fn announce(image: &[u8], key_pair: &KeyPair) -> DfuImage {
+        let digest: [u8; DFU_DIGEST_LEN] = Sha256::digest(image).into();
+        DfuImage { size: image.len() as u32, digest, signature: *key_pair.sk.sign(digest, None) }
+    }
+
+    fn transfer(updater: &mut TestUpdater, image: &[u8]) -> Result<u32, DfuError> {
+        let mut offset = 0;
+        for chunk in image.chunks(CHUNK_LEN) {
+            offset = block_on(updater.write(offset, chunk))?;
+        }
+        Ok(offset)
+    }
+
+    #[test]
+    fn verifies_signed_image() {
+        let key_pair = key_pair(1);
+        let image = image();
+        let mut updater = TestUpdater::new(RamFlash::new(), *key_pair.pk);
+
+        assert_eq!(updater.start(announce(&image, &key_pair)), Ok(0));
+        assert_eq!(transfer(&mut updater, &image), Ok(IMAGE_LEN as u32));
+        assert_eq!(block_on(updater.verify()), Ok(()));
+        assert_eq!(updater.ensure_verified(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_foreign_signature() {
+        let image = image();
+        let mut updater = TestUpdater::new(RamFlash::new(), *key_pair(1).pk);
+
+        updater.start(announce(&image, &key_pair(2))).unwrap();
+        transfer(&mut updater, &image).unwrap();
+        assert_eq!(block_on(updater.verify()), Err(DfuError::InvalidSignature));
+        assert_eq!(updater.ensure_verified(), Err(DfuError::NotVerified));
+    }
+
+    #[test]
+    fn rejects_digest_mismatch() {
+        let key_pair = key_pair(1);
+        let image = image();
+        let mut corrupted = image;
+        corrupted[IMAGE_LEN / 2] ^= 0x01;
+        let mut updater = TestUpdater::new(RamFlash::new(), *key_pair.pk);
+
+        updater.start(announce(&image, &key_pair)).unwrap();
+        transfer(&mut updater, &corrupted).unwrap();
+        assert_eq!(block_on(updater.verify()), Err(DfuError::DigestMismatch));
+        assert_eq!(updater.ensure_verified(), Err(DfuError::NotVerified));
+    }
+
+    #[test]
+    fn rejects_out_of_order_chunks() {
+        let key_pair = key_pair(1);
+        let image = image();
+        let mut updater = TestUpdater::new(RamFlash::new(), *key_pair.pk);
+        let chunk = |index: usize| &image[index * CHUNK_LEN..(index + 1) * CHUNK_LEN];
+
+        updater.start(announce(&image, &key_pair)).unwrap();
+        assert_eq!(block_on(updater.write(CHUNK_LEN as u32, chunk(1))), Err(DfuError::UnexpectedOffset));
+        assert_eq!(block_on(updater.write(0, chunk(0))), Ok(CHUNK_LEN as u32));
+        // a retried chunk is acknowledged, a skipped one is not
+        assert_eq!(block_on(updater.write(0, chunk(0))), Ok(CHUNK_LEN as u32));
+        assert_eq!(block_on(updater.write(2 * CHUNK_LEN as u32, chunk(2))), Err(DfuError::UnexpectedOffset));
+        assert_eq!(updater.offset(), CHUNK_LEN as u32);
+        assert_eq!(block_on(updater.verify()), Err(DfuError::Incomplete));
+    }
+
+    #[test]
+    fn resumes_after_reboot_from_last_completed_page() {
+        let key_pair = key_pair(1);
+        let image: Vec<u8> = (0..3 * PAGE_LEN - 5).map(|index| (index * 13 % 251) as u8).collect();
+        let announce = announce(&image, &key_pair);
+        let mut flash = RamFlash::<FLASH_LEN>::new();
+
+        // the link drops in the middle of the third page, then the device reboots
+        let pages = {
+            let mut updater = DfuUpdater::new(&mut flash, *key_pair.pk);
+            let mut offset = updater.start(announce.clone()).unwrap();
+            for chunk in image.chunks(CHUNK_LEN).take(36) {
+                offset = block_on(updater.write(offset, chunk)).unwrap();
+            }
+            assert_eq!(offset, 36 * CHUNK_LEN as u32);
+            updater.completed_pages()
+        };
+        assert_eq!(pages, 2);
+
+        let mut updater = DfuUpdater::new(&mut flash, *key_pair.pk);
+        let mut offset = updater.restore(announce.clone(), pages).unwrap();
+        assert_eq!(offset, 2 * PAGE_LEN as u32);
+        for chunk in image[offset as usize..].chunks(CHUNK_LEN) {
+            offset = block_on(updater.write(offset, chunk)).unwrap();
+        }
+        assert_eq!(updater.completed_pages(), 3);
+        assert_eq!(block_on(updater.verify()), Ok(()));
+
+        // a complete image only has to be verified again
+        let mut updater = DfuUpdater::new(&mut flash, *key_pair.pk);
+        assert_eq!(updater.restore(announce, 3), Ok(image.len() as u32));
+        assert_eq!(block_on(updater.verify()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_oversized_image() {
+        let key_pair = key_pair(1);
+        let image = image();
+        let mut updater = TestUpdater::new(RamFlash::new(), *key_pair.pk);
+
+        let mut oversized = announce(&image, &key_pair);
+        oversized.size = FLASH_LEN as u32 + 1;
+        assert_eq!(updater.start(oversized), Err(DfuError::ImageTooLarge));
+        assert_eq!(updater.state(), DfuState::Idle);
+
+        // chunks past the announced size or longer than a chunk can be are refused as well
+        updater.start(announce(&image[..CHUNK_LEN], &key_pair)).unwrap();
+        assert_eq!(block_on(updater.write(0, &image[..CHUNK_LEN + 4])), Err(DfuError::ImageTooLarge));
+        updater.start(announce(&image, &key_pair)).unwrap();
+        assert_eq!(block_on(updater.write(0, &image[..DFU_MAX_CHUNK_LEN + 4])), Err(DfuError::ImageTooLarge));
+        assert_eq!(updater.offset(), 0);
+    }
+}
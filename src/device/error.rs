@@ -1,8 +1,13 @@
 use core::fmt;
 
-use defmt::error;
+#[cfg(target_os = "none")]
 use embassy_nrf::{spim, twim};
 use embassy_sync::channel::TrySendError;
+#[cfg(not(target_os = "none"))]
+use embedded_hal_async::i2c;
+#[cfg(not(target_os = "none"))]
+use embedded_storage_async::nor_flash::NorFlashErrorKind;
+#[cfg(target_os = "none")]
 use nrf_softdevice::ble::gatt_server;
 use thiserror_no_std::Error;
 
@@ -22,24 +27,89 @@ pub enum DeviceError {
     #[error("Flash error {0}")]
     Flash(#[from] FlashManagerError),
 
+    #[cfg(target_os = "none")]
     #[error("GATT SetValueError")]
     SetValueError(#[from] gatt_server::SetValueError),
 }
 
-#[derive(Error, Debug, defmt::Format)]
+#[derive(Error, Debug)]
+#[cfg_attr(target_os = "none", derive(defmt::Format))]
 pub enum FlashManagerError {
+    #[cfg(target_os = "none")]
     #[error("Flash Error: {0:?}")]
     FlashError(#[from] nrf_softdevice::FlashError),
 
+    /// The simulator keeps the flash in RAM
+    #[cfg(not(target_os = "none"))]
+    #[error("Flash Error: {0:?}")]
+    FlashError(#[from] NorFlashErrorKind),
+
     #[error("Race condition: {0}, {1}")]
     RaceCondition(usize, usize),
+
+    #[error("Bootloader state error: {0:?}")]
+    BootloaderState(#[from] embassy_boot::FirmwareUpdaterError),
+}
+
+#[cfg(not(target_os = "none"))]
+impl defmt::Format for FlashManagerError {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{}", defmt::Debug2Format(self))
+    }
 }
 
 
 #[derive(Error, Debug)]
+pub(crate) enum ShellError {
+    #[error("unknown command, try help")]
+    UnknownCommand,
+
+    #[error("unknown key")]
+    UnknownKey,
+
+    #[error("missing argument")]
+    MissingArgument,
+
+    #[error("invalid argument")]
+    InvalidArgument,
+
+    #[error("line too long")]
+    LineTooLong,
+
+    #[error("busy, try again")]
+    Busy,
+
+    #[error("expander: {0}")]
+    Expander(#[from] ExpanderError),
+
+    #[cfg(target_os = "none")]
+    #[error("GATT SetValueError")]
+    SetValueError(#[from] gatt_server::SetValueError),
+
+    #[cfg(target_os = "none")]
+    #[error("GATT GetValueError")]
+    GetValueError(#[from] gatt_server::GetValueError),
+}
+
+#[derive(Error, Debug, defmt::Format)]
 pub enum CustomI2CError {
+    #[cfg(target_os = "none")]
     #[error("I2C error")]
     TwimError(#[from] twim::Error),
+
+    /// Returned by the simulator's `FakeI2c`
+    #[cfg(not(target_os = "none"))]
+    #[error("no device at {0:#x}")]
+    NoAcknowledge(u8),
+}
+
+#[cfg(not(target_os = "none"))]
+impl i2c::Error for CustomI2CError {
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            CustomI2CError::NoAcknowledge(_) => i2c::ErrorKind::NoAcknowledge(i2c::NoAcknowledgeSource::Address),
+        }
+    }
 }
 
 #[derive(Error, Debug, defmt::Format)]
@@ -59,18 +129,26 @@ pub(crate) enum ExpanderError {
     #[error("Invalid command")]
     InvalidCommand(u8),
 
+    #[cfg(target_os = "none")]
     #[error("GATT GetValueError")]
     GetValueError(#[from] gatt_server::GetValueError),
 
+    #[cfg(target_os = "none")]
     #[error("GATT SetValueError")]
     SetValueError(#[from] gatt_server::SetValueError),
 
+    #[cfg(target_os = "none")]
     #[error("GATT SPI Expander Error")]
     SpiError(#[from] spim::Error),
 
+    #[cfg(target_os = "none")]
     #[error("GATT I2C Expander Error")]
     I2cError(#[from] twim::Error),
 
+    #[cfg(not(target_os = "none"))]
+    #[error("GATT I2C Expander Error")]
+    I2cError(#[from] CustomI2CError),
+
     #[error("Invalid expander type {0}")]
     InvalidExpanderType(u8),
 
@@ -1,16 +1,7 @@
-use embassy_nrf::peripherals;
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
-use embassy_sync::mutex::Mutex;
 use embassy_time::Duration;
-use embassy_time::Timer;
-use futures::FutureExt;
-use futures::select_biased;
-use rclite::Arc;
 
 use crate::common::device::config::{BLE_EXPANDER_BUF_SIZE, BLE_EXPANDER_CONTROL_BYTES_SIZE, BLE_EXPANDER_EXEC_TIMEOUT};
-use crate::common::device::peripherals_manager::ExpanderPins;
 use crate::common::device::error::ExpanderError;
-use crate::common::device::expander::{handle_i2c_exec, handle_spi_exec};
 use crate::common::device::expander::command::Command;
 
 #[derive(Debug, defmt::Format, Copy, Clone)]
@@ -195,44 +186,6 @@ impl ExpanderState {
 
         Ok(())
     }
-
-    pub(crate) async fn exec(
-        &self,
-        pins: &Arc<Mutex<ThreadModeRawMutex, ExpanderPins<peripherals::SPI3, peripherals::TWISPI1>>>,
-    ) -> Result<Option<[u8; BLE_EXPANDER_BUF_SIZE]>, ExpanderError> {
-        select_biased! {
-            response = self.exec_internal(pins).fuse() => {
-                response
-            }
-            _ = Timer::after(self.timeout).fuse() => {
-                Err(ExpanderError::Timeout)
-            }
-        }
-    }
-    async fn exec_internal(
-        &self,
-        pins: &Arc<Mutex<ThreadModeRawMutex, ExpanderPins<peripherals::SPI3, peripherals::TWISPI1>>>,
-    ) -> Result<Option<[u8; BLE_EXPANDER_BUF_SIZE]>, ExpanderError> {
-        match self.flags.expander_lock_type {
-            None | Some(ExpanderType::NotSet) => Err(ExpanderError::MutexNotLocked),
-            Some(ExpanderType::Spi) => {
-                match (self.flags.command, self.flags.size_write) {
-                    (Some(command), Some(size_write)) => {
-                        handle_spi_exec(&pins, command, &self.mosi[..size_write]).await
-                    }
-                    _ => Err(ExpanderError::IncompleteData)
-                }
-            }
-            Some(ExpanderType::I2c) => {
-                match (self.flags.command, self.flags.address, self.flags.size_write, self.flags.size_read) {
-                    (Some(command), Some(address), Some(size_write), Some(size_read)) => {
-                        handle_i2c_exec(&pins, address, command, &self.mosi[..size_write], size_read).await
-                    }
-                    _ => Err(ExpanderError::IncompleteData)
-                }
-            }
-        }
-    }
 }
 
 impl TryFrom<[u8; BLE_EXPANDER_BUF_SIZE + BLE_EXPANDER_CONTROL_BYTES_SIZE]> for ExpanderState {
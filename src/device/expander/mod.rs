@@ -45,6 +45,46 @@ pub(crate) async fn authenticate(connection: &Connection) -> Result<(), Expander
     }
 }
 
+/// Runs the accumulated command within the state's timeout
+pub(crate) async fn exec_expander(
+    state: &ExpanderState,
+    pins: &Arc<Mutex<ThreadModeRawMutex, ExpanderPins<peripherals::SPI3, peripherals::TWISPI1>>>,
+) -> Result<Option<[u8; BLE_EXPANDER_BUF_SIZE]>, ExpanderError> {
+    select_biased! {
+        response = exec_expander_internal(state, pins).fuse() => {
+            response
+        }
+        _ = Timer::after(state.timeout).fuse() => {
+            Err(ExpanderError::Timeout)
+        }
+    }
+}
+
+async fn exec_expander_internal(
+    state: &ExpanderState,
+    pins: &Arc<Mutex<ThreadModeRawMutex, ExpanderPins<peripherals::SPI3, peripherals::TWISPI1>>>,
+) -> Result<Option<[u8; BLE_EXPANDER_BUF_SIZE]>, ExpanderError> {
+    match state.flags.expander_lock_type {
+        None | Some(ExpanderType::NotSet) => Err(ExpanderError::MutexNotLocked),
+        Some(ExpanderType::Spi) => {
+            match (state.flags.command, state.flags.size_write) {
+                (Some(command), Some(size_write)) => {
+                    handle_spi_exec(&pins, command, &state.mosi[..size_write]).await
+                }
+                _ => Err(ExpanderError::IncompleteData)
+            }
+        }
+        Some(ExpanderType::I2c) => {
+            match (state.flags.command, state.flags.address, state.flags.size_write, state.flags.size_read) {
+                (Some(command), Some(address), Some(size_write), Some(size_read)) => {
+                    handle_i2c_exec(&pins, address, command, &state.mosi[..size_write], size_read).await
+                }
+                _ => Err(ExpanderError::IncompleteData)
+            }
+        }
+    }
+}
+
 pub(crate) async fn handle_set_cs(
     pins: &Arc<Mutex<ThreadModeRawMutex, ExpanderPins<peripherals::SPI3, peripherals::TWISPI1>>>,
     cs: u8,
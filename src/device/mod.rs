@@ -1,7 +1,9 @@
 // Allow dead code for contrib modules
 #[allow(dead_code)]
 pub(crate) mod bme280;
+pub(crate) mod battery;
 pub(crate) mod config;
+pub(crate) mod dfu;
 pub(crate) mod peripherals_manager;
 #[allow(dead_code)]
 pub(crate) mod epd;
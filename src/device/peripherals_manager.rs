@@ -96,6 +96,7 @@ pub(crate) struct PeripheralsManager {
     pub(crate) bbi2c0_pins: Arc<Mutex<ThreadModeRawMutex, BitbangI2CPins>>,
     pub(crate) button_pins: ButtonPins,
     pub(crate) expander_pins: Arc<Mutex<ThreadModeRawMutex, ExpanderPins<peripherals::SPI3, peripherals::TWISPI1>>>,
+    pub(crate) wdt: peripherals::WDT,
 }
 
 fn prepare_nrf_peripherals() -> Peripherals {
@@ -198,6 +199,7 @@ impl PeripheralsManager {
 
             button_pins,
             expander_pins: Arc::new(Mutex::new(expander_pins)),
+            wdt: board.WDT,
         })
     }
 }
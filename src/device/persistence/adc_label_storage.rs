@@ -0,0 +1,50 @@
+use heapless::Vec;
+
+use crate::common::device::config::{BLE_ADC_CHANNELS, BLE_ADC_LABEL_LEN};
+use crate::common::device::persistence::flash_manager::{SettingsPage, SettingsRecord};
+
+/// [token: 4][label lengths: 7][reserved: 1][labels: 7 * BLE_ADC_LABEL_LEN]
+pub(crate) const ADC_LABELS_RECORD_LEN: usize = 8 + BLE_ADC_CHANNELS * BLE_ADC_LABEL_LEN;
+pub(crate) const ADC_LABELS_PAGE_TOKEN: [u8; 4] = [0xAD, 0xC1, 0xAB, 0x01];
+
+const LABELS_OFFSET: usize = 8;
+
+pub(crate) type AdcLabel = Vec<u8, BLE_ADC_LABEL_LEN>;
+
+/// UTF-8 labels of the ADC channels, the User Description of the voltage characteristics
+#[derive(Default, Clone, PartialEq, Eq)]
+pub(crate) struct AdcLabels {
+    pub(crate) labels: [AdcLabel; BLE_ADC_CHANNELS],
+}
+
+impl SettingsRecord<ADC_LABELS_RECORD_LEN> for AdcLabels {
+    const PAGE: SettingsPage = SettingsPage::AdcLabels;
+
+    fn serialize(&self) -> [u8; ADC_LABELS_RECORD_LEN] {
+        let mut buf = [0u8; ADC_LABELS_RECORD_LEN];
+        buf[0..4].copy_from_slice(&ADC_LABELS_PAGE_TOKEN);
+        for (channel, label) in self.labels.iter().enumerate() {
+            buf[4 + channel] = label.len() as u8;
+            let offset = LABELS_OFFSET + channel * BLE_ADC_LABEL_LEN;
+            buf[offset..offset + label.len()].copy_from_slice(label);
+        }
+        buf
+    }
+
+    fn deserialize(buf: &[u8; ADC_LABELS_RECORD_LEN]) -> Option<Self> {
+        if buf[0..4] != ADC_LABELS_PAGE_TOKEN {
+            return None;
+        }
+
+        let mut labels = Self::default();
+        for (channel, label) in labels.labels.iter_mut().enumerate() {
+            let len = buf[4 + channel] as usize;
+            if len > BLE_ADC_LABEL_LEN {
+                return None;
+            }
+            let offset = LABELS_OFFSET + channel * BLE_ADC_LABEL_LEN;
+            *label = Vec::from_slice(&buf[offset..offset + len]).ok()?;
+        }
+        Some(labels)
+    }
+}
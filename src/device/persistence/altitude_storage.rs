@@ -0,0 +1,31 @@
+use crate::common::device::persistence::flash_manager::{SettingsPage, SettingsRecord};
+
+/// [token: 4][reference altitude: i16][padding: 2]
+pub(crate) const ALTITUDE_RECORD_LEN: usize = 8;
+pub(crate) const ALTITUDE_PAGE_TOKEN: [u8; 4] = [0xA1, 0x7E, 0x00, 0x01];
+
+#[derive(Clone, Copy)]
+pub(crate) struct AltitudeSettings {
+    /// Metres above the sea level the device is installed at
+    pub(crate) reference_altitude: i16,
+}
+
+impl SettingsRecord<ALTITUDE_RECORD_LEN> for AltitudeSettings {
+    const PAGE: SettingsPage = SettingsPage::Altitude;
+
+    fn serialize(&self) -> [u8; ALTITUDE_RECORD_LEN] {
+        let mut buf = [0u8; ALTITUDE_RECORD_LEN];
+        buf[0..4].copy_from_slice(&ALTITUDE_PAGE_TOKEN);
+        buf[4..6].copy_from_slice(&self.reference_altitude.to_le_bytes());
+        buf
+    }
+
+    /// None if the settings have never been written
+    fn deserialize(buf: &[u8; ALTITUDE_RECORD_LEN]) -> Option<Self> {
+        if buf[0..4] != ALTITUDE_PAGE_TOKEN {
+            return None;
+        }
+
+        Some(Self { reference_altitude: i16::from_le_bytes([buf[4], buf[5]]) })
+    }
+}
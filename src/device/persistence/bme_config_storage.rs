@@ -0,0 +1,34 @@
+use crate::common::device::bme280::{BME280_CONFIGURATION_LEN, Configuration};
+use crate::common::device::persistence::flash_manager::{SettingsPage, SettingsRecord};
+
+/// [token: 4][configuration: 5][padding: 3]
+pub(crate) const BME_CONFIG_RECORD_LEN: usize = 12;
+pub(crate) const BME_CONFIG_PAGE_TOKEN: [u8; 4] = [0xB3, 0xE2, 0x80, 0x01];
+
+const _: () = assert!(4 + BME280_CONFIGURATION_LEN <= BME_CONFIG_RECORD_LEN);
+
+#[derive(Clone, Copy)]
+pub(crate) struct BmeSettings {
+    pub(crate) configuration: Configuration,
+}
+
+impl SettingsRecord<BME_CONFIG_RECORD_LEN> for BmeSettings {
+    const PAGE: SettingsPage = SettingsPage::BmeConfig;
+
+    fn serialize(&self) -> [u8; BME_CONFIG_RECORD_LEN] {
+        let mut buf = [0u8; BME_CONFIG_RECORD_LEN];
+        buf[0..4].copy_from_slice(&BME_CONFIG_PAGE_TOKEN);
+        buf[4..4 + BME280_CONFIGURATION_LEN].copy_from_slice(&self.configuration.to_bits());
+        buf
+    }
+
+    /// None if the settings have never been written or do not form a valid configuration
+    fn deserialize(buf: &[u8; BME_CONFIG_RECORD_LEN]) -> Option<Self> {
+        if buf[0..4] != BME_CONFIG_PAGE_TOKEN {
+            return None;
+        }
+
+        let bits = buf[4..4 + BME280_CONFIGURATION_LEN].try_into().ok()?;
+        Some(Self { configuration: Configuration::from_bits(bits).ok()? })
+    }
+}
@@ -0,0 +1,163 @@
+use core::mem::size_of;
+
+use defmt::info;
+use embedded_storage_async::nor_flash::NorFlash;
+use futures::pin_mut;
+use heapless::Vec;
+use nrf_softdevice::ble::{EncryptionInfo, IdentityKey, MasterId};
+use nrf_softdevice::raw;
+
+use crate::common::ble::security::Bond;
+use crate::common::device::config::{BLE_MAX_BONDS, BLE_SYS_ATTRS_LEN, FLASH_PAGE_SIZE, FLASH_WRITE_ALIGNMENT};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::flash_manager::FlashManager;
+
+const MASTER_ID_LEN: usize = size_of::<raw::ble_gap_master_id_t>();
+const ENC_INFO_LEN: usize = size_of::<raw::ble_gap_enc_info_t>();
+const ID_KEY_LEN: usize = size_of::<raw::ble_gap_id_key_t>();
+
+/// [master_id][enc_info][id_key][sys_attrs_len: u16][sys_attrs..] padded to the flash word size
+pub(crate) const BOND_RECORD_LEN: usize =
+    align_up(MASTER_ID_LEN + ENC_INFO_LEN + ID_KEY_LEN + 2 + BLE_SYS_ATTRS_LEN);
+
+/// [token: 4][accept_list_only: 1][count: 1][coded_phy: 1][reserved: 1]
+pub(crate) const BOND_HEADER_LEN: usize = 8;
+/// The last byte changes with the record layout, pages of another layout read as empty
+pub(crate) const BOND_PAGE_TOKEN: [u8; 4] = [0xB0, 0x4D, 0x5E, 0xC2];
+
+const _: () = assert!(BOND_HEADER_LEN + BOND_RECORD_LEN * BLE_MAX_BONDS <= FLASH_PAGE_SIZE);
+
+const fn align_up(len: usize) -> usize {
+    (len + FLASH_WRITE_ALIGNMENT - 1) / FLASH_WRITE_ALIGNMENT * FLASH_WRITE_ALIGNMENT
+}
+
+#[derive(Default)]
+pub(crate) struct BondPage {
+    pub(crate) accept_list_only: bool,
+    /// Extended advertising on LE Coded PHY, pages written before it was introduced read as legacy
+    pub(crate) coded_phy: bool,
+    pub(crate) bonds: Vec<Bond, BLE_MAX_BONDS>,
+}
+
+impl BondPage {
+    pub(crate) fn header(&self) -> [u8; BOND_HEADER_LEN] {
+        let mut header = [0u8; BOND_HEADER_LEN];
+        header[0..4].copy_from_slice(&BOND_PAGE_TOKEN);
+        header[4] = self.accept_list_only as u8;
+        header[5] = self.bonds.len() as u8;
+        header[6] = self.coded_phy as u8;
+        header
+    }
+
+    /// Returns a page without bonds and the number of stored bonds if the header is valid
+    pub(crate) fn parse_header(header: &[u8; BOND_HEADER_LEN]) -> Option<(Self, usize)> {
+        if header[0..4] != BOND_PAGE_TOKEN {
+            return None;
+        }
+        let page = Self { accept_list_only: header[4] != 0, coded_phy: header[6] != 0, bonds: Vec::new() };
+        Some((page, (header[5] as usize).min(BLE_MAX_BONDS)))
+    }
+}
+
+impl Bond {
+    pub(crate) fn serialize(&self) -> [u8; BOND_RECORD_LEN] {
+        let mut buf = [0u8; BOND_RECORD_LEN];
+        let mut offset = 0;
+
+        offset += write_raw(&mut buf[offset..], &self.master_id.as_raw());
+        offset += write_raw(&mut buf[offset..], &self.key.as_raw());
+        offset += write_raw(&mut buf[offset..], &self.peer_id.as_raw());
+
+        buf[offset..offset + 2].copy_from_slice(&(self.sys_attrs.len() as u16).to_le_bytes());
+        offset += 2;
+        buf[offset..offset + self.sys_attrs.len()].copy_from_slice(&self.sys_attrs);
+
+        buf
+    }
+
+    pub(crate) fn deserialize(buf: &[u8; BOND_RECORD_LEN]) -> Option<Self> {
+        let mut offset = 0;
+
+        let master_id: raw::ble_gap_master_id_t = read_raw(&buf[offset..]);
+        offset += MASTER_ID_LEN;
+        let key: raw::ble_gap_enc_info_t = read_raw(&buf[offset..]);
+        offset += ENC_INFO_LEN;
+        let peer_id: raw::ble_gap_id_key_t = read_raw(&buf[offset..]);
+        offset += ID_KEY_LEN;
+
+        let sys_attrs_len = u16::from_le_bytes([buf[offset], buf[offset + 1]]) as usize;
+        offset += 2;
+        if sys_attrs_len > BLE_SYS_ATTRS_LEN {
+            return None;
+        }
+
+        Some(Self {
+            master_id: MasterId::from_raw(master_id),
+            key: EncryptionInfo::from_raw(key),
+            peer_id: IdentityKey::from_raw(peer_id),
+            sys_attrs: Vec::from_slice(&buf[offset..offset + sys_attrs_len]).ok()?,
+        })
+    }
+}
+
+/// Softdevice key structures are plain C structs, so they are stored byte by byte
+fn write_raw<T: Copy>(dst: &mut [u8], value: &T) -> usize {
+    let len = size_of::<T>();
+    let src = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, len) };
+    dst[..len].copy_from_slice(src);
+    len
+}
+
+fn read_raw<T: Copy>(src: &[u8]) -> T {
+    assert!(src.len() >= size_of::<T>());
+    unsafe { core::ptr::read_unaligned(src.as_ptr() as *const T) }
+}
+
+/// Bond records are built from softdevice types, so they stay out of the portable flash manager
+impl<F> FlashManager<F> where F: NorFlash, FlashManagerError: From<F::Error> {
+    pub(crate) async fn read_bond_page(&self) -> Result<BondPage, FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let mut header = [0u8; BOND_HEADER_LEN];
+        flash.read(self.bonds_offset, &mut header).await?;
+
+        let Some((mut page, count)) = BondPage::parse_header(&header) else {
+            info!("Bond page is not initialized");
+            return Ok(BondPage::default());
+        };
+
+        let mut record = [0u8; BOND_RECORD_LEN];
+        for index in 0..count {
+            let record_offset = self.bonds_offset + (BOND_HEADER_LEN + index * BOND_RECORD_LEN) as u32;
+            flash.read(record_offset, &mut record).await?;
+            match Bond::deserialize(&record) {
+                Some(bond) => {
+                    let _ = page.bonds.push(bond);
+                }
+                None => info!("Skipping corrupted bond record {}", index),
+            }
+        }
+
+        info!("Read {} bonds from flash", page.bonds.len());
+
+        Ok(page)
+    }
+
+    pub(crate) async fn write_bond_page(&self, page: &BondPage) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        flash.erase(self.bonds_offset, self.bonds_offset + FLASH_PAGE_SIZE as u32).await?;
+        flash.write(self.bonds_offset, &page.header()).await?;
+
+        for (index, bond) in page.bonds.iter().enumerate() {
+            let record_offset = self.bonds_offset + (BOND_HEADER_LEN + index * BOND_RECORD_LEN) as u32;
+            flash.write(record_offset, &bond.serialize()).await?;
+        }
+
+        info!("Wrote {} bonds to flash", page.bonds.len());
+
+        Ok(())
+    }
+}
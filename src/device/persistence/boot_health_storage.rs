@@ -0,0 +1,59 @@
+use crate::common::device::config::FLASH_WRITE_ALIGNMENT;
+
+/// [pending token: 4][rollback: 4]; written by the image that requests an update, the bootloader brings it back if
+/// the new image resets before marking itself booted, and the token left behind tells it about the rollback
+pub(crate) const BOOT_HEALTH_RECORD_LEN: usize = 2 * FLASH_WRITE_ALIGNMENT;
+pub(crate) const BOOT_PENDING_TOKEN: [u8; 4] = [0x8B, 0x00, 0x7E, 0x01];
+/// [token][reason][reserved: 2]
+pub(crate) const BOOT_ROLLBACK_TOKEN: u8 = 0xB0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, defmt::Format)]
+#[repr(u8)]
+pub(crate) enum RollbackReason {
+    /// The watchdog reset the new image
+    Watchdog = 1,
+    /// Any other reset before the new image was confirmed: a fault, a reboot command or the reset pin
+    Reset = 2,
+}
+
+impl RollbackReason {
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Watchdog),
+            2 => Some(Self::Reset),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, defmt::Format)]
+pub(crate) struct Rollback {
+    pub(crate) reason: RollbackReason,
+}
+
+impl Rollback {
+    pub(crate) fn serialize(&self) -> [u8; 4] {
+        [BOOT_ROLLBACK_TOKEN, self.reason as u8, 0, 0]
+    }
+}
+
+/// An erased page is a confirmed image without a rollback in its history
+#[derive(Default, Clone, Copy, defmt::Format)]
+pub(crate) struct BootHealthRecord {
+    pub(crate) pending: bool,
+    pub(crate) rollback: Option<Rollback>,
+}
+
+impl BootHealthRecord {
+    pub(crate) fn deserialize(buf: &[u8; BOOT_HEALTH_RECORD_LEN]) -> Self {
+        let pending = buf[0..4] == BOOT_PENDING_TOKEN;
+
+        let rollback = &buf[4..8];
+        let rollback = match RollbackReason::from_u8(rollback[1]) {
+            Some(reason) if rollback[0] == BOOT_ROLLBACK_TOKEN => Some(Rollback { reason }),
+            _ => None,
+        };
+
+        Self { pending, rollback }
+    }
+}
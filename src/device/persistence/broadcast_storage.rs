@@ -0,0 +1,42 @@
+use crate::common::device::config::BTHOME_KEY_LEN;
+use crate::common::device::persistence::flash_manager::{SettingsPage, SettingsRecord};
+
+/// [token: 4][mode: 1][reserved: 1][counter_base: u16][key: 16]
+pub(crate) const BROADCAST_RECORD_LEN: usize = 8 + BTHOME_KEY_LEN;
+pub(crate) const BROADCAST_PAGE_TOKEN: [u8; 4] = [0xB7, 0x40, 0x3E, 0x02];
+
+#[derive(Default, Clone)]
+pub(crate) struct BroadcastSettings {
+    pub(crate) mode: u8,
+    /// Upper half of the next unused block of BTHome encryption counters, advanced before a block is used
+    pub(crate) counter_base: u16,
+    pub(crate) key: [u8; BTHOME_KEY_LEN],
+}
+
+impl SettingsRecord<BROADCAST_RECORD_LEN> for BroadcastSettings {
+    const PAGE: SettingsPage = SettingsPage::Broadcast;
+
+    fn serialize(&self) -> [u8; BROADCAST_RECORD_LEN] {
+        let mut buf = [0u8; BROADCAST_RECORD_LEN];
+        buf[0..4].copy_from_slice(&BROADCAST_PAGE_TOKEN);
+        buf[4] = self.mode;
+        buf[6..8].copy_from_slice(&self.counter_base.to_le_bytes());
+        buf[8..].copy_from_slice(&self.key);
+        buf
+    }
+
+    fn deserialize(buf: &[u8; BROADCAST_RECORD_LEN]) -> Option<Self> {
+        if buf[0..4] != BROADCAST_PAGE_TOKEN {
+            return None;
+        }
+
+        let mut key = [0u8; BTHOME_KEY_LEN];
+        key.copy_from_slice(&buf[8..]);
+
+        Some(Self {
+            mode: buf[4],
+            counter_base: u16::from_le_bytes([buf[6], buf[7]]),
+            key,
+        })
+    }
+}
@@ -0,0 +1,49 @@
+use crate::common::device::config::{ACTIVE_SIZE, FLASH_PAGE_SIZE, FLASH_WRITE_ALIGNMENT};
+use crate::common::device::dfu::updater::DfuImage;
+use crate::common::device::persistence::flash_manager::{SettingsPage, SettingsRecord};
+
+/// Pages of the largest image, the application partition
+pub(crate) const DFU_IMAGE_PAGES: usize = ACTIVE_SIZE as usize / FLASH_PAGE_SIZE;
+/// [token: 4][image: DfuImage::LEN][page marker: 4 x DFU_IMAGE_PAGES]; a marker is programmed once its page of the
+/// image has been written completely, so the progress costs a word write instead of a page erase
+pub(crate) const DFU_TRANSFER_RECORD_LEN: usize = 4 + DfuImage::LEN + DFU_IMAGE_PAGES * FLASH_WRITE_ALIGNMENT;
+pub(crate) const DFU_TRANSFER_TOKEN: [u8; 4] = [0xDF, 0x07, 0x2A, 0x01];
+pub(crate) const DFU_PAGE_MARKER: [u8; 4] = [0; 4];
+
+pub(crate) const fn progress_offset(page: usize) -> usize {
+    4 + DfuImage::LEN + page * FLASH_WRITE_ALIGNMENT
+}
+
+/// An interrupted transfer, resumed after a reboot from the last completely written page
+pub(crate) struct DfuTransferRecord {
+    pub(crate) image: DfuImage,
+    pub(crate) pages: u32,
+}
+
+impl SettingsRecord<DFU_TRANSFER_RECORD_LEN> for DfuTransferRecord {
+    const PAGE: SettingsPage = SettingsPage::DfuTransfer;
+
+    fn serialize(&self) -> [u8; DFU_TRANSFER_RECORD_LEN] {
+        let mut buf = [0xFF; DFU_TRANSFER_RECORD_LEN];
+        buf[0..4].copy_from_slice(&DFU_TRANSFER_TOKEN);
+        buf[4..4 + DfuImage::LEN].copy_from_slice(&self.image.serialize());
+        for page in 0..(self.pages as usize).min(DFU_IMAGE_PAGES) {
+            buf[progress_offset(page)..progress_offset(page) + 4].copy_from_slice(&DFU_PAGE_MARKER);
+        }
+        buf
+    }
+
+    /// None if no transfer has been started since the last apply or abort
+    fn deserialize(buf: &[u8; DFU_TRANSFER_RECORD_LEN]) -> Option<Self> {
+        if buf[0..4] != DFU_TRANSFER_TOKEN {
+            return None;
+        }
+
+        let image = DfuImage::deserialize(&buf[4..4 + DfuImage::LEN])?;
+        let pages = (0..DFU_IMAGE_PAGES)
+            .take_while(|&page| buf[progress_offset(page)..progress_offset(page) + 4] == DFU_PAGE_MARKER)
+            .count() as u32;
+
+        Some(Self { image, pages })
+    }
+}
@@ -1,21 +1,78 @@
 use defmt::info;
+use embassy_boot::{AlignedBuffer, FirmwareUpdater, FirmwareUpdaterConfig, State};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::mutex::Mutex;
-use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
 use futures::pin_mut;
-use nrf_softdevice::Flash;
 
-use crate::common::ble::{FLASH_MANAGER, SERVER};
-use crate::common::device::config::{CONFIG_FLASH_SIZE, FLASH_PAGE_SIZE, INIT_TOKEN};
-use crate::common::device::error::{DeviceError, FlashManagerError};
-
-pub(crate) struct FlashManager {
-    flash: Mutex<ThreadModeRawMutex, Flash>,
+use crate::common::device::config::{
+    ACTIVE_SIZE, BOOTLOADER_STATE_OFFSET, BOOTLOADER_STATE_SIZE, CONFIG_FLASH_SIZE, DFU_OFFSET, DFU_SIZE,
+    FLASH_PAGE_SIZE, FLASH_WRITE_ALIGNMENT, HISTORY_FLASH_OFFSET, HISTORY_FLASH_PAGES, INIT_TOKEN,
+    SETTINGS_FLASH_OFFSET,
+};
+use crate::common::device::persistence::boot_health_storage::{
+    BOOT_HEALTH_RECORD_LEN, BOOT_PENDING_TOKEN, BootHealthRecord, Rollback,
+};
+use crate::common::device::persistence::dfu_transfer_storage::{DFU_IMAGE_PAGES, DFU_PAGE_MARKER, progress_offset};
+use crate::common::device::persistence::history_storage::{
+    HISTORY_DELETE_WORD_OFFSET, HISTORY_RECORD_LEN, HISTORY_RECORDS_PER_PAGE, HistoryRecord,
+};
+use crate::common::device::persistence::log_storage::{LOG_RECORD_LEN, LOG_RECORDS_PER_PAGE};
+use crate::common::util::log_buffer::LogEntry;
+use crate::common::device::error::FlashManagerError;
+
+/// Generic over the flash, so the same page layout runs against `RamFlash` in the simulator
+pub(crate) struct FlashManager<F> {
+    pub(super) flash: Mutex<ThreadModeRawMutex, F>,
     offset: u32,
     token_offset: u32,
+    pub(super) bonds_offset: u32,
+    log_offset: u32,
+    boot_health_offset: u32,
     last_data: Mutex<ThreadModeRawMutex, CalibrationData>,
 }
 
+/// Pages from `SETTINGS_FLASH_OFFSET` on, each erased on its own
+#[derive(Copy, Clone, Debug, defmt::Format)]
+pub(crate) enum SettingsPage {
+    /// Calibration data and the init token
+    Calibration = 0,
+    Bonds = 1,
+    /// BTHome broadcast settings
+    Broadcast = 2,
+    /// The device name with the location label
+    Identity = 3,
+    /// Severe log entries
+    Log = 4,
+    /// Pending / confirmed state of the running image
+    BootHealth = 5,
+    /// User descriptions of the ADC channels
+    AdcLabels = 6,
+    /// Interval of the offline history, the records have a region of their own
+    HistorySettings = 7,
+    /// BME280 oversampling, IIR filter and standby
+    BmeConfig = 8,
+    /// Reference altitude for the sea-level pressure
+    Altitude = 9,
+    /// The firmware image being received and its completely written pages
+    DfuTransfer = 10,
+}
+
+impl SettingsPage {
+    fn offset(self) -> u32 {
+        SETTINGS_FLASH_OFFSET + (self as usize * FLASH_PAGE_SIZE) as u32
+    }
+}
+
+/// A single record that has a settings page of its own and starts with a page token
+pub(crate) trait SettingsRecord<const LEN: usize>: Sized {
+    const PAGE: SettingsPage;
+
+    fn serialize(&self) -> [u8; LEN];
+
+    /// None if the record has never been written
+    fn deserialize(buf: &[u8; LEN]) -> Option<Self>;
+}
 
 #[derive(Default, defmt::Format, Clone, Copy)]
 pub(crate) struct CalibrationData {
@@ -38,7 +95,7 @@ trait FlashExt {
     async fn read_calibration_data(&mut self, offset: u32) -> Result<CalibrationData, FlashManagerError>;
 }
 
-impl FlashExt for Flash {
+impl<F> FlashExt for F where F: NorFlash, FlashManagerError: From<F::Error> {
     async fn write_calibration_data(&mut self, offset: u32, data: &CalibrationData) -> Result<(), FlashManagerError> {
         let mut buf = [0u8; 16];
         buf[0..4].copy_from_slice(&data.version.to_le_bytes());
@@ -69,13 +126,16 @@ impl FlashExt for Flash {
     }
 }
 
-impl FlashManager {
-    pub fn new(flash: Flash) -> Self {
-        let offset = 100 * FLASH_PAGE_SIZE as u32;
+impl<F> FlashManager<F> where F: NorFlash, FlashManagerError: From<F::Error> {
+    pub fn new(flash: F) -> Self {
+        let offset = SettingsPage::Calibration.offset();
         Self {
             flash: Mutex::new(flash),
             offset,
             token_offset: offset + CONFIG_FLASH_SIZE as u32,
+            bonds_offset: SettingsPage::Bonds.offset(),
+            log_offset: SettingsPage::Log.offset(),
+            boot_health_offset: SettingsPage::BootHealth.offset(),
             last_data: Mutex::new(CalibrationData::default()),
         }
     }
@@ -157,6 +217,278 @@ impl FlashManager {
     pub(crate) async fn get_last_calibration_data(&self) -> CalibrationData {
         *self.last_data.lock().await
     }
+
+    /// Returns None if the record has never been written
+    pub(crate) async fn read_setting<T: SettingsRecord<LEN>, const LEN: usize>(
+        &self,
+    ) -> Result<Option<T>, FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let mut buf = [0u8; LEN];
+        flash.read(T::PAGE.offset(), &mut buf).await?;
+
+        Ok(T::deserialize(&buf))
+    }
+
+    /// Erases the page of the record before writing it
+    pub(crate) async fn write_setting<T: SettingsRecord<LEN>, const LEN: usize>(
+        &self,
+        record: &T,
+    ) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let offset = T::PAGE.offset();
+        flash.erase(offset, offset + FLASH_PAGE_SIZE as u32).await?;
+        flash.write(offset, &record.serialize()).await?;
+
+        info!("Wrote {} settings page", T::PAGE);
+
+        Ok(())
+    }
+
+    /// Returns None for an erased record
+    pub(crate) async fn read_log_record(&self, index: usize) -> Result<Option<LogEntry>, FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let mut buf = [0u8; LOG_RECORD_LEN];
+        flash.read(self.log_offset + (index * LOG_RECORD_LEN) as u32, &mut buf).await?;
+
+        Ok(LogEntry::deserialize(&buf))
+    }
+
+    /// Records are appended to the erased page; the caller wraps around with `erase_log_page`
+    pub(crate) async fn write_log_record(&self, index: usize, entry: &LogEntry) -> Result<(), FlashManagerError> {
+        assert!(index < LOG_RECORDS_PER_PAGE);
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        flash.write(self.log_offset + (index * LOG_RECORD_LEN) as u32, &entry.serialize()).await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn erase_log_page(&self) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        flash.erase(self.log_offset, self.log_offset + FLASH_PAGE_SIZE as u32).await?;
+        info!("Erased log page");
+
+        Ok(())
+    }
+
+    fn history_record_offset(page: usize, index: usize) -> u32 {
+        assert!(page < HISTORY_FLASH_PAGES && index < HISTORY_RECORDS_PER_PAGE);
+        HISTORY_FLASH_OFFSET + (page * FLASH_PAGE_SIZE + index * HISTORY_RECORD_LEN) as u32
+    }
+
+    /// Returns None for an erased record
+    pub(crate) async fn read_history_record(
+        &self,
+        page: usize,
+        index: usize,
+    ) -> Result<Option<HistoryRecord>, FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let mut buf = [0u8; HISTORY_RECORD_LEN];
+        flash.read(Self::history_record_offset(page, index), &mut buf).await?;
+
+        Ok(HistoryRecord::deserialize(&buf))
+    }
+
+    /// Records are appended to the erased page; the caller wraps around with `erase_history_page`
+    pub(crate) async fn write_history_record(
+        &self,
+        page: usize,
+        index: usize,
+        record: &HistoryRecord,
+    ) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        flash.write(Self::history_record_offset(page, index), &record.serialize()).await?;
+
+        Ok(())
+    }
+
+    /// Programs the presence word once more with the live bit cleared, the rest of the record stays readable
+    pub(crate) async fn delete_history_record(
+        &self,
+        page: usize,
+        index: usize,
+        record: &HistoryRecord,
+    ) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let offset = Self::history_record_offset(page, index) + HISTORY_DELETE_WORD_OFFSET as u32;
+        flash.write(offset, &record.delete_word()).await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn erase_history_page(&self, page: usize) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let offset = Self::history_record_offset(page, 0);
+        flash.erase(offset, offset + FLASH_PAGE_SIZE as u32).await?;
+        info!("Erased history page {}", page);
+
+        Ok(())
+    }
+
+    /// The slot firmware images are received into, addressed from 0: the start of the DFU partition, an image has to
+    /// fit into the application partition
+    pub(crate) fn dfu_partition(&'static self) -> FlashPartition<F> {
+        FlashPartition { manager: self, offset: DFU_OFFSET, size: ACTIVE_SIZE }
+    }
+
+    /// The whole DFU partition including the swap page and the bootloader state page, see bootloader/memory.x
+    fn firmware_updater<'a>(
+        &'static self,
+        aligned: &'a mut [u8],
+    ) -> FirmwareUpdater<'a, FlashPartition<F>, FlashPartition<F>> {
+        let config = FirmwareUpdaterConfig {
+            dfu: FlashPartition { manager: self, offset: DFU_OFFSET, size: DFU_SIZE },
+            state: FlashPartition { manager: self, offset: BOOTLOADER_STATE_OFFSET, size: BOOTLOADER_STATE_SIZE },
+        };
+        FirmwareUpdater::new(config, aligned)
+    }
+
+    /// The bootloader swaps in the image from the DFU partition on the next reset
+    pub(crate) async fn mark_dfu_updated(&'static self) -> Result<(), FlashManagerError> {
+        let mut aligned = AlignedBuffer([0; FLASH_WRITE_ALIGNMENT]);
+        self.firmware_updater(&mut aligned.0).mark_updated().await?;
+        info!("Marked the DFU partition for the swap");
+
+        Ok(())
+    }
+
+    /// `State::Swap` while the image swapped in has not marked itself booted, the bootloader reverts it on the next
+    /// reset
+    pub(crate) async fn dfu_boot_state(&'static self) -> Result<State, FlashManagerError> {
+        let mut aligned = AlignedBuffer([0; FLASH_WRITE_ALIGNMENT]);
+        Ok(self.firmware_updater(&mut aligned.0).get_state().await?)
+    }
+
+    pub(crate) async fn mark_dfu_booted(&'static self) -> Result<(), FlashManagerError> {
+        let mut aligned = AlignedBuffer([0; FLASH_WRITE_ALIGNMENT]);
+        self.firmware_updater(&mut aligned.0).mark_booted().await?;
+        info!("Marked the running image booted");
+
+        Ok(())
+    }
+
+    /// Programs the marker of a completely received page of the image, see `DfuTransferRecord`
+    pub(crate) async fn record_dfu_progress(&self, page: usize) -> Result<(), FlashManagerError> {
+        assert!(page < DFU_IMAGE_PAGES);
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        flash.write(SettingsPage::DfuTransfer.offset() + progress_offset(page) as u32, &DFU_PAGE_MARKER).await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn clear_dfu_transfer(&self) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let offset = SettingsPage::DfuTransfer.offset();
+        flash.erase(offset, offset + FLASH_PAGE_SIZE as u32).await?;
+        info!("Cleared the DFU transfer");
+
+        Ok(())
+    }
+
+    pub(crate) async fn read_boot_health(&self) -> Result<BootHealthRecord, FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        let mut buf = [0u8; BOOT_HEALTH_RECORD_LEN];
+        flash.read(self.boot_health_offset, &mut buf).await?;
+
+        Ok(BootHealthRecord::deserialize(&buf))
+    }
+
+    /// Tells the running image whether it requested an update before the reset, the previous rollback is forgotten
+    pub(crate) async fn mark_boot_pending(&self) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        flash.erase(self.boot_health_offset, self.boot_health_offset + FLASH_PAGE_SIZE as u32).await?;
+        flash.write(self.boot_health_offset, &BOOT_PENDING_TOKEN).await?;
+
+        Ok(())
+    }
+
+    /// Clears the pending state, the last rollback is kept for the diagnostics service
+    pub(crate) async fn confirm_boot(&self, rollback: Option<Rollback>) -> Result<(), FlashManagerError> {
+        let flash = self.flash.lock().await;
+        pin_mut!(flash);
+
+        flash.erase(self.boot_health_offset, self.boot_health_offset + FLASH_PAGE_SIZE as u32).await?;
+        if let Some(rollback) = rollback {
+            flash.write(self.boot_health_offset + BOOT_PENDING_TOKEN.len() as u32, &rollback.serialize()).await?;
+        }
+        info!("Confirmed the running image");
+
+        Ok(())
+    }
+}
+
+/// A region of the softdevice flash addressed from 0, shared with the settings pages: every operation takes the lock
+/// on its own
+pub(crate) struct FlashPartition<F: 'static> {
+    manager: &'static FlashManager<F>,
+    offset: u32,
+    size: u32,
+}
+
+impl<F> FlashPartition<F> {
+    fn check(&self, offset: u32, len: usize) -> Result<u32, NorFlashErrorKind> {
+        match offset.checked_add(len as u32) {
+            Some(end) if end <= self.size => Ok(self.offset + offset),
+            _ => Err(NorFlashErrorKind::OutOfBounds),
+        }
+    }
+}
+
+impl<F: NorFlash> ErrorType for FlashPartition<F> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<F: NorFlash> ReadNorFlash for FlashPartition<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let address = self.check(offset, bytes.len())?;
+        self.manager.flash.lock().await.read(address, bytes).await.map_err(|err| err.kind())
+    }
+
+    fn capacity(&self) -> usize {
+        self.size as usize
+    }
+}
+
+impl<F: NorFlash> NorFlash for FlashPartition<F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let address = self.check(from, to.saturating_sub(from) as usize)?;
+        self.manager.flash.lock().await.erase(address, self.offset + to).await.map_err(|err| err.kind())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let address = self.check(offset, bytes.len())?;
+        self.manager.flash.lock().await.write(address, bytes).await.map_err(|err| err.kind())
+    }
 }
 
 trait ClonedSlice<T> {
@@ -170,16 +502,3 @@ impl<T> ClonedSlice<T> for [T] where T: Copy + Default {
         arr
     }
 }
-
-
-pub(crate) async fn copy_calibration_data_from_flash() -> Result<(), DeviceError> {
-    let server = SERVER.get();
-    let calibration_data = FLASH_MANAGER.get().get_last_calibration_data().await;
-    server.bme280.humidity_offset_set(&calibration_data.bme_humidity.to_le_bytes())?;
-    server.bme280.pressure_offset_set(&calibration_data.bme_pressure.to_le_bytes())?;
-    server.bme280.temperature_offset_set(&calibration_data.bme_temperature.to_le_bytes())?;
-
-    info!("Calibration data copied from flash: {:?}", calibration_data);
-
-    Ok(())
-}
\ No newline at end of file
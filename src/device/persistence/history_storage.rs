@@ -0,0 +1,124 @@
+use crate::common::device::config::FLASH_PAGE_SIZE;
+use crate::common::device::persistence::flash_manager::{SettingsPage, SettingsRecord};
+
+/// [sequence: u32][time: u32][presence: u8][battery level: u8][temperature: i16][humidity: u16]
+/// [battery voltage: u16][pressure: u32][luminous flux: u16][nRF temperature: i16]
+pub(crate) const HISTORY_RECORD_LEN: usize = 24;
+/// Records do not cross pages, so a page can be erased on its own
+pub(crate) const HISTORY_RECORDS_PER_PAGE: usize = FLASH_PAGE_SIZE / HISTORY_RECORD_LEN;
+
+/// [token: 4][interval: u32]
+pub(crate) const HISTORY_SETTINGS_RECORD_LEN: usize = 8;
+pub(crate) const HISTORY_SETTINGS_PAGE_TOKEN: [u8; 4] = [0x41, 0x57, 0x0C, 0x01];
+
+/// The word with the presence byte, it is written a second time to delete the record
+pub(crate) const HISTORY_DELETE_WORD_OFFSET: usize = 8;
+
+const _: () = assert!(HISTORY_RECORD_LEN % 4 == 0);
+
+/// Presence bits, absent readings are zero
+const PRESENCE_BME: u8 = 1 << 0;
+const PRESENCE_BATTERY: u8 = 1 << 1;
+const PRESENCE_LUMINOUS_FLUX: u8 = 1 << 2;
+const PRESENCE_NRF_TEMPERATURE: u8 = 1 << 3;
+/// Cleared in place on deletion, flash bits can go from 1 to 0 without an erase
+const PRESENCE_LIVE: u8 = 1 << 7;
+
+/// A single sample of the offline history; the values use the encodings of the dedicated characteristics
+#[derive(Clone, Copy, Default)]
+pub(crate) struct HistoryRecord {
+    pub(crate) sequence: u32,
+    /// Wall clock seconds, see `wall_clock`; 0 if the clock had not been set yet
+    pub(crate) time: u32,
+    /// Temperature, humidity, pressure
+    pub(crate) bme: Option<(i16, u16, u32)>,
+    /// Voltage, level
+    pub(crate) battery: Option<(u16, u8)>,
+    pub(crate) luminous_flux: Option<u16>,
+    pub(crate) nrf_temperature: Option<i16>,
+    /// Deleted records keep their slot until the ring erases the page
+    pub(crate) deleted: bool,
+}
+
+impl HistoryRecord {
+    pub(crate) fn serialize(&self) -> [u8; HISTORY_RECORD_LEN] {
+        let mut buf = [0u8; HISTORY_RECORD_LEN];
+        buf[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.time.to_le_bytes());
+
+        let mut presence = if self.deleted { 0 } else { PRESENCE_LIVE };
+        if let Some((temperature, humidity, pressure)) = self.bme {
+            presence |= PRESENCE_BME;
+            buf[10..12].copy_from_slice(&temperature.to_le_bytes());
+            buf[12..14].copy_from_slice(&humidity.to_le_bytes());
+            buf[16..20].copy_from_slice(&pressure.to_le_bytes());
+        }
+        if let Some((voltage, level)) = self.battery {
+            presence |= PRESENCE_BATTERY;
+            buf[9] = level;
+            buf[14..16].copy_from_slice(&voltage.to_le_bytes());
+        }
+        if let Some(luminous_flux) = self.luminous_flux {
+            presence |= PRESENCE_LUMINOUS_FLUX;
+            buf[20..22].copy_from_slice(&luminous_flux.to_le_bytes());
+        }
+        if let Some(temperature) = self.nrf_temperature {
+            presence |= PRESENCE_NRF_TEMPERATURE;
+            buf[22..24].copy_from_slice(&temperature.to_le_bytes());
+        }
+        buf[8] = presence;
+        buf
+    }
+
+    /// An erased record reads as all 0xFF and marks the end of the written ones
+    pub(crate) fn deserialize(buf: &[u8; HISTORY_RECORD_LEN]) -> Option<Self> {
+        if buf.iter().all(|&byte| byte == 0xFF) {
+            return None;
+        }
+
+        let presence = buf[8];
+        let u16_at = |offset: usize| u16::from_le_bytes([buf[offset], buf[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]]);
+
+        Some(Self {
+            sequence: u32_at(0),
+            time: u32_at(4),
+            bme: (presence & PRESENCE_BME != 0).then(|| (u16_at(10) as i16, u16_at(12), u32_at(16))),
+            battery: (presence & PRESENCE_BATTERY != 0).then(|| (u16_at(14), buf[9])),
+            luminous_flux: (presence & PRESENCE_LUMINOUS_FLUX != 0).then(|| u16_at(20)),
+            nrf_temperature: (presence & PRESENCE_NRF_TEMPERATURE != 0).then(|| u16_at(22) as i16),
+            deleted: presence & PRESENCE_LIVE == 0,
+        })
+    }
+
+    /// The word at `HISTORY_DELETE_WORD_OFFSET` of the deleted record
+    pub(crate) fn delete_word(&self) -> [u8; 4] {
+        let buf = Self { deleted: true, ..*self }.serialize();
+        [buf[8], buf[9], buf[10], buf[11]]
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct HistorySettings {
+    /// Seconds between the samples, 0 - off
+    pub(crate) interval: u32,
+}
+
+impl SettingsRecord<HISTORY_SETTINGS_RECORD_LEN> for HistorySettings {
+    const PAGE: SettingsPage = SettingsPage::HistorySettings;
+
+    fn serialize(&self) -> [u8; HISTORY_SETTINGS_RECORD_LEN] {
+        let mut buf = [0u8; HISTORY_SETTINGS_RECORD_LEN];
+        buf[0..4].copy_from_slice(&HISTORY_SETTINGS_PAGE_TOKEN);
+        buf[4..8].copy_from_slice(&self.interval.to_le_bytes());
+        buf
+    }
+
+    fn deserialize(buf: &[u8; HISTORY_SETTINGS_RECORD_LEN]) -> Option<Self> {
+        if buf[0..4] != HISTORY_SETTINGS_PAGE_TOKEN {
+            return None;
+        }
+
+        Some(Self { interval: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) })
+    }
+}
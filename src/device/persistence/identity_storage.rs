@@ -0,0 +1,55 @@
+use heapless::Vec;
+
+use crate::common::device::config::{BLE_DEVICE_NAME_LEN, BLE_LOCATION_LEN};
+use crate::common::device::persistence::flash_manager::{SettingsPage, SettingsRecord};
+
+/// [token: 4][name_len: 1][location_len: 1][reserved: 2][name][location]
+pub(crate) const IDENTITY_RECORD_LEN: usize = 8 + BLE_DEVICE_NAME_LEN + BLE_LOCATION_LEN;
+pub(crate) const IDENTITY_PAGE_TOKEN: [u8; 4] = [0x1D, 0xE7, 0x17, 0x01];
+
+const NAME_OFFSET: usize = 8;
+const LOCATION_OFFSET: usize = NAME_OFFSET + BLE_DEVICE_NAME_LEN;
+
+/// User assigned GAP device name and a free-form location label, both UTF-8
+#[derive(Default, Clone, PartialEq, Eq)]
+pub(crate) struct DeviceIdentity {
+    pub(crate) name: Vec<u8, BLE_DEVICE_NAME_LEN>,
+    pub(crate) location: Vec<u8, BLE_LOCATION_LEN>,
+}
+
+impl DeviceIdentity {
+    pub(crate) const fn new() -> Self {
+        Self { name: Vec::new(), location: Vec::new() }
+    }
+}
+
+impl SettingsRecord<IDENTITY_RECORD_LEN> for DeviceIdentity {
+    const PAGE: SettingsPage = SettingsPage::Identity;
+
+    fn serialize(&self) -> [u8; IDENTITY_RECORD_LEN] {
+        let mut buf = [0u8; IDENTITY_RECORD_LEN];
+        buf[0..4].copy_from_slice(&IDENTITY_PAGE_TOKEN);
+        buf[4] = self.name.len() as u8;
+        buf[5] = self.location.len() as u8;
+        buf[NAME_OFFSET..NAME_OFFSET + self.name.len()].copy_from_slice(&self.name);
+        buf[LOCATION_OFFSET..LOCATION_OFFSET + self.location.len()].copy_from_slice(&self.location);
+        buf
+    }
+
+    fn deserialize(buf: &[u8; IDENTITY_RECORD_LEN]) -> Option<Self> {
+        if buf[0..4] != IDENTITY_PAGE_TOKEN {
+            return None;
+        }
+
+        let name_len = buf[4] as usize;
+        let location_len = buf[5] as usize;
+        if name_len > BLE_DEVICE_NAME_LEN || location_len > BLE_LOCATION_LEN {
+            return None;
+        }
+
+        Some(Self {
+            name: Vec::from_slice(&buf[NAME_OFFSET..NAME_OFFSET + name_len]).ok()?,
+            location: Vec::from_slice(&buf[LOCATION_OFFSET..LOCATION_OFFSET + location_len]).ok()?,
+        })
+    }
+}
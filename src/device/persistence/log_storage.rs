@@ -0,0 +1,44 @@
+use heapless::Vec;
+
+use crate::common::device::config::{BLE_DEBUG_ARRAY_LEN, FLASH_PAGE_SIZE};
+use crate::common::util::log_buffer::LogEntry;
+
+/// [sequence: u32][timestamp: u32][level: u8][len: u8][reserved: 2][time: u32][message]
+pub(crate) const LOG_RECORD_LEN: usize = 16 + BLE_DEBUG_ARRAY_LEN;
+/// The page is erased once it is full, so only the latest severe entries survive
+pub(crate) const LOG_RECORDS_PER_PAGE: usize = FLASH_PAGE_SIZE / LOG_RECORD_LEN;
+
+const _: () = assert!(LOG_RECORD_LEN % 4 == 0);
+
+impl LogEntry {
+    pub(crate) fn serialize(&self) -> [u8; LOG_RECORD_LEN] {
+        let mut buf = [0u8; LOG_RECORD_LEN];
+        buf[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        buf[8] = self.level;
+        buf[9] = self.message.len() as u8;
+        buf[12..16].copy_from_slice(&self.time.to_le_bytes());
+        buf[16..16 + self.message.len()].copy_from_slice(&self.message);
+        buf
+    }
+
+    /// An erased record reads as all 0xFF and marks the end of the written ones
+    pub(crate) fn deserialize(buf: &[u8; LOG_RECORD_LEN]) -> Option<Self> {
+        if buf.iter().all(|&byte| byte == 0xFF) {
+            return None;
+        }
+
+        let len = buf[9] as usize;
+        if len > BLE_DEBUG_ARRAY_LEN {
+            return None;
+        }
+
+        Some(Self {
+            sequence: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            timestamp: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            level: buf[8],
+            time: u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]),
+            message: Vec::from_slice(&buf[16..16 + len]).ok()?,
+        })
+    }
+}
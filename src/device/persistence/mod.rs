@@ -1 +1,11 @@
-pub(crate) mod flash_manager;
\ No newline at end of file
+pub(crate) mod adc_label_storage;
+pub(crate) mod altitude_storage;
+pub(crate) mod bme_config_storage;
+pub(crate) mod bond_storage;
+pub(crate) mod boot_health_storage;
+pub(crate) mod broadcast_storage;
+pub(crate) mod dfu_transfer_storage;
+pub(crate) mod flash_manager;
+pub(crate) mod history_storage;
+pub(crate) mod identity_storage;
+pub(crate) mod log_storage;
@@ -8,10 +8,15 @@ use embassy_nrf::timer::Frequency;
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::mutex::Mutex;
 use embassy_time::{Duration, Timer};
+use futures::{FutureExt, select_biased};
+use futures::future::Either;
 use rclite::Arc;
 
-use crate::common::ble::{ADC_EVENT_PROCESSOR, DEVICE_EVENT_PROCESSOR, SERVER};
+use crate::common::ble::{ADC_EVENT_PROCESSOR, BATTERY_EVENT_PROCESSOR, DEVICE_EVENT_PROCESSOR, SERVER};
 use crate::common::ble::conv::ConvExt;
+use crate::common::ble::snapshot::SNAPSHOT;
+use crate::common::device::battery::{BatteryGauge, BatteryState};
+use crate::common::device::config::BATTERY_LEVEL_INTERVAL;
 use crate::common::device::peripherals_manager::{Irqs, SaadcPins};
 use crate::common::device::ui::UI_STORE;
 use crate::notify_all;
@@ -21,9 +26,14 @@ pub(crate) async fn read_saadc_battery_voltage_task(
     saadc_pins: Arc<Mutex<ThreadModeRawMutex, SaadcPins<8>>>,
 ) {
     let server = SERVER.get();
+    let mut gauge = BatteryGauge::default();
+    let mut last_state: Option<BatteryState> = None;
 
     loop {
-        let _token = DEVICE_EVENT_PROCESSOR.wait_for_condition().await;
+        let _token = select_biased! {
+            token = DEVICE_EVENT_PROCESSOR.wait_for_condition().fuse() => Either::Left(token),
+            token = BATTERY_EVENT_PROCESSOR.wait_for_condition().fuse() => Either::Right(token),
+        };
 
         // taking just battery pin does not work; on the second time initialization SAADC
         // ignores sample_counter from the current run
@@ -48,22 +58,53 @@ pub(crate) async fn read_saadc_battery_voltage_task(
         let mut voltages = compute_voltages(&measurements, 3.6);
         voltages[7] = calculate_voltage_divider_in(voltages[7]);
         let serialized_voltages = serialize_voltages(voltages);
+        let state = gauge.update(voltages[7]);
 
-        UI_STORE.lock().await.bat_voltage = voltages[7];
+        {
+            let mut ui_store = UI_STORE.lock().await;
+            ui_store.bat_voltage = voltages[7];
+            ui_store.bat_level = state.level;
+            ui_store.bat_charging_estimate = state.charging_estimate;
+        }
 
         // info!("Battery: {}; other: {:?}", voltages[7], voltages);
 
+        SNAPSHOT.update(|snapshot| snapshot.battery = Some((serialized_voltages[7], state.level)));
+
         notify_all!(
                 DEVICE_EVENT_PROCESSOR,
-                server.dis,
+                server.diagnostics,
                 battery_voltage = &serialized_voltages[7]
             );
 
-        Timer::after(DEVICE_EVENT_PROCESSOR.get_timeout_duration()).await;
+        // Battery Service notifies only when the level or the status flags change
+        let level_status = state.level_status();
+        if last_state != Some(state) {
+            notify_all!(
+                BATTERY_EVENT_PROCESSOR,
+                server.bas,
+                battery_level = &state.level,
+                battery_level_status = &level_status
+            );
+            last_state = Some(state);
+        }
+        let _ = server.bas.battery_level_set(&state.level);
+        let _ = server.bas.battery_level_status_set(&level_status);
+
+        Timer::after(battery_sampling_interval()).await;
     }
 
 }
 
+fn battery_sampling_interval() -> Duration {
+    let device_timeout = DEVICE_EVENT_PROCESSOR.get_timeout_duration();
+    match (DEVICE_EVENT_PROCESSOR.is_enabled(), BATTERY_EVENT_PROCESSOR.is_enabled()) {
+        (true, true) => device_timeout.min(BATTERY_LEVEL_INTERVAL),
+        (false, true) => BATTERY_LEVEL_INTERVAL,
+        _ => device_timeout,
+    }
+}
+
 #[embassy_executor::task]
 pub(crate) async fn read_saadc_task(saadc_pins: Arc<Mutex<ThreadModeRawMutex, SaadcPins<8>>>) {
     let server = SERVER.get();
@@ -129,6 +170,11 @@ pub(crate) async fn read_saadc_task(saadc_pins: Arc<Mutex<ThreadModeRawMutex, Sa
         }
 
         let serialized_voltages = serialize_voltages(voltages);
+        SNAPSHOT.update(|snapshot| {
+            let mut adc_voltages = [0u16; 7];
+            adc_voltages.copy_from_slice(&serialized_voltages[..7]);
+            snapshot.adc_voltages = Some(adc_voltages);
+        });
 
         // info!("SAADC: {:?}\n{:?}", serialized_voltages, voltages);
 
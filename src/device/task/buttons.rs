@@ -4,6 +4,7 @@ use embassy_nrf::gpio::{AnyPin, Input, Pull};
 use embassy_time::Timer;
 use futures::{FutureExt, select_biased};
 
+use crate::common::ble::advertising::open_pairing_window;
 use crate::common::device::config::DEBOUNCE_INTERVAL;
 use crate::common::device::peripherals_manager::ButtonPins;
 use crate::common::device::ui::{BUTTON_EVENTS, BUTTON_STATE, DISPLAY_REFRESH_EVENTS};
@@ -55,6 +56,12 @@ const FULL_REFRESH_STATE: ButtonState = ButtonState {
     bottom_left: PressState::Released,
 };
 
+const PAIRING_WINDOW_STATE: ButtonState = ButtonState {
+    top_left: PressState::Released,
+    top_right: PressState::Released,
+    bottom_left: PressState::Pressed,
+};
+
 #[embassy_executor::task]
 pub(crate) async fn read_button_events() {
     loop {
@@ -65,6 +72,8 @@ pub(crate) async fn read_button_events() {
             let _ = Spawner::for_current_executor().await.spawn(handle_refresh(DisplayRefreshType::Partial));
         } else if state == FULL_REFRESH_STATE {
             let _ = Spawner::for_current_executor().await.spawn(handle_refresh(DisplayRefreshType::Full));
+        } else if state == PAIRING_WINDOW_STATE {
+            open_pairing_window();
         }
     }
 }
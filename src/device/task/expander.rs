@@ -8,13 +8,13 @@ use embassy_sync::mutex::Mutex;
 use embassy_time::{Duration, Timer, with_timeout};
 use nrf_softdevice::ble::Connection;
 use rclite::Arc;
-use crate::ble_debug;
+use crate::ble_info;
 
 use crate::common::ble::{SERVER, SPI_EXPANDER_EVENTS};
 use crate::common::ble::services::ExpanderServiceEvent;
 use crate::common::device::peripherals_manager::ExpanderPins;
 use crate::common::device::error::ExpanderError;
-use crate::common::device::expander::{authenticate, EXPANDER_STATE, handle_expander_disconnect, handle_mutex_acquire_release, handle_power, handle_set_cs, TIMEOUT_TRACKER};
+use crate::common::device::expander::{authenticate, exec_expander, EXPANDER_STATE, handle_expander_disconnect, handle_mutex_acquire_release, handle_power, handle_set_cs, TIMEOUT_TRACKER};
 use crate::common::device::expander::expander_state::{ExpanderFlags, ExpanderState, ExpanderType};
 use crate::common::util::ble_debugger::ConnectionDebug;
 
@@ -155,7 +155,7 @@ async fn handle_event(
                     }
                     Timer::after(expander_state.flags.cs_wait_duration).await;
 
-                    if let Some(response_buf) = expander_state.exec(pins).await? {
+                    if let Some(response_buf) = exec_expander(expander_state, pins).await? {
                         expander_server.miso_set(&response_buf)?;
                     }
 
@@ -175,7 +175,7 @@ pub(crate) async fn expander_mutex_timeout_task(
     loop {
         let token = with_timeout(Duration::from_secs(60 * 5), TIMEOUT_TRACKER.wait()).await;
         if token.is_err() {
-            ble_debug!("Forcing expander mutex timeout");
+            ble_info!("Forcing expander mutex timeout");
         }
 
         let mut expired_connections = LinkedList::new();
@@ -2,21 +2,27 @@ use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::mutex::Mutex;
 use embassy_time::{Duration, Timer};
 use futures::{FutureExt, select_biased};
+use futures::future::Either;
 use rclite::Arc;
 
-use crate::ble_debug;
+use crate::ble_warn;
 use crate::common::bitbang;
 use crate::common::bitbang::shared_i2c::SharedBitbangI2cPins;
-use crate::common::ble::{ACCELEROMETER_EVENT_PROCESSOR, BME_EVENT_PROCESSOR, COLOR_EVENT_PROCESSOR, FLASH_MANAGER, SERVER};
+use crate::common::ble::{ACCELEROMETER_EVENT_PROCESSOR, BME_EVENT_PROCESSOR, COLOR_EVENT_PROCESSOR, ESS_EVENT_PROCESSOR, FLASH_MANAGER, SERVER};
+use crate::common::ble::bme_config::{BME_CONFIG_CHANGED, bme_configuration};
+use crate::common::ble::ess::{BME_MEASUREMENT_KINDS, EssMeasurementKind, sampling_interval};
 use crate::common::ble::conv::ConvExt;
 use crate::common::ble::services::BleServer;
+use crate::common::ble::snapshot::SNAPSHOT;
+use crate::common::ble::weather::Weather;
 use crate::common::device::{bme280, veml6040};
-use crate::common::device::bme280::{BME280_SLEEP_MODE, Bme280Error};
+use crate::common::device::bme280::Bme280Error;
 use crate::common::device::lis2dh12::{Lis2dh12, SlaveAddr};
 use crate::common::device::lis2dh12::reg::{FifoMode, FullScale, Odr};
 use crate::common::device::peripherals_manager::BitbangI2CPins;
 use crate::common::device::persistence::flash_manager::CalibrationData;
 use crate::common::device::ui::UI_STORE;
+use crate::common::util::climate::ClimateMetrics;
 use crate::notify_all;
 
 #[embassy_executor::task]
@@ -29,17 +35,17 @@ pub(crate) async fn read_i2c0_task(i2c_pins: Arc<Mutex<ThreadModeRawMutex, Bitba
         select_biased! {
              result = bme_fut.fuse() => {
                 if let Err(err) = result {
-                    ble_debug!("BME error: {}", err);
+                    ble_warn!("BME error: {}", err);
                 }
             },
             result = accel_fut.fuse() => {
                 if let Err(err) = result {
-                    ble_debug!("Accel error: {:?}", err);
+                    ble_warn!("Accel error: {:?}", err);
                 }
             },
             result = color_fut.fuse() => {
                 if let Err(err) = result {
-                    ble_debug!("Color error: {:?}", err);
+                    ble_warn!("Color error: {:?}", err);
                 }
             },
         }
@@ -48,41 +54,47 @@ pub(crate) async fn read_i2c0_task(i2c_pins: Arc<Mutex<ThreadModeRawMutex, Bitba
     }
 }
 
+/// The sensor runs in the normal mode, so the IIR filter keeps its state and a reading is just a burst read
+/// of the latest conversion; the driver lives as long as the task, an error restarts the initialisation
 async fn read_bme_task(
     i2c_pins: Arc<Mutex<ThreadModeRawMutex, BitbangI2CPins>>,
     server: &BleServer,
 ) -> Result<(), Bme280Error> {
-    loop {
-        let _token = BME_EVENT_PROCESSOR.wait_for_condition().await;
-
-        let measurements = {
-            let mut i2c = SharedBitbangI2cPins::new(i2c_pins.as_ref());
-
-            let mut bme = bme280::Bme280::new_primary(&mut i2c);
-            let bme_config = bme280::Configuration::default()
-                .with_humidity_oversampling(bme280::Oversampling::Oversampling8X)
-                .with_temperature_oversampling(bme280::Oversampling::Oversampling8X)
-                .with_pressure_oversampling(bme280::Oversampling::Oversampling8X)
-                .with_iir_filter(bme280::IIRFilter::Coefficient8);
+    let mut i2c = SharedBitbangI2cPins::new(i2c_pins.as_ref());
+    let mut bme = bme280::Bme280::new_primary(&mut i2c);
+    bme.init(bme_configuration()).await?;
+    bme.normal().await?;
 
-            bme.init(bme_config).await?;
-            Timer::after(Duration::from_millis(10)).await;
+    loop {
+        let wakeup = select_biased! {
+            configuration = BME_CONFIG_CHANGED.wait().fuse() => Err(configuration),
+            token = BME_EVENT_PROCESSOR.wait_for_condition().fuse() => Ok(Either::Left(token)),
+            token = ESS_EVENT_PROCESSOR.wait_for_condition().fuse() => Ok(Either::Right(token)),
+        };
+        let _token = match wakeup {
+            Ok(token) => token,
+            Err(configuration) => {
+                bme.configure(configuration).await?;
+                bme.normal().await?;
+                continue;
+            }
+        };
 
-            // ignore these measurements (pressure is always around 600_000, and the next one 1m)
-            let _measurements = bme.measure().await?;
-            Timer::after(Duration::from_millis(100)).await;
+        let measurements = bme
+            .read_measurements()
+            .await?
+            .calibrate(&FLASH_MANAGER.get().get_last_calibration_data().await);
 
-            let measurements = bme.measure().await?;
-            bme.set_mode(BME280_SLEEP_MODE).await?;
-
-            measurements
-        }.calibrate(&FLASH_MANAGER.get().get_last_calibration_data().await);
+        let climate = ClimateMetrics::from_measurements(&measurements);
+        let weather = Weather::observe(&measurements);
 
         {
             let mut store = UI_STORE.lock().await;
             store.temperature = measurements.temperature;
             store.humidity = measurements.humidity;
             store.pressure = measurements.pressure;
+            store.dew_point = climate.map(|climate| climate.dew_point);
+            store.forecast = weather.and_then(|weather| weather.forecast);
         }
 
 
@@ -94,6 +106,7 @@ async fn read_bme_task(
         let temperature = measurements.temperature.as_temp();
         let humidity = measurements.humidity.as_humidity();
         let pressure = measurements.pressure.as_pressure();
+        SNAPSHOT.update(|snapshot| snapshot.bme = Some((temperature, humidity, pressure)));
 
         notify_all!(
             BME_EVENT_PROCESSOR,
@@ -102,8 +115,49 @@ async fn read_bme_task(
             humidity = &humidity,
             pressure = &pressure
         );
+        server.ess.notify_bme(temperature, humidity, pressure).await;
+
+        if let Some(climate) = climate {
+            let dew_point = climate.dew_point.as_temp();
+            let frost_point = climate.frost_point.as_temp();
+            let absolute_humidity = climate.absolute_humidity.as_absolute_humidity();
+            let heat_index = climate.heat_index.as_temp();
+            let humidex = climate.humidex.as_temp();
+            let vapour_pressure_deficit = climate.vapour_pressure_deficit.as_pressure();
+            notify_all!(
+                BME_EVENT_PROCESSOR,
+                server.bme280,
+                dew_point = &dew_point,
+                frost_point = &frost_point,
+                absolute_humidity = &absolute_humidity,
+                heat_index = &heat_index,
+                humidex = &humidex,
+                vapour_pressure_deficit = &vapour_pressure_deficit
+            );
+            server
+                .ess
+                .notify_climate(climate.dew_point.as_temp_sint8(), climate.heat_index.as_temp_sint8())
+                .await;
+        }
+
+        if let Some(weather) = weather {
+            let sea_level_pressure = weather.sea_level_pressure.as_pressure();
+            let pressure_altitude = weather.pressure_altitude.as_pressure_altitude();
+            let pressure_tendency = weather.tendency.map(|tendency| tendency.as_pressure_tendency()).unwrap_or(0);
+            let pressure_trend = weather.trend as u8;
+            let forecast = weather.forecast.unwrap_or(0);
+            notify_all!(
+                BME_EVENT_PROCESSOR,
+                server.bme280,
+                sea_level_pressure = &sea_level_pressure,
+                pressure_altitude = &pressure_altitude,
+                pressure_tendency = &pressure_tendency,
+                pressure_trend = &pressure_trend,
+                forecast = &forecast
+            );
+        }
 
-        Timer::after(BME_EVENT_PROCESSOR.get_timeout_duration()).await;
+        Timer::after(sampling_interval(&BME_EVENT_PROCESSOR, &BME_MEASUREMENT_KINDS)).await;
     }
 }
 
@@ -141,6 +195,10 @@ async fn read_accel_task(
             store.z = measurements.z;
         }
 
+        SNAPSHOT.update(|snapshot| {
+            snapshot.accelerometer = Some([measurements.x, measurements.y, measurements.z])
+        });
+
         // info!("LIS: x={}, y={}, z={}", measurements.x, measurements.y, measurements.z);
 
         notify_all!(
@@ -160,7 +218,10 @@ async fn read_veml_task(
     server: &BleServer,
 ) -> Result<(), veml6040::Error<bitbang::i2c::BitbangI2CError>> {
     loop {
-        let _token = COLOR_EVENT_PROCESSOR.wait_for_condition().await;
+        let _token = select_biased! {
+            token = COLOR_EVENT_PROCESSOR.wait_for_condition().fuse() => Either::Left(token),
+            token = ESS_EVENT_PROCESSOR.wait_for_condition().fuse() => Either::Right(token),
+        };
 
         let measurements = {
             let i2c = SharedBitbangI2cPins::new(i2c_pins.as_ref());
@@ -182,6 +243,17 @@ async fn read_veml_task(
             store.w = measurements.white;
         }
 
+        SNAPSHOT.update(|snapshot| {
+            snapshot.color = Some([
+                measurements.red,
+                measurements.green,
+                measurements.blue,
+                measurements.white,
+                cct,
+                ambient.as_luminous_flux(),
+            ])
+        });
+
         // info!("Color: {}; ambient light: {}, cct: {}", measurements, ambient, cct);
 
         notify_all!(
@@ -194,8 +266,9 @@ async fn read_veml_task(
             cct = &cct,
             lux = &ambient.as_luminous_flux()
         );
+        server.ess.notify_illuminance(ambient.as_illuminance()).await;
 
-        Timer::after(COLOR_EVENT_PROCESSOR.get_timeout_duration()).await;
+        Timer::after(sampling_interval(&COLOR_EVENT_PROCESSOR, &[EssMeasurementKind::Illuminance])).await;
     }
 }
 
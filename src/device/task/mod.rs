@@ -4,3 +4,4 @@ pub(crate) mod spi;
 pub(crate) mod buttons;
 pub(crate) mod nrf_temp;
 pub(crate) mod expander;
+pub(crate) mod watchdog;
@@ -3,7 +3,8 @@ use nrf_softdevice::{temperature_celsius, Softdevice};
 
 use crate::common::ble::conv::ConvExt;
 use crate::common::ble::{DEVICE_EVENT_PROCESSOR, SERVER};
-use crate::{ble_debug, notify_all};
+use crate::common::ble::snapshot::SNAPSHOT;
+use crate::{ble_warn, notify_all};
 
 #[embassy_executor::task]
 pub(crate) async fn notify_nrf_temp(sd: &'static Softdevice) {
@@ -12,14 +13,15 @@ pub(crate) async fn notify_nrf_temp(sd: &'static Softdevice) {
         let value = match temperature_celsius(sd) {
             Ok(value) => value.to_num::<f32>().as_temp(),
             Err(e) => {
-                ble_debug!("Failed to measure temp: {:?}", e);
+                ble_warn!("Failed to measure temp: {:?}", e);
                 continue;
             }
         };
 
         let server = SERVER.get();
+        SNAPSHOT.update(|snapshot| snapshot.nrf_temperature = Some(value));
 
-        notify_all!(DEVICE_EVENT_PROCESSOR, server.dis, temperature = &value);
+        notify_all!(DEVICE_EVENT_PROCESSOR, server.diagnostics, temperature = &value);
 
         Timer::after(DEVICE_EVENT_PROCESSOR.get_timeout_duration()).await;
     }
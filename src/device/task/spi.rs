@@ -12,7 +12,7 @@ use futures::FutureExt;
 use futures::select_biased;
 use rclite::Arc;
 use spim::Spim;
-use crate::common::ble::trigger_all_sensor_update;
+use crate::common::ble::{BONDER, trigger_all_sensor_update};
 use crate::common::device::config::ALL_TASK_COMPLETION_INTERVAL;
 
 use crate::common::device::peripherals_manager::{EpdControlPins, SpiTxPins};
@@ -27,6 +27,7 @@ use crate::common::device::ui::DISPLAY_REFRESH_EVENTS;
 use crate::common::device::ui::error::UiError;
 use crate::common::device::ui::text_repr::TextRepr;
 use crate::common::device::ui::UI_STORE;
+use crate::common::util::wall_clock::CLOCK;
 
 #[embassy_executor::task]
 pub(crate) async fn epd_task(
@@ -103,6 +104,8 @@ async fn draw_ui(
     let text_repr = {
         let store = UI_STORE.lock().await;
         TextRepr::from(store.deref())
+            .with_passkey(BONDER.pending_passkey())
+            .with_time(CLOCK.now())
     };
     ui.draw(text_repr)?;
 
@@ -0,0 +1,30 @@
+use defmt::{info, unwrap};
+use embassy_nrf::peripherals;
+use embassy_nrf::wdt::{Config, Watchdog, WatchdogHandle};
+use embassy_time::Timer;
+
+use crate::common::device::config::{WATCHDOG_FEED_INTERVAL, WATCHDOG_TIMEOUT};
+
+/// Only with the `dfu-bootloader` feature: the in-tree bootloader starts the watchdog before the application and
+/// feeds it during a swap. It can't be reconfigured once running, the running configuration is adopted.
+pub(crate) fn start_watchdog(wdt: peripherals::WDT) -> WatchdogHandle {
+    let config = Config::try_new(&wdt).unwrap_or_else(|| {
+        let mut config = Config::default();
+        config.timeout_ticks = (WATCHDOG_TIMEOUT.as_ticks() * 32768 / embassy_time::TICK_HZ) as u32;
+        config.run_during_debug_halt = false;
+        config
+    });
+    info!("Starting watchdog, timeout: {} ticks", config.timeout_ticks);
+
+    let (_watchdog, [handle]) = unwrap!(Watchdog::try_new(wdt, config).map_err(|_| ()));
+    handle
+}
+
+/// Starves only if the executor is stuck, every other task yields to it
+#[embassy_executor::task]
+pub(crate) async fn feed_watchdog_task(mut handle: WatchdogHandle) {
+    loop {
+        handle.pet();
+        Timer::after(WATCHDOG_FEED_INTERVAL).await;
+    }
+}
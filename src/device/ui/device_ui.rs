@@ -45,6 +45,10 @@ impl<'a, D> Ui<'a, D> where D: DrawTarget + OriginDimensions, UiError<<D as Draw
     pub(crate) fn draw(&mut self, text_repr: TextRepr) -> Result<(), UiError<D::Error>> {
         self.display.clear(self.background_color)?;
 
+        if let Some(passkey) = text_repr.passkey.as_ref() {
+            return self.draw_passkey(passkey);
+        }
+
         let display_area = self.display.bounding_box();
         if display_area.size.width > display_area.size.height {
             self.draw_horizontal(text_repr)
@@ -53,6 +57,23 @@ impl<'a, D> Ui<'a, D> where D: DrawTarget + OriginDimensions, UiError<<D as Draw
         }
     }
 
+    pub(crate) fn draw_passkey(&mut self, passkey: &str) -> Result<(), UiError<D::Error>> {
+        let display_area = self.display.bounding_box();
+
+        let passkey_layout = v_layout! {
+            Text::new("Pairing passkey", Point::zero(), self.text_style_small.clone()),
+            Text::new(passkey, Point::zero(), self.text_style_med.clone());
+            spacing = VERTICAL_MARGIN;
+            alignment = horizontal::Center
+        };
+
+        passkey_layout
+            .align_to(&display_area, horizontal::Center, vertical::Center)
+            .draw(self.display)?;
+
+        Ok(())
+    }
+
     pub(crate) fn draw_vertical(&mut self, text_repr: TextRepr) -> Result<(), UiError<D::Error>> {
         let display_area = self.display.bounding_box();
         let width = display_area.size.width;
@@ -102,8 +123,14 @@ impl<'a, D> Ui<'a, D> where D: DrawTarget + OriginDimensions, UiError<<D as Draw
 
         let bme_layout = v_layout! {
             Text::new(&text_repr.temp, Point::zero(), self.text_style_large_2.clone()),
-                Text::new(&text_repr.pressure, Point::zero(), self.text_style_med.clone()),
-                Text::new(&text_repr.humidity, Point::zero(), self.text_style_med.clone());
+                h_layout!(
+                    Text::new(&text_repr.forecast, Point::zero(), self.text_style_weather.clone()),
+                    Text::new(&text_repr.pressure, Point::zero(), self.text_style_med.clone());
+                    spacing = VERTICAL_MARGIN;
+                    alignment = vertical::Center
+                ),
+                Text::new(&text_repr.humidity, Point::zero(), self.text_style_med.clone()),
+                Text::new(&text_repr.dew_point, Point::zero(), self.text_style_small.clone());
             spacing = VERTICAL_MARGIN;
             alignment = horizontal::Center
         };
@@ -144,7 +171,10 @@ impl<'a, D> Ui<'a, D> where D: DrawTarget + OriginDimensions, UiError<<D as Draw
             spacing = VERTICAL_MARGIN
         };
 
+        let header = Text::new(&text_repr.header, Point::zero(), self.text_style_small.clone());
+
         let main_layout = v_layout! {
+            header,
             nrf_adc_layout,
             bme_layout,
             color_layout,
@@ -182,7 +212,12 @@ impl<'a, D> Ui<'a, D> where D: DrawTarget + OriginDimensions, UiError<<D as Draw
             Text::new(&text_repr.temp, Point::zero(), self.text_style_large.clone()),
             v_layout!(
                 Text::new(&text_repr.humidity, Point::zero(), self.text_style_med.clone()),
-                Text::new(&text_repr.pressure, Point::zero(), self.text_style_med.clone());
+                h_layout!(
+                    Text::new(&text_repr.forecast, Point::zero(), self.text_style_weather.clone()),
+                    Text::new(&text_repr.pressure, Point::zero(), self.text_style_med.clone());
+                    spacing = VERTICAL_MARGIN;
+                    alignment = vertical::Center
+                );
                 spacing = VERTICAL_MARGIN
             );
             spacing = DistributeFill(width)
@@ -228,7 +263,10 @@ impl<'a, D> Ui<'a, D> where D: DrawTarget + OriginDimensions, UiError<<D as Draw
             spacing = VERTICAL_MARGIN
         };
 
+        let header = Text::new(&text_repr.header, Point::zero(), self.text_style_small.clone());
+
         let main_layout = v_layout! {
+            header,
             first_two_rows,
             bme_layout,
             h_layout!(adc_voltages_chain; spacing = DistributeFill(width));
@@ -1,7 +1,8 @@
 use core::convert::Infallible;
 use core::fmt;
 
-use defmt::{error, Formatter};
+use defmt::Formatter;
+#[cfg(target_os = "none")]
 use embassy_nrf::spim;
 use thiserror_no_std::Error;
 
@@ -18,6 +19,7 @@ pub enum UiError<T> {
     #[error("Infallible")]
     Infallible(#[from] Infallible),
 
+    #[cfg(target_os = "none")]
     #[error("Spim")]
     Spim(#[from] spim::Error),
 }
@@ -28,6 +30,7 @@ impl<T> defmt::Format for UiError<T> where T: defmt::Format {
             UiError::FmtError(_) => defmt::write!(fmt, "Formatting error"),
             UiError::DisplayError(err) => defmt::write!(fmt, "Display error: {}", err),
             UiError::Infallible(err) => defmt::write!(fmt, "Formatting error: {}", err),
+            #[cfg(target_os = "none")]
             UiError::Spim(err) => defmt::write!(fmt, "spim error: {}", err),
         }
     }
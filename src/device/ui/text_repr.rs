@@ -1,6 +1,7 @@
 use alloc::format;
 use alloc::string::{String, ToString};
 use crate::common::device::ui::ui_store::UiStore;
+use crate::common::util::wall_clock::CalendarTime;
 pub(crate) struct TextRepr {
     pub(crate) bat: String,
     pub(crate) nrf_voltages: String,
@@ -8,43 +9,77 @@ pub(crate) struct TextRepr {
     pub(crate) temp: String,
     pub(crate) humidity: String,
     pub(crate) pressure: String,
+    /// Empty without a humidity reading
+    pub(crate) dew_point: String,
+    /// Weather glyph of the forecast, empty until the pressure trend is known
+    pub(crate) forecast: String,
     pub(crate) lux_text: String,
     pub(crate) cct_text: String,
     pub(crate) rgbw_text: String,
     pub(crate) xyz_text: String,
     pub(crate) connections: String,
+    pub(crate) header: String,
+    pub(crate) passkey: Option<String>,
 }
 
 impl TextRepr {
-    /// Depending on battery voltage, return a text representation that matches to a battery icon.
-    /// "0" - 5 values used to represent battery level.
-    /// "0" - 3.2V
-    /// "5" -  4.2V
-    /// "6" - charging
-    fn get_charge_level_icon_text(v_bat: f32) -> &'static str {
-        let max_voltage = 4.2f32;
-        let min_voltage = 3.2f32;
-        let v_bat = v_bat.min(max_voltage).max(min_voltage);
-        let v_bat = (v_bat - min_voltage) / (max_voltage - min_voltage);
-        let v_bat = v_bat * 5.0;
-        let v_bat = v_bat as u8;
-        match v_bat {
+    /// Depending on battery level, return a text representation that matches to a battery icon.
+    /// "0" - 5 values used to represent battery level, the same percentage is exposed over
+    /// the Battery Service.
+    /// "0" - 0-9%
+    /// "5" - 90-100%
+    /// "6" - charging, as estimated from the voltage trend
+    fn get_charge_level_icon_text(level: u8, charging: bool) -> &'static str {
+        if charging {
+            return "6";
+        }
+        match (level.min(100) + 10) / 20 {
             0 => "0",
             1 => "1",
             2 => "2",
             3 => "3",
             4 => "4",
-            5 => "5",
-            _ => "6",
+            _ => "5",
+        }
+    }
+
+    /// Open Iconic weather glyph of a Zambretti forecast letter (1 - "A" .. 26 - "Z"):
+    /// "E" - sun, "A" - sun behind a cloud, "@" - cloud, "C" - rain
+    fn get_forecast_icon_text(forecast: Option<u8>) -> &'static str {
+        match forecast.map(|letter| (b'A' + letter.saturating_sub(1)) as char) {
+            None => "",
+            Some('A'..='D' | 'F') => "\u{0045}",
+            Some('E' | 'G'..='K') => "\u{0041}",
+            Some('L'..='Q') => "\u{0040}",
+            Some(_) => "\u{0043}",
         }
     }
 
-    
+    fn get_header_text(name: &str, location: &str) -> String {
+        if location.is_empty() {
+            name.to_string()
+        } else {
+            format!("{} - {}", name, location)
+        }
+    }
+
+    pub(crate) fn with_passkey(mut self, passkey: Option<[u8; 6]>) -> Self {
+        self.passkey = passkey.map(|digits| digits.iter().map(|&digit| digit as char).collect());
+        self
+    }
+
+    /// Appends the time of the refresh to the header once the wall clock has been set
+    pub(crate) fn with_time(mut self, time: Option<CalendarTime>) -> Self {
+        if let Some(time) = time {
+            self.header = format!("{} {:02}:{:02}", self.header, time.hours, time.minutes);
+        }
+        self
+    }
 }
 
 impl From<&UiStore> for TextRepr {
     fn from(value: &UiStore) -> Self {
-        let bat_text = Self::get_charge_level_icon_text(value.bat_voltage);
+        let bat_text = Self::get_charge_level_icon_text(value.bat_level, value.bat_charging_estimate);
         Self {
             bat: bat_text.to_string(),
             nrf_voltages: value.nrf_adc_voltages[..7].iter().map(|v| format!("{:.2}", v)).collect::<String>(),
@@ -52,11 +87,15 @@ impl From<&UiStore> for TextRepr {
             temp: format!("{:.1}", value.temperature),
             humidity: format!("{:.1}%", value.humidity),
             pressure: format!("{:.1}", value.pressure / 100.0),
+            dew_point: value.dew_point.map(|dew_point| format!("Dew point {:.1}", dew_point)).unwrap_or_default(),
+            forecast: Self::get_forecast_icon_text(value.forecast).to_string(),
             lux_text: format!("{:.1}", value.lux as u32),
             cct_text: format!("{:.1}", value.cct as u32),
             rgbw_text: format!("R:{} G:{} B:{} W:{}; BAT:{:.2}", value.r, value.g, value.b, value.w, value.bat_voltage),
             xyz_text: format!("X: {:.2} Y: {:.2} Z: {:.2}", value.x, value.y, value.z),
             connections: format!("{}", value.num_connections),
+            header: Self::get_header_text(&value.name, &value.location),
+            passkey: None,
         }
     }
 }
@@ -1,7 +1,12 @@
+use alloc::string::String;
+
 #[derive(Debug, Default)]
 pub(crate) struct UiStore {
    pub(crate) nrf_adc_voltages: [f32; 8],
    pub(crate) bat_voltage: f32,
+   pub(crate) bat_level: u8,
+   /// See `BatteryState::charging_estimate`
+   pub(crate) bat_charging_estimate: bool,
    pub(crate) adc_voltages: [f32; 8],
 
    pub(crate) r: u16,
@@ -14,10 +19,17 @@ pub(crate) struct UiStore {
    pub(crate) temperature: f32,
    pub(crate) humidity: f32,
    pub(crate) pressure: f32,
+   /// `None` without a humidity reading
+   pub(crate) dew_point: Option<f32>,
+   /// Zambretti forecast letter, `None` until the pressure trend is known
+   pub(crate) forecast: Option<u8>,
 
    pub(crate) x: f32,
    pub(crate) y: f32,
    pub(crate) z: f32,
 
    pub(crate) num_connections: u8,
+
+   pub(crate) name: String,
+   pub(crate) location: String,
 }
@@ -4,6 +4,9 @@ use embedded_hal_async::i2c;
 use embedded_hal_async::i2c::ErrorType;
 use num_traits::float::FloatCore;
 
+/// Upper limit of the ambient light at the shortest integration time (40 ms)
+pub const VEML6040_MAX_LUX: f32 = 16496.0;
+
 /// All possible errors in this crate
 #[derive(Debug)]
 pub enum Error<E> {
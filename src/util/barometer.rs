@@ -0,0 +1,249 @@
+//! Sea-level pressure, pressure altitude and the 3 hour pressure tendency with a Zambretti forecast.
+//! The tendency is taken from the station pressure, the sea-level reduction would only add the temperature
+//! swings of the day to it.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use embassy_time::Instant;
+use heapless::Deque;
+use micromath::F32Ext;
+
+use crate::common::device::config::{
+    PRESSURE_TREND_MIN_SPAN, PRESSURE_TREND_SAMPLE_INTERVAL, PRESSURE_TREND_SAMPLES, PRESSURE_TREND_WINDOW,
+};
+
+/// ISA sea-level pressure, Pa, and temperature, K
+const STANDARD_PRESSURE: f32 = 101_325.0;
+const STANDARD_TEMPERATURE: f32 = 288.15;
+/// ISA temperature lapse rate, K/m
+const LAPSE_RATE: f32 = 0.0065;
+/// g * M / (R * L) of the ISA troposphere
+const BAROMETRIC_EXPONENT: f32 = 5.257;
+const ZERO_CELSIUS_K: f32 = 273.15;
+
+/// Tendency per window, Pa; below the first one the pressure is steady
+const TENDENCY_CHANGE: f32 = 160.0;
+const TENDENCY_FAST_CHANGE: f32 = 360.0;
+
+/// Zambretti forecast letters by the falling, steady and rising formula, fine to stormy
+const ZAMBRETTI_FALLING: &[u8] = b"ABDHORUXZ";
+const ZAMBRETTI_STEADY: &[u8] = b"ABEKNPSWXZ";
+const ZAMBRETTI_RISING: &[u8] = b"ABCFGIJLMQTYZ";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub(crate) enum PressureTrend {
+    /// Less than `PRESSURE_TREND_MIN_SPAN` of samples so far
+    Unknown = 0,
+    FallingFast = 1,
+    Falling = 2,
+    Steady = 3,
+    Rising = 4,
+    RisingFast = 5,
+}
+
+impl PressureTrend {
+    /// From the change over `PRESSURE_TREND_WINDOW`, Pa
+    pub(crate) fn from_tendency(tendency: Option<f32>) -> Self {
+        match tendency {
+            None => Self::Unknown,
+            Some(tendency) if tendency <= -TENDENCY_FAST_CHANGE => Self::FallingFast,
+            Some(tendency) if tendency <= -TENDENCY_CHANGE => Self::Falling,
+            Some(tendency) if tendency < TENDENCY_CHANGE => Self::Steady,
+            Some(tendency) if tendency < TENDENCY_FAST_CHANGE => Self::Rising,
+            Some(_) => Self::RisingFast,
+        }
+    }
+}
+
+/// QNH-like reduction of the station pressure with the hypsometric formula and the current air temperature
+pub(crate) fn sea_level_pressure(pressure: f32, altitude: f32, temperature: f32) -> f32 {
+    let lapse = LAPSE_RATE * altitude;
+    // called through the trait, the host would pick the std method and the tests would miss the device math
+    pressure * F32Ext::powf(1.0 - lapse / (temperature + lapse + ZERO_CELSIUS_K), -BAROMETRIC_EXPONENT)
+}
+
+/// Pressure altitude over the ISA sea-level pressure, m
+pub(crate) fn pressure_altitude(pressure: f32) -> f32 {
+    STANDARD_TEMPERATURE / LAPSE_RATE * (1.0 - F32Ext::powf(pressure / STANDARD_PRESSURE, 1.0 / BAROMETRIC_EXPONENT))
+}
+
+/// Forecast letter as its position in the alphabet, `A` - 1 (settled fine) .. `Z` - 26 (stormy, much rain);
+/// None while the trend is unknown. The seasonal and wind corrections of the original are left out.
+pub(crate) fn zambretti(sea_level_pressure: f32, trend: PressureTrend) -> Option<u8> {
+    let hpa = sea_level_pressure / 100.0;
+    // the Zambretti numbers are 1..9 when falling, 10..19 when steady and 20..32 when rising
+    let (letters, number, first) = match trend {
+        PressureTrend::Unknown => return None,
+        PressureTrend::FallingFast | PressureTrend::Falling => (ZAMBRETTI_FALLING, 127.0 - 0.12 * hpa, 1),
+        PressureTrend::Steady => (ZAMBRETTI_STEADY, 144.0 - 0.13 * hpa, 10),
+        PressureTrend::Rising | PressureTrend::RisingFast => (ZAMBRETTI_RISING, 185.0 - 0.16 * hpa, 20),
+    };
+
+    let index = (F32Ext::round(number) as i32 - first).clamp(0, letters.len() as i32 - 1) as usize;
+    Some(letters[index] - b'A' + 1)
+}
+
+/// Station pressure sampled once per `PRESSURE_TREND_SAMPLE_INTERVAL` over the last `PRESSURE_TREND_WINDOW`;
+/// the tests lock it from their own threads, so the mutex is a parameter
+pub(crate) struct PressureHistory<M: RawMutex = ThreadModeRawMutex> {
+    samples: Mutex<M, RefCell<Deque<(Instant, f32), PRESSURE_TREND_SAMPLES>>>,
+}
+
+impl<M: RawMutex> PressureHistory<M> {
+    const fn new() -> Self {
+        Self { samples: Mutex::new(RefCell::new(Deque::new())) }
+    }
+
+    /// Readings in between the samples, i.e. for the subscribed centrals, are dropped
+    pub(crate) fn record(&self, now: Instant, pressure: f32) {
+        // the readings are triggered at the interval, the conversion makes them a little late or early
+        let slack = PRESSURE_TREND_SAMPLE_INTERVAL / 10;
+        self.samples.lock(|samples| {
+            let mut samples = samples.borrow_mut();
+            if let Some((last, _)) = samples.back() {
+                if now.duration_since(*last) + slack < PRESSURE_TREND_SAMPLE_INTERVAL {
+                    return;
+                }
+            }
+            while let Some((first, _)) = samples.front() {
+                if now.duration_since(*first) <= PRESSURE_TREND_WINDOW && !samples.is_full() {
+                    break;
+                }
+                samples.pop_front();
+            }
+            let _ = samples.push_back((now, pressure));
+        });
+    }
+
+    /// Change over `PRESSURE_TREND_WINDOW` in Pa, extrapolated from a shorter span after a reboot
+    pub(crate) fn tendency(&self) -> Option<f32> {
+        self.samples.lock(|samples| {
+            let samples = samples.borrow();
+            let (first_at, first) = samples.front()?;
+            let (last_at, last) = samples.back()?;
+            let span = last_at.duration_since(*first_at);
+            if span < PRESSURE_TREND_MIN_SPAN {
+                return None;
+            }
+            Some((last - first) * PRESSURE_TREND_WINDOW.as_millis() as f32 / span.as_millis() as f32)
+        })
+    }
+
+    pub(crate) fn trend(&self) -> PressureTrend {
+        PressureTrend::from_tendency(self.tendency())
+    }
+}
+
+pub(crate) static PRESSURE_HISTORY: PressureHistory = PressureHistory::new();
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+    use super::*;
+
+    /// ISA station pressure and temperature at 500 m and 1000 m
+    const ISA_500_M: (f32, f32) = (95_461.0, 11.75);
+    const ISA_1000_M: (f32, f32) = (89_875.0, 8.5);
+
+    fn assert_near(value: f32, expected: f32, tolerance: f32) {
+        assert!((value - expected).abs() <= tolerance, "{} is not within {} of {}", value, tolerance, expected);
+    }
+
+    #[test]
+    fn sea_level_pressure_of_the_standard_atmosphere() {
+        assert_near(sea_level_pressure(STANDARD_PRESSURE, 0.0, 15.0), STANDARD_PRESSURE, 1.0);
+        assert_near(sea_level_pressure(ISA_500_M.0, 500.0, ISA_500_M.1), STANDARD_PRESSURE, 50.0);
+        assert_near(sea_level_pressure(ISA_1000_M.0, 1000.0, ISA_1000_M.1), STANDARD_PRESSURE, 50.0);
+        // colder air is denser, the same station pressure reduces to a higher sea-level pressure
+        assert!(sea_level_pressure(ISA_500_M.0, 500.0, -10.0) > sea_level_pressure(ISA_500_M.0, 500.0, 30.0));
+    }
+
+    #[test]
+    fn pressure_altitude_of_the_standard_atmosphere() {
+        assert_near(pressure_altitude(STANDARD_PRESSURE), 0.0, 0.5);
+        assert_near(pressure_altitude(ISA_500_M.0), 500.0, 2.0);
+        assert_near(pressure_altitude(ISA_1000_M.0), 1000.0, 2.0);
+        assert!(pressure_altitude(102_000.0) < 0.0);
+    }
+
+    #[test]
+    fn trend_thresholds() {
+        assert_eq!(PressureTrend::from_tendency(None), PressureTrend::Unknown);
+        assert_eq!(PressureTrend::from_tendency(Some(-360.0)), PressureTrend::FallingFast);
+        assert_eq!(PressureTrend::from_tendency(Some(-359.0)), PressureTrend::Falling);
+        assert_eq!(PressureTrend::from_tendency(Some(-160.0)), PressureTrend::Falling);
+        assert_eq!(PressureTrend::from_tendency(Some(-159.0)), PressureTrend::Steady);
+        assert_eq!(PressureTrend::from_tendency(Some(0.0)), PressureTrend::Steady);
+        assert_eq!(PressureTrend::from_tendency(Some(159.0)), PressureTrend::Steady);
+        assert_eq!(PressureTrend::from_tendency(Some(160.0)), PressureTrend::Rising);
+        assert_eq!(PressureTrend::from_tendency(Some(359.0)), PressureTrend::Rising);
+        assert_eq!(PressureTrend::from_tendency(Some(360.0)), PressureTrend::RisingFast);
+    }
+
+    fn letter(sea_level_pressure: f32, trend: PressureTrend) -> Option<char> {
+        zambretti(sea_level_pressure, trend).map(|letter| (b'A' + letter - 1) as char)
+    }
+
+    #[test]
+    fn zambretti_letters() {
+        assert_eq!(letter(STANDARD_PRESSURE, PressureTrend::Unknown), None);
+        // Z = 144 - 0.13 * 1013.25 = 12: "fine, possibly showers"
+        assert_eq!(letter(STANDARD_PRESSURE, PressureTrend::Steady), Some('E'));
+        // Z = 127 - 0.12 * 1000 = 7: "rain at times, worse later"
+        assert_eq!(letter(100_000.0, PressureTrend::Falling), Some('U'));
+        // Z = 185 - 0.16 * 1030 = 20: "settled fine"
+        assert_eq!(letter(103_000.0, PressureTrend::RisingFast), Some('A'));
+        // out of the range of the formulas the extremes are kept
+        assert_eq!(letter(105_000.0, PressureTrend::Steady), Some('A'));
+        assert_eq!(letter(95_000.0, PressureTrend::FallingFast), Some('Z'));
+    }
+
+    fn at_minutes(minutes: u64) -> Instant {
+        Instant::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn tendency_needs_an_hour_of_samples() {
+        let history = PressureHistory::<CriticalSectionRawMutex>::new();
+        // rising by 100 Pa an hour
+        for minutes in (0..=50).step_by(10) {
+            history.record(at_minutes(minutes), 100_000.0 + minutes as f32 * 100.0 / 60.0);
+        }
+        assert_eq!(history.tendency(), None);
+        assert_eq!(history.trend(), PressureTrend::Unknown);
+
+        history.record(at_minutes(60), 100_100.0);
+        // extrapolated to the 3 hour window
+        assert_near(history.tendency().unwrap(), 300.0, 0.5);
+        assert_eq!(history.trend(), PressureTrend::Rising);
+    }
+
+    #[test]
+    fn tendency_skips_readings_between_samples() {
+        let history = PressureHistory::<CriticalSectionRawMutex>::new();
+        history.record(at_minutes(0), 100_000.0);
+        // a subscribed central triggers readings in between, they would skew the tendency
+        history.record(at_minutes(5), 90_000.0);
+        history.record(at_minutes(60), 100_000.0);
+        // late by less than the slack
+        history.record(at_minutes(129), 99_900.0);
+
+        assert_near(history.tendency().unwrap(), -100.0 * 180.0 / 129.0, 0.5);
+    }
+
+    #[test]
+    fn tendency_covers_the_last_window() {
+        let history = PressureHistory::<CriticalSectionRawMutex>::new();
+        // falling by 200 Pa an hour for 5 hours, only the last 3 hours are kept
+        for minutes in (0..=300).step_by(10) {
+            history.record(at_minutes(minutes), 101_000.0 - minutes as f32 * 200.0 / 60.0);
+        }
+
+        assert_near(history.tendency().unwrap(), -600.0, 0.5);
+        assert_eq!(history.trend(), PressureTrend::FallingFast);
+    }
+}
@@ -3,17 +3,20 @@ use core::fmt;
 use defmt::info;
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::channel::Channel;
+use heapless::Vec;
 use nrf_softdevice::ble::Connection;
 
 use crate::common::ble::SERVER;
+use crate::common::ble::shell::mirror_log;
 use crate::common::device::config::{BLE_DEBUG_ARRAY_LEN, BLE_DEBUG_QUEUE_LEN};
 use crate::common::device::error::DeviceError;
 use crate::common::util::buf_writer::WriteTo;
+use crate::common::util::log_buffer::{LOG, LogLevel};
 use crate::DEVICE_EVENT_PROCESSOR;
-use crate::notify_all;
+use crate::ble_notify;
 
-static CHANNEL: Channel<ThreadModeRawMutex, (Option<Connection>, [u8; BLE_DEBUG_ARRAY_LEN]), BLE_DEBUG_QUEUE_LEN> =
-    Channel::new();
+/// Sequence numbers of the log entries addressed to a single connection, the rest is notified to every subscriber
+static ROUTES: Channel<ThreadModeRawMutex, (Connection, u32), BLE_DEBUG_QUEUE_LEN> = Channel::new();
 
 
 pub(crate) trait ConnectionDebug {
@@ -22,41 +25,119 @@ pub(crate) trait ConnectionDebug {
 
 impl ConnectionDebug for Connection {
     fn debug(&self, args: fmt::Arguments) {
-        let _ = ble_debug_push(Some(self.clone()), args);
+        let _ = ble_log_push(LogLevel::Debug, Some(self.clone()), args);
     }
 }
 
 #[embassy_executor::task]
 pub(crate) async fn ble_debug_notify_task() {
     let server = SERVER.get();
+    // anything logged before is only available through the backlog
+    let mut next_sequence = LOG.next_sequence();
+    let mut routes: Vec<(Connection, u32), BLE_DEBUG_QUEUE_LEN> = Vec::new();
 
     loop {
-        let (connection, message) = CHANNEL.receive().await;
-        if let Some(connection) = connection {
-            let _ = SERVER.get().dis.debug_notify(&connection, &message);
-        } else {
-            notify_all!(DEVICE_EVENT_PROCESSOR, server.dis, debug = &message);
+        LOG.appended.wait().await;
+
+        // the signal coalesces, so drain everything appended since the last wake up
+        loop {
+            let mut message = [0u8; BLE_DEBUG_ARRAY_LEN];
+            let mut sequence = None;
+            LOG.for_each_from(next_sequence, |entry| {
+                message[..entry.message.len()].copy_from_slice(&entry.message);
+                sequence = Some(entry.sequence);
+                false
+            });
+            let Some(sequence) = sequence else {
+                break;
+            };
+            next_sequence = sequence.wrapping_add(1);
+
+            // the route is queued right after the entry is pushed, so it is there by now
+            while let Ok(route) = ROUTES.try_receive() {
+                let _ = routes.push(route);
+            }
+            // routes of entries overwritten before they were notified are dropped along the way
+            let route = routes.iter().find(|(_, routed)| *routed == sequence).map(|(connection, _)| connection.clone());
+            routes.retain(|(_, routed)| *routed > sequence);
+
+            if let Some(connection) = route {
+                let _ = server.diagnostics.debug_notify(&connection, &message);
+            } else {
+                // debug messages are not sampled, so they bypass per-connection timeouts
+                for connection in Connection::iter() {
+                    let Some(settings) = DEVICE_EVENT_PROCESSOR.get_connection_settings(&connection).await else {
+                        continue;
+                    };
+                    if settings.debug {
+                        ble_notify!(server.diagnostics, &connection, debug, &message);
+                    }
+                }
+            }
+            mirror_log(&message).await;
         }
     }
 }
 
 
-pub fn ble_debug_push(connection: Option<Connection>, args: fmt::Arguments) -> Result<(), DeviceError> {
+pub(crate) fn ble_log_push(level: LogLevel, connection: Option<Connection>, args: fmt::Arguments) -> Result<(), DeviceError> {
     let mut buf = [0u8; BLE_DEBUG_ARRAY_LEN];
     let mut w = WriteTo::new(&mut buf);
     fmt::write(&mut w, args)?;
-    info!("ble_debug: {}", w.to_str().unwrap_or("invalid utf8"));
-    if let Err(_) = CHANNEL.try_send((connection, buf)) {
-        info!("Failed to put debug message to the channel");
+    info!("ble_{}: {}", level.as_str(), w.to_str().unwrap_or("invalid utf8"));
+
+    // every message is kept, the connection only decides who gets the live notification
+    let len = buf.iter().position(|&byte| byte == 0).unwrap_or(BLE_DEBUG_ARRAY_LEN);
+    let sequence = LOG.push(level, &buf[..len]);
+    if let Some(connection) = connection {
+        if ROUTES.try_send((connection, sequence)).is_err() {
+            info!("Failed to route debug message, notifying every subscriber");
+        }
     }
     Ok(())
 }
 
 #[macro_export]
-macro_rules! ble_debug {
+macro_rules! ble_log {
     (
-        $($t:tt)*
+        $level:expr, $($t:tt)*
     ) => {{
-        let _ = $crate::common::util::ble_debugger::ble_debug_push(None, format_args!($($t)*));
+        let _ = $crate::common::util::ble_debugger::ble_log_push($level, None, format_args!($($t)*));
     }};
 }
+
+#[macro_export]
+macro_rules! ble_error {
+    (
+        $($t:tt)*
+    ) => {
+        $crate::ble_log!($crate::common::util::log_buffer::LogLevel::Error, $($t)*)
+    };
+}
+
+#[macro_export]
+macro_rules! ble_warn {
+    (
+        $($t:tt)*
+    ) => {
+        $crate::ble_log!($crate::common::util::log_buffer::LogLevel::Warn, $($t)*)
+    };
+}
+
+#[macro_export]
+macro_rules! ble_info {
+    (
+        $($t:tt)*
+    ) => {
+        $crate::ble_log!($crate::common::util::log_buffer::LogLevel::Info, $($t)*)
+    };
+}
+
+#[macro_export]
+macro_rules! ble_debug {
+    (
+        $($t:tt)*
+    ) => {
+        $crate::ble_log!($crate::common::util::log_buffer::LogLevel::Debug, $($t)*)
+    };
+}
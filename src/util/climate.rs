@@ -0,0 +1,239 @@
+//! Climate metrics derived from a single BME280 reading, so the values are consistent with each other.
+//! Saturation vapour pressure is the Magnus form (Alduchov & Eskridge) over water and over ice.
+//! The math goes through `F32Ext` explicitly: on the host the inherent `f32` methods would shadow it,
+//! and the tests have to run the approximations the device uses.
+
+use micromath::F32Ext;
+
+use crate::common::device::bme280::Measurements;
+
+/// Magnus coefficients over water, hPa and °C
+const MAGNUS_WATER_A: f32 = 6.1094;
+const MAGNUS_WATER_B: f32 = 17.625;
+const MAGNUS_WATER_C: f32 = 243.04;
+/// Magnus coefficients over ice
+const MAGNUS_ICE_A: f32 = 6.1121;
+const MAGNUS_ICE_B: f32 = 22.587;
+const MAGNUS_ICE_C: f32 = 273.86;
+
+/// Specific gas constant of water vapour, J/(kg·K)
+const WATER_VAPOUR_GAS_CONSTANT: f32 = 461.5;
+const ZERO_CELSIUS_K: f32 = 273.15;
+
+/// °F, while the simple Steadman fit averaged with the air temperature is below this, the fit is the heat index;
+/// the Rothfusz regression is meaningless in the cold
+const HEAT_INDEX_MIN_F: f32 = 80.0;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, defmt::Format)]
+pub(crate) struct ClimateMetrics {
+    /// °C
+    pub(crate) dew_point: f32,
+    /// °C, equals the dew point above zero
+    pub(crate) frost_point: f32,
+    /// g/m³
+    pub(crate) absolute_humidity: f32,
+    /// °C, NOAA heat index
+    pub(crate) heat_index: f32,
+    /// Environment Canada humidex, a temperature-like value
+    pub(crate) humidex: f32,
+    /// Vapour pressure deficit, Pa
+    pub(crate) vapour_pressure_deficit: f32,
+}
+
+/// Saturation vapour pressure over water, hPa
+fn saturation_vapour_pressure(temperature: f32) -> f32 {
+    MAGNUS_WATER_A * F32Ext::exp(MAGNUS_WATER_B * temperature / (MAGNUS_WATER_C + temperature))
+}
+
+fn celsius_to_fahrenheit(value: f32) -> f32 {
+    value * 9.0 / 5.0 + 32.0
+}
+
+fn fahrenheit_to_celsius(value: f32) -> f32 {
+    (value - 32.0) * 5.0 / 9.0
+}
+
+/// Rothfusz regression with the NOAA adjustments, the simple Steadman fit when it's mild
+fn heat_index(temperature: f32, humidity: f32) -> f32 {
+    let t = celsius_to_fahrenheit(temperature);
+    let rh = humidity;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < HEAT_INDEX_MIN_F {
+        return fahrenheit_to_celsius(simple);
+    }
+
+    let mut index = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+        - 0.224_755_42 * t * rh
+        - 0.006_837_83 * t * t
+        - 0.054_817_17 * rh * rh
+        + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh
+        - 0.000_001_99 * t * t * rh * rh;
+
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        index -= (13.0 - rh) / 4.0 * F32Ext::sqrt((17.0 - F32Ext::abs(t - 95.0)) / 17.0);
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        index += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+    }
+
+    fahrenheit_to_celsius(index)
+}
+
+impl ClimateMetrics {
+    /// `None` if there is no humidity reading (BMP280 or humidity skipped), nothing can be derived then
+    pub(crate) fn from_measurements(measurements: &Measurements) -> Option<Self> {
+        let temperature = measurements.temperature;
+        let humidity = measurements.humidity.min(100.0);
+        if humidity <= 0.0 {
+            return None;
+        }
+
+        let saturation = saturation_vapour_pressure(temperature);
+        let vapour_pressure = humidity / 100.0 * saturation;
+
+        let gamma = F32Ext::ln(humidity / 100.0) + MAGNUS_WATER_B * temperature / (MAGNUS_WATER_C + temperature);
+        let dew_point = MAGNUS_WATER_C * gamma / (MAGNUS_WATER_B - gamma);
+
+        let frost_point = if dew_point < 0.0 {
+            let gamma = F32Ext::ln(vapour_pressure / MAGNUS_ICE_A);
+            MAGNUS_ICE_C * gamma / (MAGNUS_ICE_B - gamma)
+        } else {
+            dew_point
+        };
+
+        // vapour pressure is in hPa: e * 100 / (Rv * T) kg/m³, * 1000 g/m³
+        let absolute_humidity =
+            vapour_pressure * 100_000.0 / (WATER_VAPOUR_GAS_CONSTANT * (temperature + ZERO_CELSIUS_K));
+
+        Some(Self {
+            dew_point,
+            frost_point,
+            absolute_humidity,
+            heat_index: heat_index(temperature, humidity),
+            humidex: temperature + 0.5555 * (vapour_pressure - 10.0),
+            vapour_pressure_deficit: (saturation - vapour_pressure) * 100.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HUMIDITY: f32 = 50.0;
+
+    fn simple_heat_index(temperature: f32) -> f32 {
+        let t = celsius_to_fahrenheit(temperature);
+        fahrenheit_to_celsius(0.5 * (t + 61.0 + (t - 68.0) * 1.2 + HUMIDITY * 0.094))
+    }
+
+    fn metrics(temperature: f32, humidity: f32) -> ClimateMetrics {
+        ClimateMetrics::from_measurements(&Measurements { temperature, pressure: 101_325.0, humidity }).unwrap()
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() <= tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+    }
+
+    #[test]
+    fn heat_index_is_the_simple_fit_below_the_threshold() {
+        // the average of the simple fit and the air temperature crosses 80 °F at 26.65 °C with 50 %RH
+        let temperature = 26.6;
+        let simple = simple_heat_index(temperature);
+        assert!((simple + temperature) / 2.0 < fahrenheit_to_celsius(HEAT_INDEX_MIN_F));
+        assert_eq!(heat_index(temperature, HUMIDITY), simple);
+    }
+
+    #[test]
+    fn heat_index_is_the_regression_above_the_threshold() {
+        let temperature = 26.7;
+        let simple = simple_heat_index(temperature);
+        assert!((simple + temperature) / 2.0 >= fahrenheit_to_celsius(HEAT_INDEX_MIN_F));
+
+        let index = heat_index(temperature, HUMIDITY);
+        assert_ne!(index, simple);
+        // NOAA switches formulas with a small step, not back to the air temperature
+        assert!((index - heat_index(26.6, HUMIDITY)).abs() < 1.0);
+    }
+
+    #[test]
+    fn heat_index_matches_the_noaa_table() {
+        // °F, %RH, heat index °F of the NWS chart, rounded to whole degrees;
+        // 86 °F at 90 %RH takes the humid adjustment
+        let table = [
+            (80.0, 40.0, 80.0),
+            (80.0, 80.0, 84.0),
+            (86.0, 50.0, 88.0),
+            (86.0, 90.0, 105.0),
+            (90.0, 50.0, 95.0),
+            (90.0, 70.0, 106.0),
+            (100.0, 40.0, 109.0),
+            (100.0, 55.0, 124.0),
+        ];
+        for (temperature, humidity, expected) in table {
+            let index = metrics(fahrenheit_to_celsius(temperature), humidity).heat_index;
+            assert_close(celsius_to_fahrenheit(index), expected, 0.6);
+        }
+    }
+
+    #[test]
+    fn dew_point_matches_the_magnus_table() {
+        // °C, %RH, dew point °C
+        let table = [(20.0, 50.0, 9.3), (25.0, 60.0, 16.7), (30.0, 80.0, 26.2), (10.0, 90.0, 8.4), (0.0, 50.0, -9.2)];
+        for (temperature, humidity, expected) in table {
+            let metrics = metrics(temperature, humidity);
+            assert_close(metrics.dew_point, expected, 0.1);
+            if expected >= 0.0 {
+                assert_eq!(metrics.frost_point, metrics.dew_point);
+            }
+        }
+    }
+
+    #[test]
+    fn frost_point_matches_the_saturation_pressure_over_ice() {
+        // the vapour pressure equals the saturation pressure over ice at the frost point (Murphy & Koop):
+        // 1.0325 hPa at -20 °C is 53.97 % of the 1.9131 hPa over water at -15 °C,
+        // 2.5989 hPa at -10 °C is 61.62 % of the 4.2176 hPa at -5 °C
+        let table = [(-15.0, 53.97, -20.0), (-5.0, 61.62, -10.0)];
+        for (temperature, humidity, expected) in table {
+            let metrics = metrics(temperature, humidity);
+            assert_close(metrics.frost_point, expected, 0.1);
+            assert!(metrics.dew_point < metrics.frost_point);
+        }
+    }
+
+    #[test]
+    fn absolute_humidity_of_saturated_air() {
+        // °C, g/m³ at 100 %RH
+        let table = [(10.0, 9.4), (20.0, 17.3), (25.0, 23.0), (30.0, 30.4)];
+        for (temperature, expected) in table {
+            assert_close(metrics(temperature, 100.0).absolute_humidity, expected, 0.2);
+        }
+    }
+
+    #[test]
+    fn humidex_matches_environment_canada() {
+        // 30 °C with a dew point of 15 °C is a humidex of 34, with a dew point of 25 °C of 42;
+        // the relative humidity is that of the dew point at 30 °C
+        let table = [(30.0, 40.17, 34.0), (30.0, 74.63, 42.0)];
+        for (temperature, humidity, expected) in table {
+            assert_close(metrics(temperature, humidity).humidex, expected, 0.5);
+        }
+    }
+
+    #[test]
+    fn vapour_pressure_deficit_is_the_missing_saturation_pressure() {
+        // saturation pressure 3169 Pa at 25 °C and 2339 Pa at 20 °C (WMO)
+        let table = [(25.0, 50.0, 1584.5), (20.0, 60.0, 935.6), (20.0, 100.0, 0.0)];
+        for (temperature, humidity, expected) in table {
+            assert_close(metrics(temperature, humidity).vapour_pressure_deficit, expected, 8.0);
+        }
+    }
+
+    #[test]
+    fn nothing_is_derived_without_humidity() {
+        let measurements = Measurements { temperature: 20.0, pressure: 101_325.0, humidity: 0.0 };
+        assert_eq!(ClimateMetrics::from_measurements(&measurements), None);
+    }
+}
@@ -3,7 +3,7 @@ use core::sync::atomic::{AtomicBool, Ordering};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::channel::Channel;
 
-use crate::ble_debug;
+use crate::ble_error;
 
 pub(crate) struct Condition<const T: usize> {
     channel: Channel<ThreadModeRawMutex, (), T>,
@@ -20,7 +20,7 @@ impl<'a, const T: usize> Drop for ConditionToken<'a, T> {
         if self.condition.is_enabled.load(Ordering::SeqCst) {
             if let Err(err) = self.condition.channel.try_send(()) {
                 let name = self.condition.name.unwrap_or("unnamed");
-                ble_debug!("Failed to return condition token for {name}: {:?}", err);
+                ble_error!("Failed to return condition token for {name}: {:?}", err);
             }
         }
     }
@@ -49,6 +49,10 @@ impl<const T: usize> Condition<T> {
         let _ = self.channel.try_send(());
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled.load(Ordering::Relaxed)
+    }
+
     pub fn disable(&self) {
         self.is_enabled.store(false, Ordering::SeqCst);
     }
@@ -0,0 +1,152 @@
+use core::cell::RefCell;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::mutex::Mutex as AsyncMutex;
+
+use crate::common::ble::FLASH_MANAGER;
+use crate::common::device::config::HISTORY_FLASH_PAGES;
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::history_storage::{HISTORY_RECORDS_PER_PAGE, HistoryRecord};
+
+#[derive(Clone, Copy)]
+struct HistoryState {
+    /// Sequence of the first record of every page, None for erased pages
+    first_sequences: [Option<u32>; HISTORY_FLASH_PAGES],
+    /// The page records are appended to
+    head_page: usize,
+    next_index: usize,
+    next_sequence: u32,
+}
+
+impl HistoryState {
+    const fn new() -> Self {
+        Self { first_sequences: [None; HISTORY_FLASH_PAGES], head_page: 0, next_index: 0, next_sequence: 0 }
+    }
+
+    fn oldest_sequence(&self) -> u32 {
+        self.first_sequences.iter().flatten().copied().min().unwrap_or(self.next_sequence)
+    }
+
+    /// Sequences are consecutive within a page, a record is found from the first one of its page
+    fn locate(&self, sequence: u32) -> Option<(usize, usize)> {
+        self.first_sequences.iter().enumerate().find_map(|(page, first)| {
+            let index = sequence.checked_sub((*first)?)? as usize;
+            let written = if page == self.head_page { self.next_index } else { HISTORY_RECORDS_PER_PAGE };
+            (index < written).then_some((page, index))
+        })
+    }
+}
+
+/// Log-structured ring of `HistoryRecord`s over the history flash region: records are appended
+/// page by page, the oldest page is erased when the head wraps around onto it.
+pub(crate) struct HistoryLog {
+    state: Mutex<ThreadModeRawMutex, RefCell<HistoryState>>,
+    /// Appends and deletions, a deletion must not program a page the head has just erased
+    writer: AsyncMutex<ThreadModeRawMutex, ()>,
+}
+
+impl HistoryLog {
+    const fn new() -> Self {
+        Self { state: Mutex::new(RefCell::new(HistoryState::new())), writer: AsyncMutex::new(()) }
+    }
+
+    pub(crate) fn next_sequence(&self) -> u32 {
+        self.state.lock(|state| state.borrow().next_sequence)
+    }
+
+    /// Equals `next_sequence` while the history is empty
+    pub(crate) fn oldest_sequence(&self) -> u32 {
+        self.state.lock(|state| state.borrow().oldest_sequence())
+    }
+
+    async fn read_slot(&self, sequence: u32) -> Result<Option<(usize, usize, HistoryRecord)>, FlashManagerError> {
+        let Some((page, index)) = self.state.lock(|state| state.borrow().locate(sequence)) else {
+            return Ok(None);
+        };
+        let record = FLASH_MANAGER.get().read_history_record(page, index).await?;
+        // a failed write leaves a hole, the slot holds whatever has been programmed
+        Ok(record.filter(|record| record.sequence == sequence && !record.deleted).map(|record| (page, index, record)))
+    }
+
+    /// None for sequences that have been deleted, overwritten or not written yet
+    pub(crate) async fn read(&self, sequence: u32) -> Result<Option<HistoryRecord>, FlashManagerError> {
+        Ok(self.read_slot(sequence).await?.map(|(_, _, record)| record))
+    }
+
+    /// Returns false if there is no such record
+    pub(crate) async fn delete(&self, sequence: u32) -> Result<bool, FlashManagerError> {
+        let _writer = self.writer.lock().await;
+        let Some((page, index, record)) = self.read_slot(sequence).await? else {
+            return Ok(false);
+        };
+        FLASH_MANAGER.get().delete_history_record(page, index, &record).await?;
+        Ok(true)
+    }
+
+    /// Assigns the next sequence to the record and appends it, returns the sequence
+    pub(crate) async fn append(&self, mut record: HistoryRecord) -> Result<u32, FlashManagerError> {
+        let _writer = self.writer.lock().await;
+        let flash_manager = FLASH_MANAGER.get();
+        let mut state = self.state.lock(|state| *state.borrow());
+
+        if state.next_index >= HISTORY_RECORDS_PER_PAGE {
+            state.head_page = (state.head_page + 1) % HISTORY_FLASH_PAGES;
+            state.next_index = 0;
+            state.first_sequences[state.head_page] = None;
+            // readers must not look into the page while it is erased
+            self.state.lock(|shared| *shared.borrow_mut() = state);
+            flash_manager.erase_history_page(state.head_page).await?;
+        }
+
+        record.sequence = state.next_sequence;
+        if state.next_index == 0 {
+            state.first_sequences[state.head_page] = Some(record.sequence);
+        }
+        // the slot and the sequence are consumed even if the write fails, so they stay in step
+        state.next_index += 1;
+        state.next_sequence = state.next_sequence.wrapping_add(1);
+        self.state.lock(|shared| *shared.borrow_mut() = state);
+
+        flash_manager.write_history_record(state.head_page, state.next_index - 1, &record).await?;
+        Ok(record.sequence)
+    }
+}
+
+pub(crate) static HISTORY: HistoryLog = HistoryLog::new();
+
+/// Finds the head page from the first record of every page and the last programmed slot on it
+pub(crate) async fn restore_history_from_flash() -> Result<(), FlashManagerError> {
+    let flash_manager = FLASH_MANAGER.get();
+    let mut state = HistoryState::new();
+
+    for page in 0..HISTORY_FLASH_PAGES {
+        state.first_sequences[page] = flash_manager.read_history_record(page, 0).await?.map(|record| record.sequence);
+    }
+
+    let head = state
+        .first_sequences
+        .iter()
+        .enumerate()
+        .filter_map(|(page, first)| Some((page, (*first)?)))
+        .max_by_key(|(_, first)| *first);
+    if let Some((head_page, first)) = head {
+        state.head_page = head_page;
+        // a failed write leaves an erased slot before the later records, so the end is after the last programmed
+        // slot rather than at the first erased one, which `append` would program on top of a record
+        state.next_index = 1;
+        for index in (1..HISTORY_RECORDS_PER_PAGE).rev() {
+            if flash_manager.read_history_record(head_page, index).await?.is_some() {
+                state.next_index = index + 1;
+                break;
+            }
+        }
+        state.next_sequence = first.wrapping_add(state.next_index as u32);
+    }
+
+    info!("Restored history, records {} - {}", state.oldest_sequence(), state.next_sequence);
+    HISTORY.state.lock(|shared| *shared.borrow_mut() = state);
+
+    Ok(())
+}
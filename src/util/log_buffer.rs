@@ -0,0 +1,226 @@
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Instant, Timer};
+use heapless::{Deque, Vec};
+
+use crate::common::ble::FLASH_MANAGER;
+use crate::common::device::config::{
+    BLE_DEBUG_ARRAY_LEN, BLE_LOG_PAGE_LEN, LOG_PERSIST_BATCH_LEN, LOG_PERSIST_INTERVAL, LOG_RING_LEN,
+};
+use crate::common::device::error::FlashManagerError;
+use crate::common::device::persistence::log_storage::LOG_RECORDS_PER_PAGE;
+use crate::common::util::wall_clock::CLOCK;
+
+/// Entries at this level or more severe are mirrored to flash
+pub(crate) const LOG_PERSIST_LEVEL: LogLevel = LogLevel::Error;
+/// Set on entries restored from flash, their timestamps belong to a previous boot
+pub(crate) const LOG_FLAG_PREVIOUS_BOOT: u8 = 0x80;
+
+/// [next sequence: u32][count: u8]
+const PAGE_HEADER_LEN: usize = 5;
+/// [sequence: u32][timestamp: u32][level: u8][len: u8][message..]
+const PAGE_ENTRY_HEADER_LEN: usize = 10;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+#[repr(u8)]
+pub(crate) enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl LogLevel {
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value & !LOG_FLAG_PREVIOUS_BOOT {
+            0 => Some(Self::Error),
+            1 => Some(Self::Warn),
+            2 => Some(Self::Info),
+            3 => Some(Self::Debug),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "E",
+            Self::Warn => "W",
+            Self::Info => "I",
+            Self::Debug => "D",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LogEntry {
+    pub(crate) sequence: u32,
+    /// Uptime in milliseconds
+    pub(crate) timestamp: u32,
+    /// Wall clock seconds, see `wall_clock`; 0 if the clock had not been set yet
+    pub(crate) time: u32,
+    /// `LogLevel`, possibly with `LOG_FLAG_PREVIOUS_BOOT`
+    pub(crate) level: u8,
+    pub(crate) message: Vec<u8, BLE_DEBUG_ARRAY_LEN>,
+}
+
+impl LogEntry {
+    pub(crate) fn level(&self) -> LogLevel {
+        LogLevel::from_u8(self.level).unwrap_or(LogLevel::Debug)
+    }
+
+    pub(crate) fn is_from_previous_boot(&self) -> bool {
+        self.level & LOG_FLAG_PREVIOUS_BOOT != 0
+    }
+}
+
+pub(crate) struct LogBuffer {
+    entries: Mutex<ThreadModeRawMutex, RefCell<Deque<LogEntry, LOG_RING_LEN>>>,
+    next_sequence: AtomicU32,
+    /// Signalled when a severe entry has to be mirrored to flash
+    pub(crate) persist: Signal<ThreadModeRawMutex, ()>,
+    /// Signalled on every new entry
+    pub(crate) appended: Signal<ThreadModeRawMutex, ()>,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self {
+            entries: Mutex::new(RefCell::new(Deque::new())),
+            next_sequence: AtomicU32::new(0),
+            persist: Signal::new(),
+            appended: Signal::new(),
+        }
+    }
+
+    fn insert(&self, entry: LogEntry) {
+        self.entries.lock(|entries| {
+            let mut entries = entries.borrow_mut();
+            if entries.is_full() {
+                entries.pop_front();
+            }
+            let _ = entries.push_back(entry);
+        });
+    }
+
+    pub(crate) fn push(&self, level: LogLevel, message: &[u8]) -> u32 {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        let len = message.len().min(BLE_DEBUG_ARRAY_LEN);
+        self.insert(LogEntry {
+            sequence,
+            timestamp: Instant::now().as_millis() as u32,
+            time: CLOCK.now_seconds(),
+            level: level as u8,
+            message: Vec::from_slice(&message[..len]).unwrap_or_default(),
+        });
+
+        if level <= LOG_PERSIST_LEVEL {
+            self.persist.signal(());
+        }
+        self.appended.signal(());
+        sequence
+    }
+
+    /// Entries mirrored to flash are put back on boot, sequence numbers continue after them
+    pub(crate) fn restore(&self, mut entry: LogEntry) {
+        entry.level |= LOG_FLAG_PREVIOUS_BOOT;
+        self.next_sequence.fetch_max(entry.sequence.wrapping_add(1), Ordering::Relaxed);
+        self.insert(entry);
+    }
+
+    pub(crate) fn next_sequence(&self) -> u32 {
+        self.next_sequence.load(Ordering::Relaxed)
+    }
+
+    /// Calls `f` for every entry starting at `sequence` until it returns false
+    pub(crate) fn for_each_from(&self, sequence: u32, mut f: impl FnMut(&LogEntry) -> bool) {
+        self.entries.lock(|entries| {
+            for entry in entries.borrow().iter().filter(|entry| entry.sequence >= sequence) {
+                if !f(entry) {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// [next sequence: u32][count: u8] followed by
+    /// [sequence: u32][timestamp: u32][level: u8][len: u8][message..] for every entry that fits.
+    /// Clients continue from the last received sequence + 1; a gap means the entries were overwritten.
+    pub(crate) fn page(&self, sequence: u32) -> Vec<u8, BLE_LOG_PAGE_LEN> {
+        let mut page: Vec<u8, BLE_LOG_PAGE_LEN> = Vec::new();
+        let _ = page.extend_from_slice(&self.next_sequence().to_le_bytes());
+        let _ = page.push(0);
+
+        let mut count = 0u8;
+        self.for_each_from(sequence, |entry| {
+            if page.len() + PAGE_ENTRY_HEADER_LEN + entry.message.len() > BLE_LOG_PAGE_LEN {
+                return false;
+            }
+            let _ = page.extend_from_slice(&entry.sequence.to_le_bytes());
+            let _ = page.extend_from_slice(&entry.timestamp.to_le_bytes());
+            let _ = page.extend_from_slice(&[entry.level, entry.message.len() as u8]);
+            let _ = page.extend_from_slice(&entry.message);
+            count += 1;
+            true
+        });
+
+        page[PAGE_HEADER_LEN - 1] = count;
+        page
+    }
+}
+
+pub(crate) static LOG: LogBuffer = LogBuffer::new();
+
+/// Puts the mirrored entries back into the ring, returns the index of the first free record
+pub(crate) async fn restore_log_from_flash() -> Result<usize, FlashManagerError> {
+    let flash_manager = FLASH_MANAGER.get();
+    for index in 0..LOG_RECORDS_PER_PAGE {
+        match flash_manager.read_log_record(index).await? {
+            Some(entry) => LOG.restore(entry),
+            None => return Ok(index),
+        }
+    }
+    Ok(LOG_RECORDS_PER_PAGE)
+}
+
+#[embassy_executor::task]
+pub(crate) async fn persist_log_task(mut next_index: usize) {
+    let flash_manager = FLASH_MANAGER.get();
+    // restored entries are already there
+    let mut persisted_until = LOG.next_sequence();
+
+    loop {
+        LOG.persist.wait().await;
+
+        let mut batch: Vec<LogEntry, LOG_PERSIST_BATCH_LEN> = Vec::new();
+        LOG.for_each_from(persisted_until, |entry| {
+            if entry.is_from_previous_boot() || entry.level() > LOG_PERSIST_LEVEL {
+                return true;
+            }
+            batch.push(entry.clone()).is_ok()
+        });
+        // whatever did not fit into the batch is dropped rather than wearing out the page
+        persisted_until = LOG.next_sequence();
+
+        for entry in batch.iter() {
+            if next_index >= LOG_RECORDS_PER_PAGE {
+                if let Err(err) = flash_manager.erase_log_page().await {
+                    info!("Failed to erase log page: {:?}", err);
+                    break;
+                }
+                next_index = 0;
+            }
+            if let Err(err) = flash_manager.write_log_record(next_index, entry).await {
+                info!("Failed to persist log entry: {:?}", err);
+                break;
+            }
+            next_index += 1;
+        }
+
+        Timer::after(LOG_PERSIST_INTERVAL).await;
+    }
+}
@@ -1,6 +1,11 @@
+pub(crate) mod barometer;
 pub(crate) mod ble_debugger;
+pub(crate) mod climate;
 pub(crate) mod condition;
 pub(crate) mod custom_static_cell;
+pub(crate) mod history_log;
+pub(crate) mod log_buffer;
 pub(crate) mod notify_macro;
 pub(crate) mod buf_writer;
 pub(crate) mod timeout_tracker;
+pub(crate) mod wall_clock;
@@ -26,8 +26,13 @@ macro_rules! notify_all {
         ),+
     ) => {
         for connection in nrf_softdevice::ble::Connection::iter() {
+            // each call site keeps its own notification cadence per connection
+            let __key = concat!(stringify!($service), $(":", stringify!($characteristic)),+);
             // by default all notification settings are disabled
-            let ns = if let Some(ns) = $event_processor.get_connection_settings(&connection).await {
+            let ns = if let Some((ns, is_due)) = $event_processor.get_due_connection_settings(&connection, __key).await {
+                if !is_due {
+                    continue
+                }
                 ns
             } else {
                 defmt::info!(
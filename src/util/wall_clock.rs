@@ -0,0 +1,363 @@
+//! Calendar on top of the uptime (RTC1): anchored by the centrals through Current Time writes or their
+//! own Current Time Service, the RTC drift is estimated from consecutive syncs.
+//! CTS carries local time only, so times are milliseconds since 1970-01-01 in the central's local time.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_time::Instant;
+
+use crate::common::device::config::{BLE_CURRENT_TIME_LEN, CLOCK_DRIFT_MIN_INTERVAL, CLOCK_MAX_DRIFT_PPM};
+
+/// Current Time adjust reasons
+pub(crate) const ADJUST_MANUAL: u8 = 0x01;
+pub(crate) const ADJUST_EXTERNAL_REFERENCE: u8 = 0x02;
+
+const MILLIS_PER_DAY: u64 = 86_400_000;
+const PPB: i64 = 1_000_000_000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, defmt::Format)]
+pub(crate) struct CalendarTime {
+    pub(crate) year: u16,
+    pub(crate) month: u8,
+    pub(crate) day: u8,
+    pub(crate) hours: u8,
+    pub(crate) minutes: u8,
+    pub(crate) seconds: u8,
+    /// 1 - Monday .. 7 - Sunday
+    pub(crate) day_of_week: u8,
+    pub(crate) millis: u16,
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u8;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl CalendarTime {
+    pub(crate) fn from_unix_millis(millis: u64) -> Self {
+        let days = (millis / MILLIS_PER_DAY) as i64;
+        let time_of_day = millis % MILLIS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hours: (time_of_day / 3_600_000) as u8,
+            minutes: (time_of_day / 60_000 % 60) as u8,
+            seconds: (time_of_day / 1000 % 60) as u8,
+            // 1970-01-01 was a Thursday
+            day_of_week: ((days + 3) % 7 + 1) as u8,
+            millis: (time_of_day % 1000) as u16,
+        }
+    }
+
+    /// None for dates before 1970 and out of range fields
+    pub(crate) fn to_unix_millis(&self) -> Option<u64> {
+        let is_valid = self.year >= 1970
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hours < 24
+            && self.minutes < 60
+            && self.seconds < 60
+            && self.millis < 1000;
+        if !is_valid {
+            return None;
+        }
+
+        let days = days_from_civil(self.year as i64, self.month, self.day) as u64;
+        let seconds = self.hours as u64 * 3600 + self.minutes as u64 * 60 + self.seconds as u64;
+        Some(days * MILLIS_PER_DAY + seconds * 1000 + self.millis as u64)
+    }
+
+    /// Returns the time and the adjust reason; the day of week is derived from the date
+    pub(crate) fn parse_current_time(data: &[u8]) -> Option<(Self, u8)> {
+        let data: &[u8; BLE_CURRENT_TIME_LEN] = data.try_into().ok()?;
+        let time = Self {
+            year: u16::from_le_bytes([data[0], data[1]]),
+            month: data[2],
+            day: data[3],
+            hours: data[4],
+            minutes: data[5],
+            seconds: data[6],
+            day_of_week: data[7],
+            // rounded up, so that `to_current_time` gives the same Fractions256 back
+            millis: ((data[8] as u32 * 1000).div_ceil(256)) as u16,
+        };
+        let millis = time.to_unix_millis()?;
+        Some((Self::from_unix_millis(millis), data[9]))
+    }
+
+    pub(crate) fn to_current_time(&self, adjust_reason: u8) -> [u8; BLE_CURRENT_TIME_LEN] {
+        let year = self.year.to_le_bytes();
+        [
+            year[0],
+            year[1],
+            self.month,
+            self.day,
+            self.hours,
+            self.minutes,
+            self.seconds,
+            self.day_of_week,
+            (self.millis as u32 * 256 / 1000) as u8,
+            adjust_reason,
+        ]
+    }
+}
+
+impl fmt::Display for CalendarTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+#[derive(Copy, Clone)]
+struct SyncPoint {
+    uptime_ms: u64,
+    unix_ms: u64,
+}
+
+#[derive(Copy, Clone)]
+struct ClockState {
+    /// The time is extrapolated from the latest sync
+    anchor: SyncPoint,
+    /// The drift is measured against an older sync, at least `CLOCK_DRIFT_MIN_INTERVAL` back
+    reference: SyncPoint,
+    /// Positive when the RTC runs fast, parts per billion
+    drift_ppb: Option<i64>,
+    adjust_reason: u8,
+}
+
+impl ClockState {
+    fn at_uptime(&self, uptime_ms: u64) -> u64 {
+        let elapsed = uptime_ms as i64 - self.anchor.uptime_ms as i64;
+        let correction = elapsed * self.drift_ppb.unwrap_or(0) / PPB;
+        (self.anchor.unix_ms as i64 + elapsed - correction).max(0) as u64
+    }
+}
+
+pub(crate) struct WallClock {
+    state: Mutex<ThreadModeRawMutex, RefCell<Option<ClockState>>>,
+}
+
+impl WallClock {
+    const fn new() -> Self {
+        Self { state: Mutex::new(RefCell::new(None)) }
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.state.lock(|state| state.borrow().is_some())
+    }
+
+    /// Converts an uptime timestamp of this boot, None until the first sync
+    pub(crate) fn at_uptime(&self, uptime_ms: u64) -> Option<u64> {
+        self.state.lock(|state| state.borrow().map(|state| state.at_uptime(uptime_ms)))
+    }
+
+    pub(crate) fn now_millis(&self) -> Option<u64> {
+        self.at_uptime(Instant::now().as_millis())
+    }
+
+    /// Whole seconds, 0 until the first sync; the representation of log entries and stored records
+    pub(crate) fn now_seconds(&self) -> u32 {
+        self.now_millis().map_or(0, |millis| (millis / 1000) as u32)
+    }
+
+    pub(crate) fn now(&self) -> Option<CalendarTime> {
+        self.now_millis().map(CalendarTime::from_unix_millis)
+    }
+
+    pub(crate) fn adjust_reason(&self) -> u8 {
+        self.state.lock(|state| state.borrow().map_or(0, |state| state.adjust_reason))
+    }
+
+    pub(crate) fn drift_ppb(&self) -> Option<i64> {
+        self.state.lock(|state| state.borrow().and_then(|state| state.drift_ppb))
+    }
+
+    /// Anchors the calendar at `unix_ms`; returns the clock error before the sync in milliseconds,
+    /// i.e. how far the extrapolated time was off
+    pub(crate) fn sync(&self, unix_ms: u64, adjust_reason: u8) -> Option<i64> {
+        self.sync_at(Instant::now().as_millis(), unix_ms, adjust_reason)
+    }
+
+    fn sync_at(&self, uptime_ms: u64, unix_ms: u64, adjust_reason: u8) -> Option<i64> {
+        let sync = SyncPoint { uptime_ms, unix_ms };
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let Some(previous) = *state else {
+                *state = Some(ClockState { anchor: sync, reference: sync, drift_ppb: None, adjust_reason });
+                return None;
+            };
+
+            let error = previous.at_uptime(uptime_ms) as i64 - unix_ms as i64;
+            let mut next = ClockState { anchor: sync, adjust_reason, ..previous };
+
+            let rtc_elapsed = uptime_ms as i64 - previous.reference.uptime_ms as i64;
+            let real_elapsed = unix_ms as i64 - previous.reference.unix_ms as i64;
+            if rtc_elapsed >= CLOCK_DRIFT_MIN_INTERVAL.as_millis() as i64 && real_elapsed > 0 {
+                let measured = (rtc_elapsed - real_elapsed) * PPB / real_elapsed;
+                if measured.abs() <= CLOCK_MAX_DRIFT_PPM * 1000 {
+                    // a single sync is only as precise as the central's clock, so average them out
+                    next.drift_ppb = Some(previous.drift_ppb.map_or(measured, |drift| (drift + measured) / 2));
+                }
+                // larger deviations mean the time has been changed, start measuring over
+                next.reference = sync;
+            }
+
+            *state = Some(next);
+            Some(error)
+        })
+    }
+}
+
+pub(crate) static CLOCK: WallClock = WallClock::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: u64 = 3_600_000;
+    /// 2023-11-14 22:13:20
+    const EPOCH_MS: u64 = 1_700_000_000_000;
+
+    fn date(year: u16, month: u8, day: u8) -> CalendarTime {
+        CalendarTime { year, month, day, hours: 0, minutes: 0, seconds: 0, day_of_week: 0, millis: 0 }
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!(date(2024, 2, 29).to_unix_millis().is_some());
+        assert!(date(2000, 2, 29).to_unix_millis().is_some());
+        assert_eq!(date(2023, 2, 29).to_unix_millis(), None);
+        assert_eq!(date(2100, 2, 29).to_unix_millis(), None);
+
+        let march_first = date(2100, 3, 1).to_unix_millis().unwrap();
+        assert_eq!(march_first, 4_107_542_400_000);
+        let february_last = CalendarTime::from_unix_millis(march_first - MILLIS_PER_DAY);
+        assert_eq!((february_last.year, february_last.month, february_last.day), (2100, 2, 28));
+    }
+
+    #[test]
+    fn invalid_fields() {
+        assert_eq!(date(1969, 12, 31).to_unix_millis(), None);
+        assert_eq!(date(2024, 0, 1).to_unix_millis(), None);
+        assert_eq!(date(2024, 13, 1).to_unix_millis(), None);
+        assert_eq!(date(2024, 4, 31).to_unix_millis(), None);
+        assert_eq!(CalendarTime { hours: 24, ..date(2024, 1, 1) }.to_unix_millis(), None);
+        assert_eq!(CalendarTime { seconds: 60, ..date(2024, 1, 1) }.to_unix_millis(), None);
+    }
+
+    #[test]
+    fn day_of_week() {
+        assert_eq!(CalendarTime::from_unix_millis(0).day_of_week, 4);
+        let days = [(2000, 1, 1, 6), (2024, 2, 29, 4), (2024, 3, 3, 7), (2024, 3, 4, 1)];
+        for (year, month, day, day_of_week) in days {
+            let millis = date(year, month, day).to_unix_millis().unwrap();
+            assert_eq!(CalendarTime::from_unix_millis(millis).day_of_week, day_of_week, "{}-{}-{}", year, month, day);
+        }
+    }
+
+    #[test]
+    fn unix_millis_round_trip() {
+        let time = CalendarTime::from_unix_millis(1_709_210_096_789);
+        let expected = CalendarTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hours: 12,
+            minutes: 34,
+            seconds: 56,
+            day_of_week: 4,
+            millis: 789,
+        };
+        assert_eq!(time, expected);
+        assert_eq!(time.to_unix_millis(), Some(1_709_210_096_789));
+    }
+
+    #[test]
+    fn current_time_round_trip() {
+        let time = CalendarTime::from_unix_millis(1_709_210_096_789);
+        let value = time.to_current_time(ADJUST_MANUAL);
+        assert_eq!(value, [0xE8, 0x07, 2, 29, 12, 34, 56, 4, 201, ADJUST_MANUAL]);
+
+        for fractions in 0..=255 {
+            let value = [0xE8, 0x07, 2, 29, 12, 34, 56, 4, fractions, ADJUST_EXTERNAL_REFERENCE];
+            let (time, adjust_reason) = CalendarTime::parse_current_time(&value).unwrap();
+            assert_eq!(adjust_reason, ADJUST_EXTERNAL_REFERENCE);
+            assert_eq!(time.to_current_time(adjust_reason), value);
+        }
+    }
+
+    #[test]
+    fn current_time_is_validated() {
+        // the day of week written by the central is replaced
+        let (time, _) = CalendarTime::parse_current_time(&[0xE8, 0x07, 2, 29, 0, 0, 0, 1, 0, 0]).unwrap();
+        assert_eq!(time.day_of_week, 4);
+
+        assert!(CalendarTime::parse_current_time(&[0xE7, 0x07, 2, 29, 0, 0, 0, 0, 0, 0]).is_none());
+        assert!(CalendarTime::parse_current_time(&[0xE8, 0x07, 2, 29, 0, 0, 0, 0, 0]).is_none());
+        assert!(CalendarTime::parse_current_time(&[]).is_none());
+    }
+
+    #[test]
+    fn drift_correction() {
+        let clock = WallClock::new();
+        assert_eq!(clock.sync_at(0, EPOCH_MS, ADJUST_MANUAL), None);
+        assert_eq!(clock.at_uptime(HOUR_MS), Some(EPOCH_MS + HOUR_MS));
+
+        // too soon to measure the drift
+        assert_eq!(clock.sync_at(60_000, EPOCH_MS + 60_000, ADJUST_EXTERNAL_REFERENCE), Some(0));
+        assert_eq!(clock.drift_ppb(), None);
+
+        // the RTC runs 100 ppm fast
+        assert_eq!(clock.sync_at(HOUR_MS + 360, EPOCH_MS + HOUR_MS, ADJUST_EXTERNAL_REFERENCE), Some(360));
+        assert_eq!(clock.drift_ppb(), Some(100_000));
+        assert_eq!(clock.at_uptime(2 * HOUR_MS + 720), Some(EPOCH_MS + 2 * HOUR_MS));
+        assert_eq!(clock.adjust_reason(), ADJUST_EXTERNAL_REFERENCE);
+
+        // the next measurement is averaged in
+        assert_eq!(clock.sync_at(2 * HOUR_MS + 1080, EPOCH_MS + 2 * HOUR_MS, ADJUST_EXTERNAL_REFERENCE), Some(360));
+        assert_eq!(clock.drift_ppb(), Some(150_000));
+
+        // a changed time is not mistaken for drift
+        assert_eq!(clock.sync_at(3 * HOUR_MS, EPOCH_MS + 4 * HOUR_MS, ADJUST_MANUAL), Some(-(HOUR_MS as i64) - 1619));
+        assert_eq!(clock.drift_ppb(), Some(150_000));
+        assert_eq!(clock.at_uptime(3 * HOUR_MS), Some(EPOCH_MS + 4 * HOUR_MS));
+        assert_eq!(clock.adjust_reason(), ADJUST_MANUAL);
+    }
+}